@@ -0,0 +1,83 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::thread::JoinHandle;
+use tokio::sync::mpsc;
+
+/// One modification, written as a JSON line.
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+    pub ts: DateTime<Utc>,
+    pub op: &'static str,
+    pub path: String,
+    /// Target of a rename
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_path: Option<String>,
+    /// Bytes written, or the new size of a truncated file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    pub uid: u32,
+    pub pid: u32,
+}
+
+/// Append-only record of the changes made through the mount. Entries are
+/// handed to a writer thread, so FUSE operations never wait for log I/O.
+pub struct AuditLog {
+    sender: mpsc::UnboundedSender<AuditEntry>,
+    writer: JoinHandle<()>,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open audit log {}", path.display()))?;
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let writer = std::thread::Builder::new()
+            .name("audit-log".to_string())
+            .spawn(move || write_entries(file, receiver))
+            .context("Failed to start audit log writer")?;
+        tracing::info!("Recording changes in {}", path.display());
+        Ok(Self { sender, writer })
+    }
+
+    pub fn record(&self, entry: AuditEntry) {
+        if self.sender.send(entry).is_err() {
+            tracing::error!("Audit log writer has stopped; change not recorded");
+        }
+    }
+
+    /// Wait until every recorded entry is written.
+    pub fn close(self) {
+        drop(self.sender);
+        if self.writer.join().is_err() {
+            tracing::error!("Audit log writer panicked");
+        }
+    }
+}
+
+fn write_entries(mut file: File, mut receiver: mpsc::UnboundedReceiver<AuditEntry>) {
+    while let Some(entry) = receiver.blocking_recv() {
+        let mut line = match serde_json::to_vec(&entry) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::error!("Failed to serialize audit entry {:?}: {}", entry, e);
+                continue;
+            }
+        };
+        line.push(b'\n');
+        // One write per line, so concurrent appenders never interleave
+        if let Err(e) = file.write_all(&line) {
+            tracing::error!("Failed to write audit log: {}", e);
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+
+/// File content being written, kept as the extents actually written so a
+/// write far past the end does not allocate the hole before it. Holes read
+/// as zeros.
+#[derive(Debug, Default, Clone)]
+pub struct SparseBuffer {
+    // Extents by start offset; they neither overlap nor touch
+    extents: BTreeMap<u64, Vec<u8>>,
+    len: u64,
+}
+
+impl From<Vec<u8>> for SparseBuffer {
+    fn from(data: Vec<u8>) -> Self {
+        let len = data.len() as u64;
+        let mut extents = BTreeMap::new();
+        if !data.is_empty() {
+            extents.insert(0, data);
+        }
+        Self { extents, len }
+    }
+}
+
+impl SparseBuffer {
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Write `data` at `offset`, merging it with the extents it overlaps
+    /// or touches.
+    pub fn write(&mut self, offset: u64, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        let end = offset + data.len() as u64;
+
+        // Highest first; earlier extents end earlier, so stop at the first
+        // one ending before `offset`
+        let touching: Vec<u64> = self
+            .extents
+            .range(..=end)
+            .rev()
+            .take_while(|(&start, extent)| start + extent.len() as u64 >= offset)
+            .map(|(&start, _)| start)
+            .collect();
+
+        // Grow an extent starting at or before `offset` in place, so
+        // appending does not copy what was written so far
+        let (start, mut merged) = match touching.last() {
+            Some(&first) if first <= offset => (first, self.extents.remove(&first).unwrap_or_default()),
+            _ => (offset, Vec::new()),
+        };
+        for &other in touching.iter().filter(|&&other| other > start) {
+            let extent = self.extents.remove(&other).unwrap_or_default();
+            let at = (other - start) as usize;
+            if merged.len() < at + extent.len() {
+                merged.resize(at + extent.len(), 0);
+            }
+            merged[at..at + extent.len()].copy_from_slice(&extent);
+        }
+        let at = (offset - start) as usize;
+        if merged.len() < at + data.len() {
+            merged.resize(at + data.len(), 0);
+        }
+        merged[at..at + data.len()].copy_from_slice(data);
+
+        self.extents.insert(start, merged);
+        self.len = self.len.max(end);
+    }
+
+    /// Up to `size` bytes at `offset`, fewer at the end of the buffer.
+    pub fn read(&self, offset: u64, size: u64) -> Vec<u8> {
+        let end = offset.saturating_add(size).min(self.len);
+        if offset >= end {
+            return Vec::new();
+        }
+        let mut data = vec![0; (end - offset) as usize];
+        let first = self
+            .extents
+            .range(..=offset)
+            .next_back()
+            .map_or(offset, |(&start, _)| start);
+        for (&start, extent) in self.extents.range(first..end) {
+            let from = start.max(offset);
+            let to = (start + extent.len() as u64).min(end);
+            if from < to {
+                data[(from - offset) as usize..(to - offset) as usize]
+                    .copy_from_slice(&extent[(from - start) as usize..(to - start) as usize]);
+            }
+        }
+        data
+    }
+
+    /// Truncate to `len` bytes, or extend to it with a hole.
+    pub fn set_len(&mut self, len: u64) {
+        self.extents.retain(|&start, _| start < len);
+        if let Some((&start, extent)) = self.extents.iter_mut().next_back() {
+            extent.truncate((len - start) as usize);
+        }
+        self.len = len;
+    }
+
+    /// The whole content, with holes filled with zeros.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.read(0, self.len)
+    }
+}
@@ -1,101 +1,935 @@
-use std::collections::HashMap;
+use anyhow::{Context, Result};
+use indexmap::IndexMap;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
 
-use crate::webdav::DavEntry;
+use crate::global_cache::GlobalCacheManager;
+use crate::metrics::Metrics;
+use crate::webdav::{DavEntry, DirListing};
 
+/// Longest TTL `adaptive_ttl` gives a listing.
+const ADAPTIVE_TTL_MAX: Duration = Duration::from_secs(24 * 60 * 60);
+/// Fraction of the time since the last change a listing is trusted for,
+/// like the RFC 7234 heuristic for HTTP caches.
+const ADAPTIVE_TTL_DIVISOR: u32 = 10;
+
+/// Directory listing cache. Listings are persisted to SQLite so a fresh
+/// mount can serve the last known tree immediately (even offline); an
+/// in-memory LRU in front of the database keeps hot directories cheap.
 #[derive(Clone)]
 pub struct DirectoryCache {
-    entries: Arc<Mutex<HashMap<String, CachedDirectory>>>,
+    // In-memory layer in least- to most-recently-used order
+    entries: Arc<Mutex<IndexMap<String, CachedDirectory>>>,
+    db: Arc<Mutex<Connection>>,
     ttl: Duration,
+    // Trust listings of directories that have not changed in a long time
+    // for longer than `ttl`
+    adaptive_ttl: bool,
+    max_entries: usize,
+    // Limit on the approximate memory of the listings in memory; 0 for none
+    max_memory_bytes: usize,
+    evicted_count: Arc<AtomicUsize>,
+    metrics: Arc<Metrics>,
+    // Paths recently found missing, so repeated stats skip the server
+    negative_entries: Arc<Mutex<HashMap<String, Instant>>>,
+    negative_ttl: Duration,
+    // Listings being fetched right now, shared with concurrent callers
+    inflight: PropfindInflight,
+    // Paths kept in memory and refreshed in the background, with
+    // everything below them
+    pinned: Arc<Mutex<HashSet<String>>>,
+    // Limit on the listings in memory across all mounts
+    global: Option<Arc<GlobalCacheManager>>,
 }
 
+#[derive(Clone)]
 struct CachedDirectory {
     entries: Vec<DavEntry>,
-    cached_at: Instant,
+    cached_at: SystemTime,
+    // Etag of the collection itself, for conditional revalidation
+    etag: Option<String>,
+    // Last lookup in memory, to find the oldest listing across mounts
+    last_used: SystemTime,
+    // Approximate heap footprint, set when it is kept in memory
+    size: usize,
+}
+
+/// Outcome of a cache lookup that distinguishes expired listings, which can
+/// be revalidated with their etag, from fresh ones.
+pub enum CacheResult {
+    Fresh(Vec<DavEntry>),
+    Stale {
+        entries: Vec<DavEntry>,
+        etag: Option<String>,
+    },
+    Miss,
+}
+
+impl CachedDirectory {
+    fn age(&self) -> Duration {
+        self.cached_at.elapsed().unwrap_or_default()
+    }
 }
 
 impl DirectoryCache {
-    pub fn new(ttl: Duration) -> Self {
-        Self {
-            entries: Arc::new(Mutex::new(HashMap::new())),
+    /// Open (or create) the cache database at `db_path`, keeping at most
+    /// `max_entries` directories in memory.
+    pub fn open(db_path: &Path, ttl: Duration, max_entries: usize) -> Result<Self> {
+        if let Some(parent) = db_path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+
+        let db = Connection::open(db_path)
+            .with_context(|| format!("Failed to open cache database {}", db_path.display()))?;
+        db.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            PRAGMA synchronous = NORMAL;
+            CREATE TABLE IF NOT EXISTS directories (
+                path TEXT PRIMARY KEY,
+                cached_at INTEGER NOT NULL,
+                etag TEXT,
+                entries BLOB NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS counters (
+                name TEXT PRIMARY KEY,
+                value INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS inodes (
+                inode INTEGER PRIMARY KEY,
+                path TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS inode_ctimes (
+                inode INTEGER PRIMARY KEY,
+                ctime_ns INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS ttl_overrides (
+                path TEXT PRIMARY KEY,
+                ttl_secs INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS pinned_paths (
+                path TEXT PRIMARY KEY
+            );",
+        )
+        .context("Failed to initialize cache database")?;
+        let pinned = load_pinned(&db).context("Failed to read pinned paths")?;
+
+        // Persisted so `status` can report it from outside the mount process
+        let evicted_count: i64 = db
+            .query_row("SELECT value FROM counters WHERE name = 'evicted'", [], |row| row.get(0))
+            .optional()
+            .context("Failed to read cache counters")?
+            .unwrap_or(0);
+
+        tracing::info!("Opened directory cache at {}", db_path.display());
+
+        Ok(Self {
+            entries: Arc::new(Mutex::new(IndexMap::new())),
+            db: Arc::new(Mutex::new(db)),
             ttl,
+            adaptive_ttl: false,
+            max_entries: max_entries.max(1),
+            max_memory_bytes: 0,
+            evicted_count: Arc::new(AtomicUsize::new(evicted_count.max(0) as usize)),
+            metrics: Arc::new(Metrics::default()),
+            negative_entries: Arc::new(Mutex::new(HashMap::new())),
+            negative_ttl: Duration::from_secs(5),
+            inflight: PropfindInflight::default(),
+            pinned: Arc::new(Mutex::new(pinned)),
+            global: None,
+        })
+    }
+
+    /// How long a path found missing is reported as missing without asking
+    /// the server again.
+    pub fn with_negative_ttl(mut self, negative_ttl: Duration) -> Self {
+        self.negative_ttl = negative_ttl;
+        self
+    }
+
+    /// Also evict listings while those in memory take more than
+    /// `max_memory_bytes` (as counted by `memory_usage_bytes`); 0 for no
+    /// limit.
+    pub fn with_max_memory_bytes(mut self, max_memory_bytes: usize) -> Self {
+        self.max_memory_bytes = max_memory_bytes;
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Register with `global` as mount `name`, which then evicts listings
+    /// of this cache to keep all mounts under its limit.
+    pub fn with_global_cache(mut self, global: Arc<GlobalCacheManager>, name: &str) -> Self {
+        self.global = Some(global.clone());
+        global.register(name, self.clone());
+        self
+    }
+
+    /// Give each listing a TTL of a tenth of the time since its newest
+    /// entry was modified, between the cache-wide TTL and a day.
+    pub fn with_adaptive_ttl(mut self, adaptive_ttl: bool) -> Self {
+        self.adaptive_ttl = adaptive_ttl;
+        self
+    }
+
+    /// How long `cached` is fresh for.
+    fn ttl_of(&self, cached: &CachedDirectory) -> Duration {
+        if !self.adaptive_ttl {
+            return self.ttl;
         }
+        let Some(newest) = cached.entries.iter().filter_map(|entry| entry.modified).max() else {
+            return self.ttl;
+        };
+        let unchanged_for = cached
+            .cached_at
+            .duration_since(SystemTime::from(newest))
+            .unwrap_or_default();
+        (unchanged_for / ADAPTIVE_TTL_DIVISOR).clamp(self.ttl, ADAPTIVE_TTL_MAX.max(self.ttl))
+    }
+
+    /// List `path` with `fetch`, or wait for the result of a listing of
+    /// `path` that is already in flight.
+    pub async fn fetch_listing<F>(&self, path: &str, fetch: F) -> Result<DirListing>
+    where
+        F: Future<Output = Result<DirListing>>,
+    {
+        self.inflight.run(path, fetch).await
     }
 
-    pub fn get(&self, path: &str) -> Option<Vec<DavEntry>> {
+    /// Insert into the in-memory layer as most recently used, evicting the
+    /// least recently used directories beyond `max_entries` or
+    /// `max_memory_bytes`. Pinned directories are never evicted; evicted
+    /// listings remain in the database.
+    fn remember(&self, path: String, mut cached: CachedDirectory) {
+        let mut evicted_now = 0;
+        let memory = {
+            let mut entries = self.entries.lock().unwrap();
+            entries.shift_remove(&path);
+            cached.last_used = SystemTime::now();
+            cached.size = listing_size(&path, &cached.entries);
+            entries.insert(path, cached);
+
+            let pinned = self.pinned.lock().unwrap();
+            let mut memory = Self::memory_usage(&entries);
+            while entries.len() > self.max_entries || (self.max_memory_bytes > 0 && memory > self.max_memory_bytes) {
+                let Some(index) = entries.keys().position(|path| !is_below_any(&pinned, path)) else {
+                    break;
+                };
+                // The listing just inserted stays even if it alone is too large
+                if index == entries.len() - 1 && entries.len() <= self.max_entries {
+                    break;
+                }
+                if let Some((evicted, cached)) = entries.shift_remove_index(index) {
+                    evicted_now += 1;
+                    memory -= cached.size;
+                    tracing::debug!("Evicted {} from memory cache", evicted);
+                }
+            }
+            memory
+        };
+        self.count_evictions(evicted_now);
+        self.store_memory_usage(memory);
+        if let Some(global) = &self.global {
+            global.enforce();
+        }
+    }
+
+    /// Number of listings in memory.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Last use of the least recently used listing in memory that may be
+    /// evicted.
+    pub fn least_recent_use(&self) -> Option<SystemTime> {
         let entries = self.entries.lock().unwrap();
-        
-        if let Some(cached) = entries.get(path) {
-            if cached.cached_at.elapsed() < self.ttl {
+        let pinned = self.pinned.lock().unwrap();
+        entries
+            .iter()
+            .find(|(path, _)| !is_below_any(&pinned, path))
+            .map(|(_, cached)| cached.last_used)
+    }
+
+    /// Evict the least recently used listing from memory, unless all are
+    /// pinned. Returns whether one was evicted.
+    pub fn evict_least_recent(&self) -> bool {
+        let evicted = {
+            let mut entries = self.entries.lock().unwrap();
+            let pinned = self.pinned.lock().unwrap();
+            let index = entries.keys().position(|path| !is_below_any(&pinned, path));
+            index.and_then(|index| entries.shift_remove_index(index))
+        };
+        let Some((evicted, _)) = evicted else {
+            return false;
+        };
+        tracing::debug!("Evicted {} from memory cache for the global limit", evicted);
+        self.count_evictions(1);
+        self.store_memory_usage(self.memory_usage_bytes());
+        true
+    }
+
+    fn count_evictions(&self, evicted_now: usize) {
+        if evicted_now == 0 {
+            return;
+        }
+
+        let total = self.evicted_count.fetch_add(evicted_now, Ordering::Relaxed) + evicted_now;
+        let db = self.db.lock().unwrap();
+        if let Err(e) = db.execute(
+            "INSERT OR REPLACE INTO counters (name, value) VALUES ('evicted', ?1)",
+            params![total as i64],
+        ) {
+            tracing::warn!("Failed to persist eviction count: {}", e);
+        }
+    }
+
+    // Persisted so `status` can report it from outside the mount process
+    fn store_memory_usage(&self, bytes: usize) {
+        let db = self.db.lock().unwrap();
+        if let Err(e) = db.execute(
+            "INSERT OR REPLACE INTO counters (name, value) VALUES ('memory_bytes', ?1)",
+            params![bytes as i64],
+        ) {
+            tracing::warn!("Failed to persist cache memory usage: {}", e);
+        }
+    }
+
+    /// Find `path` in memory, falling back to the database.
+    fn lookup(&self, path: &str) -> Option<CachedDirectory> {
+        {
+            let mut entries = self.entries.lock().unwrap();
+            if let Some(index) = entries.get_index_of(path) {
+                let last = entries.len() - 1;
+                entries.move_index(index, last);
+                let (_, cached) = entries.get_index_mut(last)?;
+                cached.last_used = SystemTime::now();
+                return Some(cached.clone());
+            }
+        }
+
+        let cached = match self.load(path) {
+            Ok(cached) => cached?,
+            Err(e) => {
+                tracing::warn!("Failed to read cache database for {}: {}", path, e);
+                return None;
+            }
+        };
+        tracing::debug!("Loaded {} from cache database", path);
+        self.remember(path.to_string(), cached.clone());
+        Some(cached)
+    }
+
+    fn load(&self, path: &str) -> Result<Option<CachedDirectory>> {
+        let db = self.db.lock().unwrap();
+        let row = db
+            .query_row(
+                "SELECT cached_at, etag, entries FROM directories WHERE path = ?1",
+                params![path],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                        row.get::<_, Vec<u8>>(2)?,
+                    ))
+                },
+            )
+            .optional()?;
+
+        let Some((cached_at, etag, blob)) = row else {
+            return Ok(None);
+        };
+        let entries: Vec<DavEntry> = bincode::deserialize(&blob)?;
+
+        Ok(Some(CachedDirectory {
+            entries,
+            cached_at: UNIX_EPOCH + Duration::from_secs(cached_at.max(0) as u64),
+            etag,
+            last_used: SystemTime::now(),
+            size: 0,
+        }))
+    }
+
+    fn store(&self, path: &str, cached: &CachedDirectory) -> Result<()> {
+        let blob = bincode::serialize(&cached.entries)?;
+        let cached_at = cached.cached_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+
+        let db = self.db.lock().unwrap();
+        db.execute(
+            "INSERT OR REPLACE INTO directories (path, cached_at, etag, entries)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![path, cached_at, cached.etag, blob],
+        )?;
+        Ok(())
+    }
+
+    /// Look up `path`, treating it as fresh for `ttl_override` instead of
+    /// the cache-wide TTL if given.
+    pub fn get(&self, path: &str, ttl_override: Option<Duration>) -> CacheResult {
+        match self.lookup(path) {
+            Some(cached) if cached.age() < ttl_override.unwrap_or_else(|| self.ttl_of(&cached)) => {
                 tracing::debug!("Cache hit for path: {}", path);
-                return Some(cached.entries.clone());
-            } else {
+                self.metrics.cache_hits.fetch_add(1, Ordering::Relaxed);
+                CacheResult::Fresh(cached.entries)
+            }
+            Some(cached) => {
                 tracing::debug!("Cache expired for path: {}", path);
+                // Needs a round trip to revalidate
+                self.metrics.cache_misses.fetch_add(1, Ordering::Relaxed);
+                CacheResult::Stale {
+                    entries: cached.entries,
+                    etag: cached.etag,
+                }
+            }
+            None => {
+                tracing::debug!("Cache miss for path: {}", path);
+                self.metrics.cache_misses.fetch_add(1, Ordering::Relaxed);
+                CacheResult::Miss
             }
-        } else {
-            tracing::debug!("Cache miss for path: {}", path);
         }
-        
-        None
     }
-    
+
+    /// Etag of the cached listing of `path`, without counting a lookup.
+    pub fn etag(&self, path: &str) -> Option<String> {
+        self.lookup(path)?.etag
+    }
+
+    /// Look up `path` like `get`, without counting a lookup.
+    pub fn peek(&self, path: &str) -> CacheResult {
+        match self.lookup(path) {
+            Some(cached) if cached.age() < self.ttl_of(&cached) => CacheResult::Fresh(cached.entries),
+            Some(cached) => CacheResult::Stale {
+                entries: cached.entries,
+                etag: cached.etag,
+            },
+            None => CacheResult::Miss,
+        }
+    }
+
+    /// Remember that `path` does not exist on the server.
+    pub fn insert_negative(&self, path: &str) {
+        let mut negative = self.negative_entries.lock().unwrap();
+        if negative.len() >= self.max_entries {
+            let ttl = self.negative_ttl;
+            negative.retain(|_, found| found.elapsed() < ttl);
+        }
+        negative.insert(path.to_string(), Instant::now());
+    }
+
+    /// Whether `path` was found missing within the negative TTL.
+    pub fn is_negative(&self, path: &str) -> bool {
+        let mut negative = self.negative_entries.lock().unwrap();
+        match negative.get(path) {
+            Some(found) if found.elapsed() < self.negative_ttl => true,
+            Some(_) => {
+                negative.remove(path);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Forget that `path` and anything below it were missing, e.g. after
+    /// creating it.
+    pub fn remove_negative(&self, path: &str) {
+        let prefix = format!("{}/", path);
+        self.negative_entries
+            .lock()
+            .unwrap()
+            .retain(|p, _| p != path && !p.starts_with(&prefix));
+    }
+
     pub fn get_stale(&self, path: &str) -> Option<Vec<DavEntry>> {
         // Return cached data even if stale - for fast responses
-        let entries = self.entries.lock().unwrap();
-        
-        if let Some(cached) = entries.get(path) {
-            let age = cached.cached_at.elapsed();
-            if age < self.ttl {
+        if let Some(cached) = self.lookup(path) {
+            let age = cached.age();
+            if age < self.ttl_of(&cached) {
                 tracing::debug!("Cache hit (fresh) for path: {}", path);
             } else {
                 tracing::debug!("Cache hit (stale, age={:?}) for path: {}", age, path);
             }
-            return Some(cached.entries.clone());
+            self.metrics.cache_hits.fetch_add(1, Ordering::Relaxed);
+            return Some(cached.entries);
         }
-        
+
         tracing::debug!("Cache miss for path: {}", path);
+        self.metrics.cache_misses.fetch_add(1, Ordering::Relaxed);
         None
     }
 
-    pub fn insert(&self, path: String, entries: Vec<DavEntry>) {
-        let mut cache = self.entries.lock().unwrap();
-        cache.insert(path.clone(), CachedDirectory {
+    pub fn insert(&self, path: String, entries: Vec<DavEntry>, etag: Option<String>) {
+        let cached = CachedDirectory {
             entries,
-            cached_at: Instant::now(),
-        });
-        tracing::debug!("Cached {} entries for path: {}", cache.get(&path).map(|c| c.entries.len()).unwrap_or(0), path);
+            cached_at: SystemTime::now(),
+            etag,
+            last_used: SystemTime::now(),
+            size: 0,
+        };
+        if let Err(e) = self.store(&path, &cached) {
+            tracing::warn!("Failed to persist cache entry for {}: {}", path, e);
+        }
+
+        let count = cached.entries.len();
+        tracing::debug!("Cached {} entries for path: {}", count, path);
+        self.remember(path, cached);
+    }
+
+    /// Apply a local change to a cached listing, keeping its age. Used for
+    /// offline changes that the server has not seen yet.
+    pub fn update(&self, path: &str, change: impl FnOnce(&mut Vec<DavEntry>)) {
+        let Some(mut cached) = self.lookup(path) else {
+            return;
+        };
+        change(&mut cached.entries);
+        if let Err(e) = self.store(path, &cached) {
+            tracing::warn!("Failed to persist cache entry for {}: {}", path, e);
+        }
+        self.remember(path.to_string(), cached);
+    }
+
+    /// Mark a cached listing as fresh again after the server confirmed it is
+    /// unchanged (304 Not Modified).
+    pub fn touch(&self, path: &str) {
+        let now = SystemTime::now();
+        if let Some(cached) = self.entries.lock().unwrap().get_mut(path) {
+            cached.cached_at = now;
+        }
+
+        let cached_at = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+        let db = self.db.lock().unwrap();
+        if let Err(e) = db.execute(
+            "UPDATE directories SET cached_at = ?1 WHERE path = ?2",
+            params![cached_at, path],
+        ) {
+            tracing::warn!("Failed to refresh cache entry for {}: {}", path, e);
+        }
+        tracing::debug!("Revalidated cache for path: {}", path);
+    }
+
+    /// Paths and etags of all persisted listings older than the TTL.
+    pub fn expired_paths(&self) -> Vec<(String, Option<String>)> {
+        let expired_before = SystemTime::now()
+            .checked_sub(self.ttl)
+            .unwrap_or(UNIX_EPOCH)
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+
+        let db = self.db.lock().unwrap();
+        let result = db
+            .prepare("SELECT path, etag FROM directories WHERE cached_at <= ?1")
+            .and_then(|mut stmt| {
+                stmt.query_map(params![expired_before], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect()
+            });
+        result.unwrap_or_else(|e| {
+            tracing::warn!("Failed to read expired cache entries: {}", e);
+            Vec::new()
+        })
     }
 
     pub fn invalidate(&self, path: &str) {
-        let mut cache = self.entries.lock().unwrap();
-        cache.remove(path);
+        self.entries.lock().unwrap().shift_remove(path);
+        let db = self.db.lock().unwrap();
+        if let Err(e) = db.execute("DELETE FROM directories WHERE path = ?1", params![path]) {
+            tracing::warn!("Failed to invalidate cache entry for {}: {}", path, e);
+        }
         tracing::debug!("Invalidated cache for path: {}", path);
     }
 
+    /// Mark every listing as expired, so it is revalidated with its etag on
+    /// next use.
+    pub fn expire_all(&self) {
+        for cached in self.entries.lock().unwrap().values_mut() {
+            cached.cached_at = UNIX_EPOCH;
+        }
+        let db = self.db.lock().unwrap();
+        if let Err(e) = db.execute("UPDATE directories SET cached_at = 0", []) {
+            tracing::warn!("Failed to expire cache entries: {}", e);
+        }
+        tracing::debug!("Expired all cached listings");
+    }
+
+    /// Entries in the in-memory listings whose `oc:fileid` is one of
+    /// `ids`, with the path of the directory listing them.
+    pub fn find_file_ids(&self, ids: &[String]) -> Vec<(String, DavEntry)> {
+        let cache = self.entries.lock().unwrap();
+        cache
+            .iter()
+            .flat_map(|(path, cached)| cached.entries.iter().map(move |entry| (path, entry)))
+            .filter(|(_, entry)| entry.properties.get("oc:fileid").is_some_and(|id| ids.contains(id)))
+            .map(|(path, entry)| (path.clone(), entry.clone()))
+            .collect()
+    }
+
+    /// Invalidate `path` and every cached directory below it.
+    pub fn invalidate_tree(&self, path: &str) {
+        let prefix = format!("{}/", path.trim_end_matches('/'));
+        self.entries.lock().unwrap()
+            .retain(|p, _| p != path && !p.starts_with(&prefix));
+
+        let db = self.db.lock().unwrap();
+        if let Err(e) = db.execute(
+            "DELETE FROM directories WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2",
+            params![path, prefix],
+        ) {
+            tracing::warn!("Failed to invalidate cache tree for {}: {}", path, e);
+        }
+        tracing::debug!("Invalidated cache tree for path: {}", path);
+    }
+
+    /// Inode numbers handed out by earlier mounts.
+    pub fn load_inodes(&self) -> Result<Vec<(u64, String)>> {
+        let db = self.db.lock().unwrap();
+        let mut stmt = db.prepare("SELECT inode, path FROM inodes")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)? as u64, row.get::<_, String>(1)?))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    pub fn store_inode(&self, ino: u64, path: &str) {
+        let db = self.db.lock().unwrap();
+        if let Err(e) = db.execute(
+            "INSERT OR REPLACE INTO inodes (inode, path) VALUES (?1, ?2)",
+            params![ino as i64, path],
+        ) {
+            tracing::warn!("Failed to persist inode {} for {}: {}", ino, path, e);
+        }
+    }
+
+    pub fn remove_inode(&self, ino: u64) {
+        let db = self.db.lock().unwrap();
+        if let Err(e) = db
+            .execute("DELETE FROM inodes WHERE inode = ?1", params![ino as i64])
+            .and_then(|_| db.execute("DELETE FROM inode_ctimes WHERE inode = ?1", params![ino as i64]))
+        {
+            tracing::warn!("Failed to remove inode {}: {}", ino, e);
+        }
+    }
+
+    /// Local ctimes recorded by earlier mounts.
+    pub fn load_ctimes(&self) -> Result<Vec<(u64, SystemTime)>> {
+        let db = self.db.lock().unwrap();
+        let mut stmt = db.prepare("SELECT inode, ctime_ns FROM inode_ctimes")?;
+        let rows = stmt.query_map([], |row| {
+            let ctime = UNIX_EPOCH + Duration::from_nanos(row.get::<_, i64>(1)?.max(0) as u64);
+            Ok((row.get::<_, i64>(0)? as u64, ctime))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    pub fn store_ctime(&self, ino: u64, ctime: SystemTime) {
+        let nanos = ctime.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as i64;
+        let db = self.db.lock().unwrap();
+        if let Err(e) = db.execute(
+            "INSERT OR REPLACE INTO inode_ctimes (inode, ctime_ns) VALUES (?1, ?2)",
+            params![ino as i64, nanos],
+        ) {
+            tracing::warn!("Failed to persist ctime of inode {}: {}", ino, e);
+        }
+    }
+
+    /// Forget the inodes of `path` and everything below it.
+    pub fn remove_inode_tree(&self, path: &str) {
+        let prefix = format!("{}/", path.trim_end_matches('/'));
+        let db = self.db.lock().unwrap();
+        if let Err(e) = db
+            .execute(
+                "DELETE FROM inode_ctimes WHERE inode IN
+                 (SELECT inode FROM inodes WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2)",
+                params![path, prefix],
+            )
+            .and_then(|_| {
+                db.execute(
+                    "DELETE FROM inodes WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2",
+                    params![path, prefix],
+                )
+            })
+        {
+            tracing::warn!("Failed to remove inodes below {}: {}", path, e);
+        }
+    }
+
+    /// Move the inodes of `old` and everything below it to `new`.
+    pub fn rename_inode_tree(&self, old: &str, new: &str) {
+        let prefix = format!("{}/", old.trim_end_matches('/'));
+        let db = self.db.lock().unwrap();
+        if let Err(e) = db.execute(
+            "UPDATE inodes SET path = ?3 || substr(path, length(?1) + 1)
+             WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2",
+            params![old, prefix, new],
+        ) {
+            tracing::warn!("Failed to rename inodes from {} to {}: {}", old, new, e);
+        }
+    }
+
+    /// Per-directory TTLs set by the user.
+    pub fn load_ttl_overrides(&self) -> Result<Vec<(String, Duration)>> {
+        let db = self.db.lock().unwrap();
+        let mut stmt = db.prepare("SELECT path, ttl_secs FROM ttl_overrides")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, Duration::from_secs(row.get::<_, i64>(1)?.max(0) as u64)))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Persist the TTL override of `path`, or remove it with `None`.
+    pub fn store_ttl_override(&self, path: &str, ttl: Option<Duration>) -> Result<()> {
+        let db = self.db.lock().unwrap();
+        match ttl {
+            Some(ttl) => db.execute(
+                "INSERT OR REPLACE INTO ttl_overrides (path, ttl_secs) VALUES (?1, ?2)",
+                params![path, ttl.as_secs() as i64],
+            )?,
+            None => db.execute("DELETE FROM ttl_overrides WHERE path = ?1", params![path])?,
+        };
+        Ok(())
+    }
+
+    /// Move the TTL overrides of `old` and everything below it to `new`.
+    pub fn rename_ttl_overrides(&self, old: &str, new: &str) {
+        let prefix = format!("{}/", old.trim_end_matches('/'));
+        let db = self.db.lock().unwrap();
+        if let Err(e) = db.execute(
+            "UPDATE ttl_overrides SET path = ?3 || substr(path, length(?1) + 1)
+             WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2",
+            params![old, prefix, new],
+        ) {
+            tracing::warn!("Failed to rename TTL overrides from {} to {}: {}", old, new, e);
+        }
+    }
+
+    /// Pin `path` and everything below it.
+    pub fn pin(&self, path: &str) -> Result<()> {
+        let db = self.db.lock().unwrap();
+        db.execute("INSERT OR IGNORE INTO pinned_paths (path) VALUES (?1)", params![path])?;
+        self.pinned.lock().unwrap().insert(path.to_string());
+        Ok(())
+    }
+
+    /// Unpin `path`. Returns whether it was pinned.
+    pub fn unpin(&self, path: &str) -> Result<bool> {
+        let db = self.db.lock().unwrap();
+        let removed = db.execute("DELETE FROM pinned_paths WHERE path = ?1", params![path])? > 0;
+        self.pinned.lock().unwrap().remove(path);
+        Ok(removed)
+    }
+
+    /// Whether `path` itself is pinned.
+    pub fn is_pinned(&self, path: &str) -> bool {
+        self.pinned.lock().unwrap().contains(path)
+    }
+
+    /// The pinned paths, re-read from the database so pins made by the
+    /// `pin` command while mounted are picked up.
+    pub fn pinned_paths(&self) -> Vec<String> {
+        let db = self.db.lock().unwrap();
+        match load_pinned(&db) {
+            Ok(loaded) => *self.pinned.lock().unwrap() = loaded,
+            Err(e) => tracing::warn!("Failed to read pinned paths: {}", e),
+        }
+        let mut paths: Vec<String> = self.pinned.lock().unwrap().iter().cloned().collect();
+        paths.sort();
+        paths
+    }
+
+    /// Move the pins of `old` and everything below it to `new`.
+    pub fn rename_pins(&self, old: &str, new: &str) {
+        let prefix = format!("{}/", old.trim_end_matches('/'));
+        let db = self.db.lock().unwrap();
+        if let Err(e) = db.execute(
+            "UPDATE pinned_paths SET path = ?3 || substr(path, length(?1) + 1)
+             WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2",
+            params![old, prefix, new],
+        ) {
+            tracing::warn!("Failed to rename pins from {} to {}: {}", old, new, e);
+            return;
+        }
+        match load_pinned(&db) {
+            Ok(loaded) => *self.pinned.lock().unwrap() = loaded,
+            Err(e) => tracing::warn!("Failed to read pinned paths: {}", e),
+        }
+    }
+
     pub fn clear(&self) {
-        let mut cache = self.entries.lock().unwrap();
-        cache.clear();
+        self.entries.lock().unwrap().clear();
+        let db = self.db.lock().unwrap();
+        if let Err(e) = db.execute("DELETE FROM directories", []) {
+            tracing::warn!("Failed to clear cache database: {}", e);
+        }
         tracing::info!("Cleared all cache entries");
     }
 
-    pub fn stats(&self) -> CacheStats {
+    /// Approximate heap footprint of the in-memory listings, in bytes.
+    pub fn memory_usage_bytes(&self) -> usize {
         let cache = self.entries.lock().unwrap();
-        let total_entries = cache.len();
-        let expired = cache.values()
-            .filter(|c| c.cached_at.elapsed() >= self.ttl)
-            .count();
-        
+        Self::memory_usage(&cache)
+    }
+
+    fn memory_usage(cache: &IndexMap<String, CachedDirectory>) -> usize {
+        cache.values().map(|cached| cached.size).sum()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let expired_before = SystemTime::now()
+            .checked_sub(self.ttl)
+            .unwrap_or(UNIX_EPOCH)
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+
+        let (total_entries, expired, last_refreshed, stored_memory) = {
+            let db = self.db.lock().unwrap();
+            let stored_memory: i64 = db
+                .query_row("SELECT value FROM counters WHERE name = 'memory_bytes'", [], |row| row.get(0))
+                .optional()
+                .unwrap_or_default()
+                .unwrap_or(0);
+            let (total_entries, expired, last_refreshed) = db.query_row(
+                "SELECT COUNT(*), COALESCE(SUM(cached_at <= ?1), 0), MAX(cached_at) FROM directories",
+                params![expired_before],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)? as usize,
+                        row.get::<_, i64>(1)? as usize,
+                        row.get::<_, Option<i64>>(2)?,
+                    ))
+                },
+            )
+            .unwrap_or_else(|e| {
+                tracing::warn!("Failed to read cache statistics: {}", e);
+                (0, 0, None)
+            });
+            (total_entries, expired, last_refreshed, stored_memory.max(0) as usize)
+        };
+        // Outside the mount process only the last usage it stored is known
+        let memory_bytes = match self.memory_usage_bytes() {
+            0 => stored_memory,
+            memory => memory,
+        };
+
         CacheStats {
             total_directories: total_entries,
             expired_directories: expired,
             active_directories: total_entries - expired,
+            memory_bytes,
+            evicted_count: self.evicted_count.load(Ordering::Relaxed),
+            last_refreshed: last_refreshed.and_then(|secs| chrono::DateTime::from_timestamp(secs, 0)),
+        }
+    }
+}
+
+/// Approximate heap footprint of the listing of `path`.
+fn listing_size(path: &str, entries: &[DavEntry]) -> usize {
+    path.len()
+        + entries
+            .iter()
+            .map(|e| e.name.len() + std::mem::size_of::<DavEntry>())
+            .sum::<usize>()
+}
+
+fn load_pinned(db: &Connection) -> rusqlite::Result<HashSet<String>> {
+    let mut stmt = db.prepare("SELECT path FROM pinned_paths")?;
+    let rows = stmt.query_map([], |row| row.get(0))?;
+    rows.collect()
+}
+
+/// Whether `path` is one of `roots` or below one of them.
+fn is_below_any(roots: &HashSet<String>, path: &str) -> bool {
+    let mut current = path;
+    loop {
+        if roots.contains(current) {
+            return true;
+        }
+        match current.rfind('/') {
+            Some(0) if current.len() > 1 => current = "/",
+            Some(i) if i > 0 => current = &current[..i],
+            _ => return false,
         }
     }
 }
 
+#[derive(Debug, Serialize)]
 pub struct CacheStats {
     pub total_directories: usize,
     pub expired_directories: usize,
     pub active_directories: usize,
+    pub memory_bytes: usize,
+    pub evicted_count: usize,
+    /// When a listing was last fetched from the server
+    pub last_refreshed: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Coalesces concurrent PROPFINDs of the same directory: the first caller
+/// sends the request and later callers subscribe to its result, like Go's
+/// singleflight. Followers of a failed listing send their own request, so
+/// each caller still gets its own error to map to an errno.
+#[derive(Clone, Default)]
+pub struct PropfindInflight {
+    // `None` is sent when the listing failed
+    inflight: Arc<Mutex<HashMap<String, broadcast::Sender<Option<DirListing>>>>>,
+}
+
+impl PropfindInflight {
+    async fn run<F>(&self, path: &str, fetch: F) -> Result<DirListing>
+    where
+        F: Future<Output = Result<DirListing>>,
+    {
+        let follower = {
+            let mut inflight = self.inflight.lock().unwrap();
+            match inflight.get(path) {
+                Some(sender) => Some(sender.subscribe()),
+                None => {
+                    inflight.insert(path.to_string(), broadcast::channel(1).0);
+                    None
+                }
+            }
+        };
+
+        if let Some(mut receiver) = follower {
+            if let Ok(Some(listing)) = receiver.recv().await {
+                tracing::debug!("Shared in-flight listing of {}", path);
+                return Ok(listing);
+            }
+            return fetch.await;
+        }
+
+        // Resolves followers even if this future is dropped mid-request
+        let mut flight = Flight { inflight: self, path, listing: None };
+        let result = fetch.await;
+        if let Ok(listing) = &result {
+            flight.listing = Some(listing.clone());
+        }
+        result
+    }
+}
+
+/// The request of the first caller of `PropfindInflight::run`; hands its
+/// result to the followers when done.
+struct Flight<'a> {
+    inflight: &'a PropfindInflight,
+    path: &'a str,
+    listing: Option<DirListing>,
+}
+
+impl Drop for Flight<'_> {
+    fn drop(&mut self) {
+        if let Some(sender) = self.inflight.inflight.lock().unwrap().remove(self.path) {
+            // No followers is not an error
+            let _ = sender.send(self.listing.take());
+        }
+    }
 }
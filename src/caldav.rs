@@ -0,0 +1,140 @@
+use anyhow::Result;
+use chrono::{NaiveDate, NaiveDateTime, TimeZone};
+
+use crate::webdav::WebDavClient;
+
+/// `calendar-data` as named in the properties of report entries.
+const CALENDAR_DATA_PROP: &str = "{urn:ietf:params:xml:ns:caldav}calendar-data";
+
+/// `calendar-query` REPORT for every event of a calendar, with its data.
+const CALENDAR_QUERY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+  <d:prop>
+    <d:getetag/>
+    <c:calendar-data/>
+  </d:prop>
+  <c:filter>
+    <c:comp-filter name="VCALENDAR">
+      <c:comp-filter name="VEVENT"/>
+    </c:comp-filter>
+  </c:filter>
+</c:calendar-query>"#;
+
+/// The parts of a `VEVENT` shown in summaries.
+#[derive(Debug, Clone, Default)]
+pub struct Event {
+    /// Name of the `.ics` resource holding the event
+    pub resource: String,
+    pub summary: String,
+    /// Start and end as `YYYY-MM-DD` for all-day events, else
+    /// `YYYY-MM-DD HH:MM` in local time (floating and TZID times as given)
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub location: Option<String>,
+}
+
+/// Events of the calendar collection `path`, ordered by start.
+pub async fn list_events(webdav: &WebDavClient, path: &str) -> Result<Vec<Event>> {
+    let entries = webdav.report(path, CALENDAR_QUERY).await?;
+    let mut events: Vec<Event> = entries
+        .iter()
+        .filter_map(|entry| Some((entry, entry.properties.get(CALENDAR_DATA_PROP)?)))
+        .flat_map(|(entry, data)| parse_events(&entry.name, data))
+        .collect();
+    events.sort_by(|a, b| a.start.cmp(&b.start));
+    Ok(events)
+}
+
+/// The `VEVENT`s of iCalendar text `data`, stored as `resource`.
+fn parse_events(resource: &str, data: &str) -> Vec<Event> {
+    let mut events = Vec::new();
+    let mut event: Option<Event> = None;
+    // Nested components such as VALARM have properties of their own
+    let mut depth = 0;
+
+    for line in unfold(data) {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let (name, params) = name.split_once(';').unwrap_or((name, ""));
+        match (name.to_ascii_uppercase().as_str(), value) {
+            ("BEGIN", "VEVENT") if event.is_none() => {
+                event = Some(Event {
+                    resource: resource.to_string(),
+                    ..Default::default()
+                })
+            }
+            ("BEGIN", _) if event.is_some() => depth += 1,
+            ("END", "VEVENT") if depth == 0 => events.extend(event.take()),
+            ("END", _) if event.is_some() => depth -= 1,
+            (name, value) if depth == 0 => {
+                let Some(event) = event.as_mut() else {
+                    continue;
+                };
+                match name {
+                    "SUMMARY" => event.summary = unescape(value),
+                    "LOCATION" => event.location = Some(unescape(value)),
+                    "DTSTART" => event.start = Some(format_time(value, params)),
+                    "DTEND" => event.end = Some(format_time(value, params)),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    events
+}
+
+/// Content lines with folded continuations (RFC 5545 3.1) joined.
+fn unfold(data: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in data.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continued), Some(last)) => last.push_str(continued),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => unescaped.push('\n'),
+            Some(escaped) => unescaped.push(escaped),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+/// A `DTSTART`/`DTEND` value for display. UTC times are shown in local
+/// time; anything unparseable is shown as is.
+fn format_time(value: &str, params: &str) -> String {
+    let all_day = params.split(';').any(|param| param.eq_ignore_ascii_case("VALUE=DATE")) || value.len() == 8;
+    if all_day {
+        return match NaiveDate::parse_from_str(value, "%Y%m%d") {
+            Ok(date) => date.format("%Y-%m-%d").to_string(),
+            Err(_) => value.to_string(),
+        };
+    }
+    let (local, utc) = match value.strip_suffix('Z') {
+        Some(local) => (local, true),
+        None => (value, false),
+    };
+    match NaiveDateTime::parse_from_str(local, "%Y%m%dT%H%M%S") {
+        Ok(time) if utc => chrono::Utc
+            .from_utc_datetime(&time)
+            .with_timezone(&chrono::Local)
+            .format("%Y-%m-%d %H:%M")
+            .to_string(),
+        Ok(time) => time.format("%Y-%m-%d %H:%M").to_string(),
+        Err(_) => value.to_string(),
+    }
+}
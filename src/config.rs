@@ -1,10 +1,434 @@
-use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use crate::crypto::EncryptionConfig;
+use crate::journal::ConflictStrategy;
+use crate::prefetch::PrefetchStrategy;
+use crate::webdav::{RetryPolicy, TlsConfig};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MountConfig {
     pub name: String,
-    pub url: String,
+    /// Server URLs; the ones after the first are mirrors used while it is
+    /// unreachable. Configs from before mirror support have a single `url`.
+    #[serde(alias = "url", deserialize_with = "one_or_many")]
+    pub urls: Vec<String>,
     pub username: String,
     pub mount_point: PathBuf,
+    /// Mount without write support
+    #[serde(default)]
+    pub read_only: bool,
+    /// Let other users access the mount; needs `user_allow_other` in
+    /// `/etc/fuse.conf` unless mounting as root
+    #[serde(default)]
+    pub allow_other: bool,
+    /// Let root access the mount; same requirement as `allow_other`
+    #[serde(default)]
+    pub allow_root: bool,
+    /// Random ID generated at setup; seeds the inode numbers so they stay
+    /// stable across remounts but differ between mounts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mount_id: Option<uuid::Uuid>,
+    /// Authentication scheme: `basic`, `digest` or `bearer`
+    #[serde(default = "default_auth_method")]
+    pub auth_method: String,
+    /// Environment variable holding the password, or the token for bearer
+    /// auth (file-based config only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_env: Option<String>,
+    /// SOCKS5 proxy (`host:port` or `socks5h://host:port`), used instead of
+    /// the proxy environment variables
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socks5_proxy: Option<String>,
+    /// Hosts that bypass the proxy; defaults to `DAVFS_NO_PROXY` or `NO_PROXY`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_proxy: Option<String>,
+    /// Custom CA and client certificates. Kept in their own keyring item
+    /// (`type=tls-config`), so only read from the mounts file here.
+    #[serde(flatten, skip_serializing)]
+    pub tls: TlsConfig,
+    /// Glob patterns limiting which files are synced (empty: all)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include_patterns: Vec<String>,
+    /// Glob patterns for paths that are listed but never opened or synced
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_patterns: Vec<String>,
+    /// Download bandwidth limit in bytes per second
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_bps: Option<u64>,
+    /// Upload bandwidth limit in bytes per second
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_bps: Option<u64>,
+    /// Files larger than this are uploaded to Nextcloud in chunks of this
+    /// size
+    #[serde(default = "default_chunk_size_bytes")]
+    pub chunk_size_bytes: u64,
+    /// Seconds a path that was not found is reported missing without
+    /// asking the server again
+    #[serde(default = "default_negative_ttl_secs")]
+    pub negative_ttl_secs: u64,
+    /// Send requests to all `urls` in turn instead of only failing over
+    #[serde(default)]
+    pub load_balance: bool,
+    /// Talk HTTP/2 to the server: negotiated via ALPN over TLS, assumed
+    /// for plain `http://` URLs
+    #[serde(default)]
+    pub use_http2: bool,
+    /// Idle connections kept open to the server for reuse
+    #[serde(default = "default_pool_max_idle_per_host")]
+    pub pool_max_idle_per_host: usize,
+    /// Seconds an idle connection is kept before it is closed
+    #[serde(default = "default_pool_idle_timeout_secs")]
+    pub pool_idle_timeout_secs: u64,
+    /// Log every read and write on the server connections (trace level)
+    #[serde(default)]
+    pub connection_verbose: bool,
+    /// Check downloaded files against the checksum the server announces
+    /// (Nextcloud `oc:checksums`, SHA256 or MD5)
+    #[serde(default)]
+    pub checksum_verify: bool,
+    /// OTLP collector receiving a span per server request, e.g.
+    /// `http://localhost:4317`; `OTEL_EXPORTER_OTLP_ENDPOINT` also enables
+    /// the export
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub otel_endpoint: Option<String>,
+    /// File that every change made through the mount is appended to as a
+    /// JSON line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit_log: Option<PathBuf>,
+    /// Encrypt file contents and names before they reach the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<EncryptionConfig>,
+    /// `User-Agent` sent to the server instead of the default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// Headers added to every request, e.g. an API key for a gateway. They
+    /// may hold secrets, so they are only read from the mounts file and
+    /// never shown in `.davfs-status`.
+    #[serde(default, skip_serializing)]
+    pub extra_headers: HashMap<String, String>,
+    /// Order in which subdirectories of opened directories are listed
+    /// ahead of time: `bfs`, `dfs` or `none`
+    #[serde(default)]
+    pub prefetch_strategy: PrefetchStrategy,
+    /// Prefetch listings requested at the same time
+    #[serde(default = "default_prefetch_concurrency")]
+    pub prefetch_concurrency: usize,
+    /// Fill the cache at mount time from one `Depth: infinity` PROPFIND of
+    /// the whole tree instead of listing directories one by one
+    #[serde(default)]
+    pub full_tree_prefetch: bool,
+    /// Trees with more entries than this are prefetched per directory
+    #[serde(default = "default_max_entries_for_infinity")]
+    pub max_entries_for_infinity: usize,
+    /// Maximum number of directory listings kept in memory
+    #[serde(default = "default_cache_max_entries")]
+    pub cache_max_entries: usize,
+    /// Approximate memory the listings kept in memory may take, in bytes;
+    /// 0 for no limit besides `cache_max_entries`
+    #[serde(default = "default_cache_max_memory_bytes")]
+    pub cache_max_memory_bytes: usize,
+    /// Retry behaviour for transient network and server errors
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// Failed requests in a row after which requests fail right away
+    /// instead of waiting for the server
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// Seconds requests fail right away before the server is tried again
+    #[serde(default = "default_circuit_open_duration_secs")]
+    pub circuit_open_duration_secs: u64,
+    /// Seconds between connectivity checks of the background sync
+    #[serde(default = "default_sync_interval_secs")]
+    pub sync_interval_secs: u64,
+    /// Seconds between checks of files processes wait on in `poll(2)` for
+    /// remote changes; 0 disables the checks
+    #[serde(default = "default_poll_interval_secs")]
+    pub poll_interval_secs: u32,
+    /// Keep listings of directories that rarely change for longer: a tenth
+    /// of the time since their newest entry was modified, up to a day
+    #[serde(default)]
+    pub adaptive_ttl: bool,
+    /// Let several processes have a file open for writing at once, e.g.
+    /// for append-only logs; otherwise a second writer gets EWOULDBLOCK
+    #[serde(default)]
+    pub allow_parallel_write: bool,
+    /// Upload files to a temporary name next to them and MOVE them into
+    /// place, so a failed upload never leaves a truncated file behind
+    #[serde(default = "default_atomic_upload")]
+    pub atomic_upload: bool,
+    /// How offline writes that conflict with server changes are resolved
+    #[serde(default)]
+    pub conflict_strategy: ConflictStrategy,
+    /// Move deleted files to a `.Trash-<uid>` collection on the server
+    /// instead of deleting them; shown in the mount as `.trash`
+    #[serde(default)]
+    pub use_trash: bool,
+    /// Show earlier versions of files kept by Nextcloud next to them, as
+    /// read-only `<name>.v<timestamp>` files
+    #[serde(default)]
+    pub show_versions: bool,
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(url) => vec![url],
+        OneOrMany::Many(urls) => urls,
+    })
+}
+
+fn default_auth_method() -> String {
+    "basic".to_string()
+}
+
+fn default_sync_interval_secs() -> u64 {
+    30
+}
+
+fn default_poll_interval_secs() -> u32 {
+    10
+}
+
+fn default_failure_threshold() -> u32 {
+    3
+}
+
+fn default_circuit_open_duration_secs() -> u64 {
+    30
+}
+
+fn default_chunk_size_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_negative_ttl_secs() -> u64 {
+    5
+}
+
+fn default_pool_max_idle_per_host() -> usize {
+    10
+}
+
+fn default_pool_idle_timeout_secs() -> u64 {
+    90
+}
+
+fn default_prefetch_concurrency() -> usize {
+    4
+}
+
+fn default_max_entries_for_infinity() -> usize {
+    50_000
+}
+
+fn default_cache_max_entries() -> usize {
+    10_000
+}
+
+fn default_cache_max_memory_bytes() -> usize {
+    64 * 1024 * 1024
+}
+
+fn default_atomic_upload() -> bool {
+    true
+}
+
+impl Default for MountConfig {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            urls: Vec::new(),
+            username: String::new(),
+            mount_point: PathBuf::new(),
+            read_only: false,
+            allow_other: false,
+            allow_root: false,
+            mount_id: None,
+            auth_method: default_auth_method(),
+            password_env: None,
+            socks5_proxy: None,
+            no_proxy: None,
+            tls: TlsConfig::default(),
+            include_patterns: Vec::new(),
+            exclude_patterns: Vec::new(),
+            download_bps: None,
+            upload_bps: None,
+            chunk_size_bytes: default_chunk_size_bytes(),
+            negative_ttl_secs: default_negative_ttl_secs(),
+            load_balance: false,
+            use_http2: false,
+            pool_max_idle_per_host: default_pool_max_idle_per_host(),
+            pool_idle_timeout_secs: default_pool_idle_timeout_secs(),
+            connection_verbose: false,
+            checksum_verify: false,
+            otel_endpoint: None,
+            audit_log: None,
+            encryption: None,
+            user_agent: None,
+            extra_headers: HashMap::new(),
+            prefetch_strategy: PrefetchStrategy::default(),
+            prefetch_concurrency: default_prefetch_concurrency(),
+            full_tree_prefetch: false,
+            max_entries_for_infinity: default_max_entries_for_infinity(),
+            cache_max_entries: default_cache_max_entries(),
+            cache_max_memory_bytes: default_cache_max_memory_bytes(),
+            retry: RetryPolicy::default(),
+            failure_threshold: default_failure_threshold(),
+            circuit_open_duration_secs: default_circuit_open_duration_secs(),
+            sync_interval_secs: default_sync_interval_secs(),
+            poll_interval_secs: default_poll_interval_secs(),
+            adaptive_ttl: false,
+            allow_parallel_write: false,
+            atomic_upload: default_atomic_upload(),
+            conflict_strategy: ConflictStrategy::default(),
+            use_trash: false,
+            show_versions: false,
+        }
+    }
+}
+
+impl MountConfig {
+    /// The primary server URL.
+    pub fn url(&self) -> &str {
+        self.urls.first().map(String::as_str).unwrap_or_default()
+    }
+
+    /// Seed for stable inode numbers. Mounts configured before IDs were
+    /// generated (or by hand in mounts.toml) fall back to the mount name.
+    pub fn inode_seed(&self) -> u64 {
+        match self.mount_id {
+            Some(id) => {
+                let (high, low) = id.as_u64_pair();
+                high ^ low
+            }
+            None => {
+                let mut hasher = std::hash::DefaultHasher::new();
+                self.name.hash(&mut hasher);
+                hasher.finish()
+            }
+        }
+    }
+}
+
+/// Layout of `mounts.toml`: one `[[mount]]` table per mount.
+#[derive(Debug, Deserialize)]
+struct FileConfig {
+    #[serde(default)]
+    mount: Vec<MountConfig>,
+}
+
+/// `$XDG_CONFIG_HOME`, falling back to `~/.config`.
+pub fn config_home() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+}
+
+/// Default location of the mounts file: `$XDG_CONFIG_HOME/davfs-sync/mounts.toml`,
+/// falling back to `~/.config`.
+pub fn default_config_path() -> Option<PathBuf> {
+    Some(config_home()?.join("davfs-sync").join("mounts.toml"))
+}
+
+/// Per-mount cache directory: `$XDG_CACHE_HOME/davfs-sync/<name>`, falling
+/// back to `~/.cache`.
+pub fn cache_dir(name: &str) -> Option<PathBuf> {
+    Some(cache_home()?.join("davfs-sync").join(name))
+}
+
+/// File content cache shared by all mounts:
+/// `$XDG_CACHE_HOME/davfs-sync/content`.
+pub fn content_cache_dir() -> Option<PathBuf> {
+    Some(cache_home()?.join("davfs-sync").join("content"))
+}
+
+fn cache_home() -> Option<PathBuf> {
+    std::env::var_os("XDG_CACHE_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+}
+
+/// Per-mount directory for logs: `$XDG_STATE_HOME/davfs-sync/<name>`,
+/// falling back to `~/.local/state`.
+pub fn state_dir(name: &str) -> Option<PathBuf> {
+    let state_home = std::env::var_os("XDG_STATE_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))?;
+    Some(state_home.join("davfs-sync").join(name))
+}
+
+/// Move files of mount `name` that earlier versions kept elsewhere: the
+/// daemon log used to live in the cache directory.
+pub fn migrate_legacy_paths(name: &str) -> Result<()> {
+    let (Some(cache_dir), Some(state_dir)) = (cache_dir(name), state_dir(name)) else {
+        return Ok(());
+    };
+    let legacy = cache_dir.join("daemon.log");
+    let target = state_dir.join("daemon.log");
+    if !legacy.exists() || target.exists() {
+        return Ok(());
+    }
+
+    std::fs::create_dir_all(&state_dir)
+        .with_context(|| format!("Failed to create {}", state_dir.display()))?;
+    // A rename fails across filesystems, e.g. with a tmpfs cache
+    if std::fs::rename(&legacy, &target).is_err() {
+        std::fs::copy(&legacy, &target)
+            .with_context(|| format!("Failed to move {} to {}", legacy.display(), target.display()))?;
+        std::fs::remove_file(&legacy)?;
+    }
+    println!("⚠ Moved daemon log of '{}' to {}", name, target.display());
+    Ok(())
+}
+
+/// Directory for PID files: `$XDG_RUNTIME_DIR/davfs-sync`, or the system
+/// temp directory if that is not set.
+pub fn runtime_dir() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("davfs-sync")
+}
+
+/// `config` as a mounts file with a single `[[mount]]` table, including
+/// the certificate paths. Extra headers are left out since they may hold
+/// secrets.
+pub fn export_toml(config: &MountConfig) -> Result<String> {
+    let mut mount = toml::Table::try_from(config).context("Failed to serialize mount")?;
+    mount.extend(toml::Table::try_from(&config.tls).context("Failed to serialize TLS settings")?);
+    let mut file = toml::Table::new();
+    file.insert("mount".to_string(), toml::Value::Array(vec![toml::Value::Table(mount)]));
+    Ok(toml::to_string_pretty(&file)?)
+}
+
+pub fn load_file_config(path: &Path) -> Result<Vec<MountConfig>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let config: FileConfig = toml::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok(config.mount)
+}
+
+/// Look up `name` in the default mounts file. Returns `Ok(None)` if the file
+/// does not exist or has no such mount.
+pub fn find_file_mount(name: &str) -> Result<Option<MountConfig>> {
+    let path = match default_config_path() {
+        Some(path) if path.exists() => path,
+        _ => return Ok(None),
+    };
+    let mounts = load_file_config(&path)?;
+    Ok(mounts.into_iter().find(|m| m.name == name))
 }
@@ -0,0 +1,97 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// File contents on disk, named by a hash of the resource URL and etag so
+/// a changed file never hits an old entry. The directory is shared by all
+/// mounts, so mounts of the same server share entries.
+#[derive(Clone)]
+pub struct ContentCache {
+    dir: PathBuf,
+}
+
+impl ContentCache {
+    pub fn open(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create content cache {}", dir.display()))?;
+        Ok(Self { dir: dir.to_path_buf() })
+    }
+
+    /// Cache key of the version of `url` with `etag`.
+    pub fn key(url: &str, etag: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(url.as_bytes());
+        hasher.update(b"\n");
+        hasher.update(etag.as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.dir.join(key).is_file()
+    }
+
+    /// Up to `size` bytes at `offset` of the cached content for `key`.
+    pub fn read(&self, key: &str, offset: u64, size: u64) -> Option<Vec<u8>> {
+        let mut file = File::open(self.dir.join(key)).ok()?;
+        let result = file.seek(SeekFrom::Start(offset)).and_then(|_| {
+            let mut data = Vec::new();
+            file.by_ref().take(size).read_to_end(&mut data)?;
+            Ok(data)
+        });
+        match result {
+            Ok(data) => {
+                tracing::debug!("Content cache hit for {}", key);
+                Some(data)
+            }
+            Err(e) => {
+                tracing::warn!("Failed to read cached content {}: {}", key, e);
+                None
+            }
+        }
+    }
+
+    /// Store `data` for `key`, via a temporary file so readers never see a
+    /// partial entry.
+    pub fn insert(&self, key: &str, data: &[u8]) -> Result<()> {
+        let path = self.dir.join(key);
+        let tmp = self.dir.join(format!("{}.tmp.{}", key, std::process::id()));
+        std::fs::write(&tmp, data).with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &path).with_context(|| format!("Failed to write {}", path.display()))?;
+        tracing::debug!("Cached {} bytes of content as {}", data.len(), key);
+        Ok(())
+    }
+
+    pub fn remove(&self, key: &str) {
+        if let Err(e) = std::fs::remove_file(self.dir.join(key)) {
+            tracing::warn!("Failed to remove cached content {}: {}", key, e);
+        }
+    }
+
+    /// Remove entries downloaded more than `age` ago. Returns the number of
+    /// files and bytes removed.
+    pub fn evict_older_than(&self, age: Duration) -> Result<(usize, u64)> {
+        let cutoff = SystemTime::now().checked_sub(age).unwrap_or(SystemTime::UNIX_EPOCH);
+        let mut removed = (0, 0);
+
+        let entries = std::fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to read {}", self.dir.display()))?;
+        for entry in entries {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() || metadata.modified()? >= cutoff {
+                continue;
+            }
+            match std::fs::remove_file(entry.path()) {
+                Ok(()) => {
+                    removed.0 += 1;
+                    removed.1 += metadata.len();
+                }
+                Err(e) => tracing::warn!("Failed to remove {}: {}", entry.path().display(), e),
+            }
+        }
+        Ok(removed)
+    }
+}
@@ -0,0 +1,84 @@
+use age::secrecy::ExposeSecret;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Client-side encryption of everything stored on the server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptionConfig {
+    /// age public key (`age1…`) file contents are encrypted to. The
+    /// matching identity is kept in the Secret Service (`type=age-key`).
+    pub recipient: String,
+}
+
+/// Encrypts file contents with age and hides names: on the server each
+/// path segment is a keyed hash, with the real name stored encrypted in a
+/// dead property so listings can show it again.
+pub struct Cipher {
+    recipient: age::x25519::Recipient,
+    identity: age::x25519::Identity,
+    // Derived from the identity, so names hash the same on every client
+    name_key: [u8; 32],
+}
+
+impl Cipher {
+    pub fn new(config: &EncryptionConfig, identity: &str) -> Result<Self> {
+        let recipient: age::x25519::Recipient = config
+            .recipient
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid age recipient {}: {}", config.recipient, e))?;
+        let identity: age::x25519::Identity = identity
+            .trim()
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid age identity: {}", e))?;
+        if identity.to_public().to_string() != recipient.to_string() {
+            anyhow::bail!("The stored age key does not belong to recipient {}", config.recipient);
+        }
+
+        let name_key = Sha256::new()
+            .chain_update(b"davfs-sync name key\n")
+            .chain_update(identity.to_string().expose_secret().as_bytes())
+            .finalize()
+            .into();
+        Ok(Self { recipient, identity, name_key })
+    }
+
+    /// A new identity and its recipient.
+    pub fn generate_identity() -> (String, String) {
+        let identity = age::x25519::Identity::generate();
+        let recipient = identity.to_public().to_string();
+        (identity.to_string().expose_secret().to_string(), recipient)
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        age::encrypt(&self.recipient, plaintext).context("Failed to encrypt file")
+    }
+
+    pub fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        age::decrypt(&self.identity, ciphertext).context("Failed to decrypt file (not encrypted with this mount's key?)")
+    }
+
+    /// Name of a path segment on the server.
+    pub fn hash_name(&self, name: &str) -> String {
+        let digest = Sha256::new()
+            .chain_update(self.name_key)
+            .chain_update(name.as_bytes())
+            .finalize();
+        format!("{:x}", digest)
+    }
+
+    /// `name` as stored in the name property.
+    pub fn encrypt_name(&self, name: &str) -> Result<String> {
+        Ok(self.encrypt(name.as_bytes())?.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    /// The name in a name property, or `None` if it was not encrypted with
+    /// this key.
+    pub fn decrypt_name(&self, value: &str) -> Option<String> {
+        let ciphertext = (0..value.len())
+            .step_by(2)
+            .map(|i| value.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+            .collect::<Option<Vec<u8>>>()?;
+        String::from_utf8(self.decrypt(&ciphertext).ok()?).ok()
+    }
+}
@@ -0,0 +1,198 @@
+use anyhow::{Context, Result};
+use nix::errno::Errno;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::{fork, setsid, ForkResult, Pid};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::config;
+
+/// How long `mount --daemon` waits for the child to report back.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long `stop` waits for the daemon to unmount and exit.
+const STOP_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Held by the daemonized child to tell the waiting parent how startup
+/// went. Dropping it without reporting (e.g. because the child failed)
+/// makes the parent exit with an error.
+pub struct ReadyNotifier(File);
+
+impl ReadyNotifier {
+    pub fn connected(self) {
+        self.send("ok");
+    }
+
+    /// Mounted, but the server could not be reached yet.
+    pub fn offline(self, error: &str) {
+        self.send(&format!("offline {}", error));
+    }
+
+    fn send(mut self, message: &str) {
+        // The parent may have given up waiting already
+        let _ = writeln!(self.0, "{}", message.replace('\n', " "));
+    }
+}
+
+pub fn pid_file(name: &str) -> PathBuf {
+    config::runtime_dir().join(format!("{}.pid", name))
+}
+
+/// Detach the mount `name` into the background. Must be called before the
+/// async runtime starts, since only the calling thread survives a fork.
+/// Returns in the child; the parent waits for the child to report and
+/// exits.
+pub fn daemonize(name: &str) -> Result<ReadyNotifier> {
+    let pid_path = pid_file(name);
+    if let Some(pid) = running_pid(&pid_path)? {
+        anyhow::bail!("Mount '{}' is already running (PID {})", name, pid);
+    }
+
+    if let Err(e) = config::migrate_legacy_paths(name) {
+        println!("⚠ Failed to move old files of '{}': {:#}", name, e);
+    }
+    let log_dir = config::state_dir(name)
+        .ok_or_else(|| anyhow::anyhow!("Cannot determine state directory (HOME not set)"))?;
+    std::fs::create_dir_all(&log_dir)
+        .with_context(|| format!("Failed to create {}", log_dir.display()))?;
+    let log_path = log_dir.join("daemon.log");
+
+    let (read_end, write_end) = nix::unistd::pipe().context("Failed to create pipe")?;
+
+    // SAFETY: no other threads exist yet, so the child starts from a
+    // consistent state
+    match unsafe { fork() }.context("Failed to fork")? {
+        ForkResult::Parent { child } => {
+            drop(write_end);
+            wait_for_child(name, child, File::from(read_end), &log_path)
+        }
+        ForkResult::Child => {
+            drop(read_end);
+            setsid().context("Failed to start a new session")?;
+            redirect_stdio(&log_path)?;
+
+            if let Some(parent) = pid_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&pid_path, format!("{}\n", std::process::id()))
+                .with_context(|| format!("Failed to write {}", pid_path.display()))?;
+
+            Ok(ReadyNotifier(File::from(write_end)))
+        }
+    }
+}
+
+/// Exit the parent once the child reports (or dies).
+fn wait_for_child(name: &str, child: Pid, pipe: File, log_path: &Path) -> ! {
+    // Read on a thread so the wait can time out
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let mut line = String::new();
+        let _ = BufReader::new(pipe).read_line(&mut line);
+        let _ = tx.send(line);
+    });
+
+    match rx.recv_timeout(STARTUP_TIMEOUT) {
+        Ok(line) if line.trim() == "ok" => {
+            println!("✓ Mounted '{}' in the background (PID {})", name, child);
+            std::process::exit(0);
+        }
+        Ok(line) if line.starts_with("offline") => {
+            let error = line.trim_start_matches("offline").trim();
+            println!("⚠ Mounted '{}' in the background (PID {}), but the server is unreachable: {}", name, child, error);
+            std::process::exit(0);
+        }
+        Ok(_) => {
+            eprintln!("✗ Failed to start mount '{}'; see {}", name, log_path.display());
+            std::process::exit(1);
+        }
+        Err(_) => {
+            eprintln!(
+                "✗ Mount '{}' (PID {}) did not finish starting within {}s; see {}",
+                name,
+                child,
+                STARTUP_TIMEOUT.as_secs(),
+                log_path.display()
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Point stdin at /dev/null and stdout/stderr at the daemon log.
+fn redirect_stdio(log_path: &Path) -> Result<()> {
+    let dev_null = File::open("/dev/null")?;
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)
+        .with_context(|| format!("Failed to open {}", log_path.display()))?;
+
+    nix::unistd::dup2(dev_null.as_raw_fd(), 0)?;
+    nix::unistd::dup2(log.as_raw_fd(), 1)?;
+    nix::unistd::dup2(log.as_raw_fd(), 2)?;
+    Ok(())
+}
+
+/// PID from `path` if that process is still alive. Stale files are removed.
+fn running_pid(path: &Path) -> Result<Option<Pid>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let pid: i32 = content
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid PID file {}", path.display()))?;
+    let pid = Pid::from_raw(pid);
+
+    match kill(pid, None) {
+        Ok(()) | Err(Errno::EPERM) => Ok(Some(pid)),
+        Err(_) => {
+            tracing::debug!("Removing stale PID file {}", path.display());
+            let _ = std::fs::remove_file(path);
+            Ok(None)
+        }
+    }
+}
+
+/// Remove the PID file of `name` if it belongs to this process.
+pub fn remove_pid_file(name: &str) {
+    let path = pid_file(name);
+    let ours = std::fs::read_to_string(&path)
+        .is_ok_and(|content| content.trim() == std::process::id().to_string());
+    if ours {
+        let _ = std::fs::remove_file(&path);
+    }
+}
+
+/// Ask the daemon for `name` to unmount and exit, waiting until it has.
+pub async fn stop(name: &str) -> Result<()> {
+    let pid_path = pid_file(name);
+    let Some(pid) = running_pid(&pid_path)? else {
+        anyhow::bail!("Mount '{}' is not running in the background", name);
+    };
+
+    println!("Stopping '{}' (PID {})...", name, pid);
+    kill(pid, Signal::SIGTERM).with_context(|| format!("Failed to signal PID {}", pid))?;
+
+    let deadline = tokio::time::Instant::now() + STOP_TIMEOUT;
+    while tokio::time::Instant::now() < deadline {
+        if kill(pid, None).is_err() {
+            let _ = std::fs::remove_file(&pid_path);
+            println!("✓ Stopped");
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+
+    anyhow::bail!(
+        "PID {} is still running after {}s",
+        pid,
+        STOP_TIMEOUT.as_secs()
+    )
+}
@@ -1,20 +1,174 @@
+use futures_util::StreamExt;
 use fuser::{
-    FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, Request,
-    ReplyXattr,
+    FileAttr, FileType, Filesystem, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory,
+    ReplyBmap, ReplyEmpty, ReplyEntry, ReplyIoctl, ReplyLock, ReplyOpen, ReplyPoll, ReplyStatfs, ReplyWrite, Request,
+    ReplyXattr, TimeOrNow,
 };
-use libc::ENOENT;
-use std::collections::HashMap;
+use libc::{
+    EACCES, EAGAIN, EBADF, EEXIST, EINVAL, EIO, ENETUNREACH, ENOENT, ENOLCK, ENOSPC, ENOTDIR, ENOTEMPTY,
+    EOPNOTSUPP, EPERM,
+};
+use reqwest::StatusCode;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ffi::OsStr;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::webdav::WebDavClient;
-use crate::cache::DirectoryCache;
+use crate::watch::PollWatcher;
+use crate::webdav::{
+    checksum_matches, guess_content_type, is_network_error, CircuitOpenError, DavCapabilities, DavEntry, DavStatusError, DirListing, LockScope, LockToken,
+    PropRequest, Quota, WebDavClient,
+};
+use crate::audit::{AuditEntry, AuditLog};
+use crate::buffer::SparseBuffer;
+use crate::cache::{CacheResult, DirectoryCache};
+use crate::config::MountConfig;
+use crate::content_cache::ContentCache;
+use crate::filter::PathFilter;
+use crate::ioctl::{self, DavfsStats};
+use crate::platform;
+use crate::journal::{JournalOp, WriteJournal};
+use crate::prefetch::{PrefetchScheduler, PrefetchStrategy};
+use crate::sync::SyncManager;
+use crate::trash::{Trash, TRASH_DIR_NAME};
 
 const TTL: Duration = Duration::from_secs(1);
 
 const ROOT_INO: u64 = 1;
 
+// Virtual read-only file in the root with a JSON snapshot of the mount state
+const STATUS_INO: u64 = u64::MAX - 1;
+const STATUS_FILE_NAME: &str = ".davfs-status";
+
+// How long a quota fetched for statfs is reused
+const QUOTA_TTL: Duration = Duration::from_secs(60);
+
+// Reported by statfs when the server has no quota properties
+const FALLBACK_AVAILABLE_BYTES: u64 = 1 << 40;
+
+// Block size reported by statfs
+const STATFS_BLOCK_SIZE: u64 = 512;
+// Bytes of made-up block numbers each inode gets in `bmap`
+const BMAP_INODE_SPAN: u64 = 4096;
+
+// Largest range copied locally per copy_file_range call
+const COPY_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+// Files up to this size are downloaded whole into the content cache on
+// first read; larger ones are read in ranges
+const CONTENT_CACHE_MAX_FILE: u64 = 16 * 1024 * 1024;
+
+// Lifetime requested for write locks taken on open
+const LOCK_TIMEOUT_SECS: u32 = 600;
+
+// Lifetime requested for locks taken for fcntl locks
+const POSIX_LOCK_TIMEOUT_SECS: u32 = 60;
+
+// How long F_SETLKW waits for a lock held elsewhere. Bounded, since the
+// filesystem handles one request at a time while waiting
+const POSIX_LOCK_WAIT: Duration = Duration::from_secs(30);
+
+/// Files whose versions are listed at the same time when a directory is
+/// opened
+const VERSION_LIST_CONCURRENCY: usize = 8;
+
+const XATTR_STATE: &str = "user.davfs.state";
+const XATTR_JOURNAL_OPS: &str = "user.davfs.journal_ops";
+const XATTR_SYNC_STATUS: &str = "user.davfs.sync_status";
+// Server-side metadata of the resource, from the cached listing
+const XATTR_ETAG: &str = "user.davfs.etag";
+const XATTR_URL: &str = "user.davfs.url";
+const XATTR_MODIFIED: &str = "user.davfs.modified";
+const XATTR_CONTENT_TYPE: &str = "user.davfs.content_type";
+// Per-directory cache TTL in seconds, settable by the user
+const XATTR_TTL: &str = "user.davfs.ttl";
+// "1" on pinned paths; setting it pins or unpins
+const XATTR_PINNED: &str = "user.davfs.pinned";
+// Read-only xattrs computed by the filesystem
+const XATTRS: [&str; 7] = [
+    XATTR_STATE,
+    XATTR_JOURNAL_OPS,
+    XATTR_SYNC_STATUS,
+    XATTR_ETAG,
+    XATTR_URL,
+    XATTR_MODIFIED,
+    XATTR_CONTENT_TYPE,
+];
+// Xattrs under this prefix map to WebDAV properties, e.g. user.dav.oc:tags
+const XATTR_DAV_PREFIX: &str = "user.dav.";
+// Shorter names for the Nextcloud tags and favorite flag
+const XATTR_NC_ALIASES: [(&str, &str); 2] = [
+    ("user.nc.tags", "oc:tags"),
+    ("user.nc.favorite", "oc:favorite"),
+];
+
+/// Local xattrs of one inode, by name.
+type XattrMap = BTreeMap<String, Vec<u8>>;
+
+/// Local copy of a file being written, uploaded on flush/release.
+struct WriteBuffer {
+    data: SparseBuffer,
+    // Etag the buffer was based on, sent as If-Match on upload
+    etag: Option<String>,
+    dirty: bool,
+    // mtime set while dirty; the upload resets it on the server, so it is
+    // applied again afterwards
+    mtime: Option<SystemTime>,
+}
+
+/// State for a file opened through FUSE, keyed by file handle. Content is
+/// buffered per inode in `write_buffers` so all handles of a file see the
+/// same data.
+struct OpenFileHandle {
+    ino: u64,
+    path: String,
+    // Flags passed to open(2)
+    flags: i32,
+    etag: Option<String>,
+    // Server-side lock held while the file is open for writing
+    lock: Option<LockToken>,
+    // Written through this handle since the buffer was last uploaded
+    dirty: bool,
+    // fcntl lock taken through this handle
+    posix_lock: Option<PosixLock>,
+    // Whole content downloaded at open, read instead of the server
+    local_copy: Option<LocalCopy>,
+}
+
+/// A file's content in the content cache, for a handle opened for reading.
+struct LocalCopy {
+    key: String,
+    // Only for this handle (the file has no etag), removed on release
+    temporary: bool,
+}
+
+/// An earlier version of a file, shown next to it.
+#[derive(Clone)]
+struct VersionFile {
+    // Named `<file>.v<timestamp>`
+    entry: DavEntry,
+    file_id: String,
+    version: String,
+}
+
+/// An fcntl lock, held on the server as a lock on the whole file (WebDAV
+/// has no byte ranges).
+struct PosixLock {
+    owner: u64,
+    shared: bool,
+    // None when the handle's own write lock already covers it
+    token: Option<LockToken>,
+}
+
+impl OpenFileHandle {
+    fn writable(&self) -> bool {
+        self.flags & libc::O_ACCMODE != libc::O_RDONLY
+    }
+}
+
 pub struct DavFS {
     webdav: WebDavClient,
     runtime: tokio::runtime::Runtime,
@@ -22,89 +176,186 @@ pub struct DavFS {
     inode_to_path: Arc<Mutex<HashMap<u64, String>>>,
     // Map path to inode
     path_to_inode: Arc<Mutex<HashMap<String, u64>>>,
-    next_inode: Arc<Mutex<u64>>,
+    // Per-mount seed for path_to_stable_inode
+    inode_seed: u64,
+    // Kernel lookup count per inode, decremented by forget()
+    lookup_counts: Arc<Mutex<HashMap<u64, u64>>>,
+    // Local ctime per inode (WebDAV has no notion of metadata change time)
+    ctime_map: Arc<Mutex<HashMap<u64, SystemTime>>>,
     // Directory listing cache
     dir_cache: DirectoryCache,
+    // Pending writes per inode
+    write_buffers: Arc<Mutex<HashMap<u64, WriteBuffer>>>,
+    // Open files by file handle
+    open_handles: Arc<Mutex<HashMap<u64, OpenFileHandle>>>,
+    // Inodes open for writing, which a second writer may not open
+    open_for_write: Arc<Mutex<HashSet<u64>>>,
+    next_fh: Arc<Mutex<u64>>,
+    // Changes made while the server was unreachable
+    journal: Arc<WriteJournal>,
+    sync: Arc<SyncManager>,
+    // Selective sync patterns
+    filter: PathFilter,
+    // Last quota fetched for statfs
+    quota: Arc<Mutex<Option<(Instant, Quota)>>>,
+    // What the server advertised at mount time; None if it was unreachable
+    capabilities: Option<DavCapabilities>,
+    // Xattrs outside user.dav., kept in memory only
+    local_xattrs: Arc<Mutex<HashMap<u64, XattrMap>>>,
+    // Shown in .davfs-status
+    mount_config: Option<MountConfig>,
+    // Cache TTLs set per directory through XATTR_TTL
+    ttl_overrides: Arc<Mutex<HashMap<u64, Duration>>>,
+    // Downloaded file contents by URL and etag
+    content_cache: Option<ContentCache>,
+    // Background listing of subdirectories of opened directories
+    prefetch: PrefetchScheduler,
+    // Etag of each file when it was last opened, to tell whether the
+    // kernel's page cache of it is still current
+    opened_etags: Arc<Mutex<HashMap<u64, String>>>,
+    // Where changes made through the mount are recorded
+    audit: Option<AuditLog>,
+    // Versions of the files in each opened directory, by directory path
+    versions: Arc<Mutex<HashMap<String, Vec<VersionFile>>>>,
+    // Paths of inodes with emulated hard links. Each link is a copy on the
+    // server, kept identical on upload.
+    hardlinks: Arc<Mutex<HashMap<u64, Vec<String>>>>,
+    // Checks polled files for remote changes
+    poll_watcher: Option<Arc<PollWatcher>>,
 }
 
 impl DavFS {
-    pub fn new(webdav: WebDavClient) -> Self {
+    pub fn new(
+        webdav: WebDavClient,
+        dir_cache: DirectoryCache,
+        sync: Arc<SyncManager>,
+        filter: PathFilter,
+    ) -> Self {
         let runtime = tokio::runtime::Runtime::new().unwrap();
         let mut inode_to_path = HashMap::new();
-        let path_to_inode = HashMap::new();
+        let mut path_to_inode = HashMap::new();
+        
+        // Keep the inodes of earlier mounts valid
+        match dir_cache.load_inodes() {
+            Ok(inodes) => {
+                tracing::info!("Loaded {} inodes from cache", inodes.len());
+                for (ino, path) in inodes {
+                    path_to_inode.insert(path.clone(), ino);
+                    inode_to_path.insert(ino, path);
+                }
+            }
+            Err(e) => tracing::warn!("Failed to load inode table: {}", e),
+        }
         
         // Root directory is at /
         inode_to_path.insert(ROOT_INO, String::from("/"));
-        
-        // Create cache with 5 second TTL
-        let dir_cache = DirectoryCache::new(std::time::Duration::from_secs(5));
+
+        let ctime_map = match dir_cache.load_ctimes() {
+            Ok(ctimes) => ctimes.into_iter().collect(),
+            Err(e) => {
+                tracing::warn!("Failed to load inode ctimes: {}", e);
+                HashMap::new()
+            }
+        };
+
+        let prefetch =
+            PrefetchScheduler::new(runtime.handle().clone(), webdav.clone(), dir_cache.clone(), filter.clone());
         
         Self {
             webdav,
             runtime,
             inode_to_path: Arc::new(Mutex::new(inode_to_path)),
             path_to_inode: Arc::new(Mutex::new(path_to_inode)),
-            next_inode: Arc::new(Mutex::new(2)),
+            inode_seed: 0,
+            lookup_counts: Arc::new(Mutex::new(HashMap::new())),
+            ctime_map: Arc::new(Mutex::new(ctime_map)),
             dir_cache,
+            write_buffers: Arc::new(Mutex::new(HashMap::new())),
+            open_handles: Arc::new(Mutex::new(HashMap::new())),
+            open_for_write: Arc::new(Mutex::new(HashSet::new())),
+            next_fh: Arc::new(Mutex::new(1)),
+            journal: sync.journal().clone(),
+            sync,
+            filter,
+            quota: Arc::new(Mutex::new(None)),
+            capabilities: None,
+            local_xattrs: Arc::new(Mutex::new(HashMap::new())),
+            mount_config: None,
+            ttl_overrides: Arc::new(Mutex::new(HashMap::new())),
+            content_cache: None,
+            prefetch,
+            opened_etags: Arc::new(Mutex::new(HashMap::new())),
+            audit: None,
+            versions: Arc::new(Mutex::new(HashMap::new())),
+            hardlinks: Arc::new(Mutex::new(HashMap::new())),
+            poll_watcher: None,
         }
     }
-    
+
+    pub fn with_inode_seed(mut self, inode_seed: u64) -> Self {
+        self.inode_seed = inode_seed;
+        self
+    }
+
+    pub fn with_capabilities(mut self, capabilities: Option<DavCapabilities>) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn with_mount_config(mut self, config: MountConfig) -> Self {
+        self.mount_config = Some(config);
+        self
+    }
+
+    pub fn with_content_cache(mut self, content_cache: ContentCache) -> Self {
+        self.content_cache = Some(content_cache);
+        self
+    }
+
+    pub fn with_prefetch(mut self, strategy: PrefetchStrategy, concurrency: usize) -> Self {
+        self.prefetch = self.prefetch.with_strategy(strategy, concurrency);
+        self
+    }
+
+    pub fn with_audit_log(mut self, audit: Option<AuditLog>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    pub fn with_poll_watcher(mut self, poll_watcher: Option<Arc<PollWatcher>>) -> Self {
+        self.poll_watcher = poll_watcher;
+        self
+    }
+
     pub fn prefetch_initial(&self) {
-        // Aggressive initial prefetch: root + 2 levels deep
+        // List the root, then let the scheduler work through the levels below
         let webdav = self.webdav.clone();
         let cache = self.dir_cache.clone();
+        let filter = self.filter.clone();
+        let prefetch = self.prefetch.clone();
+        let full_tree = self
+            .mount_config
+            .as_ref()
+            .filter(|config| config.full_tree_prefetch)
+            .map(|config| config.max_entries_for_infinity);
         
         std::thread::spawn(move || {
             let rt = tokio::runtime::Builder::new_current_thread()
                 .enable_all()
                 .build()
                 .unwrap();
+
+            if let Some(max_entries) = full_tree {
+                if Self::prefetch_tree(&rt, &webdav, &cache, &filter, max_entries) {
+                    return;
+                }
+            }
             
-            // Fetch root
-            match rt.block_on(webdav.list_dir("")) {
+            match rt.block_on(webdav.list_dir("", &PropRequest::default())) {
                 Ok(root_entries) => {
                     tracing::info!("Prefetched root with {} entries", root_entries.len());
-                    
-                    let subdirs: Vec<_> = root_entries.iter()
-                        .filter(|e| e.is_dir)
-                        .map(|e| e.name.clone())
-                        .collect();
-                    
-                    cache.insert("/".to_string(), root_entries);
-                    
-                    // Prefetch all first-level directories
-                    for subdir in &subdirs {
-                        let path = format!("/{}", subdir);
-                        match rt.block_on(webdav.list_dir(subdir)) {
-                            Ok(entries) => {
-                                tracing::info!("Prefetched: {} ({} entries)", path, entries.len());
-                                
-                                // Prefetch second level too
-                                let subdirs2: Vec<_> = entries.iter()
-                                    .filter(|e| e.is_dir)
-                                    .map(|e| format!("{}/{}", subdir, e.name))
-                                    .take(5) // Limit per directory to avoid overwhelming
-                                    .collect();
-                                
-                                cache.insert(path, entries);
-                                
-                                // Prefetch second level
-                                for subdir2 in subdirs2 {
-                                    let path2 = format!("/{}", subdir2);
-                                    match rt.block_on(webdav.list_dir(&subdir2)) {
-                                        Ok(entries2) => {
-                                            let count = entries2.len();
-                                            cache.insert(path2.clone(), entries2);
-                                            tracing::info!("Prefetched: {} ({} entries)", path2, count);
-                                        }
-                                        Err(_) => {}
-                                    }
-                                }
-                            }
-                            Err(_) => {}
-                        }
-                    }
-                    tracing::info!("Initial prefetch complete: {} top-level directories", subdirs.len());
+                    prefetch.schedule("/", &root_entries);
+                    cache.insert("/".to_string(), root_entries, None);
                 }
                 Err(e) => {
                     tracing::warn!("Failed to prefetch: {}", e);
@@ -113,94 +364,104 @@ impl DavFS {
         });
     }
     
-    fn prefetch_subdirectories(&self, dir_path: &str, entries: &[crate::webdav::DavEntry]) {
-        // Background prefetch of subdirectories for faster navigation
-        // Go 3 levels deep for rapid prefetching
-        let subdirs: Vec<_> = entries.iter()
-            .filter(|e| e.is_dir)
-            .map(|e| {
-                if dir_path == "/" {
-                    format!("/{}", e.name)
-                } else {
-                    format!("{}/{}", dir_path, e.name)
+    /// Cache every listing of the tree from one `Depth: infinity` PROPFIND.
+    /// Returns false, having cached nothing, if the server does not list
+    /// recursively or the tree has more than `max_entries` entries.
+    fn prefetch_tree(
+        rt: &tokio::runtime::Runtime,
+        webdav: &WebDavClient,
+        cache: &DirectoryCache,
+        filter: &PathFilter,
+        max_entries: usize,
+    ) -> bool {
+        let listing = match rt.block_on(webdav.list_tree("", max_entries)) {
+            Ok(listing) => listing,
+            Err(e) => {
+                tracing::info!("Depth: infinity listing failed, prefetching per directory: {}", e);
+                return false;
+            }
+        };
+        if listing.truncated {
+            tracing::info!("Tree has more than {} entries, prefetching per directory", max_entries);
+            return false;
+        }
+        // Servers that forbid infinite depth may answer as for Depth: 1
+        let has_dirs = listing.entries.iter().any(|entry| entry.is_dir);
+        if has_dirs && !listing.entries.iter().any(|entry| entry.name.contains('/')) {
+            tracing::info!("Server listed only one level, prefetching per directory");
+            return false;
+        }
+
+        let mut listings: HashMap<String, (Vec<DavEntry>, Option<String>)> = HashMap::new();
+        listings.insert("/".to_string(), (Vec::new(), listing.etag));
+        // Empty directories have no children, but still get a listing
+        for entry in listing.entries.iter().filter(|entry| entry.is_dir) {
+            listings.entry(format!("/{}", entry.name)).or_default().1 = entry.etag.clone();
+        }
+        let total = listing.entries.len();
+        for mut entry in listing.entries {
+            let parent = match entry.name.rsplit_once('/') {
+                Some((parent, name)) => {
+                    let parent = format!("/{}", parent);
+                    entry.name = name.to_string();
+                    parent
                 }
-            })
-            .collect();
-        
-        if subdirs.is_empty() {
-            return;
+                None => "/".to_string(),
+            };
+            listings.entry(parent).or_default().0.push(entry);
         }
-        
+
+        let directories = listings.len();
+        for (path, (entries, etag)) in listings {
+            if path != "/" && filter.excludes(path.trim_start_matches('/'), true) {
+                continue;
+            }
+            cache.insert(path, entries, etag);
+        }
+        tracing::info!("Prefetched {} entries in {} directories with one request", total, directories);
+        true
+    }
+
+    /// List `dir_path` from the server, sharing the request with concurrent
+    /// listings of the same directory.
+    fn fetch_listing(&self, dir_path: &str) -> anyhow::Result<DirListing> {
+        let dav_path = dir_path.trim_start_matches('/');
+        self.runtime.block_on(self.dir_cache.fetch_listing(dir_path, async {
+            Ok(self.webdav.list_dir_conditional(dav_path, None).await?.unwrap_or_default())
+        }))
+    }
+
+    /// Re-check an expired listing with a conditional PROPFIND: a 304 just
+    /// marks the cache fresh, anything else replaces the listing.
+    fn revalidate_in_background(&self, dir_path: &str, etag: Option<String>) {
         let webdav = self.webdav.clone();
         let cache = self.dir_cache.clone();
-        
-        // Spawn background task to prefetch recursively
-        std::thread::spawn(move || {
-            // Create a new runtime in the background thread
-            let rt = tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .unwrap();
-            
-            fn prefetch_recursive(
-                rt: &tokio::runtime::Runtime,
-                webdav: &crate::webdav::WebDavClient,
-                cache: &crate::cache::DirectoryCache,
-                path: &str,
-                depth: u32,
-                max_depth: u32,
-            ) {
-                if depth >= max_depth {
-                    return;
-                }
-                
-                // Check if already cached
-                if cache.get_stale(path).is_some() {
-                    return;
-                }
-                
-                // Fetch directory listing
-                let dav_path = if path == "/" { "" } else { &path[1..] };
-                match rt.block_on(webdav.list_dir(dav_path)) {
-                    Ok(entries) => {
-                        let num_entries = entries.len();
-                        
-                        // Find subdirectories before we move entries
-                        let subdirs: Vec<_> = entries.iter()
-                            .filter(|e| e.is_dir)
-                            .map(|e| {
-                                if path == "/" {
-                                    format!("/{}", e.name)
-                                } else {
-                                    format!("{}/{}", path, e.name)
-                                }
-                            })
-                            .collect();
-                        
-                        // Cache this directory
-                        cache.insert(path.to_string(), entries);
-                        
-                        tracing::info!("Prefetched {} (depth {}, {} entries, {} subdirs)", 
-                                      path, depth, num_entries, subdirs.len());
-                        
-                        // Recursively prefetch subdirectories
-                        for subdir in subdirs {
-                            prefetch_recursive(rt, webdav, cache, &subdir, depth + 1, max_depth);
-                        }
-                    }
-                    Err(e) => {
-                        tracing::debug!("Failed to prefetch {}: {}", path, e);
-                    }
-                }
-            }
-            
-            // Prefetch up to 4 levels deep for very aggressive caching
-            for subdir_path in subdirs {
-                prefetch_recursive(&rt, &webdav, &cache, &subdir_path, 1, 4);
+        let dir_path = dir_path.to_string();
+
+        self.runtime.spawn(async move {
+            let dav_path = dir_path.trim_start_matches('/');
+            match webdav.list_dir_conditional(dav_path, etag.as_deref()).await {
+                Ok(None) => cache.touch(&dir_path),
+                Ok(Some(listing)) => cache.insert(dir_path, listing.entries, listing.etag),
+                Err(e) => tracing::debug!("Failed to revalidate {}: {}", dir_path, e),
             }
         });
     }
     
+    /// Inode number for `path` that stays the same across remounts, so
+    /// tools like git and rsync that remember inodes keep working.
+    ///
+    /// With 64-bit hashes the chance of any collision among a million paths
+    /// is about 1 in 37 million (n²/2⁶⁵); get_or_create_inode still probes
+    /// past one rather than aliasing two files.
+    fn path_to_stable_inode(&self, path: &str) -> u64 {
+        let mut hasher = std::hash::DefaultHasher::new();
+        self.inode_seed.hash(&mut hasher);
+        path.hash(&mut hasher);
+        // 0 is invalid and 1 is the root
+        hasher.finish().max(ROOT_INO + 1)
+    }
+
     fn get_or_create_inode(&self, path: &str) -> u64 {
         let mut path_to_inode = self.path_to_inode.lock().unwrap();
         
@@ -208,222 +469,2360 @@ impl DavFS {
             return ino;
         }
         
-        let mut next_inode = self.next_inode.lock().unwrap();
-        let ino = *next_inode;
-        *next_inode += 1;
-        drop(next_inode);
+        let mut inode_to_path = self.inode_to_path.lock().unwrap();
+        let mut ino = self.path_to_stable_inode(path);
+        while ino == STATUS_INO || inode_to_path.contains_key(&ino) {
+            tracing::warn!("Inode hash collision for {}", path);
+            ino = ino.checked_add(1).unwrap_or(ROOT_INO + 1);
+        }
         
         path_to_inode.insert(path.to_string(), ino);
-        drop(path_to_inode);
-        
-        let mut inode_to_path = self.inode_to_path.lock().unwrap();
         inode_to_path.insert(ino, path.to_string());
+        drop(inode_to_path);
+        drop(path_to_inode);
         
+        self.dir_cache.store_inode(ino, path);
         ino
     }
     
+    fn remember_lookup(&self, ino: u64) {
+        let mut lookup_counts = self.lookup_counts.lock().unwrap();
+        *lookup_counts.entry(ino).or_insert(0) += 1;
+    }
+    
+    /// Drop `nlookup` kernel references to `ino`; once none remain the
+    /// inode is unmapped.
+    fn release_inode(&self, ino: u64, nlookup: u64) {
+        if ino == ROOT_INO {
+            return;
+        }
+        
+        let mut lookup_counts = self.lookup_counts.lock().unwrap();
+        let remaining = match lookup_counts.get_mut(&ino) {
+            Some(count) => {
+                *count = count.saturating_sub(nlookup);
+                *count
+            }
+            None => 0,
+        };
+        if remaining > 0 {
+            return;
+        }
+        lookup_counts.remove(&ino);
+        drop(lookup_counts);
+        // Its paths would get separate inodes when looked up again
+        if self.hardlinks.lock().unwrap().contains_key(&ino) {
+            return;
+        }
+        
+        let path = self.inode_to_path.lock().unwrap().remove(&ino);
+        if let Some(path) = path {
+            let mut path_to_inode = self.path_to_inode.lock().unwrap();
+            if path_to_inode.get(&path) == Some(&ino) {
+                path_to_inode.remove(&path);
+            }
+        }
+        self.ctime_map.lock().unwrap().remove(&ino);
+        self.dir_cache.remove_inode(ino);
+        if let Some(watcher) = &self.poll_watcher {
+            watcher.unwatch(ino);
+        }
+        tracing::debug!("Reclaimed inode {}", ino);
+    }
+    
     fn get_path(&self, ino: u64) -> Option<String> {
         let inode_to_path = self.inode_to_path.lock().unwrap();
         inode_to_path.get(&ino).cloned()
     }
 
-    fn root_attr() -> FileAttr {
-        FileAttr {
-            ino: ROOT_INO,
-            size: 0,
-            blocks: 0,
-            atime: UNIX_EPOCH,
-            mtime: UNIX_EPOCH,
-            ctime: UNIX_EPOCH,
-            crtime: UNIX_EPOCH,
-            kind: FileType::Directory,
-            perm: 0o755,
-            nlink: 2,
-            uid: unsafe { libc::getuid() },
-            gid: unsafe { libc::getgid() },
-            rdev: 0,
-            blksize: 512,
-            flags: 0,
+    pub(crate) fn child_path(parent: &str, name: &str) -> String {
+        if parent == "/" {
+            format!("/{}", name)
+        } else {
+            format!("{}/{}", parent.trim_end_matches('/'), name)
         }
     }
 
-    fn dir_attr(ino: u64) -> FileAttr {
-        FileAttr {
-            ino,
-            size: 0,
-            blocks: 0,
-            atime: UNIX_EPOCH,
-            mtime: UNIX_EPOCH,
-            ctime: UNIX_EPOCH,
-            crtime: UNIX_EPOCH,
-            kind: FileType::Directory,
-            perm: 0o755,
-            nlink: 2,
-            uid: unsafe { libc::getuid() },
-            gid: unsafe { libc::getgid() },
-            rdev: 0,
-            blksize: 512,
-            flags: 0,
+    pub(crate) fn parent_of(path: &str) -> &str {
+        match path.rfind('/') {
+            Some(0) | None => "/",
+            Some(idx) => &path[..idx],
         }
     }
 
-    fn file_attr(ino: u64, size: u64) -> FileAttr {
-        FileAttr {
-            ino,
-            size,
-            blocks: (size + 511) / 512,
-            atime: UNIX_EPOCH,
-            mtime: UNIX_EPOCH,
-            ctime: UNIX_EPOCH,
-            crtime: UNIX_EPOCH,
-            kind: FileType::RegularFile,
-            perm: 0o644,
-            nlink: 1,
-            uid: unsafe { libc::getuid() },
-            gid: unsafe { libc::getgid() },
-            rdev: 0,
-            blksize: 512,
-            flags: 0,
+    /// Look up the cached listing entry for `path` in its parent directory.
+    fn cached_entry(&self, path: &str) -> Option<DavEntry> {
+        let name = path.rsplit('/').next()?;
+        self.dir_cache
+            .get_stale(Self::parent_of(path))?
+            .into_iter()
+            .find(|e| e.name == name)
+    }
+
+    /// Resolve `parent`/`name` to a full path, replying with an error if
+    /// either is unusable.
+    fn resolve_child(&self, parent: u64, name: &OsStr) -> Result<(String, String), i32> {
+        let parent_path = self.get_path(parent).ok_or(ENOENT)?;
+        let name = name.to_str().ok_or(EINVAL)?;
+        Ok((Self::child_path(&parent_path, name), parent_path))
+    }
+
+    /// Refresh state after the directory at `parent_path` changed remotely.
+    fn directory_changed(&self, parent_path: &str) {
+        self.dir_cache.invalidate(parent_path);
+        let parent_ino = self.path_to_inode.lock().unwrap().get(parent_path).copied();
+        let parent_ino = if parent_path == "/" { Some(ROOT_INO) } else { parent_ino };
+        if let Some(ino) = parent_ino {
+            self.touch_ctime(ino);
         }
     }
-}
 
-impl Filesystem for DavFS {
-    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
-        tracing::debug!("lookup: parent={}, name={:?}", parent, name);
+    /// Drop the path mappings for `path` and everything below it.
+    fn unmap_path(&self, path: &str) {
+        let prefix = format!("{}/", path);
+        let mut path_to_inode = self.path_to_inode.lock().unwrap();
+        let mut inode_to_path = self.inode_to_path.lock().unwrap();
+        path_to_inode.retain(|p, ino| {
+            let keep = p != path && !p.starts_with(&prefix);
+            if !keep {
+                inode_to_path.remove(ino);
+            }
+            keep
+        });
+        drop(inode_to_path);
+        drop(path_to_inode);
+        self.dir_cache.remove_inode_tree(path);
+    }
 
-        let parent_path = match self.get_path(parent) {
-            Some(p) => p,
-            None => {
-                reply.error(ENOENT);
-                return;
+    /// Re-key every inode at or below `old` to live under `new`.
+    fn rename_paths(&self, old: &str, new: &str) {
+        let prefix = format!("{}/", old);
+        let mut path_to_inode = self.path_to_inode.lock().unwrap();
+        let mut inode_to_path = self.inode_to_path.lock().unwrap();
+
+        let moved: Vec<(String, u64)> = path_to_inode
+            .iter()
+            .filter(|(p, _)| p.as_str() == old || p.starts_with(&prefix))
+            .map(|(p, &ino)| (p.clone(), ino))
+            .collect();
+
+        for (path, ino) in moved {
+            path_to_inode.remove(&path);
+            let renamed = format!("{}{}", new, &path[old.len()..]);
+            path_to_inode.insert(renamed.clone(), ino);
+            if let Some(links) = self.hardlinks.lock().unwrap().get_mut(&ino) {
+                for link in links.iter_mut().filter(|link| **link == path) {
+                    link.clone_from(&renamed);
+                }
             }
-        };
-        
-        let name_str = match name.to_str() {
-            Some(n) => n,
-            None => {
-                reply.error(ENOENT);
-                return;
+            inode_to_path.insert(ino, renamed);
+        }
+        drop(inode_to_path);
+        drop(path_to_inode);
+        // Open handles upload, lock and unlock by path
+        for handle in self.open_handles.lock().unwrap().values_mut() {
+            if handle.path == old || handle.path.starts_with(&prefix) {
+                handle.path = format!("{}{}", new, &handle.path[old.len()..]);
             }
+        }
+        self.dir_cache.rename_inode_tree(old, new);
+        self.dir_cache.rename_ttl_overrides(old, new);
+        self.dir_cache.rename_pins(old, new);
+    }
+
+    /// Hard links of `ino` other than `path`.
+    fn other_links(&self, ino: u64, path: &str) -> Vec<String> {
+        self.hardlinks
+            .lock()
+            .unwrap()
+            .get(&ino)
+            .map(|links| links.iter().filter(|link| *link != path).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Drop `path` from the hard links of its inode. True if other links
+    /// keep the inode, so it must not be unmapped.
+    fn remove_link(&self, path: &str) -> bool {
+        let Some(ino) = self.path_to_inode.lock().unwrap().get(path).copied() else {
+            return false;
         };
-        
-        // Build the full path
-        let full_path = if parent_path == "/" {
-            format!("/{}", name_str)
-        } else {
-            format!("{}/{}", parent_path.trim_end_matches('/'), name_str)
-        };
-        
-        // Try to list parent directory to find this entry
-        let dav_path = if parent_path == "/" { "" } else { &parent_path[1..] };
-        
-        // Try stale cache first for instant response, then fetch if needed
-        let entries = if let Some(cached) = self.dir_cache.get_stale(&parent_path) {
-            cached
-        } else {
-            match self.runtime.block_on(self.webdav.list_dir(dav_path)) {
-                Ok(entries) => {
-                    self.dir_cache.insert(parent_path.clone(), entries.clone());
-                    entries
-                }
-                Err(_) => {
-                    reply.error(ENOENT);
-                    return;
-                }
+        let remaining = {
+            let mut hardlinks = self.hardlinks.lock().unwrap();
+            let Some(links) = hardlinks.get_mut(&ino) else {
+                return false;
+            };
+            links.retain(|link| link != path);
+            let remaining = links.first().cloned();
+            if links.len() < 2 {
+                hardlinks.remove(&ino);
             }
+            remaining
         };
-        
-        for entry in entries {
-            if entry.name == name_str {
-                let ino = self.get_or_create_inode(&full_path);
-                let attr = if entry.is_dir {
-                    Self::dir_attr(ino)
-                } else {
-                    Self::file_attr(ino, entry.size)
-                };
-                reply.entry(&TTL, &attr, 0);
-                return;
+        let Some(remaining) = remaining else {
+            return false;
+        };
+        self.path_to_inode.lock().unwrap().remove(path);
+        self.inode_to_path.lock().unwrap().insert(ino, remaining.clone());
+        self.dir_cache.store_inode(ino, &remaining);
+        self.touch_ctime(ino);
+        true
+    }
+
+    fn allocate_handle(
+        &self,
+        ino: u64,
+        path: String,
+        flags: i32,
+        etag: Option<String>,
+        lock: Option<LockToken>,
+    ) -> u64 {
+        let mut next_fh = self.next_fh.lock().unwrap();
+        let fh = *next_fh;
+        *next_fh += 1;
+        self.open_handles.lock().unwrap().insert(fh, OpenFileHandle {
+            ino,
+            path,
+            flags,
+            etag,
+            lock,
+            dirty: false,
+            posix_lock: None,
+            local_copy: None,
+        });
+        fh
+    }
+
+    /// The buffer of `ino` was uploaded (or journaled), so no handle has
+    /// unsaved writes any more.
+    fn mark_handles_clean(&self, ino: u64) {
+        for handle in self.open_handles.lock().unwrap().values_mut() {
+            if handle.ino == ino {
+                handle.dirty = false;
             }
         }
-        reply.error(ENOENT);
     }
 
-    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
-        tracing::debug!("getattr: ino={}", ino);
+    /// Queue `op` for replay once the server is reachable again and apply
+    /// `change` to the cached listing of `parent_path` so the result is
+    /// visible locally in the meantime.
+    fn queue_offline(
+        &self,
+        op: JournalOp,
+        parent_path: &str,
+        change: impl FnOnce(&mut Vec<DavEntry>),
+    ) -> Result<(), i32> {
+        // Excluded paths are not synced, so offline changes to them fail
+        let is_dir = matches!(op, JournalOp::Mkdir { .. });
+        if op.paths().iter().any(|path| self.filter.excludes(path, is_dir)) {
+            return Err(ENETUNREACH);
+        }
+        self.journal.append(op).map_err(|e| {
+            tracing::error!("Failed to journal offline change: {}", e);
+            EIO
+        })?;
+        self.dir_cache.update(parent_path, change);
+        Ok(())
+    }
 
-        if ino == ROOT_INO {
-            reply.attr(&TTL, &Self::root_attr());
-            return;
+    /// Lock `path` before it is opened for writing. Servers without lock
+    /// support (or unreachable ones) leave the file unlocked; only a lock
+    /// held by someone else fails the open, with EAGAIN.
+    fn lock_for_write(&self, path: &str) -> Result<Option<LockToken>, i32> {
+        // Unknown capabilities (offline at mount) still try, as before
+        if self.capabilities.is_some_and(|c| !c.class2) {
+            return Ok(None);
         }
-        
-        let path = match self.get_path(ino) {
-            Some(p) => p,
-            None => {
-                reply.error(ENOENT);
-                return;
+        match self.runtime.block_on(self.webdav.lock(path, "0", LockScope::Exclusive, LOCK_TIMEOUT_SECS)) {
+            Ok(token) => {
+                tracing::debug!("Locked {}", path);
+                Ok(Some(token))
             }
-        };
-        
-        // Get parent directory path
-        let parent_path = if let Some(idx) = path.rfind('/') {
-            if idx == 0 {
+            Err(e) => match errno_for(&e) {
+                EAGAIN => {
+                    tracing::info!("{} is locked by another client", path);
+                    Err(EAGAIN)
+                }
+                _ => {
+                    tracing::warn!("Could not lock {}, writing without a lock: {}", path, e);
+                    Ok(None)
+                }
+            },
+        }
+    }
+
+    /// Lock `path` for one more writer. Parallel writers share the lock the
+    /// first one took, as the server would refuse a second one.
+    fn lock_for_writer(&self, path: &str) -> Result<Option<LockToken>, i32> {
+        if self.allows_parallel_write() {
+            let held = self.open_handles.lock().unwrap()
+                .values()
+                .filter(|handle| handle.path == path)
+                .find_map(|handle| handle.lock.clone());
+            if held.is_some() {
+                return Ok(held);
+            }
+        }
+        self.lock_for_write(path)
+    }
+
+    /// Lock again what handles had open for writing at or below `path`: the
+    /// server dropped their locks when it was moved there.
+    fn relock_moved(&self, path: &str) {
+        let prefix = format!("{}/", path);
+        let moved: HashSet<String> = self
+            .open_handles
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|handle| handle.lock.is_some() && (handle.path == path || handle.path.starts_with(&prefix)))
+            .map(|handle| handle.path.clone())
+            .collect();
+        // Handles writing the same file share one lock
+        for path in moved {
+            let lock = self.lock_for_write(&path).unwrap_or(None);
+            for handle in self.open_handles.lock().unwrap().values_mut() {
+                if handle.path == path && handle.lock.is_some() {
+                    handle.lock.clone_from(&lock);
+                }
+            }
+        }
+    }
+
+    /// Lock token held by any open handle for `path`, from opening it for
+    /// writing or from an fcntl lock.
+    fn held_lock(&self, path: &str) -> Option<LockToken> {
+        self.open_handles.lock().unwrap()
+            .values()
+            .filter(|handle| handle.path == path)
+            .find_map(|handle| {
+                handle.lock.clone().or_else(|| handle.posix_lock.as_ref()?.token.clone())
+            })
+    }
+
+    /// Take a server lock for an fcntl lock, retrying for up to
+    /// `POSIX_LOCK_WAIT` while it is held elsewhere if `wait` is set.
+    fn acquire_posix_lock(&self, path: &str, shared: bool, wait: bool) -> Result<LockToken, i32> {
+        let scope = if shared { LockScope::Shared } else { LockScope::Exclusive };
+        let deadline = Instant::now() + POSIX_LOCK_WAIT;
+        loop {
+            match self.runtime.block_on(self.webdav.lock(path, "0", scope, POSIX_LOCK_TIMEOUT_SECS)) {
+                Ok(token) => return Ok(token),
+                Err(e) => match errno_for(&e) {
+                    EAGAIN if wait && Instant::now() < deadline => std::thread::sleep(Duration::from_secs(1)),
+                    EAGAIN => return Err(EAGAIN),
+                    _ => {
+                        tracing::warn!("Failed to lock {}: {}", path, e);
+                        return Err(ENOLCK);
+                    }
+                },
+            }
+        }
+    }
+
+    fn release_posix_lock(&self, path: &str, lock: PosixLock) {
+        if let Some(token) = lock.token {
+            if let Err(e) = self.runtime.block_on(self.webdav.unlock(path, &token)) {
+                tracing::warn!("Failed to unlock {}: {}", path, e);
+            }
+        }
+    }
+
+    /// Upload the write buffer for `ino` if it holds unsaved changes.
+    fn flush_buffer(&self, ino: u64) -> Result<(), i32> {
+        let (data, etag) = {
+            let buffers = self.write_buffers.lock().unwrap();
+            match buffers.get(&ino) {
+                Some(buffer) if buffer.dirty => (buffer.data.to_bytes(), buffer.etag.clone()),
+                _ => return Ok(()),
+            }
+        };
+        let path = self.get_path(ino).ok_or(ENOENT)?;
+        let lock = self.held_lock(&path);
+
+        let upload = self
+            .runtime
+            .block_on(self.webdav.put_file(&path, data, etag.as_deref(), lock.as_ref()));
+        let new_etag = match upload {
+            Ok(new_etag) => new_etag,
+            Err(e) if is_network_error(&e) => {
+                let data = match self.write_buffers.lock().unwrap().get(&ino) {
+                    Some(buffer) => buffer.data.to_bytes(),
+                    None => return Err(EIO),
+                };
+                let size = data.len() as u64;
+                // Hard links get the same content, whatever they hold now
+                let mut writes = vec![(path.clone(), etag)];
+                writes.extend(self.other_links(ino, &path).into_iter().map(|link| (link, None)));
+                for (link, etag) in writes {
+                    let name = link.rsplit('/').next().unwrap_or_default().to_string();
+                    let parent = Self::parent_of(&link).to_string();
+                    let op = JournalOp::Write { path: link, offset: 0, data: data.clone(), etag };
+                    self.queue_offline(op, &parent, |entries| {
+                        if let Some(entry) = entries.iter_mut().find(|e| e.name == name) {
+                            entry.size = size;
+                            entry.modified = Some(chrono::Utc::now());
+                        }
+                    })?;
+                }
+                if let Some(buffer) = self.write_buffers.lock().unwrap().get_mut(&ino) {
+                    buffer.dirty = false;
+                }
+                self.mark_handles_clean(ino);
+                tracing::info!("Server unreachable, queued upload of {}", path);
+                return Ok(());
+            }
+            Err(e) => {
+                tracing::error!("Failed to upload {}: {}", path, e);
+                return Err(errno_for(&e));
+            }
+        };
+
+        if let Some(buffer) = self.write_buffers.lock().unwrap().get_mut(&ino) {
+            buffer.dirty = false;
+            buffer.etag = new_etag;
+        }
+        self.mark_handles_clean(ino);
+        self.touch_ctime(ino);
+        self.directory_changed(Self::parent_of(&path));
+        tracing::info!("Uploaded {}", path);
+
+        for link in self.other_links(ino, &path) {
+            match self.runtime.block_on(self.webdav.copy_entry(&path, &link, true, None)) {
+                Ok(()) => self.directory_changed(Self::parent_of(&link)),
+                Err(e) => tracing::warn!("Failed to update hard link {} of {}: {}", link, path, e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Read `size` bytes at `offset`, from the write buffer if the file has
+    /// one, otherwise with a Range request.
+    fn read_at(&self, ino: u64, fh: u64, offset: u64, size: u64) -> Result<Vec<u8>, i32> {
+        // Serve pending local writes from the buffer
+        {
+            let buffers = self.write_buffers.lock().unwrap();
+            if let Some(buffer) = buffers.get(&ino) {
+                return Ok(buffer.data.read(offset, size));
+            }
+        }
+
+        let (path, etag, local_copy) = match self.open_handles.lock().unwrap().get(&fh) {
+            Some(handle) => (
+                handle.path.clone(),
+                handle.etag.clone(),
+                handle.local_copy.as_ref().map(|copy| copy.key.clone()),
+            ),
+            None => {
+                let path = self.get_path(ino).ok_or(ENOENT)?;
+                let etag = self.cached_entry(&path).and_then(|entry| entry.etag);
+                (path, etag, None)
+            }
+        };
+        let local = local_copy.zip(self.content_cache.as_ref());
+        if let Some(data) = local.and_then(|(key, cache)| cache.read(&key, offset, size)) {
+            return Ok(data);
+        }
+        if let Some(data) = self.read_content_cache(&path, etag.as_deref(), offset, size)? {
+            return Ok(data);
+        }
+
+        self.runtime
+            .block_on(self.webdav.read_range(&path, offset, size))
+            .map_err(|e| {
+                tracing::error!("Failed to read {}: {}", path, e);
+                errno_for(&e)
+            })
+    }
+
+    /// Serve a read from the content cache, first downloading the whole
+    /// file into it if it is small enough. `None` to read from the server.
+    fn read_content_cache(
+        &self,
+        path: &str,
+        etag: Option<&str>,
+        offset: u64,
+        size: u64,
+    ) -> Result<Option<Vec<u8>>, i32> {
+        let (Some(cache), Some(etag)) = (self.content_cache.as_ref(), etag) else {
+            return Ok(None);
+        };
+        let Ok(url) = self.webdav.url_for(path) else {
+            return Ok(None);
+        };
+        let key = ContentCache::key(url.as_str(), etag);
+        if let Some(data) = cache.read(&key, offset, size) {
+            return Ok(Some(data));
+        }
+        if self.cached_entry(path).is_none_or(|entry| entry.size > CONTENT_CACHE_MAX_FILE) {
+            return Ok(None);
+        }
+
+        let content = match self.runtime.block_on(self.webdav.read_file(path)) {
+            Ok(content) => content,
+            Err(e) => {
+                tracing::debug!("Failed to download {} for the content cache: {}", path, e);
+                return Ok(None);
+            }
+        };
+        self.verify_checksum(path, &content)?;
+        if let Err(e) = cache.insert(&key, &content) {
+            tracing::warn!("Failed to cache content of {}: {:#}", path, e);
+        }
+        let start = (offset as usize).min(content.len());
+        let end = (start + size as usize).min(content.len());
+        Ok(Some(content[start..end].to_vec()))
+    }
+
+    /// Whether the content of `path` as last listed is in the content cache.
+    fn content_is_cached(&self, path: &str) -> bool {
+        let (Some(cache), Some(etag)) = (
+            self.content_cache.as_ref(),
+            self.cached_entry(path).and_then(|entry| entry.etag),
+        ) else {
+            return false;
+        };
+        self.webdav
+            .url_for(path)
+            .is_ok_and(|url| cache.contains(&ContentCache::key(url.as_str(), &etag)))
+    }
+
+    /// Compare a downloaded file with the checksum in its listing. Only
+    /// verified content enters the content cache, so cache hits need no
+    /// check.
+    fn verify_checksum(&self, path: &str, content: &[u8]) -> Result<(), i32> {
+        if !self.webdav.verifies_checksums() {
+            return Ok(());
+        }
+        let Some(announced) = self
+            .cached_entry(path)
+            .and_then(|entry| entry.properties.get("oc:checksums").cloned())
+        else {
+            return Ok(());
+        };
+        match checksum_matches(&announced, content) {
+            Some(false) => {
+                tracing::error!("Checksum mismatch for {}: server announced {}", path, announced);
+                // The file may have changed since it was listed; list it
+                // again before the next attempt
+                self.dir_cache.invalidate(Self::parent_of(path));
+                Err(EIO)
+            }
+            Some(true) => Ok(()),
+            None => {
+                tracing::debug!("No supported checksum for {} in {:?}", path, announced);
+                Ok(())
+            }
+        }
+    }
+
+    /// Download `path` whole when it is opened for reading, so reads and
+    /// memory maps of it are served from disk. Files without an etag get a
+    /// copy of their own for handle `fh`. Files with a checksum to verify
+    /// are downloaded whatever their size, as range reads cannot be checked.
+    fn local_copy(&self, path: &str, etag: Option<&str>, fh: u64) -> Result<Option<LocalCopy>, i32> {
+        let Some(cache) = self.content_cache.as_ref() else {
+            return Ok(None);
+        };
+        let Some(entry) = self.cached_entry(path).filter(|entry| !entry.is_dir) else {
+            return Ok(None);
+        };
+        // Encrypted files cannot be read by range, so they are always
+        // downloaded whole
+        let verify = self.webdav.verifies_checksums() && entry.properties.contains_key("oc:checksums");
+        if entry.size > CONTENT_CACHE_MAX_FILE && !self.webdav.encrypts() && !verify {
+            return Ok(None);
+        }
+        let (key, temporary) = match etag {
+            Some(etag) => match self.webdav.url_for(path) {
+                Ok(url) => (ContentCache::key(url.as_str(), etag), false),
+                Err(_) => return Ok(None),
+            },
+            None => (format!("open.{}.{}", std::process::id(), fh), true),
+        };
+
+        if !cache.contains(&key) {
+            let content = match self.runtime.block_on(self.webdav.read_file(path)) {
+                Ok(content) => content,
+                // Range reads would go unverified
+                Err(e) if verify => {
+                    tracing::error!("Failed to download {} for verification: {}", path, e);
+                    return Err(errno_for(&e));
+                }
+                Err(e) => {
+                    tracing::debug!("Failed to download {} on open: {}", path, e);
+                    return Ok(None);
+                }
+            };
+            self.verify_checksum(path, &content)?;
+            if let Err(e) = cache.insert(&key, &content) {
+                tracing::warn!("Failed to cache content of {}: {:#}", path, e);
+                return Ok(None);
+            }
+        }
+        Ok(Some(LocalCopy { key, temporary }))
+    }
+
+    /// Write `data` at `offset` into the buffer of `ino`, fetching the
+    /// current content first if there is no buffer yet.
+    fn write_at(&self, ino: u64, fh: u64, offset: u64, data: &[u8]) -> Result<(), i32> {
+        self.load_buffer(ino, fh)?;
+
+        let mut buffers = self.write_buffers.lock().unwrap();
+        let buffer = buffers.get_mut(&ino).expect("write buffer was just loaded");
+        buffer.data.write(offset, data);
+        buffer.dirty = true;
+        drop(buffers);
+
+        if let Some(handle) = self.open_handles.lock().unwrap().get_mut(&fh) {
+            handle.dirty = true;
+        }
+        Ok(())
+    }
+
+    /// Make sure `ino` has a write buffer, fetching the current content if
+    /// it has none yet. Fails with `EBADF` if `fh` is not open for writing.
+    fn load_buffer(&self, ino: u64, fh: u64) -> Result<(), i32> {
+        let path = self.get_path(ino).ok_or(ENOENT)?;
+
+        let handle_etag = match self.open_handles.lock().unwrap().get(&fh) {
+            Some(handle) if !handle.writable() => return Err(EBADF),
+            Some(handle) => Some(handle.etag.clone()),
+            None => None,
+        };
+
+        let has_buffer = self.write_buffers.lock().unwrap().contains_key(&ino);
+        if !has_buffer {
+            // Guard the upload with the etag seen when the file was opened
+            let etag = handle_etag
+                .unwrap_or_else(|| self.cached_entry(&path).and_then(|entry| entry.etag));
+            match self.runtime.block_on(self.webdav.read_file(&path)) {
+                Ok(content) => {
+                    self.write_buffers.lock().unwrap().insert(ino, WriteBuffer {
+                        data: content.into(),
+                        etag,
+                        dirty: false,
+                        mtime: None,
+                    });
+                }
+                Err(e) => {
+                    tracing::error!("Failed to fetch {} for writing: {}", path, e);
+                    return Err(errno_for(&e));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy a whole file with a server-side COPY. Returns `None` when the
+    /// request is not a plain whole-file copy into an empty file, or the
+    /// server refuses, so the caller falls back to copying locally.
+    fn server_copy(&self, ino_in: u64, ino_out: u64, offset_in: i64, offset_out: i64, len: u64) -> Option<u32> {
+        if offset_in != 0 || offset_out != 0 {
+            return None;
+        }
+        let src = self.get_path(ino_in)?;
+        let dst = self.get_path(ino_out)?;
+
+        // The server copy must include everything written to the source
+        self.flush_buffer(ino_in).ok()?;
+        let src_size = match self.write_buffers.lock().unwrap().get(&ino_in) {
+            Some(buffer) => buffer.data.len(),
+            None => self.cached_entry(&src)?.size,
+        };
+        if len < src_size || src_size > u32::MAX as u64 {
+            return None;
+        }
+        let dst_empty = match self.write_buffers.lock().unwrap().get(&ino_out) {
+            Some(buffer) => buffer.data.is_empty(),
+            None => self.cached_entry(&dst).is_some_and(|entry| entry.size == 0),
+        };
+        if !dst_empty {
+            return None;
+        }
+
+        let lock = self.held_lock(&dst);
+        if let Err(e) = self.runtime.block_on(self.webdav.copy_entry(&src, &dst, true, lock.as_ref())) {
+            tracing::debug!("Server-side copy of {} to {} failed, copying locally: {}", src, dst, e);
+            return None;
+        }
+
+        // The (empty) buffer is outdated now; later writes fetch the copy
+        self.write_buffers.lock().unwrap().remove(&ino_out);
+        for handle in self.open_handles.lock().unwrap().values_mut() {
+            if handle.ino == ino_out {
+                handle.etag = None;
+                handle.dirty = false;
+            }
+        }
+        self.touch_ctime(ino_out);
+        self.directory_changed(Self::parent_of(&dst));
+        tracing::info!("Copied {} to {} on the server", src, dst);
+        Some(src_size as u32)
+    }
+
+    /// Quota of the mount root, refetched at most every `QUOTA_TTL`. Failed
+    /// lookups are cached too, so an unsupported server is asked once.
+    fn cached_quota(&self) -> Quota {
+        if let Some((fetched, quota)) = *self.quota.lock().unwrap() {
+            if fetched.elapsed() < QUOTA_TTL {
+                return quota;
+            }
+        }
+
+        let quota = match self.runtime.block_on(self.webdav.quota("/")) {
+            Ok(quota) => quota,
+            Err(e) => {
+                tracing::debug!("Failed to fetch quota: {}", e);
+                Quota::default()
+            }
+        };
+        *self.quota.lock().unwrap() = Some((Instant::now(), quota));
+        quota
+    }
+
+    /// Current value of xattr `name` on `ino`: WebDAV properties come from
+    /// the cached listing, anything else from the local map.
+    fn xattr_value(&self, ino: u64, path: &str, name: &str) -> Option<Vec<u8>> {
+        if name == XATTR_TTL {
+            let ttl = self.ttl_overrides.lock().unwrap().get(&ino).copied()?;
+            return Some(ttl.as_secs().to_string().into_bytes());
+        }
+        if name == XATTR_PINNED {
+            return self.dir_cache.is_pinned(path).then(|| b"1".to_vec());
+        }
+        let value = match name {
+            XATTR_ETAG => self
+                .cached_entry(path)
+                .and_then(|entry| entry.etag)
+                .or_else(|| self.dir_cache.etag(path)),
+            XATTR_URL => self.webdav.url_for(path).ok().map(String::from),
+            XATTR_MODIFIED => self
+                .cached_entry(path)?
+                .modified
+                .map(|modified| modified.to_rfc3339()),
+            XATTR_CONTENT_TYPE => self.cached_entry(path)?.content_type,
+            _ => None,
+        };
+        if value.is_some() {
+            return value.map(String::into_bytes);
+        }
+        match dav_property(name) {
+            Some(prop) => self
+                .cached_entry(path)?
+                .properties
+                .get(prop)
+                .map(|value| value.clone().into_bytes()),
+            None => self.local_xattrs.lock().unwrap().get(&ino)?.get(name).cloned(),
+        }
+    }
+
+    /// Set (or with `None`, remove) the cache TTL override of directory `ino`.
+    fn set_ttl_override(&self, ino: u64, path: &str, ttl: Option<Duration>) -> Result<(), i32> {
+        let is_dir = ino == ROOT_INO || self.cached_entry(path).is_some_and(|entry| entry.is_dir);
+        if !is_dir {
+            return Err(ENOTDIR);
+        }
+        self.dir_cache.store_ttl_override(path, ttl).map_err(|e| {
+            tracing::error!("Failed to store TTL override for {}: {}", path, e);
+            EIO
+        })?;
+        let mut overrides = self.ttl_overrides.lock().unwrap();
+        match ttl {
+            Some(ttl) => overrides.insert(ino, ttl),
+            None => overrides.remove(&ino),
+        };
+        Ok(())
+    }
+
+    /// Pin or unpin `path`, keeping its listings and content cached.
+    fn set_pinned(&self, path: &str, pinned: bool) -> Result<(), i32> {
+        let result = match pinned {
+            true => self.dir_cache.pin(path),
+            false => self.dir_cache.unpin(path).map(|_| ()),
+        };
+        result.map_err(|e| {
+            tracing::error!("Failed to update pin of {}: {}", path, e);
+            EIO
+        })
+    }
+
+    /// Set (or with `None`, remove) WebDAV property `prop` of `path` on the
+    /// server and in the cached listing.
+    fn update_dav_property(&self, path: &str, prop: &str, value: Option<&str>) -> Result<(), i32> {
+        crate::webdav::property_tags(prop).map_err(|_| EINVAL)?;
+        let (set, remove) = match value {
+            Some(value) => (vec![(prop.to_string(), value.to_string())], Vec::new()),
+            None => (Vec::new(), vec![prop.to_string()]),
+        };
+        self.runtime
+            .block_on(self.webdav.proppatch(path, &set, &remove))
+            .map_err(|e| {
+                tracing::warn!("Failed to update {} of {}: {}", prop, path, e);
+                errno_for(&e)
+            })?;
+
+        let name = path.rsplit('/').next().unwrap_or_default();
+        self.dir_cache.update(Self::parent_of(path), |entries| {
+            if let Some(entry) = entries.iter_mut().find(|e| e.name == name) {
+                match value {
+                    Some(value) => entry.properties.insert(prop.to_string(), value.to_string()),
+                    None => entry.properties.remove(prop),
+                };
+            }
+        });
+        Ok(())
+    }
+
+    /// Re-apply an mtime set on a dirty buffer once its content has been
+    /// uploaded.
+    fn apply_pending_mtime(&self, ino: u64) {
+        let mtime = match self.write_buffers.lock().unwrap().get_mut(&ino) {
+            Some(buffer) if !buffer.dirty => buffer.mtime.take(),
+            _ => None,
+        };
+        let (Some(mtime), Some(path)) = (mtime, self.get_path(ino)) else {
+            return;
+        };
+        match self.runtime.block_on(self.webdav.set_modified(&path, mtime.into())) {
+            Ok(()) => self.directory_changed(Self::parent_of(&path)),
+            Err(e) => tracing::warn!("Failed to set mtime of {}: {}", path, e),
+        }
+    }
+
+    /// Record a metadata change on `ino` (chmod, chown, truncate, entry
+    /// created/removed in a directory) by bumping its local ctime.
+    fn touch_ctime(&self, ino: u64) {
+        let now = SystemTime::now();
+        self.ctime_map.lock().unwrap().insert(ino, now);
+        self.dir_cache.store_ctime(ino, now);
+    }
+
+    /// Append a successful change by the process behind `req` to the audit
+    /// log, if there is one.
+    fn audit(&self, req: &Request, op: &'static str, path: &str, new_path: Option<&str>, size: Option<u64>) {
+        if let Some(audit) = &self.audit {
+            audit.record(AuditEntry {
+                ts: chrono::Utc::now(),
+                op,
+                path: path.to_string(),
+                new_path: new_path.map(str::to_string),
+                size,
+                uid: req.uid(),
+                pid: req.pid(),
+            });
+        }
+    }
+
+    /// Trash of `uid` if deleted files go there.
+    fn trash_for(&self, uid: u32) -> Option<Trash> {
+        self.mount_config
+            .as_ref()
+            .is_some_and(|config| config.use_trash)
+            .then(|| Trash::for_uid(uid))
+    }
+
+    /// Whether several handles may have a file open for writing at once.
+    fn allows_parallel_write(&self) -> bool {
+        self.mount_config.as_ref().is_some_and(|config| config.allow_parallel_write)
+    }
+
+    /// Claim `ino` for the one handle that may write it. Returns whether
+    /// that worked.
+    fn claim_for_write(&self, ino: u64) -> bool {
+        self.allows_parallel_write() || self.open_for_write.lock().unwrap().insert(ino)
+    }
+
+    fn shows_versions(&self) -> bool {
+        self.mount_config.as_ref().is_some_and(|config| config.show_versions) && !self.webdav.encrypts()
+    }
+
+    /// List the earlier versions of the files in `entries` of `dir_path`.
+    fn load_versions(&self, dir_path: &str, entries: &[DavEntry]) {
+        let files: Vec<(&str, &str)> = entries
+            .iter()
+            .filter(|entry| !entry.is_dir)
+            .filter_map(|entry| Some((entry.name.as_str(), entry.properties.get("oc:fileid")?.as_str())))
+            .collect();
+        let webdav = &self.webdav;
+        let versions: Vec<VersionFile> = self
+            .runtime
+            .block_on(
+                futures_util::stream::iter(files)
+                    .map(|(name, file_id)| async move {
+                        let versions = match webdav.list_versions(file_id).await {
+                            Ok(versions) => versions,
+                            Err(e) => {
+                                tracing::debug!("Failed to list versions of {}: {}", name, e);
+                                Vec::new()
+                            }
+                        };
+                        versions.into_iter().map(move |version| VersionFile {
+                            file_id: file_id.to_string(),
+                            version: version.name.clone(),
+                            entry: DavEntry {
+                                name: format!("{}.v{}", name, version.name),
+                                ..version
+                            },
+                        })
+                    })
+                    .buffer_unordered(VERSION_LIST_CONCURRENCY)
+                    .collect::<Vec<_>>(),
+            )
+            .into_iter()
+            .flatten()
+            .collect();
+
+        for version in &versions {
+            self.dir_cache.remove_negative(&Self::child_path(dir_path, &version.entry.name));
+        }
+        self.versions.lock().unwrap().insert(dir_path.to_string(), versions);
+    }
+
+    fn version_file(&self, path: &str) -> Option<VersionFile> {
+        let name = path.rsplit('/').next()?;
+        self.versions
+            .lock()
+            .unwrap()
+            .get(Self::parent_of(path))?
+            .iter()
+            .find(|version| version.entry.name == name)
+            .cloned()
+    }
+
+    fn version_attr(&self, ino: u64, version: &VersionFile) -> FileAttr {
+        FileAttr {
+            perm: 0o444,
+            ..self.entry_attr(ino, &version.entry)
+        }
+    }
+
+    fn ctime(&self, ino: u64, mtime: SystemTime) -> SystemTime {
+        let ctime_map = self.ctime_map.lock().unwrap();
+        ctime_map.get(&ino).copied().unwrap_or(mtime)
+    }
+
+    fn root_attr(&self) -> FileAttr {
+        FileAttr {
+            ino: ROOT_INO,
+            size: 0,
+            blocks: 0,
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: self.ctime(ROOT_INO, UNIX_EPOCH),
+            crtime: UNIX_EPOCH,
+            kind: FileType::Directory,
+            perm: 0o755,
+            nlink: 2,
+            uid: platform::uid(),
+            gid: platform::gid(),
+            rdev: 0,
+            blksize: 512,
+            flags: 0,
+        }
+    }
+
+    fn entry_attr(&self, ino: u64, entry: &DavEntry) -> FileAttr {
+        let mtime = entry.modified.map(SystemTime::from).unwrap_or(UNIX_EPOCH);
+        if let Some(target) = entry.symlink_target() {
+            self.symlink_attr(ino, target.len() as u64, mtime)
+        } else if entry.is_dir {
+            self.dir_attr(ino, mtime)
+        } else {
+            self.file_attr(ino, entry.size, mtime)
+        }
+    }
+
+    fn dir_attr(&self, ino: u64, mtime: SystemTime) -> FileAttr {
+        FileAttr {
+            ino,
+            size: 0,
+            blocks: 0,
+            atime: mtime,
+            mtime,
+            ctime: self.ctime(ino, mtime),
+            crtime: UNIX_EPOCH,
+            kind: FileType::Directory,
+            perm: 0o755,
+            nlink: 2,
+            uid: platform::uid(),
+            gid: platform::gid(),
+            rdev: 0,
+            blksize: 512,
+            flags: 0,
+        }
+    }
+
+    fn symlink_attr(&self, ino: u64, target_len: u64, mtime: SystemTime) -> FileAttr {
+        FileAttr {
+            kind: FileType::Symlink,
+            perm: 0o777,
+            nlink: 1,
+            ..self.file_attr(ino, target_len, mtime)
+        }
+    }
+
+    /// Content of `.davfs-status`, rendered on every read.
+    fn status_snapshot(&self) -> Vec<u8> {
+        let metrics = self.webdav.metrics();
+        let mut cache = serde_json::to_value(self.dir_cache.stats()).unwrap_or_default();
+        if let Some(cache) = cache.as_object_mut() {
+            cache.insert("hits".to_string(), metrics.cache_hits.load(Ordering::Relaxed).into());
+            cache.insert("misses".to_string(), metrics.cache_misses.load(Ordering::Relaxed).into());
+        }
+
+        let status = serde_json::json!({
+            "mount": self.mount_config,
+            "connection": self.sync.status(),
+            "cache": cache,
+            "network": self.webdav.network_stats(),
+            "pending_journal_ops": self.journal.len(),
+            "root_etag": self.dir_cache.etag("/"),
+        });
+        let mut data = serde_json::to_vec_pretty(&status).unwrap_or_default();
+        data.push(b'\n');
+        data
+    }
+
+    fn status_attr(&self) -> FileAttr {
+        let size = self.status_snapshot().len() as u64;
+        FileAttr {
+            perm: 0o444,
+            ..self.file_attr(STATUS_INO, size, SystemTime::now())
+        }
+    }
+
+    fn file_attr(&self, ino: u64, size: u64, mtime: SystemTime) -> FileAttr {
+        FileAttr {
+            ino,
+            size,
+            blocks: (size + 511) / 512,
+            atime: mtime,
+            mtime,
+            ctime: self.ctime(ino, mtime),
+            crtime: UNIX_EPOCH,
+            kind: FileType::RegularFile,
+            perm: 0o644,
+            nlink: self.hardlinks.lock().unwrap().get(&ino).map_or(1, |links| links.len() as u32),
+            uid: platform::uid(),
+            gid: platform::gid(),
+            rdev: 0,
+            blksize: 512,
+            flags: 0,
+        }
+    }
+}
+
+impl Filesystem for DavFS {
+    fn init(&mut self, _req: &Request, _config: &mut fuser::KernelConfig) -> Result<(), libc::c_int> {
+        // Overrides are stored by path. Resolved here rather than in new()
+        // so new inodes use the seed set with with_inode_seed
+        match self.dir_cache.load_ttl_overrides() {
+            Ok(overrides) => {
+                for (path, ttl) in overrides {
+                    let ino = if path == "/" { ROOT_INO } else { self.get_or_create_inode(&path) };
+                    self.ttl_overrides.lock().unwrap().insert(ino, ttl);
+                }
+            }
+            Err(e) => tracing::warn!("Failed to load TTL overrides: {}", e),
+        }
+        Ok(())
+    }
+
+    fn destroy(&mut self) {
+        // Upload whatever is still buffered (or journal it if offline)
+        // before the mount goes away
+        let dirty: Vec<u64> = self
+            .write_buffers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, buffer)| buffer.dirty)
+            .map(|(ino, _)| *ino)
+            .collect();
+        for ino in dirty {
+            if let Err(errno) = self.flush_buffer(ino) {
+                tracing::error!("Failed to flush inode {} on unmount: errno {}", ino, errno);
+            }
+        }
+
+        let locks: Vec<(String, LockToken)> = self
+            .open_handles
+            .lock()
+            .unwrap()
+            .drain()
+            .flat_map(|(_, handle)| {
+                let posix = handle.posix_lock.and_then(|lock| lock.token);
+                [handle.lock, posix]
+                    .into_iter()
+                    .flatten()
+                    .map(move |token| (handle.path.clone(), token))
+            })
+            .collect();
+        for (path, token) in locks {
+            if let Err(e) = self.runtime.block_on(self.webdav.unlock(&path, &token)) {
+                tracing::warn!("Failed to unlock {}: {}", path, e);
+            }
+        }
+
+        if let Some(audit) = self.audit.take() {
+            audit.close();
+        }
+    }
+
+    fn lookup(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        tracing::debug!("lookup: parent={}, name={:?}", parent, name);
+
+        if parent == ROOT_INO && name == STATUS_FILE_NAME {
+            reply.entry(&TTL, &self.status_attr(), 0);
+            return;
+        }
+        if parent == ROOT_INO && name == TRASH_DIR_NAME {
+            if let Some(trash) = self.trash_for(req.uid()) {
+                let ino = self.get_or_create_inode(&trash.files_dir());
+                self.remember_lookup(ino);
+                reply.entry(&TTL, &self.dir_attr(ino, SystemTime::now()), 0);
+                return;
+            }
+        }
+
+        let parent_path = match self.get_path(parent) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        
+        let name_str = match name.to_str() {
+            Some(n) => n,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        
+        // Build the full path
+        let full_path = if parent_path == "/" {
+            format!("/{}", name_str)
+        } else {
+            format!("{}/{}", parent_path.trim_end_matches('/'), name_str)
+        };
+        
+        if self.dir_cache.is_negative(&full_path) {
+            reply.error(ENOENT);
+            return;
+        }
+
+        // Try stale cache first for instant response, then list the parent
+        // directory to find this entry
+        let entries = if let Some(cached) = self.dir_cache.get_stale(&parent_path) {
+            cached
+        } else {
+            match self.fetch_listing(&parent_path) {
+                Ok(DirListing { entries, etag, .. }) => {
+                    self.dir_cache.insert(parent_path.clone(), entries.clone(), etag);
+                    entries
+                }
+                Err(_) => {
+                    reply.error(ENOENT);
+                    return;
+                }
+            }
+        };
+        
+        for entry in entries {
+            if entry.name == name_str {
+                let ino = self.get_or_create_inode(&full_path);
+                self.remember_lookup(ino);
+                let attr = self.entry_attr(ino, &entry);
+                reply.entry(&TTL, &attr, 0);
+                return;
+            }
+        }
+        if let Some(version) = self.version_file(&full_path) {
+            let ino = self.get_or_create_inode(&full_path);
+            self.remember_lookup(ino);
+            reply.entry(&TTL, &self.version_attr(ino, &version), 0);
+            return;
+        }
+        self.dir_cache.insert_negative(&full_path);
+        reply.error(ENOENT);
+    }
+
+    fn forget(&mut self, _req: &Request, ino: u64, nlookup: u64) {
+        tracing::debug!("forget: ino={}, nlookup={}", ino, nlookup);
+        self.release_inode(ino, nlookup);
+    }
+
+    fn getattr(&mut self, req: &Request, ino: u64, reply: ReplyAttr) {
+        tracing::debug!("getattr: ino={}", ino);
+
+        if ino == ROOT_INO {
+            reply.attr(&TTL, &self.root_attr());
+            return;
+        }
+        if ino == STATUS_INO {
+            reply.attr(&TTL, &self.status_attr());
+            return;
+        }
+        
+        // Files with pending writes report their local size
+        let buffered_size = self.write_buffers.lock().unwrap()
+            .get(&ino)
+            .map(|b| b.data.len());
+        if let Some(size) = buffered_size {
+            reply.attr(&TTL, &self.file_attr(ino, size, SystemTime::now()));
+            return;
+        }
+        
+        let path = match self.get_path(ino) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        
+        if self.dir_cache.is_negative(&path) {
+            reply.error(ENOENT);
+            return;
+        }
+        // Shown as .trash even before anything was deleted
+        if self.trash_for(req.uid()).is_some_and(|trash| path == trash.files_dir()) {
+            reply.attr(&TTL, &self.dir_attr(ino, SystemTime::now()));
+            return;
+        }
+
+        // Get parent directory path
+        let parent_path = if let Some(idx) = path.rfind('/') {
+            if idx == 0 {
                 "/"
             } else {
                 &path[..idx]
             }
         } else {
-            "/"
+            "/"
+        };
+        
+        let name = path.rsplit('/').next().unwrap_or("");
+        
+        // Try stale cache first for instant response, then list the parent
+        // to find this entry
+        let entries = if let Some(cached) = self.dir_cache.get_stale(parent_path) {
+            cached
+        } else {
+            match self.fetch_listing(parent_path) {
+                Ok(DirListing { entries, etag, .. }) => {
+                    self.dir_cache.insert(parent_path.to_string(), entries.clone(), etag);
+                    entries
+                }
+                Err(_) => {
+                    // Fallback to generic file attributes
+                    let attr = self.file_attr(ino, 1024, UNIX_EPOCH);
+                    reply.attr(&TTL, &attr);
+                    return;
+                }
+            }
+        };
+        
+        for entry in entries {
+            if entry.name == name {
+                let attr = self.entry_attr(ino, &entry);
+                reply.attr(&TTL, &attr);
+                return;
+            }
+        }
+        match self.version_file(&path) {
+            Some(version) => reply.attr(&TTL, &self.version_attr(ino, &version)),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn setattr(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        tracing::debug!("setattr: ino={}, size={:?}, mtime={:?}", ino, size, mtime);
+
+        // mode/uid/gid have no WebDAV equivalent and are ignored, like davfs2
+        if ino == ROOT_INO {
+            reply.attr(&TTL, &self.root_attr());
+            return;
+        }
+        let path = match self.get_path(ino) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        if let Some(size) = size {
+            let open_buffer = self.write_buffers.lock().unwrap().get_mut(&ino).map(|buffer| {
+                buffer.data.set_len(size);
+                buffer.dirty = true;
+            });
+
+            // Without an open buffer, rewrite the file on the server right away
+            if open_buffer.is_none() {
+                let etag = self.cached_entry(&path).and_then(|entry| entry.etag);
+                let mut data = if size == 0 {
+                    SparseBuffer::default()
+                } else {
+                    match self.runtime.block_on(self.webdav.read_file(&path)) {
+                        Ok(content) => SparseBuffer::from(content),
+                        Err(e) => {
+                            tracing::error!("Failed to fetch {} for truncate: {}", path, e);
+                            reply.error(errno_for(&e));
+                            return;
+                        }
+                    }
+                };
+                data.set_len(size);
+
+                self.write_buffers.lock().unwrap().insert(ino, WriteBuffer {
+                    data,
+                    etag,
+                    dirty: true,
+                    mtime: None,
+                });
+                let result = self.flush_buffer(ino);
+                self.write_buffers.lock().unwrap().remove(&ino);
+                if let Err(errno) = result {
+                    reply.error(errno);
+                    return;
+                }
+            }
+            self.touch_ctime(ino);
+        }
+
+        if let Some(mtime) = mtime {
+            let mtime = match mtime {
+                TimeOrNow::SpecificTime(time) => time,
+                TimeOrNow::Now => SystemTime::now(),
+            };
+            if let Some(buffer) = self.write_buffers.lock().unwrap().get_mut(&ino) {
+                if buffer.dirty {
+                    buffer.mtime = Some(mtime);
+                }
+            }
+            // Many servers treat getlastmodified as protected; not fatal
+            match self.runtime.block_on(self.webdav.set_modified(&path, mtime.into())) {
+                Ok(()) => self.directory_changed(Self::parent_of(&path)),
+                Err(e) => tracing::warn!("Failed to set mtime of {}: {}", path, e),
+            }
+        }
+
+        if size.is_some() || mtime.is_some() {
+            self.audit(req, "setattr", &path, None, size);
+        }
+        self.getattr(req, ino, reply);
+    }
+
+    fn opendir(&mut self, _req: &Request, ino: u64, _flags: i32, reply: ReplyOpen) {
+        tracing::debug!("opendir: ino={}", ino);
+
+        if self.shows_versions() {
+            if let Some(dir_path) = self.get_path(ino) {
+                let entries = match self.dir_cache.get_stale(&dir_path) {
+                    Some(entries) => Some(entries),
+                    None => self.fetch_listing(&dir_path).ok().map(|DirListing { entries, etag, .. }| {
+                        self.dir_cache.insert(dir_path.clone(), entries.clone(), etag);
+                        entries
+                    }),
+                };
+                if let Some(entries) = entries {
+                    self.load_versions(&dir_path, &entries);
+                }
+            }
+        }
+        reply.opened(0, 0);
+    }
+
+    fn readdir(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        tracing::debug!("readdir: ino={}, offset={}", ino, offset);
+
+        let dir_path = match self.get_path(ino) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        let mut entries = vec![
+            (ino, FileType::Directory, "."),
+            (ino, FileType::Directory, ".."),
+        ];
+        let trash = self.trash_for(req.uid());
+        if ino == ROOT_INO {
+            entries.push((STATUS_INO, FileType::RegularFile, STATUS_FILE_NAME));
+            if let Some(trash) = &trash {
+                let trash_ino = self.get_or_create_inode(&trash.files_dir());
+                entries.push((trash_ino, FileType::Directory, TRASH_DIR_NAME));
+            }
+        }
+
+        // Convert filesystem path to WebDAV path (remove leading /)
+        let dav_path = if dir_path == "/" {
+            ""
+        } else {
+            &dir_path[1..]
+        };
+        
+        // Serve cached entries for instant response; expired listings are
+        // revalidated in the background, misses are fetched now
+        let ttl_override = self.ttl_overrides.lock().unwrap().get(&ino).copied();
+        let dav_entries = match self.dir_cache.get(&dir_path, ttl_override) {
+            CacheResult::Fresh(cached) => cached,
+            CacheResult::Stale { entries: cached, etag } => {
+                tracing::debug!("Using stale entries for path {}, revalidating", dir_path);
+                if offset == 0 {
+                    self.revalidate_in_background(&dir_path, etag);
+                }
+                cached
+            }
+            CacheResult::Miss => match self.fetch_listing(&dir_path) {
+                Ok(DirListing { entries, etag, .. }) => {
+                    tracing::info!("Listed {} entries from WebDAV at path {}", entries.len(), dav_path);
+                    self.dir_cache.insert(dir_path.clone(), entries.clone(), etag);
+                    
+                    // Trigger background prefetch of subdirectories
+                    self.prefetch.schedule(&dir_path, &entries);
+                    
+                    entries
+                }
+                Err(e) => {
+                    tracing::error!("Failed to list directory: {}", e);
+                    
+                    // Still return . and ..
+                    for (i, entry) in entries.iter().enumerate().skip(offset as usize) {
+                        if reply.add(entry.0, (i + 1) as i64, entry.1, entry.2) {
+                            break;
+                        }
+                    }
+                    reply.ok();
+                    return;
+                }
+            },
+        };
+        
+        let mut all_entries = entries;
+        
+        for entry in dav_entries.iter() {
+            // The virtual entries hide remote files of the same name
+            if ino == ROOT_INO
+                && (entry.name == STATUS_FILE_NAME || (trash.is_some() && entry.name == TRASH_DIR_NAME))
+            {
+                continue;
+            }
+            let full_path = if dir_path == "/" {
+                format!("/{}", entry.name)
+            } else {
+                format!("{}/{}", dir_path, entry.name)
+            };
+            
+            let ino = self.get_or_create_inode(&full_path);
+            let kind = if entry.symlink_target().is_some() {
+                FileType::Symlink
+            } else if entry.is_dir {
+                FileType::Directory
+            } else {
+                FileType::RegularFile
+            };
+            all_entries.push((ino, kind, entry.name.as_str()));
+        }
+
+        let versions = self.versions.lock().unwrap().get(&dir_path).cloned().unwrap_or_default();
+        for version in &versions {
+            let ino = self.get_or_create_inode(&Self::child_path(&dir_path, &version.entry.name));
+            all_entries.push((ino, FileType::RegularFile, version.entry.name.as_str()));
+        }
+
+        for (i, entry) in all_entries.iter().enumerate().skip(offset as usize) {
+            if reply.add(entry.0, (i + 1) as i64, entry.1, entry.2) {
+                break;
+            }
+        }
+
+        reply.ok();
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock: Option<u64>,
+        reply: ReplyData,
+    ) {
+        tracing::debug!("read: ino={}, fh={}, offset={}, size={}", ino, fh, offset, size);
+
+        if ino == STATUS_INO {
+            let data = self.status_snapshot();
+            let start = (offset as usize).min(data.len());
+            let end = (start + size as usize).min(data.len());
+            reply.data(&data[start..end]);
+            return;
+        }
+        if let Some(version) = self.get_path(ino).and_then(|path| self.version_file(&path)) {
+            let read = self.webdav.read_version_range(&version.file_id, &version.version, offset as u64, size as u64);
+            match self.runtime.block_on(read) {
+                Ok(data) => reply.data(&data),
+                Err(e) => {
+                    tracing::error!("Failed to read version {}: {}", version.entry.name, e);
+                    reply.error(errno_for(&e));
+                }
+            }
+            return;
+        }
+
+        match self.read_at(ino, fh, offset as u64, size as u64) {
+            Ok(data) => reply.data(&data),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn open(&mut self, _req: &Request, ino: u64, flags: i32, reply: ReplyOpen) {
+        tracing::debug!("open: ino={}, flags={:#x}", ino, flags);
+
+        if ino == STATUS_INO {
+            if flags & libc::O_ACCMODE != libc::O_RDONLY {
+                reply.error(EACCES);
+                return;
+            }
+            // Direct I/O so reads are not cut off at the size from getattr
+            reply.opened(0, fuser::consts::FOPEN_DIRECT_IO);
+            return;
+        }
+
+        let path = match self.get_path(ino) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        if self.filter.excludes(&path, false) {
+            tracing::debug!("open: {} is excluded from sync", path);
+            reply.error(EACCES);
+            return;
+        }
+        // Versions never change
+        if self.version_file(&path).is_some() {
+            if flags & libc::O_ACCMODE != libc::O_RDONLY {
+                reply.error(EACCES);
+                return;
+            }
+            reply.opened(0, fuser::consts::FOPEN_KEEP_CACHE);
+            return;
+        }
+
+        let etag = self.cached_entry(&path).and_then(|entry| entry.etag);
+        let writable = flags & libc::O_ACCMODE != libc::O_RDONLY;
+
+        // Each writer uploads the whole file on close, so a second one
+        // would silently replace what the first wrote
+        if writable && !self.claim_for_write(ino) {
+            tracing::debug!("open: {} is already open for writing", path);
+            reply.error(libc::EWOULDBLOCK);
+            return;
+        }
+        let lock = if writable {
+            match self.lock_for_writer(&path) {
+                Ok(lock) => lock,
+                Err(errno) => {
+                    self.open_for_write.lock().unwrap().remove(&ino);
+                    reply.error(errno);
+                    return;
+                }
+            }
+        } else {
+            None
         };
-        
-        let name = path.rsplit('/').next().unwrap_or("");
-        
-        // List parent to find this entry
-        let dav_path = if parent_path == "/" { "" } else { &parent_path[1..] };
-        
-        // Try stale cache first for instant response, then fetch if needed
-        let entries = if let Some(cached) = self.dir_cache.get_stale(parent_path) {
-            cached
+
+        // Truncating opens start from an empty buffer; other writable opens
+        // fetch the current content on first write.
+        if writable && flags & libc::O_TRUNC != 0 {
+            self.write_buffers.lock().unwrap().insert(ino, WriteBuffer {
+                data: SparseBuffer::default(),
+                etag: etag.clone(),
+                dirty: true,
+                mtime: None,
+            });
+        }
+
+        // Pages cached by the kernel from an earlier open, e.g. of a
+        // memory map, stay valid while the etag is unchanged
+        let keep_cache = !writable
+            && etag.as_ref().is_some_and(|etag| {
+                let previous = self.opened_etags.lock().unwrap().insert(ino, etag.clone());
+                previous.as_ref() == Some(etag)
+            });
+        let reading = if writable { None } else { Some((path.clone(), etag.clone())) };
+
+        let fh = self.allocate_handle(ino, path, flags, etag, lock);
+        if let Some((path, etag)) = reading {
+            let copy = match self.local_copy(&path, etag.as_deref(), fh) {
+                Ok(copy) => copy,
+                Err(errno) => {
+                    self.open_handles.lock().unwrap().remove(&fh);
+                    reply.error(errno);
+                    return;
+                }
+            };
+            if let Some(handle) = self.open_handles.lock().unwrap().get_mut(&fh) {
+                handle.local_copy = copy;
+            }
+        }
+        reply.opened(fh, if keep_cache { fuser::consts::FOPEN_KEEP_CACHE } else { 0 });
+    }
+
+    fn write(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        tracing::debug!("write: ino={}, offset={}, len={}", ino, offset, data.len());
+
+        match self.write_at(ino, fh, offset as u64, data) {
+            Ok(()) => {
+                if let Some(path) = self.get_path(ino) {
+                    self.audit(req, "write", &path, None, Some(data.len() as u64));
+                }
+                reply.written(data.len() as u32)
+            }
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn fallocate(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        length: i64,
+        mode: i32,
+        reply: ReplyEmpty,
+    ) {
+        tracing::debug!("fallocate: ino={}, offset={}, length={}, mode={:#x}", ino, offset, length, mode);
+
+        // Punching holes, zeroing or shifting ranges has no WebDAV
+        // equivalent; plain preallocation only sizes the write buffer
+        if mode & !platform::FALLOC_FL_KEEP_SIZE != 0 {
+            reply.error(EOPNOTSUPP);
+            return;
+        }
+        if offset < 0 || length <= 0 {
+            reply.error(EINVAL);
+            return;
+        }
+        if let Err(errno) = self.load_buffer(ino, fh) {
+            reply.error(errno);
+            return;
+        }
+
+        let end = (offset + length) as u64;
+        let grown = {
+            let mut buffers = self.write_buffers.lock().unwrap();
+            let buffer = buffers.get_mut(&ino).expect("write buffer was just loaded");
+            let grow = mode & platform::FALLOC_FL_KEEP_SIZE == 0 && buffer.data.len() < end;
+            if grow {
+                buffer.data.set_len(end);
+                buffer.dirty = true;
+            }
+            grow
+        };
+        if grown {
+            if let Some(handle) = self.open_handles.lock().unwrap().get_mut(&fh) {
+                handle.dirty = true;
+            }
+            self.touch_ctime(ino);
+        }
+        reply.ok();
+    }
+
+    fn copy_file_range(
+        &mut self,
+        _req: &Request,
+        ino_in: u64,
+        fh_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        _flags: u32,
+        reply: ReplyWrite,
+    ) {
+        tracing::debug!(
+            "copy_file_range: ino_in={}, offset_in={}, ino_out={}, offset_out={}, len={}",
+            ino_in, offset_in, ino_out, offset_out, len
+        );
+
+        if let Some(copied) = self.server_copy(ino_in, ino_out, offset_in, offset_out, len) {
+            reply.written(copied);
+            return;
+        }
+
+        // Partial copies go through the buffers; the kernel calls again for
+        // the rest
+        let size = len.min(COPY_CHUNK_SIZE);
+        let result = self
+            .read_at(ino_in, fh_in, offset_in as u64, size)
+            .and_then(|data| {
+                self.write_at(ino_out, fh_out, offset_out as u64, &data)?;
+                Ok(data.len() as u32)
+            });
+        match result {
+            Ok(copied) => reply.written(copied),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn flush(&mut self, _req: &Request, ino: u64, _fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
+        tracing::debug!("flush: ino={}", ino);
+
+        match self.flush_buffer(ino) {
+            Ok(()) => reply.ok(),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn fsync(&mut self, _req: &Request, ino: u64, fh: u64, datasync: bool, reply: ReplyEmpty) {
+        tracing::debug!("fsync: ino={}, fh={}, datasync={}", ino, fh, datasync);
+
+        // While offline the buffer goes to the journal, which is itself
+        // synced to disk, so that also counts as safely stored
+        if let Err(errno) = self.flush_buffer(ino) {
+            reply.error(if errno == ENOSPC { ENOSPC } else { EIO });
+            return;
+        }
+        // fdatasync only cares about the content
+        if !datasync {
+            self.apply_pending_mtime(ino);
+        }
+        reply.ok();
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        tracing::debug!("release: ino={}, fh={}", ino, fh);
+
+        if ino == STATUS_INO {
+            reply.ok();
+            return;
+        }
+
+        let wrote = self.open_handles.lock().unwrap().get(&fh).is_some_and(|h| h.dirty);
+
+        // Upload while the handle (and its lock) is still registered
+        let result = self.flush_buffer(ino);
+        if result.is_ok() {
+            self.apply_pending_mtime(ino);
+        }
+
+        let handle = {
+            let mut handles = self.open_handles.lock().unwrap();
+            let handle = handles.remove(&fh);
+            // The buffer is shared with any other handle still open on the file
+            if !handles.values().any(|other| other.ino == ino) {
+                self.write_buffers.lock().unwrap().remove(&ino);
+            }
+            handle
+        };
+        if let Some(handle) = handle {
+            if handle.flags & libc::O_ACCMODE != libc::O_RDONLY {
+                self.open_for_write.lock().unwrap().remove(&ino);
+            }
+            if let Some(lock) = handle.posix_lock {
+                self.release_posix_lock(&handle.path, lock);
+            }
+            let temporary = handle.local_copy.filter(|copy| copy.temporary);
+            if let Some((copy, cache)) = temporary.zip(self.content_cache.as_ref()) {
+                cache.remove(&copy.key);
+            }
+            // Parallel writers share one lock, which the last of them releases
+            let shared = handle.lock.as_ref().is_some_and(|token| {
+                self.open_handles.lock().unwrap().values().any(|other| other.lock.as_ref() == Some(token))
+            });
+            if let Some(token) = handle.lock.filter(|_| !shared) {
+                if let Err(e) = self.runtime.block_on(self.webdav.unlock(&handle.path, &token)) {
+                    tracing::warn!("Failed to unlock {}: {}", handle.path, e);
+                }
+            }
+        }
+        // Only report upload failures to handles that wrote the data
+        match result {
+            Err(errno) if wrote => reply.error(errno),
+            _ => reply.ok(),
+        }
+    }
+
+    fn create(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        flags: i32,
+        reply: ReplyCreate,
+    ) {
+        tracing::debug!("create: parent={}, name={:?}", parent, name);
+
+        let (path, parent_path) = match self.resolve_child(parent, name) {
+            Ok(p) => p,
+            Err(errno) => {
+                reply.error(errno);
+                return;
+            }
+        };
+
+        // The file may exist and be open for writing already, e.g. with
+        // O_CREAT but without O_EXCL, or re-created while still open
+        let writable = flags & libc::O_ACCMODE != libc::O_RDONLY;
+        let claimed = self.path_to_inode.lock().unwrap().get(&path).copied().filter(|_| writable);
+        if let Some(ino) = claimed {
+            if !self.claim_for_write(ino) {
+                tracing::debug!("create: {} is already open for writing", path);
+                reply.error(libc::EWOULDBLOCK);
+                return;
+            }
+        }
+        let release_claim = |fs: &Self| {
+            if let Some(ino) = claimed {
+                fs.open_for_write.lock().unwrap().remove(&ino);
+            }
+        };
+
+        let (etag, online) = match self.runtime.block_on(self.webdav.create_file(&path)) {
+            Ok(etag) => {
+                self.directory_changed(&parent_path);
+                (etag, true)
+            }
+            Err(e) if is_network_error(&e) => {
+                let entry = DavEntry {
+                    name: name.to_string_lossy().into_owned(),
+                    is_dir: false,
+                    size: 0,
+                    modified: Some(chrono::Utc::now()),
+                    etag: None,
+                    properties: BTreeMap::new(),
+                    permissions: None,
+                    content_type: Some(guess_content_type(&path)),
+                };
+                let op = JournalOp::Create { path: path.clone() };
+                if let Err(errno) = self.queue_offline(op, &parent_path, |entries| entries.push(entry)) {
+                    release_claim(self);
+                    reply.error(errno);
+                    return;
+                }
+                (None, false)
+            }
+            Err(e) => {
+                tracing::error!("Failed to create {}: {}", path, e);
+                release_claim(self);
+                reply.error(errno_for(&e));
+                return;
+            }
+        };
+
+        self.audit(req, "create", &path, None, None);
+        self.dir_cache.remove_negative(&path);
+        let ino = self.get_or_create_inode(&path);
+        if writable && claimed != Some(ino) && !self.claim_for_write(ino) {
+            release_claim(self);
+            reply.error(libc::EWOULDBLOCK);
+            return;
+        }
+        let lock = if writable && online {
+            match self.lock_for_writer(&path) {
+                Ok(lock) => lock,
+                Err(errno) => {
+                    self.open_for_write.lock().unwrap().remove(&ino);
+                    reply.error(errno);
+                    return;
+                }
+            }
         } else {
-            match self.runtime.block_on(self.webdav.list_dir(dav_path)) {
-                Ok(entries) => {
-                    self.dir_cache.insert(parent_path.to_string(), entries.clone());
-                    entries
+            None
+        };
+        self.remember_lookup(ino);
+        self.touch_ctime(ino);
+        self.write_buffers.lock().unwrap().insert(ino, WriteBuffer {
+            data: SparseBuffer::default(),
+            etag: etag.clone(),
+            dirty: false,
+            mtime: None,
+        });
+        let fh = self.allocate_handle(ino, path, flags, etag, lock);
+
+        reply.created(&TTL, &self.file_attr(ino, 0, SystemTime::now()), 0, fh, 0);
+    }
+
+    fn mkdir(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        tracing::debug!("mkdir: parent={}, name={:?}", parent, name);
+
+        let (path, parent_path) = match self.resolve_child(parent, name) {
+            Ok(p) => p,
+            Err(errno) => {
+                reply.error(errno);
+                return;
+            }
+        };
+
+        match self.runtime.block_on(self.webdav.mkcol(&path)) {
+            Ok(()) => self.directory_changed(&parent_path),
+            Err(e) if is_network_error(&e) => {
+                let entry = DavEntry {
+                    name: name.to_string_lossy().into_owned(),
+                    is_dir: true,
+                    size: 0,
+                    modified: Some(chrono::Utc::now()),
+                    etag: None,
+                    properties: BTreeMap::new(),
+                    permissions: None,
+                    content_type: None,
+                };
+                let op = JournalOp::Mkdir { path: path.clone() };
+                if let Err(errno) = self.queue_offline(op, &parent_path, |entries| entries.push(entry)) {
+                    reply.error(errno);
+                    return;
                 }
-                Err(_) => {
-                    // Fallback to generic file attributes
-                    let attr = Self::file_attr(ino, 1024);
-                    reply.attr(&TTL, &attr);
+            }
+            Err(e) => {
+                tracing::error!("Failed to create directory {}: {}", path, e);
+                reply.error(errno_for(&e));
+                return;
+            }
+        }
+
+        self.audit(req, "mkdir", &path, None, None);
+        self.dir_cache.remove_negative(&path);
+        let ino = self.get_or_create_inode(&path);
+        self.remember_lookup(ino);
+        self.touch_ctime(ino);
+        self.dir_cache.insert(path, Vec::new(), None);
+
+        reply.entry(&TTL, &self.dir_attr(ino, SystemTime::now()), 0);
+    }
+
+    fn readlink(&mut self, _req: &Request, ino: u64, reply: ReplyData) {
+        tracing::debug!("readlink: ino={}", ino);
+
+        let Some(path) = self.get_path(ino) else {
+            reply.error(ENOENT);
+            return;
+        };
+        // Listings carry the target, so only links not listed yet cost a request
+        if let Some(entry) = self.cached_entry(&path) {
+            match entry.symlink_target() {
+                Some(target) => reply.data(target.as_bytes()),
+                None => reply.error(EINVAL),
+            }
+            return;
+        }
+
+        match self.runtime.block_on(self.webdav.symlink_target(&path)) {
+            Ok(Some(target)) => reply.data(target.as_bytes()),
+            Ok(None) => reply.error(EINVAL),
+            Err(e) => {
+                tracing::error!("Failed to read link {}: {}", path, e);
+                reply.error(errno_for(&e));
+            }
+        }
+    }
+
+    fn symlink(&mut self, req: &Request, parent: u64, link_name: &OsStr, target: &Path, reply: ReplyEntry) {
+        tracing::debug!("symlink: parent={}, name={:?}, target={:?}", parent, link_name, target);
+
+        // The target would be stored in the clear next to encrypted names
+        if self.webdav.encrypts() {
+            reply.error(EPERM);
+            return;
+        }
+        let (path, parent_path) = match self.resolve_child(parent, link_name) {
+            Ok(p) => p,
+            Err(errno) => {
+                reply.error(errno);
+                return;
+            }
+        };
+        let Some(target) = target.to_str().filter(|target| !target.is_empty()) else {
+            reply.error(EINVAL);
+            return;
+        };
+
+        if let Err(e) = self.runtime.block_on(self.webdav.create_symlink(&path, target)) {
+            tracing::error!("Failed to create link {}: {}", path, e);
+            reply.error(errno_for(&e));
+            return;
+        }
+
+        self.audit(req, "symlink", &path, None, None);
+        self.directory_changed(&parent_path);
+        self.dir_cache.remove_negative(&path);
+        let ino = self.get_or_create_inode(&path);
+        self.remember_lookup(ino);
+        self.touch_ctime(ino);
+
+        reply.entry(&TTL, &self.symlink_attr(ino, target.len() as u64, SystemTime::now()), 0);
+    }
+
+    fn unlink(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        tracing::debug!("unlink: parent={}, name={:?}", parent, name);
+
+        let (path, parent_path) = match self.resolve_child(parent, name) {
+            Ok(p) => p,
+            Err(errno) => {
+                reply.error(errno);
+                return;
+            }
+        };
+
+        // The file may be open for writing, and so locked, here
+        let lock = self.held_lock(&path);
+
+        // Deleting from the trash itself is for good
+        if let Some(trash) = self.trash_for(req.uid()).filter(|trash| !trash.contains(&path)) {
+            match self.runtime.block_on(trash.put(&self.webdav, &path, lock.as_ref())) {
+                Ok(trashed) => {
+                    let files_dir = trash.files_dir();
+                    self.directory_changed(&parent_path);
+                    self.directory_changed(&files_dir);
+                    self.audit(req, "trash", &path, Some(&Self::child_path(&files_dir, &trashed)), None);
+                    if !self.remove_link(&path) {
+                        self.unmap_path(&path);
+                    }
+                    reply.ok();
+                }
+                // Not journaled: the server has to be there to keep the file
+                Err(e) => {
+                    tracing::error!("Failed to move {} to the trash: {}", path, e);
+                    reply.error(errno_for(&e));
+                }
+            }
+            return;
+        }
+
+        match self.runtime.block_on(self.webdav.delete(&path, lock.as_ref())) {
+            Ok(()) => self.directory_changed(&parent_path),
+            Err(e) if is_network_error(&e) => {
+                let op = JournalOp::Delete { path: path.clone() };
+                if let Err(errno) = self.queue_offline(op, &parent_path, |entries| {
+                    entries.retain(|entry| entry.name.as_str() != name)
+                }) {
+                    reply.error(errno);
+                    return;
+                }
+            }
+            Err(e) => {
+                tracing::error!("Failed to delete {}: {}", path, e);
+                reply.error(errno_for(&e));
+                return;
+            }
+        }
+        self.audit(req, "unlink", &path, None, None);
+        if !self.remove_link(&path) {
+            self.unmap_path(&path);
+        }
+
+        reply.ok();
+    }
+
+    fn link(&mut self, req: &Request, ino: u64, newparent: u64, newname: &OsStr, reply: ReplyEntry) {
+        tracing::debug!("link: ino={}, newparent={}, newname={:?}", ino, newparent, newname);
+
+        let Some(path) = self.get_path(ino) else {
+            reply.error(ENOENT);
+            return;
+        };
+        if ino == ROOT_INO || self.cached_entry(&path).is_some_and(|entry| entry.is_dir) {
+            reply.error(EPERM);
+            return;
+        }
+        let (new_path, new_parent) = match self.resolve_child(newparent, newname) {
+            Ok(p) => p,
+            Err(errno) => {
+                reply.error(errno);
+                return;
+            }
+        };
+
+        // The copy has to include writes not uploaded yet
+        if let Err(errno) = self.flush_buffer(ino) {
+            reply.error(errno);
+            return;
+        }
+        if let Err(e) = self.runtime.block_on(self.webdav.copy_entry(&path, &new_path, false, None)) {
+            tracing::error!("Failed to link {} to {}: {}", new_path, path, e);
+            let errno = match e.downcast_ref::<DavStatusError>() {
+                Some(status_err) if status_err.status == StatusCode::PRECONDITION_FAILED => EEXIST,
+                _ => errno_for(&e),
+            };
+            reply.error(errno);
+            return;
+        }
+
+        self.hardlinks
+            .lock()
+            .unwrap()
+            .entry(ino)
+            .or_insert_with(|| vec![path.clone()])
+            .push(new_path.clone());
+        self.path_to_inode.lock().unwrap().insert(new_path.clone(), ino);
+        self.dir_cache.remove_negative(&new_path);
+        self.directory_changed(&new_parent);
+        self.touch_ctime(ino);
+        self.audit(req, "link", &path, Some(&new_path), None);
+
+        self.remember_lookup(ino);
+        let attr = match self.cached_entry(&path) {
+            Some(entry) => self.entry_attr(ino, &entry),
+            None => self.file_attr(ino, 0, SystemTime::now()),
+        };
+        reply.entry(&TTL, &attr, 0);
+    }
+
+    fn rmdir(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        tracing::debug!("rmdir: parent={}, name={:?}", parent, name);
+
+        let (path, parent_path) = match self.resolve_child(parent, name) {
+            Ok(p) => p,
+            Err(errno) => {
+                reply.error(errno);
+                return;
+            }
+        };
+
+        match self.runtime.block_on(self.webdav.is_collection(&path)) {
+            Ok(true) => {}
+            Ok(false) => {
+                reply.error(ENOTDIR);
+                return;
+            }
+            Err(e) => {
+                reply.error(errno_for(&e));
+                return;
+            }
+        }
+
+        // DELETE on a collection is recursive, so refuse non-empty directories
+        // the way rmdir(2) would.
+        match self.runtime.block_on(self.webdav.list_dir(&path, &PropRequest::default())) {
+            Ok(entries) if !entries.is_empty() => {
+                reply.error(ENOTEMPTY);
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                reply.error(errno_for(&e));
+                return;
+            }
+        }
+
+        if let Err(e) = self.runtime.block_on(self.webdav.delete(&path, None)) {
+            tracing::error!("Failed to delete directory {}: {}", path, e);
+            // Some servers refuse to delete non-empty collections with 409
+            let errno = match e.downcast_ref::<DavStatusError>() {
+                Some(status_err) if status_err.status == StatusCode::CONFLICT => ENOTEMPTY,
+                _ => errno_for(&e),
+            };
+            reply.error(errno);
+            return;
+        }
+        self.audit(req, "rmdir", &path, None, None);
+        self.directory_changed(&parent_path);
+        self.dir_cache.invalidate_tree(&path);
+        self.unmap_path(&path);
+
+        reply.ok();
+    }
+
+    fn rename(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        tracing::debug!(
+            "rename: parent={}, name={:?}, newparent={}, newname={:?}",
+            parent, name, newparent, newname
+        );
+
+        if flags & platform::RENAME_EXCHANGE != 0 {
+            reply.error(EINVAL);
+            return;
+        }
+        let overwrite = flags & platform::RENAME_NOREPLACE == 0;
+
+        let (from, from_parent) = match self.resolve_child(parent, name) {
+            Ok(p) => p,
+            Err(errno) => {
+                reply.error(errno);
+                return;
+            }
+        };
+        let (to, to_parent) = match self.resolve_child(newparent, newname) {
+            Ok(p) => p,
+            Err(errno) => {
+                reply.error(errno);
+                return;
+            }
+        };
+
+        let lock = self.held_lock(&from);
+        let moved_online = match self.runtime.block_on(self.webdav.move_entry(&from, &to, overwrite, lock.as_ref())) {
+            Ok(()) => {
+                self.directory_changed(&from_parent);
+                self.directory_changed(&to_parent);
+                true
+            }
+            Err(e) if is_network_error(&e) => {
+                let mut moved = None;
+                let op = JournalOp::Rename { from: from.clone(), to: to.clone() };
+                if let Err(errno) = self.queue_offline(op, &from_parent, |entries| {
+                    if let Some(index) = entries.iter().position(|entry| entry.name.as_str() == name) {
+                        moved = Some(entries.remove(index));
+                    }
+                }) {
+                    reply.error(errno);
                     return;
                 }
+                if let Some(mut entry) = moved {
+                    entry.name = newname.to_string_lossy().into_owned();
+                    self.dir_cache.update(&to_parent, |entries| {
+                        entries.retain(|e| e.name != entry.name);
+                        entries.push(entry);
+                    });
+                }
+                false
             }
-        };
-        
-        for entry in entries {
-            if entry.name == name {
-                let attr = if entry.is_dir {
-                    Self::dir_attr(ino)
-                } else {
-                    Self::file_attr(ino, entry.size)
-                };
-                reply.attr(&TTL, &attr);
+            Err(e) => {
+                tracing::error!("Failed to move {} to {}: {}", from, to, e);
+                reply.error(errno_for(&e));
                 return;
             }
+        };
+        self.audit(req, "rename", &from, Some(&to), None);
+        self.dir_cache.invalidate_tree(&from);
+        self.dir_cache.invalidate_tree(&to);
+        self.dir_cache.remove_negative(&to);
+        self.unmap_path(&to);
+        self.rename_paths(&from, &to);
+        if moved_online {
+            self.relock_moved(&to);
         }
-        reply.error(ENOENT);
+
+        reply.ok();
     }
 
-    fn readdir(
+    fn listxattr(&mut self, _req: &Request, ino: u64, size: u32, reply: ReplyXattr) {
+        tracing::debug!("listxattr: ino={}, size={}", ino, size);
+        
+        let mut names: Vec<String> = XATTRS.iter().map(|name| name.to_string()).collect();
+        if self.ttl_overrides.lock().unwrap().contains_key(&ino) {
+            names.push(XATTR_TTL.to_string());
+        }
+        if self.get_path(ino).is_some_and(|path| self.dir_cache.is_pinned(&path)) {
+            names.push(XATTR_PINNED.to_string());
+        }
+        if let Some(entry) = self.get_path(ino).and_then(|path| self.cached_entry(&path)) {
+            names.extend(entry.properties.keys().map(|prop| format!("{}{}", XATTR_DAV_PREFIX, prop)));
+            names.extend(
+                XATTR_NC_ALIASES
+                    .iter()
+                    .filter(|(_, prop)| entry.properties.contains_key(*prop))
+                    .map(|(alias, _)| alias.to_string()),
+            );
+        }
+        if let Some(local) = self.local_xattrs.lock().unwrap().get(&ino) {
+            names.extend(local.keys().cloned());
+        }
+
+        // Null-terminated list of the xattrs we expose
+        let mut buffer = Vec::new();
+        for xattr_name in names {
+            buffer.extend_from_slice(xattr_name.as_bytes());
+            buffer.push(0);
+        }
+        reply_xattr(reply, &buffer, size);
+    }
+
+    fn setxattr(
         &mut self,
         _req: &Request,
         ino: u64,
-        _fh: u64,
-        offset: i64,
-        mut reply: ReplyDirectory,
+        name: &OsStr,
+        value: &[u8],
+        flags: i32,
+        _position: u32,
+        reply: ReplyEmpty,
     ) {
-        tracing::debug!("readdir: ino={}, offset={}", ino, offset);
+        tracing::debug!("setxattr: ino={}, name={:?}, len={}", ino, name, value.len());
 
-        let dir_path = match self.get_path(ino) {
+        let Some(name) = name.to_str() else {
+            reply.error(EINVAL);
+            return;
+        };
+        if XATTRS.contains(&name) {
+            reply.error(libc::EPERM);
+            return;
+        }
+        let path = match self.get_path(ino) {
             Some(p) => p,
             None => {
                 reply.error(ENOENT);
@@ -431,128 +2830,403 @@ impl Filesystem for DavFS {
             }
         };
 
-        let entries = vec![
-            (ino, FileType::Directory, "."),
-            (ino, FileType::Directory, ".."),
-        ];
+        // For WebDAV properties this only knows what the last listing showed
+        let exists = self.xattr_value(ino, &path, name).is_some();
+        if flags & libc::XATTR_CREATE != 0 && exists {
+            reply.error(EEXIST);
+            return;
+        }
+        if flags & libc::XATTR_REPLACE != 0 && !exists {
+            reply.error(platform::ENOATTR);
+            return;
+        }
 
-        // Convert filesystem path to WebDAV path (remove leading /)
-        let dav_path = if dir_path == "/" {
-            ""
-        } else {
-            &dir_path[1..]
-        };
-        
-        // Try stale cache first for instant response, then fetch if needed
-        let dav_entries = if let Some(cached) = self.dir_cache.get_stale(&dir_path) {
-            tracing::debug!("Using cached (possibly stale) entries for path {}", dir_path);
-            cached
-        } else {
-            match self.runtime.block_on(self.webdav.list_dir(dav_path)) {
-                Ok(entries) => {
-                    tracing::info!("Listed {} entries from WebDAV at path {}", entries.len(), dav_path);
-                    self.dir_cache.insert(dir_path.clone(), entries.clone());
-                    
-                    // Trigger background prefetch of subdirectories
-                    self.prefetch_subdirectories(&dir_path, &entries);
-                    
-                    entries
-                }
-                Err(e) => {
-                    tracing::error!("Failed to list directory: {}", e);
-                    
-                    // Still return . and ..
-                    for (i, entry) in entries.iter().enumerate().skip(offset as usize) {
-                        if reply.add(entry.0, (i + 1) as i64, entry.1, entry.2) {
-                            break;
-                        }
-                    }
-                    reply.ok();
+        if name == XATTR_TTL {
+            let secs = std::str::from_utf8(value).ok().and_then(|v| v.trim().parse::<u64>().ok());
+            let Some(secs) = secs else {
+                reply.error(EINVAL);
+                return;
+            };
+            match self.set_ttl_override(ino, &path, Some(Duration::from_secs(secs))) {
+                Ok(()) => reply.ok(),
+                Err(errno) => reply.error(errno),
+            }
+            return;
+        }
+
+        if name == XATTR_PINNED {
+            let pinned = match std::str::from_utf8(value).map(str::trim) {
+                Ok("1") => true,
+                Ok("0") => false,
+                _ => {
+                    reply.error(EINVAL);
                     return;
                 }
+            };
+            match self.set_pinned(&path, pinned) {
+                Ok(()) => reply.ok(),
+                Err(errno) => reply.error(errno),
+            }
+            return;
+        }
+
+        if let Some(prop) = dav_property(name) {
+            let Ok(value) = std::str::from_utf8(value) else {
+                reply.error(EINVAL);
+                return;
+            };
+            if prop == "oc:favorite" && value != "0" && value != "1" {
+                reply.error(EINVAL);
+                return;
+            }
+            match self.update_dav_property(&path, prop, Some(value)) {
+                Ok(()) => reply.ok(),
+                Err(errno) => reply.error(errno),
             }
+            return;
+        }
+
+        self.local_xattrs.lock().unwrap()
+            .entry(ino)
+            .or_default()
+            .insert(name.to_string(), value.to_vec());
+        reply.ok();
+    }
+
+    fn removexattr(&mut self, _req: &Request, ino: u64, name: &OsStr, reply: ReplyEmpty) {
+        tracing::debug!("removexattr: ino={}, name={:?}", ino, name);
+
+        let Some(name) = name.to_str() else {
+            reply.error(EINVAL);
+            return;
         };
-        
-        let mut all_entries = entries;
-        
-        for entry in dav_entries.iter() {
-            let full_path = if dir_path == "/" {
-                format!("/{}", entry.name)
-            } else {
-                format!("{}/{}", dir_path, entry.name)
+        if XATTRS.contains(&name) {
+            reply.error(libc::EPERM);
+            return;
+        }
+
+        if name == XATTR_TTL {
+            if !self.ttl_overrides.lock().unwrap().contains_key(&ino) {
+                reply.error(platform::ENOATTR);
+                return;
+            }
+            let Some(path) = self.get_path(ino) else {
+                reply.error(ENOENT);
+                return;
             };
-            
-            let ino = self.get_or_create_inode(&full_path);
-            let kind = if entry.is_dir {
-                FileType::Directory
-            } else {
-                FileType::RegularFile
+            match self.set_ttl_override(ino, &path, None) {
+                Ok(()) => reply.ok(),
+                Err(errno) => reply.error(errno),
+            }
+            return;
+        }
+
+        if name == XATTR_PINNED {
+            let Some(path) = self.get_path(ino) else {
+                reply.error(ENOENT);
+                return;
             };
-            all_entries.push((ino, kind, entry.name.as_str()));
+            if !self.dir_cache.is_pinned(&path) {
+                reply.error(platform::ENOATTR);
+                return;
+            }
+            match self.set_pinned(&path, false) {
+                Ok(()) => reply.ok(),
+                Err(errno) => reply.error(errno),
+            }
+            return;
         }
 
-        for (i, entry) in all_entries.iter().enumerate().skip(offset as usize) {
-            if reply.add(entry.0, (i + 1) as i64, entry.1, entry.2) {
-                break;
+        if let Some(prop) = dav_property(name) {
+            let Some(path) = self.get_path(ino) else {
+                reply.error(ENOENT);
+                return;
+            };
+            match self.update_dav_property(&path, prop, None) {
+                Ok(()) => reply.ok(),
+                Err(errno) => reply.error(errno),
             }
+            return;
         }
 
-        reply.ok();
+        let removed = self.local_xattrs.lock().unwrap()
+            .get_mut(&ino)
+            .and_then(|attrs| attrs.remove(name));
+        match removed {
+            Some(_) => reply.ok(),
+            None => reply.error(platform::ENOATTR),
+        }
     }
 
-    fn read(
+    fn getlk(
         &mut self,
         _req: &Request,
         ino: u64,
         _fh: u64,
-        offset: i64,
-        _size: u32,
-        _flags: i32,
-        _lock: Option<u64>,
-        reply: ReplyData,
+        lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        pid: u32,
+        reply: ReplyLock,
     ) {
-        tracing::debug!("read: ino={}, offset={}", ino, offset);
+        tracing::debug!("getlk: ino={}, owner={}, typ={}", ino, lock_owner, typ);
 
-        // For PoC: Always return "no network" error when trying to read file content
-        tracing::error!("Read operation not supported (PoC: no network error)");
-        reply.error(libc::ENETUNREACH); // Network unreachable
+        // Only locks taken through this mount are known; locks of other
+        // clients show up as EAGAIN from setlk
+        let handles = self.open_handles.lock().unwrap();
+        let conflict = handles
+            .values()
+            .filter(|handle| handle.ino == ino)
+            .filter_map(|handle| handle.posix_lock.as_ref())
+            .find(|lock| lock.owner != lock_owner && (!lock.shared || typ == libc::F_WRLCK));
+        match conflict {
+            Some(lock) => {
+                let typ = if lock.shared { libc::F_RDLCK } else { libc::F_WRLCK };
+                reply.locked(0, u64::MAX, typ, 0);
+            }
+            None => reply.locked(start, end, libc::F_UNLCK, pid),
+        }
     }
 
-    fn open(&mut self, _req: &Request, ino: u64, _flags: i32, reply: fuser::ReplyOpen) {
-        tracing::debug!("open: ino={}", ino);
-        // Allow opening files, but read will fail
-        reply.opened(0, 0);
+    fn setlk(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+        _start: u64,
+        _end: u64,
+        typ: i32,
+        _pid: u32,
+        sleep: bool,
+        reply: ReplyEmpty,
+    ) {
+        tracing::debug!("setlk: ino={}, fh={}, owner={}, typ={}, wait={}", ino, fh, lock_owner, typ, sleep);
+
+        // Class 1 servers have no locking at all
+        if self.capabilities.is_some_and(|c| !c.class2) {
+            reply.error(ENOLCK);
+            return;
+        }
+
+        let (path, has_write_lock, current) = match self.open_handles.lock().unwrap().get_mut(&fh) {
+            Some(handle) => (handle.path.clone(), handle.lock.is_some(), handle.posix_lock.take()),
+            None => {
+                reply.error(EBADF);
+                return;
+            }
+        };
+
+        if typ == libc::F_UNLCK {
+            if let Some(lock) = current {
+                self.release_posix_lock(&path, lock);
+            }
+            reply.ok();
+            return;
+        }
+
+        let shared = typ == libc::F_RDLCK;
+        let lock = match current {
+            Some(lock) if lock.shared == shared => Ok(lock),
+            current => {
+                // Changing the lock type means giving up the old lock first
+                if let Some(lock) = current {
+                    self.release_posix_lock(&path, lock);
+                }
+                // An exclusive lock from opening for writing already covers it
+                let token = if has_write_lock {
+                    Ok(None)
+                } else {
+                    self.acquire_posix_lock(&path, shared, sleep).map(Some)
+                };
+                token.map(|token| PosixLock { owner: lock_owner, shared, token })
+            }
+        };
+
+        match lock {
+            Ok(lock) => {
+                match self.open_handles.lock().unwrap().get_mut(&fh) {
+                    Some(handle) => handle.posix_lock = Some(lock),
+                    None => self.release_posix_lock(&path, lock),
+                }
+                reply.ok();
+            }
+            Err(errno) => reply.error(errno),
+        }
     }
 
-    fn listxattr(&mut self, _req: &Request, ino: u64, size: u32, reply: ReplyXattr) {
-        tracing::debug!("listxattr: ino={}, size={}", ino, size);
-        
-        // We expose user.davfs.state xattr
-        let xattr_name = "user.davfs.state";
-        let total_size = xattr_name.len() + 1; // +1 for null terminator
-        
-        if size == 0 {
-            // Return size needed
-            reply.size(total_size as u32);
-        } else if size >= total_size as u32 {
-            // Return the list of xattr names
-            let mut buffer = Vec::with_capacity(total_size);
-            buffer.extend_from_slice(xattr_name.as_bytes());
-            buffer.push(0); // null terminator
-            reply.data(&buffer);
+    fn access(&mut self, _req: &Request, ino: u64, mask: i32, reply: ReplyEmpty) {
+        tracing::debug!("access: ino={}, mask={:#o}", ino, mask);
+
+        if ino == STATUS_INO {
+            if mask & (libc::W_OK | libc::X_OK) == 0 {
+                reply.ok();
+            } else {
+                reply.error(EACCES);
+            }
+            return;
+        }
+        if ino == ROOT_INO || mask == libc::F_OK {
+            reply.ok();
+            return;
+        }
+        let Some(path) = self.get_path(ino) else {
+            reply.error(ENOENT);
+            return;
+        };
+
+        // Only the listing is consulted; entries from servers without
+        // oc:permissions are allowed everything
+        match self.cached_entry(&path) {
+            Some(DavEntry { permissions: Some(permissions), is_dir, .. }) if !permissions.allows(mask, is_dir) => {
+                tracing::debug!("access: {} denied by server permissions {:?}", path, permissions);
+                reply.error(EACCES);
+            }
+            _ => reply.ok(),
+        }
+    }
+
+    fn ioctl(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        _flags: u32,
+        cmd: u32,
+        in_data: &[u8],
+        _out_size: u32,
+        reply: ReplyIoctl,
+    ) {
+        tracing::debug!("ioctl: ino={}, cmd={:#x}", ino, cmd);
+
+        if cmd == ioctl::DAVFS_IOCTL_GET_STATS {
+            let metrics = self.webdav.metrics();
+            let cache = self.dir_cache.stats();
+            let stats = DavfsStats {
+                cached_directories: cache.total_directories as u64,
+                expired_directories: cache.expired_directories as u64,
+                cache_hits: metrics.cache_hits.load(Ordering::Relaxed),
+                cache_misses: metrics.cache_misses.load(Ordering::Relaxed),
+                requests: metrics.requests_total(),
+                pending_journal_ops: self.journal.len() as u64,
+                prefetch_queued: metrics.prefetch_queued.load(Ordering::Relaxed),
+            };
+            reply.ioctl(0, &stats.to_bytes());
+            return;
+        }
+        if cmd != ioctl::DAVFS_IOCTL_INVALIDATE_PATH && cmd != ioctl::DAVFS_IOCTL_PREFETCH_PATH {
+            reply.error(libc::ENOTTY);
+            return;
+        }
+
+        // Paths are relative to the file the ioctl was issued on
+        let (Some(base), Some(relative)) = (self.get_path(ino), ioctl::decode_path(in_data)) else {
+            reply.error(EINVAL);
+            return;
+        };
+        let relative = relative.trim_matches('/');
+        let path = if relative.is_empty() { base } else { Self::child_path(&base, relative) };
+
+        if cmd == ioctl::DAVFS_IOCTL_INVALIDATE_PATH {
+            tracing::info!("Invalidating {} on request", path);
+            self.dir_cache.invalidate(&path);
+            self.dir_cache.invalidate(Self::parent_of(&path));
+            self.dir_cache.remove_negative(&path);
         } else {
-            reply.error(libc::ERANGE);
+            tracing::info!("Prefetching {} on request", path);
+            self.prefetch.prefetch_path(&path);
+        }
+        reply.ioctl(0, &[]);
+    }
+
+    fn poll(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        kh: u64,
+        events: u32,
+        flags: u32,
+        reply: ReplyPoll,
+    ) {
+        tracing::debug!("poll: ino={}, events={:#x}", ino, events);
+
+        // Files can always be read and written; POLLPRI reports that the
+        // file changed on the server since the last poll
+        let ready = (libc::POLLIN | libc::POLLOUT | libc::POLLRDNORM | libc::POLLWRNORM) as u32;
+        let mut revents = events & ready;
+        if let (Some(watcher), Some(path)) = (&self.poll_watcher, self.get_path(ino)) {
+            let modified = self.cached_entry(&path).and_then(|entry| entry.modified);
+            let kh = (flags & fuser::consts::FUSE_POLL_SCHEDULE_NOTIFY != 0).then_some(kh);
+            if watcher.poll(ino, &path, modified, kh) {
+                revents |= events & libc::POLLPRI as u32;
+            }
+        }
+        reply.poll(revents);
+    }
+
+    fn statfs(&mut self, _req: &Request, _ino: u64, reply: ReplyStatfs) {
+        let quota = self.cached_quota();
+        let used = quota.used.unwrap_or(0);
+        let available = quota.available.unwrap_or(FALLBACK_AVAILABLE_BYTES);
+        let total = used.saturating_add(available);
+
+        // WebDAV has no inode limit; report plenty of free ones
+        let files = self.inode_to_path.lock().unwrap().len() as u64;
+        reply.statfs(
+            total / STATFS_BLOCK_SIZE,
+            available / STATFS_BLOCK_SIZE,
+            available / STATFS_BLOCK_SIZE,
+            files + 1_000_000,
+            1_000_000,
+            STATFS_BLOCK_SIZE as u32,
+            255,
+            STATFS_BLOCK_SIZE as u32,
+        );
+    }
+
+    fn bmap(&mut self, _req: &Request, ino: u64, blocksize: u32, idx: u64, reply: ReplyBmap) {
+        tracing::debug!("bmap: ino={}, blocksize={}, idx={}", ino, blocksize, idx);
+
+        let Some(path) = self.get_path(ino) else {
+            reply.error(ENOENT);
+            return;
+        };
+        if blocksize == 0 {
+            reply.error(EINVAL);
+            return;
         }
+        let local = self.write_buffers.lock().unwrap().contains_key(&ino) || self.content_is_cached(&path);
+        if !local {
+            reply.error(EOPNOTSUPP);
+            return;
+        }
+
+        // No device backs the mount; a stable number per inode and block
+        // keeps tools that merely probe bmap going. Inodes are path hashes,
+        // so the numbers wrap rather than saturate to stay distinct
+        let blocksize = u64::from(blocksize);
+        let offset = idx.wrapping_mul(blocksize);
+        reply.bmap(ino.wrapping_mul(BMAP_INODE_SPAN).wrapping_add(offset) / blocksize);
     }
 
     fn getxattr(&mut self, _req: &Request, ino: u64, name: &OsStr, size: u32, reply: ReplyXattr) {
         tracing::debug!("getxattr: ino={}, name={:?}, size={}", ino, name, size);
         
-        if name != "user.davfs.state" {
-            reply.error(libc::ENODATA);
+        // Mount-wide values: pending offline changes and connectivity
+        let mount_value = if name == XATTR_JOURNAL_OPS {
+            Some(self.journal.len().to_string())
+        } else if name == XATTR_SYNC_STATUS {
+            Some(self.sync.status().state.as_str().to_string())
+        } else {
+            None
+        };
+        if let Some(value) = mount_value {
+            reply_xattr(reply, value.as_bytes(), size);
             return;
         }
-        
+
         let path = match self.get_path(ino) {
             Some(p) => p,
             None => {
@@ -560,6 +3234,13 @@ impl Filesystem for DavFS {
                 return;
             }
         };
+        if name != XATTR_STATE {
+            match name.to_str().and_then(|name| self.xattr_value(ino, &path, name)) {
+                Some(value) => reply_xattr(reply, &value, size),
+                None => reply.error(platform::ENOATTR),
+            }
+            return;
+        }
         
         // Determine state based on cache
         let state = if ino == ROOT_INO {
@@ -585,15 +3266,51 @@ impl Filesystem for DavFS {
             }
         };
         
-        let value = state.as_bytes();
-        
-        if size == 0 {
-            // Return size needed
-            reply.size(value.len() as u32);
-        } else if size >= value.len() as u32 {
-            reply.data(value);
-        } else {
-            reply.error(libc::ERANGE);
-        }
+        reply_xattr(reply, state.as_bytes(), size);
+    }
+}
+
+/// WebDAV property an xattr name stands for, if any.
+fn dav_property(name: &str) -> Option<&str> {
+    name.strip_prefix(XATTR_DAV_PREFIX).or_else(|| {
+        XATTR_NC_ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == name)
+            .map(|(_, prop)| *prop)
+    })
+}
+
+/// Reply with an xattr value, or just its length when `size` is 0.
+fn reply_xattr(reply: ReplyXattr, value: &[u8], size: u32) {
+    if size == 0 {
+        reply.size(value.len() as u32);
+    } else if size >= value.len() as u32 {
+        reply.data(value);
+    } else {
+        reply.error(libc::ERANGE);
+    }
+}
+
+/// Map a WebDAV client error to the errno reported to the kernel.
+fn errno_for(err: &anyhow::Error) -> i32 {
+    if let Some(status_err) = err.downcast_ref::<DavStatusError>() {
+        return match status_err.status {
+            // 409 on PUT/MKCOL/MOVE means an intermediate collection is missing
+            StatusCode::NOT_FOUND | StatusCode::CONFLICT => ENOENT,
+            // 412: If-Match/If-None-Match failed; 405: MKCOL on an existing resource
+            StatusCode::PRECONDITION_FAILED | StatusCode::METHOD_NOT_ALLOWED => EEXIST,
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => EACCES,
+            StatusCode::INSUFFICIENT_STORAGE => ENOSPC,
+            StatusCode::LOCKED => EAGAIN,
+            _ => EIO,
+        };
+    }
+    // Still queued offline like other network errors, but reported as EIO
+    if err.is::<CircuitOpenError>() {
+        return EIO;
+    }
+    if is_network_error(err) {
+        return ENETUNREACH;
     }
+    EIO
 }
@@ -0,0 +1,63 @@
+use anyhow::{Context, Result};
+use glob::{MatchOptions, Pattern};
+
+use crate::config::MountConfig;
+
+/// Selective sync: which paths are cached and synced. Excluded paths still
+/// show up in listings but cannot be opened, are not prefetched and are
+/// not queued for offline sync.
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+// `*` stays within one path component, `**` crosses them
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+impl PathFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        let compile = |patterns: &[String]| -> Result<Vec<Pattern>> {
+            patterns
+                .iter()
+                .map(|p| Pattern::new(p).with_context(|| format!("Invalid glob pattern '{}'", p)))
+                .collect()
+        };
+        Ok(Self {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+        })
+    }
+
+    pub fn from_config(config: &MountConfig) -> Result<Self> {
+        Self::new(&config.include_patterns, &config.exclude_patterns)
+    }
+
+    /// Whether `path` (absolute within the mount) is excluded. Exclude
+    /// patterns also cover everything below a matching directory. Include
+    /// patterns, if any, only restrict files so that directories leading
+    /// to included files stay reachable.
+    pub fn excludes(&self, path: &str, is_dir: bool) -> bool {
+        let relative = path.trim_start_matches('/');
+        if relative.is_empty() {
+            return false;
+        }
+
+        if Self::ancestors(relative).any(|p| self.exclude.iter().any(|pat| pat.matches_with(p, MATCH_OPTIONS))) {
+            return true;
+        }
+        if is_dir || self.include.is_empty() {
+            return false;
+        }
+        !Self::ancestors(relative).any(|p| self.include.iter().any(|pat| pat.matches_with(p, MATCH_OPTIONS)))
+    }
+
+    /// `a/b/c`, `a/b`, `a`
+    fn ancestors(relative: &str) -> impl Iterator<Item = &str> {
+        std::iter::successors(Some(relative), |p| p.rfind('/').map(|idx| &p[..idx]))
+    }
+}
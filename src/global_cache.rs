@@ -0,0 +1,159 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::cache::DirectoryCache;
+use crate::config;
+
+/// How often the shared state is read and this process's usage published.
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(5);
+/// Usage not published for this long belongs to a process that exited.
+const STALE_AFTER: Duration = Duration::from_secs(30);
+
+/// In-memory listings of one process, as published in the state file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ProcessUsage {
+    entries: usize,
+    /// Last use of its least recently used listing, in Unix milliseconds
+    least_recent_use: Option<u64>,
+    /// When this was published, in Unix seconds
+    updated: u64,
+}
+
+/// Contents of the state file, by process ID.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SharedState {
+    processes: HashMap<u32, ProcessUsage>,
+}
+
+/// Keeps the listings all mounts hold in memory under one limit by evicting
+/// the least recently used one across mounts. Caches of this process
+/// register here; other mount processes are known from a JSON file in the
+/// runtime directory where each publishes its usage. A process only evicts
+/// its own listings: while another one holds the oldest, that one evicts
+/// them on its next check.
+pub struct GlobalCacheManager {
+    limit: usize,
+    state_path: PathBuf,
+    caches: Mutex<Vec<(String, DirectoryCache)>>,
+    // Usage of other processes, as last read from `state_path`
+    others: Mutex<Vec<ProcessUsage>>,
+}
+
+impl GlobalCacheManager {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit: limit.max(1),
+            state_path: config::runtime_dir().join("cache-quota.json"),
+            caches: Mutex::new(Vec::new()),
+            others: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn register(&self, name: &str, cache: DirectoryCache) {
+        self.caches.lock().unwrap().push((name.to_string(), cache));
+    }
+
+    /// Publish this process's usage and enforce the limit in the background
+    /// of the current runtime, so idle mounts also make room for busy ones.
+    pub fn start(self: &Arc<Self>) {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(PUBLISH_INTERVAL);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = manager.exchange_usage() {
+                    tracing::debug!("Failed to share cache usage: {:#}", e);
+                }
+                manager.enforce();
+            }
+        });
+    }
+
+    /// Evict listings of this process while all mounts together hold more
+    /// than the limit and the least recently used listing is ours.
+    pub fn enforce(&self) {
+        let (other_entries, other_oldest) = {
+            let others = self.others.lock().unwrap();
+            (
+                others.iter().map(|usage| usage.entries).sum::<usize>(),
+                others.iter().filter_map(|usage| usage.least_recent_use).min(),
+            )
+        };
+        // Evicting locks the caches, so not under our lock
+        let caches = self.caches.lock().unwrap().clone();
+
+        let mut evicted = 0;
+        while other_entries + caches.iter().map(|(_, cache)| cache.len()).sum::<usize>() > self.limit {
+            let Some((name, cache, used)) = caches
+                .iter()
+                .filter_map(|(name, cache)| Some((name, cache, cache.least_recent_use()?)))
+                .min_by_key(|(_, _, used)| *used)
+            else {
+                break;
+            };
+            if other_oldest.is_some_and(|oldest| oldest < unix_millis(used)) {
+                break;
+            }
+            if !cache.evict_least_recent() {
+                break;
+            }
+            tracing::trace!("Evicted a listing of mount '{}' for the global cache limit", name);
+            evicted += 1;
+        }
+        if evicted > 0 {
+            tracing::debug!("Evicted {} listings to stay within {} across mounts", evicted, self.limit);
+        }
+    }
+
+    fn usage(&self, now: u64) -> ProcessUsage {
+        let caches = self.caches.lock().unwrap();
+        ProcessUsage {
+            entries: caches.iter().map(|(_, cache)| cache.len()).sum(),
+            least_recent_use: caches
+                .iter()
+                .filter_map(|(_, cache)| cache.least_recent_use())
+                .min()
+                .map(unix_millis),
+            updated: now,
+        }
+    }
+
+    /// Read the usage of other processes and publish ours. Processes write
+    /// the file without locking, so one may overwrite another's update; the
+    /// lost usage is published again on the next round.
+    fn exchange_usage(&self) -> Result<()> {
+        let mut state: SharedState = match std::fs::read_to_string(&self.state_path) {
+            // A torn or foreign file is replaced with a fresh one
+            Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => SharedState::default(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", self.state_path.display())),
+        };
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let pid = std::process::id();
+        state
+            .processes
+            .retain(|&other, usage| other != pid && now.saturating_sub(usage.updated) < STALE_AFTER.as_secs());
+        *self.others.lock().unwrap() = state.processes.values().cloned().collect();
+        state.processes.insert(pid, self.usage(now));
+
+        if let Some(parent) = self.state_path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let tmp = self.state_path.with_extension(format!("json.{}.tmp", pid));
+        std::fs::write(&tmp, serde_json::to_vec(&state)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.state_path)
+            .with_context(|| format!("Failed to write {}", self.state_path.display()))?;
+        Ok(())
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
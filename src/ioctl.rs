@@ -0,0 +1,77 @@
+use nix::{request_code_read, request_code_write};
+
+/// Size of the path argument: a path relative to the file the ioctl is
+/// issued on (empty for that file itself), NUL-padded.
+pub const PATH_ARG_SIZE: usize = 4096;
+
+/// Drop the cached listing of the path and of its directory.
+pub const DAVFS_IOCTL_INVALIDATE_PATH: u32 = request_code_write!(b'D', 1, PATH_ARG_SIZE) as u32;
+/// List the path and the directories below it into the cache in the
+/// background.
+pub const DAVFS_IOCTL_PREFETCH_PATH: u32 = request_code_write!(b'D', 2, PATH_ARG_SIZE) as u32;
+/// Fill a `DavfsStats`.
+pub const DAVFS_IOCTL_GET_STATS: u32 = request_code_read!(b'D', 3, DavfsStats::SIZE) as u32;
+
+/// Counters returned by `DAVFS_IOCTL_GET_STATS`, as native-endian `u64`s
+/// in field order.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct DavfsStats {
+    pub cached_directories: u64,
+    pub expired_directories: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub requests: u64,
+    pub pending_journal_ops: u64,
+    pub prefetch_queued: u64,
+}
+
+impl DavfsStats {
+    pub const SIZE: usize = std::mem::size_of::<Self>();
+
+    fn fields(self) -> [u64; 7] {
+        [
+            self.cached_directories,
+            self.expired_directories,
+            self.cache_hits,
+            self.cache_misses,
+            self.requests,
+            self.pending_journal_ops,
+            self.prefetch_queued,
+        ]
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        self.fields().iter().flat_map(|field| field.to_ne_bytes()).collect()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let mut fields = data.chunks_exact(8).map(|chunk| u64::from_ne_bytes(chunk.try_into().unwrap()));
+        let mut next = || fields.next();
+        Some(Self {
+            cached_directories: next()?,
+            expired_directories: next()?,
+            cache_hits: next()?,
+            cache_misses: next()?,
+            requests: next()?,
+            pending_journal_ops: next()?,
+            prefetch_queued: next()?,
+        })
+    }
+}
+
+/// Encode `path` as a path argument. `None` if it is too long.
+pub fn encode_path(path: &str) -> Option<Vec<u8>> {
+    if path.len() >= PATH_ARG_SIZE {
+        return None;
+    }
+    let mut arg = path.as_bytes().to_vec();
+    arg.resize(PATH_ARG_SIZE, 0);
+    Some(arg)
+}
+
+/// The path in a path argument. `None` if it is not UTF-8.
+pub fn decode_path(arg: &[u8]) -> Option<&str> {
+    let end = arg.iter().position(|&b| b == 0).unwrap_or(arg.len());
+    std::str::from_utf8(&arg[..end]).ok()
+}
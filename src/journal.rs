@@ -0,0 +1,447 @@
+use anyhow::{Context, Result};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::webdav::{is_network_error, DavStatusError, WebDavClient};
+
+/// A change made while the server was unreachable, replayed once it is
+/// back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum JournalOp {
+    Create {
+        path: String,
+    },
+    /// Replace the file content from `offset` on with `data`. `etag` is the
+    /// version the change was based on, sent as If-Match on replay.
+    Write {
+        path: String,
+        offset: u64,
+        data: Vec<u8>,
+        etag: Option<String>,
+    },
+    Delete {
+        path: String,
+    },
+    Rename {
+        from: String,
+        to: String,
+    },
+    Mkdir {
+        path: String,
+    },
+}
+
+impl JournalOp {
+    /// Paths whose parent listings change when the op is applied.
+    pub fn paths(&self) -> Vec<&str> {
+        match self {
+            JournalOp::Create { path }
+            | JournalOp::Write { path, .. }
+            | JournalOp::Delete { path }
+            | JournalOp::Mkdir { path } => vec![path],
+            JournalOp::Rename { from, to } => vec![from, to],
+        }
+    }
+}
+
+/// What to do when a journaled write conflicts with a change made on the
+/// server in the meantime (the If-Match precondition failed).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictStrategy {
+    /// Discard the local change
+    ServerWins,
+    /// Overwrite the server version
+    LocalWins,
+    /// Keep the server version and upload the local one next to it as
+    /// `<path><suffix>-YYYYMMDDHHMMSS`
+    RenameLocal { suffix: String },
+    /// Stop replaying and leave the change queued until resolved by hand
+    Fail,
+}
+
+impl Default for ConflictStrategy {
+    fn default() -> Self {
+        ConflictStrategy::RenameLocal {
+            suffix: ".conflict".to_string(),
+        }
+    }
+}
+
+/// A journaled write conflicted and the strategy is `Fail`.
+#[derive(Debug, thiserror::Error)]
+#[error("{0} was changed on the server; resolve the conflict by hand")]
+struct UnresolvedConflict(String);
+
+/// Append-only log of pending offline changes. Each record is a
+/// little-endian u32 length followed by a bincode-encoded `JournalOp`.
+pub struct WriteJournal {
+    path: PathBuf,
+    // Ops not yet replayed, oldest first; mirrors the file
+    pending: Mutex<VecDeque<JournalOp>>,
+    conflict_strategy: ConflictStrategy,
+}
+
+impl WriteJournal {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+
+        let pending = match File::open(path) {
+            Ok(file) => Self::read_ops(file)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => VecDeque::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to open journal {}", path.display()))
+            }
+        };
+        if !pending.is_empty() {
+            tracing::info!("Journal has {} pending operations", pending.len());
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            pending: Mutex::new(pending),
+            conflict_strategy: ConflictStrategy::default(),
+        })
+    }
+
+    pub fn with_conflict_strategy(mut self, conflict_strategy: ConflictStrategy) -> Self {
+        self.conflict_strategy = conflict_strategy;
+        self
+    }
+
+    fn read_ops(file: File) -> Result<VecDeque<JournalOp>> {
+        let mut reader = BufReader::new(file);
+        let mut ops = VecDeque::new();
+
+        loop {
+            let mut len = [0u8; 4];
+            match reader.read_exact(&mut len) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+            let mut record = vec![0u8; u32::from_le_bytes(len) as usize];
+            if reader.read_exact(&mut record).is_err() {
+                // Torn write at the end of the log, e.g. after a crash
+                tracing::warn!("Ignoring truncated journal record");
+                break;
+            }
+            ops.push_back(bincode::deserialize(&record).context("Corrupt journal record")?);
+        }
+
+        Ok(ops)
+    }
+
+    fn encode(op: &JournalOp) -> Result<Vec<u8>> {
+        let record = bincode::serialize(op)?;
+        let mut buf = Vec::with_capacity(record.len() + 4);
+        buf.extend_from_slice(&(record.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&record);
+        Ok(buf)
+    }
+
+    /// Durably record `op` at the end of the journal.
+    pub fn append(&self, op: JournalOp) -> Result<()> {
+        let mut pending = self.pending.lock().unwrap();
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open journal {}", self.path.display()))?;
+        file.write_all(&Self::encode(&op)?)?;
+        file.sync_data()?;
+
+        tracing::info!("Journaled offline change: {:?}", op.paths());
+        pending.push_back(op);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop the oldest op and rewrite the file with the remaining ones.
+    fn pop_front(&self) -> Result<()> {
+        let mut pending = self.pending.lock().unwrap();
+        pending.pop_front();
+
+        let tmp = self.path.with_extension("log.tmp");
+        let mut file = File::create(&tmp)?;
+        for op in pending.iter() {
+            file.write_all(&Self::encode(op)?)?;
+        }
+        file.sync_data()?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// Apply pending ops to the server in order. Stops at the first network
+    /// error or unresolved conflict, leaving the rest for the next attempt. Returns the ops that
+    /// were applied (or dropped as conflicting).
+    pub async fn replay(&self, webdav: &WebDavClient) -> Result<Vec<JournalOp>> {
+        let mut replayed = Vec::new();
+
+        loop {
+            let Some(op) = self.pending.lock().unwrap().front().cloned() else {
+                break;
+            };
+
+            match self.apply(webdav, &op).await {
+                Ok(new_etag) => {
+                    tracing::info!("Replayed {:?}", op.paths());
+                    if let JournalOp::Write { path, etag, .. } = &op {
+                        self.rebase_writes(path, etag, new_etag);
+                    }
+                }
+                Err(e) if is_network_error(&e) => {
+                    tracing::debug!("Server still unreachable, pausing replay: {}", e);
+                    break;
+                }
+                Err(e) if e.is::<UnresolvedConflict>() => {
+                    tracing::error!("Journal replay blocked: {}", e);
+                    break;
+                }
+                Err(e) => {
+                    // Keeping the op would block everything queued after it
+                    tracing::error!("Dropping journaled change to {:?}: {}", op.paths(), e);
+                }
+            }
+
+            self.pop_front().context("Failed to update journal")?;
+            replayed.push(op);
+        }
+
+        Ok(replayed)
+    }
+
+    /// Later writes to `path` were based on the version the replayed write
+    /// just replaced; point their If-Match at the new version instead.
+    fn rebase_writes(&self, path: &str, old_etag: &Option<String>, new_etag: Option<String>) {
+        let mut pending = self.pending.lock().unwrap();
+        for op in pending.iter_mut().skip(1) {
+            if let JournalOp::Write { path: p, etag, .. } = op {
+                if p == path && etag == old_etag {
+                    etag.clone_from(&new_etag);
+                }
+            }
+        }
+    }
+
+    /// Apply a single op, returning the new etag for writes.
+    async fn apply(&self, webdav: &WebDavClient, op: &JournalOp) -> Result<Option<String>> {
+        let result = match op {
+            JournalOp::Create { path } => webdav.create_file(path).await.map(|_| None),
+            JournalOp::Write { path, offset, data, etag } => {
+                let content = Self::content_for(webdav, path, *offset, data).await?;
+                webdav.put_file(path, content, etag.as_deref(), None).await
+            }
+            JournalOp::Delete { path } => webdav.delete(path, None).await.map(|_| None),
+            JournalOp::Rename { from, to } => webdav.move_entry(from, to, true, None).await.map(|_| None),
+            JournalOp::Mkdir { path } => webdav.mkcol(path).await.map(|_| None),
+        };
+
+        let e = match result {
+            Ok(new_etag) => return Ok(new_etag),
+            Err(e) => e,
+        };
+        let status = e.downcast_ref::<DavStatusError>().map(|s| s.status);
+        match (op, status) {
+            // Already in the desired state on the server
+            (JournalOp::Create { .. }, Some(StatusCode::PRECONDITION_FAILED))
+            | (JournalOp::Delete { .. }, Some(StatusCode::NOT_FOUND))
+            | (JournalOp::Mkdir { .. }, Some(StatusCode::METHOD_NOT_ALLOWED)) => Ok(None),
+            (JournalOp::Write { path, offset, data, etag }, Some(StatusCode::PRECONDITION_FAILED)) => {
+                self.resolve_conflict(webdav, path, *offset, data, etag).await
+            }
+            _ => Err(e),
+        }
+    }
+
+    /// Handle a write whose base version `etag` no longer matches the
+    /// server, according to the conflict strategy.
+    async fn resolve_conflict(
+        &self,
+        webdav: &WebDavClient,
+        path: &str,
+        offset: u64,
+        data: &[u8],
+        etag: &Option<String>,
+    ) -> Result<Option<String>> {
+        match &self.conflict_strategy {
+            ConflictStrategy::ServerWins => {
+                tracing::warn!("Conflict replaying write to {}: keeping the server version", path);
+                // Later writes were based on the same lost version
+                Ok(etag.clone())
+            }
+            ConflictStrategy::LocalWins => {
+                tracing::warn!("Conflict replaying write to {}: overwriting the server version", path);
+                let content = Self::content_for(webdav, path, offset, data).await?;
+                webdav.put_file(path, content, None, None).await
+            }
+            ConflictStrategy::RenameLocal { suffix } => {
+                let conflict_path = format!(
+                    "{}{}-{}",
+                    path,
+                    suffix,
+                    chrono::Local::now().format("%Y%m%d%H%M%S")
+                );
+                tracing::warn!(
+                    "Conflict replaying write to {}: saving the local version as {}",
+                    path,
+                    conflict_path
+                );
+                let content = Self::content_for(webdav, path, offset, data).await?;
+                webdav.put_file(&conflict_path, content, None, None).await?;
+                Ok(etag.clone())
+            }
+            ConflictStrategy::Fail => Err(UnresolvedConflict(path.to_string()).into()),
+        }
+    }
+
+    /// Full file content after writing `data` at `offset`.
+    async fn content_for(webdav: &WebDavClient, path: &str, offset: u64, data: &[u8]) -> Result<Vec<u8>> {
+        if offset == 0 {
+            return Ok(data.to_vec());
+        }
+        let mut content = webdav.read_file(path).await?;
+        content.resize(offset as usize, 0);
+        content.extend_from_slice(data);
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use crate::webdav::{AuthMethod, ClientOptions};
+
+    /// Files on the mock server by path, with a version for their etag.
+    type Files = Arc<Mutex<BTreeMap<String, (Vec<u8>, u32)>>>;
+
+    /// Minimal WebDAV server answering GET and PUT (with If-Match) from
+    /// `files`. Returns its base URL.
+    fn mock_server(files: Files) -> String {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let port = server.server_addr().to_ip().unwrap().port();
+        std::thread::spawn(move || {
+            for mut request in server.incoming_requests() {
+                let path = request.url().to_string();
+                let if_match = request
+                    .headers()
+                    .iter()
+                    .find(|header| header.field.equiv("If-Match"))
+                    .map(|header| header.value.to_string());
+                let mut body = Vec::new();
+                request.as_reader().read_to_end(&mut body).unwrap();
+
+                let mut files = files.lock().unwrap();
+                let response = match request.method().as_str() {
+                    "GET" => match files.get(&path) {
+                        Some((content, _)) => tiny_http::Response::from_data(content.clone()),
+                        None => tiny_http::Response::from_data(Vec::new()).with_status_code(404),
+                    },
+                    "PUT" => {
+                        let current = files.get(&path).map(|(_, version)| format!("\"v{}\"", version));
+                        if if_match.is_some() && if_match != current {
+                            tiny_http::Response::from_data(Vec::new()).with_status_code(412)
+                        } else {
+                            let version = files.get(&path).map_or(1, |(_, version)| version + 1);
+                            files.insert(path, (body, version));
+                            let etag = tiny_http::Header::from_bytes("ETag", format!("\"v{}\"", version)).unwrap();
+                            tiny_http::Response::from_data(Vec::new()).with_status_code(201).with_header(etag)
+                        }
+                    }
+                    _ => tiny_http::Response::from_data(Vec::new()).with_status_code(405),
+                };
+                drop(files);
+                let _ = request.respond(response);
+            }
+        });
+        format!("http://127.0.0.1:{}/", port)
+    }
+
+    /// Replay a write based on version 1 of `/notes.txt` after the server
+    /// moved on to version 2, with `strategy`. Returns the server's files
+    /// and the ops left in the journal.
+    async fn replay_conflict(strategy: ConflictStrategy) -> (BTreeMap<String, (Vec<u8>, u32)>, usize) {
+        let files: Files = Arc::new(Mutex::new(BTreeMap::new()));
+        files.lock().unwrap().insert("/notes.txt".to_string(), (b"server".to_vec(), 2));
+        let url = mock_server(files.clone());
+        let auth = AuthMethod::Basic {
+            username: "alice".to_string(),
+            password: "secret".to_string(),
+        };
+        let webdav = WebDavClient::new(&[url], auth, &ClientOptions::default()).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("davfs-journal-{}", uuid::Uuid::new_v4()));
+        let journal = WriteJournal::open(&dir.join("journal.log")).unwrap().with_conflict_strategy(strategy);
+        journal
+            .append(JournalOp::Write {
+                path: "/notes.txt".to_string(),
+                offset: 0,
+                data: b"local".to_vec(),
+                etag: Some("\"v1\"".to_string()),
+            })
+            .unwrap();
+
+        journal.replay(&webdav).await.unwrap();
+        let left = journal.len();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let files = files.lock().unwrap().clone();
+        (files, left)
+    }
+
+    #[tokio::test]
+    async fn server_wins_discards_local_write() {
+        let (files, left) = replay_conflict(ConflictStrategy::ServerWins).await;
+        assert_eq!(left, 0);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files["/notes.txt"].0, b"server");
+    }
+
+    #[tokio::test]
+    async fn local_wins_overwrites_server_version() {
+        let (files, left) = replay_conflict(ConflictStrategy::LocalWins).await;
+        assert_eq!(left, 0);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files["/notes.txt"].0, b"local");
+    }
+
+    #[tokio::test]
+    async fn rename_local_keeps_both_versions() {
+        let (files, left) = replay_conflict(ConflictStrategy::default()).await;
+        assert_eq!(left, 0);
+        assert_eq!(files.len(), 2);
+        assert_eq!(files["/notes.txt"].0, b"server");
+
+        let (conflict_path, (content, _)) = files.iter().find(|(path, _)| *path != "/notes.txt").unwrap();
+        let timestamp = conflict_path.strip_prefix("/notes.txt.conflict-").unwrap();
+        assert_eq!(timestamp.len(), 14);
+        assert!(timestamp.bytes().all(|b| b.is_ascii_digit()));
+        assert_eq!(content, b"local");
+    }
+
+    #[tokio::test]
+    async fn fail_keeps_write_queued() {
+        let (files, left) = replay_conflict(ConflictStrategy::Fail).await;
+        assert_eq!(left, 1);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files["/notes.txt"].0, b"server");
+    }
+}
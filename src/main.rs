@@ -1,12 +1,36 @@
-use anyhow::Result;
-use clap::{Parser, Subcommand};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use tokio::signal::unix::{signal, SignalKind};
+use opentelemetry_otlp::WithExportConfig;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
 
+mod audit;
+mod buffer;
 mod config;
+mod content_cache;
+mod crypto;
+mod daemon;
 mod filesystem;
+mod filter;
+mod global_cache;
+mod ioctl;
 mod secrets;
 mod webdav;
 mod cache;
+mod caldav;
+mod journal;
+mod metrics;
+mod nextcloud;
+mod platform;
+mod prefetch;
+mod push;
+mod sync;
+mod systemd;
+mod tls;
+mod trash;
+mod uploads;
+mod watch;
 
 use config::MountConfig;
 use filesystem::DavFS;
@@ -14,11 +38,56 @@ use filesystem::DavFS;
 #[derive(Parser)]
 #[command(name = "davfs-sync")]
 #[command(about = "WebDAV FUSE filesystem with offline support", long_about = None)]
+#[command(after_help = ENVIRONMENT_HELP)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Log format: text, or json for log aggregation (ELK, Loki)
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Log to this file instead of stdout, rotated daily (<path>.YYYY-MM-DD)
+    #[arg(long, global = true)]
+    log_file: Option<std::path::PathBuf>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum IoctlCommand {
+    /// Drop the cached listing of PATH and its directory
+    Invalidate,
+    /// List PATH and the directories below it into the cache
+    Prefetch,
+    /// Print cache and connection counters
+    Stats,
+}
+
+const ENVIRONMENT_HELP: &str = "\
+Environment variables:
+  DAVFS_SYNC_PASSWORD_<NAME>       Password of mount <NAME> (upper case, other characters as _)
+  DAVFS_SYNC_PASSWORD_FILE_<NAME>  File holding the password of mount <NAME>
+  DAVFS_SYNC_PASSWORD              Password of any mount without one of the above
+  DAVFS_CLIENT_CERT_PASSWORD       Password of a PKCS#12 client certificate
+  DAVFS_NO_PROXY, NO_PROXY         Hosts that bypass the proxy
+  HTTPS_PROXY, HTTP_PROXY, ALL_PROXY  Proxy for server requests
+  XDG_CONFIG_HOME                  Location of davfs-sync/mounts.toml
+  XDG_CACHE_HOME                   Location of caches and offline journals
+  XDG_STATE_HOME                   Location of daemon logs
+  XDG_RUNTIME_DIR                  Location of daemon PID files and shared cache usage
+  RUST_LOG                         Log filter, e.g. davfs_sync=debug
+  OTEL_EXPORTER_OTLP_ENDPOINT      OTLP collector to export request spans to
+
+The password variables take precedence over the Secret Service, but not over
+a password_env named in mounts.toml.";
+
+// Parsed once at startup, so the size of the Setup variant does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 enum Commands {
     /// Configure a new mount
@@ -26,9 +95,9 @@ enum Commands {
         /// Name of the mount
         name: String,
         
-        /// WebDAV URL
-        #[arg(long)]
-        url: String,
+        /// WebDAV URL; repeat for mirrors used when the first is unreachable
+        #[arg(long, required = true)]
+        url: Vec<String>,
         
         /// Username
         #[arg(long)]
@@ -37,17 +106,259 @@ enum Commands {
         /// Mount point path
         #[arg(long)]
         mount_point: String,
+        
+        /// Mount read-only
+        #[arg(long)]
+        read_only: bool,
+        
+        /// Authentication method: basic, digest or bearer
+        #[arg(long, default_value = "basic")]
+        auth_method: String,
+        
+        /// OAuth2 token endpoint used to refresh bearer tokens
+        #[arg(long)]
+        token_url: Option<String>,
+        
+        /// Extra CA certificate to trust (PEM or DER)
+        #[arg(long)]
+        ca_cert: Option<std::path::PathBuf>,
+        
+        /// Client certificate: PKCS#12 bundle, or PEM with --client-key
+        #[arg(long)]
+        client_cert: Option<std::path::PathBuf>,
+        
+        /// PKCS#8 PEM private key for --client-cert
+        #[arg(long, requires = "client_cert")]
+        client_key: Option<std::path::PathBuf>,
+        
+        /// Do not verify the server's TLS certificate (INSECURE)
+        #[arg(long)]
+        no_verify: bool,
+
+        /// Refuse TLS versions older than this: 1.2 or 1.3
+        #[arg(long, value_parser = ["1.2", "1.3"])]
+        tls_min_version: Option<String>,
+
+        /// Only offer this TLS cipher suite, e.g. TLS13_AES_256_GCM_SHA384
+        /// (repeatable)
+        #[arg(long = "tls-cipher")]
+        tls_ciphers: Vec<String>,
+        
+        /// Only sync files matching this glob (repeatable)
+        #[arg(long = "include")]
+        include_patterns: Vec<String>,
+        
+        /// Never open or sync paths matching this glob, e.g. '**/*.tmp' (repeatable)
+        #[arg(long = "exclude")]
+        exclude_patterns: Vec<String>,
+        
+        /// Download bandwidth limit in bytes per second, e.g. 10M
+        #[arg(long, value_parser = parse_rate)]
+        download_limit: Option<u64>,
+        
+        /// Upload bandwidth limit in bytes per second, e.g. 500K
+        #[arg(long, value_parser = parse_rate)]
+        upload_limit: Option<u64>,
+
+        /// Encrypt files and names with a new age key before upload
+        #[arg(long)]
+        encrypt: bool,
     },
     
     /// Mount filesystem (stays in foreground)
     Mount {
         /// Name of the mount to use
         name: String,
+        
+        /// Serve Prometheus metrics on this port (localhost only)
+        #[arg(long)]
+        metrics_port: Option<u16>,
+        
+        /// Run in the background; stop with `davfs-sync stop <name>`
+        #[arg(long)]
+        daemon: bool,
+
+        /// Let other users access the mount this time, without changing
+        /// the stored configuration
+        #[arg(long)]
+        allow_other: bool,
+
+        /// Directory listings kept in memory by all running mounts together
+        #[arg(long, default_value_t = 1_000_000)]
+        global_cache_limit: usize,
+    },
+    
+    /// Stop a mount started with `mount --daemon`
+    Stop {
+        /// Name of the mount to stop
+        name: String,
+    },
+    
+    /// Generate a systemd service that mounts at boot or login
+    InstallSystemd {
+        /// Name of the mount
+        name: String,
+        
+        /// Install as a user service instead of system-wide
+        #[arg(long)]
+        user: bool,
+        
+        /// Also run `systemctl enable --now`
+        #[arg(long)]
+        enable: bool,
+    },
+    
+    /// Unmount a mounted filesystem
+    Unmount {
+        /// Name of the mount to unmount
+        name: String,
+    },
+    
+    /// Remove a mount configuration and its stored password
+    Remove {
+        /// Name of the mount to remove
+        name: String,
+        
+        /// Remove even if the mount is currently mounted
+        #[arg(long)]
+        force: bool,
+        
+        /// Do not ask for confirmation
+        #[arg(long, short)]
+        yes: bool,
     },
     
     /// List configured mounts
     List,
     
+    /// Show mount health and cache statistics
+    Status {
+        /// Only show this mount
+        name: Option<String>,
+        
+        /// Print machine-readable JSON
+        #[arg(long)]
+        json: bool,
+    },
+    
+    /// Search a mount's server for files by name, without listing every
+    /// directory
+    Search {
+        /// Name of the mount
+        name: String,
+
+        /// Name pattern; `%` matches anything (default: names containing
+        /// the query). With --xml, a complete DASL searchrequest document
+        query: String,
+
+        /// Directory to search below, relative to the mount root
+        #[arg(long, default_value = "/")]
+        scope: String,
+
+        /// Send QUERY as the raw SEARCH request body
+        #[arg(long)]
+        xml: bool,
+    },
+
+    /// List a mount's directories into its cache, e.g. before going
+    /// offline, without mounting it
+    Prefetch {
+        /// Name of the mount
+        name: String,
+
+        /// Levels below the start directory to list
+        #[arg(long, default_value_t = 3)]
+        depth: u32,
+
+        /// Directory to start at, relative to the mount root
+        #[arg(long)]
+        path: Option<String>,
+    },
+
+    /// Keep a path's listings and file contents cached and refreshed, so
+    /// it stays available offline
+    Pin {
+        /// Name of the mount
+        name: String,
+
+        /// File or directory, relative to the mount root
+        remote_path: String,
+    },
+
+    /// Stop keeping a pinned path cached
+    Unpin {
+        /// Name of the mount
+        name: String,
+
+        /// File or directory, relative to the mount root
+        remote_path: String,
+    },
+
+    /// List the events of a CalDAV calendar on a mount's server
+    ListCalendar {
+        /// Name of the mount
+        name: String,
+
+        /// Calendar collection, relative to the mount root
+        path: String,
+    },
+
+    /// Send a control command to a running mount
+    Ioctl {
+        /// Mount point, or any file or directory in the mount
+        mount_point: std::path::PathBuf,
+
+        command: IoctlCommand,
+
+        /// Path relative to MOUNT_POINT for invalidate and prefetch
+        #[arg(default_value = "")]
+        path: String,
+    },
+
+    /// Move a deleted file or directory back out of a mount's trash
+    Restore {
+        /// Name of the mount
+        name: String,
+
+        /// Where it was deleted from, relative to the mount root
+        remote_path: String,
+    },
+
+    /// Permanently delete everything in a mount's trash
+    EmptyTrash {
+        /// Name of the mount
+        name: String,
+    },
+
+    /// Check a mount's configuration and server connection
+    Validate {
+        /// Name of the mount
+        name: String,
+    },
+
+    /// Write a mount's configuration to a TOML file, without credentials
+    Export {
+        /// Name of the mount
+        name: String,
+
+        /// File to write, in the format of mounts.toml
+        #[arg(long, short)]
+        output: std::path::PathBuf,
+    },
+
+    /// Configure the mounts in an exported TOML file, asking for their
+    /// credentials
+    Import {
+        /// File written by export (or a mounts.toml)
+        input: std::path::PathBuf,
+    },
+
+    /// Manage the downloaded file contents shared by all mounts
+    ContentCache {
+        #[command(subcommand)]
+        action: ContentCacheAction,
+    },
+
     /// Setup mount using Nextcloud Desktop credentials
     SetupFromNextcloud {
         /// Name for this mount
@@ -60,209 +371,1452 @@ enum Commands {
         /// Mount point path
         #[arg(long)]
         mount_point: String,
+        
+        /// Mount read-only
+        #[arg(long)]
+        read_only: bool,
+    },
+
+    /// Setup mount by logging in to Nextcloud in the browser (creates an
+    /// app password)
+    SetupFromNextcloudFlow {
+        /// Name for this mount
+        name: String,
+
+        /// Nextcloud server URL (e.g., https://cloud.example.com)
+        #[arg(long)]
+        server: String,
+
+        /// Remote path on WebDAV server (e.g., /Photos or /Documents)
+        #[arg(long, default_value = "/")]
+        remote_path: String,
+
+        /// Mount point path
+        #[arg(long)]
+        mount_point: String,
+
+        /// Mount read-only
+        #[arg(long)]
+        read_only: bool,
     },
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Initialize logging
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "davfs_sync=info".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+#[derive(Subcommand)]
+enum ContentCacheAction {
+    /// Remove cached file contents
+    Evict {
+        /// Only remove contents downloaded longer ago than this, e.g. 7d or 12h
+        #[arg(long, value_parser = parse_age)]
+        older_than: std::time::Duration,
+    },
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    // Forking has to happen before the runtime starts its threads
+    let ready = match &cli.command {
+        Commands::Mount { name, daemon: true, .. } => Some(daemon::daemonize(name)?),
+        _ => None,
+    };
+
+    tokio::runtime::Runtime::new()?.block_on(run(cli, ready))
+}
+
+async fn run(cli: Cli, ready: Option<daemon::ReadyNotifier>) -> Result<()> {
+    let otel_tracer = otel_tracer(otel_endpoint(&cli.command).await.as_deref())?;
+
+    // Initialize logging
+    let fmt_layer = tracing_subscriber::fmt::layer()
+        .with_ansi(cli.log_file.is_none())
+        .with_writer(log_writer(cli.log_file.as_deref())?);
+    let fmt_layer = match cli.log_format {
+        LogFormat::Text => fmt_layer.boxed(),
+        LogFormat::Json => fmt_layer.json().with_current_span(true).with_span_list(true).boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "davfs_sync=info".into()),
+        )
+        .with(fmt_layer)
+        .with(otel_tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)))
+        .init();
+
+    match cli.command {
+        Commands::Setup {
+            name,
+            url,
+            username,
+            mount_point,
+            read_only,
+            auth_method,
+            token_url,
+            ca_cert,
+            client_cert,
+            client_key,
+            no_verify,
+            tls_min_version,
+            tls_ciphers,
+            include_patterns,
+            exclude_patterns,
+            download_limit,
+            upload_limit,
+            encrypt,
+        } => {
+            let age_key = encrypt.then(crypto::Cipher::generate_identity);
+            let config = MountConfig {
+                name,
+                urls: url,
+                username,
+                mount_point: mount_point.into(),
+                read_only,
+                mount_id: Some(uuid::Uuid::new_v4()),
+                auth_method,
+                tls: webdav::TlsConfig {
+                    ca_cert_path: ca_cert,
+                    client_cert_path: client_cert,
+                    client_key_path: client_key,
+                    danger_accept_invalid_certs: no_verify,
+                    tls_min_version,
+                    tls_ciphers,
+                },
+                include_patterns,
+                exclude_patterns,
+                download_bps: download_limit,
+                upload_bps: upload_limit,
+                encryption: age_key
+                    .as_ref()
+                    .map(|(_, recipient)| crypto::EncryptionConfig { recipient: recipient.clone() }),
+                ..Default::default()
+            };
+            setup_mount(config, token_url, age_key.map(|(identity, _)| identity)).await?;
+        }
+        Commands::Mount { name, metrics_port, allow_other, global_cache_limit, .. } => {
+            mount_filesystem(name, metrics_port, allow_other, global_cache_limit, ready).await?;
+        }
+        Commands::Stop { name } => {
+            daemon::stop(&name).await?;
+        }
+        Commands::InstallSystemd { name, user, enable } => {
+            install_systemd(name, user, enable).await?;
+        }
+        Commands::Unmount { name } => {
+            unmount_filesystem(name).await?;
+        }
+        Commands::Remove { name, force, yes } => {
+            remove_mount(name, force, yes).await?;
+        }
+        Commands::List => {
+            list_mounts().await?;
+        }
+        Commands::Status { name, json } => {
+            show_status(name, json).await?;
+        }
+        Commands::Search { name, query, scope, xml } => {
+            search(name, query, scope, xml).await?;
+        }
+        Commands::Prefetch { name, depth, path } => {
+            prefetch_mount(name, depth, path).await?;
+        }
+        Commands::Pin { name, remote_path } => {
+            pin_path(&name, &remote_path, true).await?;
+        }
+        Commands::Unpin { name, remote_path } => {
+            pin_path(&name, &remote_path, false).await?;
+        }
+        Commands::ListCalendar { name, path } => {
+            list_calendar(&name, &path).await?;
+        }
+        Commands::Ioctl { mount_point, command, path } => {
+            send_ioctl(&mount_point, command, &path)?;
+        }
+        Commands::Restore { name, remote_path } => {
+            restore_from_trash(name, remote_path).await?;
+        }
+        Commands::EmptyTrash { name } => {
+            empty_trash(name).await?;
+        }
+        Commands::Validate { name } => {
+            let (config, auth) = load_mount(&name).await?;
+            let problems = validate_mount(&config, auth).await;
+            if problems > 0 {
+                anyhow::bail!("Mount '{}' has {} problem(s)", name, problems);
+            }
+        }
+        Commands::Export { name, output } => {
+            export_mount(name, output).await?;
+        }
+        Commands::Import { input } => {
+            import_mounts(input).await?;
+        }
+        Commands::ContentCache { action: ContentCacheAction::Evict { older_than } } => {
+            evict_content(older_than)?;
+        }
+        Commands::SetupFromNextcloud {
+            name,
+            remote_path,
+            mount_point,
+            read_only,
+        } => {
+            setup_from_nextcloud(name, remote_path, mount_point, read_only).await?;
+        }
+        Commands::SetupFromNextcloudFlow {
+            name,
+            server,
+            remote_path,
+            mount_point,
+            read_only,
+        } => {
+            setup_from_nextcloud_flow(name, server, remote_path, mount_point, read_only).await?;
+        }
+    }
+
+    // Send the spans still queued for export
+    opentelemetry::global::shutdown_tracer_provider();
+    Ok(())
+}
+
+/// Writer for log lines: stdout, or `path` with the date appended, starting
+/// a new file every day.
+fn log_writer(path: Option<&std::path::Path>) -> Result<BoxMakeWriter> {
+    let Some(path) = path else {
+        return Ok(BoxMakeWriter::new(std::io::stdout));
+    };
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("Invalid log file {}", path.display()))?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => std::path::Path::new("."),
+    };
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    Ok(BoxMakeWriter::new(tracing_appender::rolling::daily(dir, file_name)))
+}
+
+/// `otel_endpoint` of the mount about to be mounted. Errors loading it are
+/// left for the mount itself to report.
+async fn otel_endpoint(command: &Commands) -> Option<String> {
+    let Commands::Mount { name, .. } = command else {
+        return None;
+    };
+    if let Ok(Some(config)) = config::find_file_mount(name) {
+        return config.otel_endpoint;
+    }
+    let secret_store = secrets::SecretStore::new().await.ok()?;
+    secret_store.load_mount_config(name).await.ok()?.otel_endpoint
+}
+
+/// Tracer exporting spans over OTLP to `endpoint`, or to the collector
+/// named by `OTEL_EXPORTER_OTLP_ENDPOINT`. `None` if neither is set.
+fn otel_tracer(endpoint: Option<&str>) -> Result<Option<opentelemetry_sdk::trace::Tracer>> {
+    if endpoint.is_none() && std::env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").is_none() {
+        return Ok(None);
+    }
+    let mut exporter = opentelemetry_otlp::SpanExporter::builder().with_tonic();
+    if let Some(endpoint) = endpoint {
+        exporter = exporter.with_endpoint(endpoint);
+    }
+    let provider = opentelemetry_sdk::trace::TracerProvider::builder()
+        .with_batch_exporter(
+            exporter.build().context("Failed to set up OTLP export")?,
+            opentelemetry_sdk::runtime::Tokio,
+        )
+        .with_resource(opentelemetry_sdk::Resource::new([opentelemetry::KeyValue::new(
+            "service.name",
+            "davfs-sync",
+        )]))
+        .build();
+    let tracer = opentelemetry::trace::TracerProvider::tracer(&provider, "davfs-sync");
+    opentelemetry::global::set_tracer_provider(provider);
+    Ok(Some(tracer))
+}
+
+async fn setup_mount(config: MountConfig, token_url: Option<String>, age_identity: Option<String>) -> Result<()> {
+    // Reject bad patterns now rather than at mount time
+    filter::PathFilter::from_config(&config)?;
+
+    let name = config.name.clone();
+    println!("Setting up mount: {}", name);
+    println!("URL: {}", config.urls.join(", "));
+    println!("Username: {}", config.username);
+    println!("Mount point: {}", config.mount_point.display());
+    println!("Auth method: {}", config.auth_method);
+
+    if config.tls.danger_accept_invalid_certs {
+        eprintln!("\n⚠ WARNING: TLS certificate verification will be DISABLED for this mount.");
+        eprintln!("⚠ Anyone on the network path can read and modify your files and credentials.\n");
+    }
+
+    let auth = prompt_auth(&config, token_url)?;
+    if let Some(identity) = &age_identity {
+        secrets::SecretStore::new().await?.store_age_key(&name, identity).await?;
+    }
+    store_mount(&config, &auth).await?;
+
+    println!("\n✓ Mount '{}' configured successfully!", name);
+    if age_identity.is_some() {
+        println!("✓ Files are encrypted with a new age key before upload");
+        println!("⚠ Without the key they cannot be read; back it up from the keyring:");
+        println!("  secret-tool lookup application davfs-sync type age-key mount {}", name);
+    }
+    validate_mount(&config, auth).await;
+    println!("\nTo mount:");
+    println!("  davfs-sync mount {}", name);
+
+    Ok(())
+}
+
+/// Ask for the credentials `config.auth_method` needs.
+fn prompt_auth(config: &MountConfig, token_url: Option<String>) -> Result<webdav::AuthMethod> {
+    Ok(match (config.auth_method.as_str(), token_url) {
+        ("basic", None) => webdav::AuthMethod::Basic {
+            username: config.username.clone(),
+            password: prompt_secret("Password: ")?,
+        },
+        ("digest", None) => webdav::AuthMethod::Digest {
+            username: config.username.clone(),
+            password: prompt_secret("Password: ")?,
+        },
+        ("basic" | "digest", Some(_)) => anyhow::bail!("--token-url requires --auth-method bearer"),
+        ("bearer", None) => webdav::AuthMethod::Bearer {
+            token: prompt_secret("Token: ")?,
+        },
+        ("bearer", Some(token_url)) => webdav::AuthMethod::BearerWithRefresh {
+            access_token: prompt_secret("Access token: ")?,
+            refresh_token: prompt_secret("Refresh token: ")?,
+            // Expiry is unknown, so refresh on first use
+            expires_at: chrono::Utc::now(),
+            token_url,
+        },
+        (other, _) => anyhow::bail!("Unknown auth method '{}' (expected basic, digest or bearer)", other),
+    })
+}
+
+/// Store config and credentials in Secret Service.
+async fn store_mount(config: &MountConfig, auth: &webdav::AuthMethod) -> Result<()> {
+    let secret_store = secrets::SecretStore::new().await?;
+    secret_store.store_mount_config(&config.name, config).await?;
+    secret_store.store_auth(&config.name, auth).await?;
+    if !config.tls.is_empty() {
+        secret_store.store_tls_config(&config.name, &config.tls).await?;
+    }
+    Ok(())
+}
+
+/// Check `config` and report each problem with how to fix it. Returns the
+/// number of problems found.
+async fn validate_mount(config: &MountConfig, auth: webdav::AuthMethod) -> usize {
+    println!("\nValidating mount '{}'...", config.name);
+    let mut problems = 0;
+    let mut problem = |message: String| {
+        println!("✗ {}", message);
+        problems += 1;
+    };
+
+    for url in &config.urls {
+        match url::Url::parse(url) {
+            Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+            Ok(parsed) => problem(format!("{} uses {}://; only http and https are supported", url, parsed.scheme())),
+            Err(e) => problem(format!("{} is not a valid URL: {}", url, e)),
+        }
+    }
+
+    let mount_point = &config.mount_point;
+    if !mount_point.exists() {
+        problem(format!(
+            "The mount point {} does not exist; create it with: mkdir -p {}",
+            mount_point.display(),
+            mount_point.display()
+        ));
+    } else if !mount_point.is_dir() {
+        problem(format!("The mount point {} is not a directory", mount_point.display()));
+    } else if platform::is_mounted(mount_point) {
+        println!("⚠ {} is already mounted", mount_point.display());
+    } else if nix::unistd::access(mount_point, nix::unistd::AccessFlags::W_OK).is_err() {
+        problem(format!(
+            "The mount point {} is not writable; fix it with: sudo chown $USER {}",
+            mount_point.display(),
+            mount_point.display()
+        ));
+    }
+
+    if let Err(e) = filter::PathFilter::from_config(config) {
+        problem(format!("Invalid include/exclude pattern: {:#}", e));
+    }
+    let tls = &config.tls;
+    for path in [&tls.ca_cert_path, &tls.client_cert_path, &tls.client_key_path].into_iter().flatten() {
+        if !path.is_file() {
+            problem(format!("Certificate file {} does not exist", path.display()));
+        }
+    }
+
+    if let Some(encryption) = &config.encryption {
+        match load_cipher(&config.name, encryption).await {
+            Ok(_) => println!("✓ Files are encrypted to {}", encryption.recipient),
+            Err(e) => problem(format!("{:#}", e)),
+        }
+    }
+
+    let webdav = match webdav::WebDavClient::new(&config.urls, auth, &client_options(config)) {
+        Ok(webdav) => webdav,
+        Err(e) => {
+            problem(format!("Cannot set up the HTTP client: {:#}", e));
+            return problems;
+        }
+    };
+    match webdav.detect_capabilities().await {
+        Ok(capabilities) => {
+            println!("✓ Server reachable at {}", webdav.active_url());
+            if !config.read_only && !capabilities.class2 {
+                println!("⚠ The server does not support locking; files are not locked while open for writing");
+            }
+        }
+        Err(e) => {
+            problem(format!(
+                "Cannot reach the server: {:#}; check the URL and your network or proxy settings",
+                e
+            ));
+            return problems;
+        }
+    }
+    match webdav.test_connection().await {
+        Ok(()) => println!("✓ Logged in as {}", config.username),
+        Err(e) => match e.downcast_ref::<webdav::DavStatusError>().map(|e| e.status) {
+            Some(reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN) => problem(format!(
+                "The server rejected the credentials; store new ones with: davfs-sync setup {} ...",
+                config.name
+            )),
+            Some(reqwest::StatusCode::NOT_FOUND) => {
+                problem(format!("{} does not exist on the server; check the path in the URL", config.url()))
+            }
+            _ => problem(format!("WebDAV request failed: {:#}", e)),
+        },
+    }
+
+    if problems == 0 {
+        println!("✓ Mount '{}' is valid", config.name);
+    }
+    problems
+}
+
+async fn export_mount(name: String, output: std::path::PathBuf) -> Result<()> {
+    let mut config = match config::find_file_mount(&name)? {
+        Some(config) => config,
+        None => {
+            let secret_store = secrets::SecretStore::new().await?;
+            let mut config = secret_store.load_mount_config(&name).await?;
+            config.tls = secret_store.load_tls_config(&name).await?;
+            config
+        }
+    };
+    // Inode numbers are per machine; import generates a new ID
+    config.mount_id = None;
+    if config.password_env.is_none() {
+        config.password_env = Some(secrets::password_env_var(&name));
+    }
+
+    std::fs::write(&output, config::export_toml(&config)?)
+        .with_context(|| format!("Failed to write {}", output.display()))?;
+    println!("✓ Exported '{}' to {}", name, output.display());
+    if !config.extra_headers.is_empty() {
+        println!("⚠ extra_headers were not exported, add them by hand");
+    }
+    Ok(())
+}
+
+async fn import_mounts(input: std::path::PathBuf) -> Result<()> {
+    let mounts = config::load_file_config(&input)?;
+    if mounts.is_empty() {
+        anyhow::bail!("No [[mount]] tables in {}", input.display());
+    }
+
+    for mut config in mounts {
+        filter::PathFilter::from_config(&config)?;
+        println!("Importing mount: {} ({})", config.name, config.url());
+        config.mount_id.get_or_insert_with(uuid::Uuid::new_v4);
+        // The keyring holds the credentials from now on
+        config.password_env = None;
+
+        let auth = prompt_auth(&config, None)?;
+        let webdav = webdav::WebDavClient::new(&config.urls, auth.clone(), &client_options(&config))?;
+        webdav
+            .test_connection()
+            .await
+            .with_context(|| format!("Cannot connect to {}", config.url()))?;
+        println!("✓ Connected to {}", config.url());
+
+        store_mount(&config, &auth).await?;
+        println!("✓ Mount '{}' imported", config.name);
+    }
+    Ok(())
+}
+
+/// Shared by the directory caches of this process, created on first use.
+static GLOBAL_CACHE: std::sync::OnceLock<std::sync::Arc<global_cache::GlobalCacheManager>> =
+    std::sync::OnceLock::new();
+
+/// The global cache manager, started with `limit` if this is the first use.
+fn global_cache(limit: usize) -> std::sync::Arc<global_cache::GlobalCacheManager> {
+    GLOBAL_CACHE
+        .get_or_init(|| {
+            let manager = std::sync::Arc::new(global_cache::GlobalCacheManager::new(limit));
+            manager.start();
+            manager
+        })
+        .clone()
+}
+
+async fn mount_filesystem(
+    name: String,
+    metrics_port: Option<u16>,
+    allow_other: bool,
+    global_cache_limit: usize,
+    ready: Option<daemon::ReadyNotifier>,
+) -> Result<()> {
+    println!("Loading mount configuration for '{}'...", name);
+
+    let (mut config, auth) = load_mount(&name).await?;
+    config.allow_other |= allow_other;
+    if config.allow_other && config.allow_root {
+        anyhow::bail!("allow_other and allow_root cannot both be set; allow_other includes root");
+    }
+    if (config.allow_other || config.allow_root) && !platform::may_allow_other() {
+        anyhow::bail!(
+            "Mounting with {} needs `user_allow_other` in /etc/fuse.conf.\n\
+             Add that line as root, or mount without it.",
+            if config.allow_other { "allow_other" } else { "allow_root" }
+        );
+    }
+
+    println!("Connecting to: {}", config.url());
+    println!("Mount point: {}", config.mount_point.display());
+
+    // Check if mount point is already mounted and try to unmount it
+    println!("Checking for existing mounts...");
+    
+    if platform::is_mounted(&config.mount_point) {
+        println!("Found existing mount, attempting to unmount...");
+        if platform::unmount(&config.mount_point) {
+            println!("✓ Successfully unmounted existing mount");
+            // Give it a moment to fully unmount
+            std::thread::sleep(std::time::Duration::from_millis(500));
+        } else {
+            // Try lazy unmount as fallback
+            println!("Regular unmount failed, trying lazy unmount...");
+            if platform::unmount_busy(&config.mount_point) {
+                println!("✓ Lazy unmount succeeded");
+                std::thread::sleep(std::time::Duration::from_millis(500));
+            } else {
+                anyhow::bail!("Failed to unmount existing mount at {}. Please run: {} {}",
+                    config.mount_point.display(), platform::UNMOUNT_BUSY.join(" "), config.mount_point.display());
+            }
+        }
+    } else {
+        // Even if not mounted, might be a stale directory - try unmounting anyway
+        platform::unmount_busy(&config.mount_point);
+    }
+
+    // Create mount point if it doesn't exist
+    std::fs::create_dir_all(&config.mount_point)?;
+
+    // Create WebDAV client
+    if config.tls.danger_accept_invalid_certs {
+        eprintln!("⚠ WARNING: TLS certificate verification is disabled for this mount");
+    }
+    let cipher = match &config.encryption {
+        Some(encryption) => Some(load_cipher(&name, encryption).await?),
+        None => None,
+    };
+    let metrics = std::sync::Arc::new(metrics::Metrics::default());
+    let webdav = webdav::WebDavClient::new(&config.urls, auth.clone(), &client_options(&config))?
+    .with_encryption(cipher)
+    .with_retry_policy(config.retry.clone())
+    .with_circuit_breaker(
+        config.failure_threshold,
+        std::time::Duration::from_secs(config.circuit_open_duration_secs),
+    )
+    .with_metrics(metrics.clone());
+
+    // Test connection
+    println!("Testing connection...");
+    let connection_error = match webdav.test_connection().await {
+        Ok(_) => {
+            println!("✓ Connected successfully!");
+            if config.tls.tls_min_version.is_none() && negotiates_legacy_tls(&config, auth, &webdav.active_url()).await {
+                println!(
+                    "⚠ The server negotiated TLS 1.0/1.1; once it supports TLS 1.2, set tls_min_version = \"1.2\" \
+                     so a downgrade is refused"
+                );
+            }
+            None
+        }
+        Err(e) => {
+            eprintln!("✗ Connection failed: {}", e);
+            eprintln!("The filesystem will mount, but operations will fail until connection is available.");
+            Some(e.to_string())
+        }
+    };
+
+    let capabilities = if connection_error.is_none() {
+        match webdav.detect_capabilities().await {
+            Ok(capabilities) => {
+                println!("Server capabilities: {}", capabilities.summary());
+                if !capabilities.class2 {
+                    println!("⚠ Server does not support locking; files are written without locks");
+                }
+                Some(capabilities)
+            }
+            Err(e) => {
+                eprintln!("⚠ Could not detect server capabilities: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let webdav = webdav.with_capabilities(capabilities);
+
+    // Open the persistent directory cache (5 second TTL)
+    let cache_dir = config::cache_dir(&config.name)
+        .ok_or_else(|| anyhow::anyhow!("Cannot determine cache directory (HOME not set)"))?;
+    let dir_cache = cache::DirectoryCache::open(
+        &cache_dir.join("meta.db"),
+        std::time::Duration::from_secs(5),
+        config.cache_max_entries,
+    )?
+    .with_max_memory_bytes(config.cache_max_memory_bytes)
+    .with_negative_ttl(std::time::Duration::from_secs(config.negative_ttl_secs))
+    .with_adaptive_ttl(config.adaptive_ttl)
+    .with_metrics(metrics.clone())
+    .with_global_cache(global_cache(global_cache_limit), &config.name);
+
+    // Large uploads cut off by the network resume where they stopped
+    let webdav = webdav.with_upload_log(Some(uploads::UploadLog::open(&cache_dir.join("meta.db"))?));
+
+    let content_dir = config::content_cache_dir()
+        .ok_or_else(|| anyhow::anyhow!("Cannot determine cache directory (HOME not set)"))?;
+    let content_cache = content_cache::ContentCache::open(&content_dir)?;
+
+    if let Some(port) = metrics_port {
+        metrics::serve(metrics, ([127, 0, 0, 1], port).into())?;
+        println!("Metrics available at http://127.0.0.1:{}/metrics", port);
+    }
+
+    // Changes made while offline are queued here and replayed later
+    let journal = journal::WriteJournal::open(&cache_dir.join("journal.log"))?
+        .with_conflict_strategy(config.conflict_strategy.clone());
+    if !journal.is_empty() {
+        println!("{} offline changes pending upload", journal.len());
+    }
+
+    // Nextcloud can announce remote changes instead of us waiting for TTLs;
+    // without notify_push, its activity feed still tells what changed
+    let mut activity_feed = None;
+    if capabilities.is_some_and(|c| c.nextcloud) {
+        let push = match webdav.notify_push_endpoint().await {
+            Ok(Some(endpoint)) if webdav.basic_credentials().await.is_some() => {
+                println!("✓ Listening for remote changes at {}", endpoint);
+                tokio::spawn(push::run(webdav.clone(), dir_cache.clone(), endpoint));
+                true
+            }
+            Ok(Some(_)) => {
+                println!("⚠ notify_push needs password authentication");
+                false
+            }
+            Ok(None) => {
+                tracing::debug!("Server has no notify_push endpoint");
+                false
+            }
+            Err(e) => {
+                eprintln!("⚠ Could not check for notify_push: {}", e);
+                false
+            }
+        };
+        // Activity paths are the hashed names on encrypted mounts
+        if !push && !webdav.encrypts() {
+            activity_feed = nextcloud::ActivityFeed::new(webdav.clone(), cache_dir.join("activity_since"));
+            if activity_feed.is_some() {
+                println!("✓ Polling the activity feed for remote changes");
+            }
+        }
+    }
+
+    // Drains the journal and refreshes listings whenever the server is back
+    let sync = std::sync::Arc::new(
+        sync::SyncManager::new(
+            webdav.clone(),
+            dir_cache.clone(),
+            journal,
+            std::time::Duration::from_secs(config.sync_interval_secs.max(1)),
+            cache_dir.join("sync.json"),
+        )
+        .with_activity_feed(activity_feed)
+        .with_content_cache(content_cache.clone()),
+    );
+    tokio::spawn(sync.clone().run());
+
+    // Create filesystem
+    let poll_watcher = (config.poll_interval_secs > 0).then(|| {
+        std::sync::Arc::new(watch::PollWatcher::new(
+            webdav.clone(),
+            dir_cache.clone(),
+            std::time::Duration::from_secs(config.poll_interval_secs.into()),
+        ))
+    });
+    let fs = DavFS::new(
+        webdav.clone(),
+        dir_cache,
+        sync.clone(),
+        filter::PathFilter::from_config(&config)?,
+    )
+    .with_inode_seed(config.inode_seed())
+    .with_capabilities(capabilities)
+    .with_mount_config(config.clone())
+    .with_content_cache(content_cache)
+    .with_prefetch(config.prefetch_strategy, config.prefetch_concurrency)
+    .with_audit_log(config.audit_log.as_deref().map(audit::AuditLog::open).transpose()?)
+    .with_poll_watcher(poll_watcher.clone());
+    
+    // Start aggressive background prefetching
+    println!("Starting background prefetch...");
+    fs.prefetch_initial();
+
+    println!("\nMounting filesystem at {}...", config.mount_point.display());
+    println!("Press Ctrl+C to unmount\n");
+
+    // Mount options - minimal set to avoid permission issues
+    let mut options = vec![
+        fuser::MountOption::FSName("davfs-sync".to_string()),
+        if config.read_only {
+            fuser::MountOption::RO
+        } else {
+            fuser::MountOption::RW
+        },
+    ];
+    if config.allow_other || config.allow_root {
+        options.push(if config.allow_other {
+            fuser::MountOption::AllowOther
+        } else {
+            fuser::MountOption::AllowRoot
+        });
+        // Everything is owned by the mounting user; have the kernel check
+        // the mode bits so others only get what they allow
+        options.push(fuser::MountOption::DefaultPermissions);
+    }
+    options.extend(platform::mount_options(&config.name));
+
+    // Ctrl+C and SIGTERM unmount; the filesystem flushes its buffers as the
+    // session ends and its run below returns. SIGHUP reloads credentials.
+    let mount_point_for_signal = config.mount_point.clone();
+    let name_for_signal = config.name.clone();
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => println!("\n\nReceived Ctrl+C, unmounting..."),
+                _ = terminate.recv() => println!("Received SIGTERM, unmounting..."),
+                _ = hangup.recv() => {
+                    reload_credentials(&name_for_signal, &webdav).await;
+                    continue;
+                }
+            }
+            break;
+        }
+        unmount_for_shutdown(&mount_point_for_signal);
+    });
+
+    // Let `mount --daemon` exit now that everything is set up
+    match (ready, connection_error) {
+        (Some(ready), None) => ready.connected(),
+        (Some(ready), Some(error)) => ready.offline(&error),
+        (None, _) => {}
+    }
+
+    // Spawn blocking mount operation in a separate thread to avoid runtime conflicts
+    let mount_point = config.mount_point.clone();
+    let mount_result = tokio::task::spawn_blocking(move || {
+        let mut session = fuser::Session::new(fs, &mount_point, &options)?;
+        if let Some(watcher) = poll_watcher {
+            watcher.start(session.notifier());
+        }
+        session.run()
+    }).await?;
+
+    daemon::remove_pid_file(&config.name);
+    mount_result?;
+    println!("\nFilesystem unmounted.");
+
+    // Last chance to upload changes made while offline
+    let pending = sync.journal().len();
+    if pending > 0 {
+        println!("Replaying {} pending offline changes...", pending);
+        sync.sync_once().await;
+        let left = sync.journal().len();
+        if left > 0 {
+            println!("{} changes remain queued for the next mount", left);
+        }
+    }
+    Ok(())
+}
+
+/// Unmount for shutdown, detaching lazily if files are still open so the
+/// FUSE session can end once they are closed.
+fn unmount_for_shutdown(mount_point: &std::path::Path) {
+    if !platform::unmount(mount_point) {
+        println!("Mount is busy, detaching lazily...");
+        platform::unmount_busy(mount_point);
+    }
+}
+
+/// Re-read the mount's credentials (e.g. after rotating a password) and
+/// use them for all further requests. Credentials the server rejects are
+/// rolled back.
+async fn reload_credentials(name: &str, webdav: &webdav::WebDavClient) {
+    println!("Received SIGHUP, reloading credentials...");
+    let auth = match load_mount(name).await {
+        Ok((_, auth)) => auth,
+        Err(e) => {
+            eprintln!("✗ Credential reload failed, keeping old credentials: {:#}", e);
+            return;
+        }
+    };
+
+    let old_auth = webdav.set_auth(auth).await;
+    match webdav.test_connection().await {
+        Ok(()) => {
+            tracing::info!("Server accepted the reloaded credentials");
+            println!("✓ Credentials refreshed; other settings take effect on the next mount");
+        }
+        Err(e) => match e.downcast_ref::<webdav::DavStatusError>().map(|e| e.status) {
+            Some(reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN) => {
+                webdav.set_auth(old_auth).await;
+                tracing::warn!("Server rejected the reloaded credentials: {:#}", e);
+                eprintln!("✗ Credential reload failed, keeping old credentials: the server rejected them");
+            }
+            // Can't tell whether they work; the old ones may well be revoked
+            _ => {
+                tracing::warn!("Could not check the reloaded credentials: {:#}", e);
+                println!("⚠ Credentials refreshed, but the server could not be reached to check them");
+            }
+        },
+    }
+}
+
+/// Load a mount's config and credentials, preferring the TOML mounts file
+/// and falling back to the Secret Service.
+async fn load_mount(name: &str) -> Result<(MountConfig, webdav::AuthMethod)> {
+    let env_password = secrets::SecretStore::load_password_env(name)?;
+
+    if let Some(config) = config::find_file_mount(name)? {
+        let secret = match (config.password_env.as_deref(), env_password) {
+            (Some(var), _) => std::env::var(var)
+                .map_err(|_| anyhow::anyhow!("Environment variable {} is not set", var))?,
+            (None, Some(password)) => password,
+            (None, None) => anyhow::bail!(
+                "Mount '{}' in the mounts file has no password_env and DAVFS_SYNC_PASSWORD is not set",
+                name
+            ),
+        };
+        let auth = auth_from_secret(&config, secret)?;
+        return Ok((config, auth));
+    }
+
+    let secret_store = secrets::SecretStore::new().await?;
+    let mut config = secret_store.load_mount_config(name).await?;
+    config.tls = secret_store.load_tls_config(name).await?;
+    if let Some(password) = env_password {
+        tracing::info!("Using the password for '{}' from the environment", name);
+        let auth = auth_from_secret(&config, password)?;
+        return Ok((config, auth));
+    }
+    // Mounts set up before bearer support only have a password item
+    let auth = match secret_store.load_auth(name).await? {
+        Some(auth) => auth,
+        None => webdav::AuthMethod::Basic {
+            username: config.username.clone(),
+            password: secret_store.load_password(name).await?,
+        },
+    };
+    Ok((config, auth))
+}
+
+/// Cipher of an encrypted mount, with the age key stored at setup.
+async fn load_cipher(name: &str, encryption: &crypto::EncryptionConfig) -> Result<crypto::Cipher> {
+    let identity = secrets::SecretStore::new()
+        .await?
+        .load_age_key(name)
+        .await?
+        .with_context(|| format!("No age key stored for encrypted mount '{}'", name))?;
+    crypto::Cipher::new(encryption, &identity)
+}
+
+/// Credentials for `config` from a password, or a token for bearer auth.
+fn auth_from_secret(config: &MountConfig, secret: String) -> Result<webdav::AuthMethod> {
+    Ok(match config.auth_method.as_str() {
+        "basic" => webdav::AuthMethod::Basic {
+            username: config.username.clone(),
+            password: secret,
+        },
+        "digest" => webdav::AuthMethod::Digest {
+            username: config.username.clone(),
+            password: secret,
+        },
+        "bearer" => webdav::AuthMethod::Bearer { token: secret },
+        other => anyhow::bail!("Mount '{}' has unknown auth_method '{}'", config.name, other),
+    })
+}
+
+fn client_options(config: &MountConfig) -> webdav::ClientOptions {
+    webdav::ClientOptions {
+        no_proxy: config.no_proxy.clone()
+            .or_else(|| std::env::var("DAVFS_NO_PROXY").ok()),
+        socks5_proxy: config.socks5_proxy.clone(),
+        tls: config.tls.clone(),
+        download_bps: config.download_bps,
+        upload_bps: config.upload_bps,
+        chunk_size: Some(config.chunk_size_bytes.max(1)),
+        http2: config.use_http2,
+        load_balance: config.load_balance,
+        pool_max_idle_per_host: Some(config.pool_max_idle_per_host),
+        pool_idle_timeout: Some(std::time::Duration::from_secs(config.pool_idle_timeout_secs)),
+        connection_verbose: config.connection_verbose,
+        user_agent: config.user_agent.clone(),
+        extra_headers: config.extra_headers.clone(),
+        verify_checksums: config.checksum_verify,
+        atomic_upload: config.atomic_upload,
+    }
+}
+
+/// Whether the server at `url` only speaks TLS older than 1.2: it was
+/// reachable, but a client refusing those versions cannot connect.
+async fn negotiates_legacy_tls(config: &MountConfig, auth: webdav::AuthMethod, url: &url::Url) -> bool {
+    if url.scheme() != "https" {
+        return false;
+    }
+    let mut options = client_options(config);
+    options.tls.tls_min_version = Some("1.2".to_string());
+    let Ok(probe) = webdav::WebDavClient::new(&[url.to_string()], auth, &options) else {
+        return false;
+    };
+    let probe = probe.with_retry_policy(webdav::RetryPolicy { max_attempts: 1, ..config.retry.clone() });
+    probe.test_connection().await.is_err_and(|e| is_tls_version_error(&e))
+}
+
+/// Whether `err` is a TLS handshake refused over the protocol version, and
+/// not e.g. a timeout.
+fn is_tls_version_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<rustls::Error>() {
+            return matches!(
+                e,
+                rustls::Error::PeerIncompatible(_) | rustls::Error::AlertReceived(rustls::AlertDescription::ProtocolVersion)
+            );
+        }
+        // native-tls only has messages, from OpenSSL or the platform library
+        let message = cause.to_string().to_lowercase();
+        ["protocol version", "unsupported protocol", "wrong version number", "handshake failure"]
+            .iter()
+            .any(|needle| message.contains(needle))
+    })
+}
+
+/// Parse a byte rate with an optional SI suffix: `500K`, `10M`, `1G`.
+fn parse_rate(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, multiplier) = match value.char_indices().last() {
+        Some((idx, 'k' | 'K')) => (&value[..idx], 1_000),
+        Some((idx, 'm' | 'M')) => (&value[..idx], 1_000_000),
+        Some((idx, 'g' | 'G')) => (&value[..idx], 1_000_000_000),
+        _ => (value, 1),
+    };
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid rate '{}', expected e.g. 500K or 10M", value))?;
+    if !number.is_finite() || number <= 0.0 {
+        return Err("rate must be positive".to_string());
+    }
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Parse an age with a unit suffix: `30s`, `15m`, `12h`, `7d`.
+fn parse_age(value: &str) -> Result<std::time::Duration, String> {
+    let value = value.trim();
+    let (number, unit_secs) = match value.char_indices().last() {
+        Some((idx, 's')) => (&value[..idx], 1),
+        Some((idx, 'm')) => (&value[..idx], 60),
+        Some((idx, 'h')) => (&value[..idx], 3600),
+        Some((idx, 'd')) => (&value[..idx], 86400),
+        _ => return Err(format!("invalid age '{}', expected e.g. 12h or 7d", value)),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid age '{}', expected e.g. 12h or 7d", value))?;
+    Ok(std::time::Duration::from_secs(number * unit_secs))
+}
+
+/// Prompt for a secret on the terminal without echoing it.
+fn prompt_secret(prompt: &str) -> Result<String> {
+    use std::io::Write;
+
+    print!("{}", prompt);
+    std::io::stdout().flush()?;
+    Ok(rpassword::read_password()?)
+}
+
+async fn install_systemd(name: String, user: bool, enable: bool) -> Result<()> {
+    let config = match config::find_file_mount(&name)? {
+        Some(config) => config,
+        None => secrets::SecretStore::new().await?.load_mount_config(&name).await?,
+    };
+
+    let path = systemd::install(&config, user)?;
+    println!("✓ Wrote {}", path.display());
+
+    let commands = systemd::activation_commands(&name, user);
+    if enable {
+        for command in &commands {
+            println!("Running: {}", systemd::display_command(command));
+            let status = std::process::Command::new(&command[0])
+                .args(&command[1..])
+                .status()
+                .with_context(|| format!("Failed to run {}", command[0]))?;
+            if !status.success() {
+                anyhow::bail!("{} failed: {}", systemd::display_command(command), status);
+            }
+        }
+        println!("✓ Enabled and started {}", systemd::unit_name(&name));
+    } else {
+        println!("\nTo enable and start the mount, run:");
+        for command in &commands {
+            println!("  {}", systemd::display_command(command));
+        }
+    }
+
+    Ok(())
+}
+
+async fn unmount_filesystem(name: String) -> Result<()> {
+    println!("Loading mount configuration for '{}'...", name);
+
+    let config = match config::find_file_mount(&name)? {
+        Some(config) => config,
+        None => secrets::SecretStore::new().await?.load_mount_config(&name).await?,
+    };
+
+    println!("Mount point: {}", config.mount_point.display());
+
+    if !platform::is_mounted(&config.mount_point) {
+        println!("Not mounted, nothing to do.");
+        return Ok(());
+    }
+
+    println!("Unmounting...");
+    if !platform::unmount(&config.mount_point) {
+        println!("Regular unmount failed, trying lazy unmount...");
+        platform::unmount_busy(&config.mount_point);
+    }
+
+    if platform::is_mounted(&config.mount_point) {
+        anyhow::bail!("Failed to unmount {}. Please run: {} {}",
+            config.mount_point.display(), platform::UNMOUNT_BUSY.join(" "), config.mount_point.display());
+    }
+
+    println!("✓ Unmounted '{}'", name);
+    Ok(())
+}
+
+async fn remove_mount(name: String, force: bool, yes: bool) -> Result<()> {
+    use std::io::Write;
+
+    let secret_store = secrets::SecretStore::new().await?;
+    let config = secret_store.load_mount_config(&name).await?;
+
+    if platform::is_mounted(&config.mount_point) && !force {
+        anyhow::bail!("Mount '{}' is currently mounted at {}. Unmount it first or pass --force",
+            name, config.mount_point.display());
+    }
+
+    if !yes {
+        println!("This will remove the configuration and password for '{}'.", name);
+        print!("Continue? [y/N] ");
+        std::io::stdout().flush()?;
+
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            println!("Aborted.");
+            return Ok(());
+        }
+    }
+
+    secret_store.delete_mount(&name).await?;
+
+    println!("✓ Mount '{}' removed", name);
+    Ok(())
+}
+
+/// Check whether `path` is a mount point using `mountpoint -q`.
+/// Health of one mount as reported by `status`.
+#[derive(serde::Serialize)]
+struct MountStatus {
+    name: String,
+    url: String,
+    mount_point: std::path::PathBuf,
+    mounted: bool,
+    reachable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Entries in the remote root directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remote_entries: Option<usize>,
+    /// Size of the remote root; folders count only where the server
+    /// reports their size (ownCloud/Nextcloud `oc:size`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    remote_bytes: Option<u64>,
+    pending_journal_ops: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sync: Option<sync::SyncStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache: Option<cache::CacheStats>,
+}
 
-    let cli = Cli::parse();
+async fn show_status(name: Option<String>, json: bool) -> Result<()> {
+    // Mounts from the mounts file first, then the keyring
+    let mut names: Vec<String> = match config::default_config_path() {
+        Some(path) if path.exists() => config::load_file_config(&path)?
+            .into_iter()
+            .map(|m| m.name)
+            .collect(),
+        _ => Vec::new(),
+    };
+    match secrets::SecretStore::new().await {
+        Ok(secret_store) => names.extend(secret_store.list_mounts().await?),
+        Err(e) if !names.is_empty() => tracing::debug!("Keyring unavailable: {}", e),
+        Err(e) => return Err(e),
+    }
+    let mut seen = std::collections::HashSet::new();
+    names.retain(|n| seen.insert(n.clone()) && name.as_ref().is_none_or(|want| want == n));
 
-    match cli.command {
-        Commands::Setup {
-            name,
-            url,
-            username,
-            mount_point,
-        } => {
-            setup_mount(name, url, username, mount_point).await?;
+    if let Some(name) = &name {
+        if names.is_empty() {
+            anyhow::bail!("Mount '{}' not found", name);
         }
-        Commands::Mount { name } => {
-            mount_filesystem(name).await?;
+    }
+
+    let mut statuses = Vec::new();
+    for name in names {
+        statuses.push(mount_status(&name).await?);
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&statuses)?);
+        return Ok(());
+    }
+
+    for status in &statuses {
+        println!("{}", status.name);
+        println!("  URL:             {}", status.url);
+        println!("  Mount point:     {}", status.mount_point.display());
+        println!("  Mounted:         {}", if status.mounted { "yes" } else { "no" });
+        match &status.error {
+            None => println!("  Server:          reachable"),
+            Some(e) => println!("  Server:          unreachable ({})", e),
         }
-        Commands::List => {
-            list_mounts().await?;
+        if let (Some(entries), Some(bytes)) = (status.remote_entries, status.remote_bytes) {
+            println!("  Remote root:     {} entries, {} bytes", entries, bytes);
         }
-        Commands::SetupFromNextcloud {
-            name,
-            remote_path,
-            mount_point,
-        } => {
-            setup_from_nextcloud(name, remote_path, mount_point).await?;
+        println!("  Pending changes: {}", status.pending_journal_ops);
+        if let Some(sync) = &status.sync {
+            // Only meaningful while mounted; otherwise it is the state at unmount
+            println!("  Sync state:      {}", sync.state.as_str());
+            match sync.last_sync {
+                Some(time) => println!(
+                    "  Last sync:       {}",
+                    time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S")
+                ),
+                None => println!("  Last sync:       never"),
+            }
+            if let Some(e) = &sync.last_error {
+                println!("  Last error:      {}", e);
+            }
+        }
+        if let Some(stats) = &status.cache {
+            println!(
+                "  Cache:           {} directories ({} active, {} expired), {} evicted",
+                stats.total_directories,
+                stats.active_directories,
+                stats.expired_directories,
+                stats.evicted_count
+            );
+            println!("  Cache memory:    {:.1} MiB", stats.memory_bytes as f64 / (1024.0 * 1024.0));
+            if let Some(time) = stats.last_refreshed {
+                println!(
+                    "  Last listing:    {}",
+                    time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S")
+                );
+            }
         }
+        println!();
     }
 
     Ok(())
 }
 
-async fn setup_mount(
-    name: String,
-    url: String,
-    username: String,
-    mount_point: String,
-) -> Result<()> {
-    use rpassword::read_password;
-    use std::io::Write;
+async fn mount_status(name: &str) -> Result<MountStatus> {
+    let (config, auth) = load_mount(name).await?;
 
-    println!("Setting up mount: {}", name);
-    println!("URL: {}", url);
-    println!("Username: {}", username);
-    println!("Mount point: {}", mount_point);
-    
-    // Prompt for password
-    print!("Password: ");
-    std::io::stdout().flush()?;
-    let password = read_password()?;
+    // One attempt is enough to tell whether the server is up
+    let webdav = webdav::WebDavClient::new(&config.urls, auth, &client_options(&config))?
+        .with_retry_policy(webdav::RetryPolicy { max_attempts: 1, ..config.retry.clone() });
+    // A full listing of the root doubles as the reachability check
+    let (root, error) = match webdav.list_dir_full("").await {
+        Ok(entries) => (Some(entries), None),
+        Err(e) => (None, Some(format!("{:#}", e))),
+    };
+    let remote_bytes = root.as_ref().map(|entries| {
+        entries
+            .iter()
+            .map(|entry| {
+                entry
+                    .properties
+                    .get("oc:size")
+                    .and_then(|size| size.parse().ok())
+                    .unwrap_or(entry.size)
+            })
+            .sum()
+    });
 
-    // Create config
-    let config = MountConfig {
-        name: name.clone(),
-        url,
-        username,
-        mount_point: mount_point.into(),
+    // The cache directory only exists once the mount has been used
+    let (cache, pending_journal_ops, sync) = match config::cache_dir(&config.name) {
+        Some(dir) if dir.exists() => {
+            let stats = cache::DirectoryCache::open(
+                &dir.join("meta.db"),
+                std::time::Duration::from_secs(5),
+                config.cache_max_entries,
+            )?
+            .stats();
+            let journal = journal::WriteJournal::open(&dir.join("journal.log"))?;
+            let sync = sync::SyncManager::load_status(&dir.join("sync.json"))?;
+            (Some(stats), journal.len(), sync)
+        }
+        _ => (None, 0, None),
     };
 
-    // Store config and password in Secret Service
-    let secret_store = secrets::SecretStore::new().await?;
-    secret_store.store_mount_config(&name, &config).await?;
-    secret_store.store_password(&name, &password).await?;
+    Ok(MountStatus {
+        mounted: platform::is_mounted(&config.mount_point),
+        reachable: error.is_none(),
+        error,
+        remote_entries: root.as_ref().map(Vec::len),
+        remote_bytes,
+        pending_journal_ops,
+        sync,
+        cache,
+        url: config.url().to_string(),
+        name: config.name,
+        mount_point: config.mount_point,
+    })
+}
 
-    println!("\n✓ Mount '{}' configured successfully!", name);
-    println!("\nTo mount:");
-    println!("  davfs-sync mount {}", name);
+fn evict_content(older_than: std::time::Duration) -> Result<()> {
+    let dir = config::content_cache_dir()
+        .ok_or_else(|| anyhow::anyhow!("Cannot determine cache directory (HOME not set)"))?;
+    if !dir.exists() {
+        println!("Content cache is empty");
+        return Ok(());
+    }
+    let (files, bytes) = content_cache::ContentCache::open(&dir)?.evict_older_than(older_than)?;
+    println!("✓ Removed {} cached files ({} bytes)", files, bytes);
+    Ok(())
+}
+
+async fn search(name: String, query: String, scope: String, xml: bool) -> Result<()> {
+    let (config, auth) = load_mount(&name).await?;
+    let webdav = webdav::WebDavClient::new(&config.urls, auth, &client_options(&config))?
+        .with_retry_policy(config.retry.clone());
 
+    let query = if xml {
+        webdav::DavSearchQuery::Raw(query)
+    } else if query.contains('%') {
+        webdav::DavSearchQuery::NameLike(query)
+    } else {
+        webdav::DavSearchQuery::NameLike(format!("%{}%", query))
+    };
+    let entries = webdav.search(&scope, &query).await?;
+
+    for entry in &entries {
+        println!("/{}{}", entry.name, if entry.is_dir { "/" } else { "" });
+    }
+    if entries.is_empty() {
+        eprintln!("No matches");
+    }
     Ok(())
 }
 
-async fn mount_filesystem(name: String) -> Result<()> {
-    println!("Loading mount configuration for '{}'...", name);
+/// Client for a one-off command on mount `name`'s server.
+async fn command_client(name: &str) -> Result<webdav::WebDavClient> {
+    let (config, auth) = load_mount(name).await?;
+    let cipher = match &config.encryption {
+        Some(encryption) => Some(load_cipher(name, encryption).await?),
+        None => None,
+    };
+    Ok(webdav::WebDavClient::new(&config.urls, auth, &client_options(&config))?
+        .with_encryption(cipher)
+        .with_retry_policy(config.retry.clone()))
+}
 
-    // Load config from Secret Service
-    let secret_store = secrets::SecretStore::new().await?;
-    let config = secret_store.load_mount_config(&name).await?;
-    let password = secret_store.load_password(&name).await?;
+async fn prefetch_mount(name: String, depth: u32, path: Option<String>) -> Result<()> {
+    let (config, _) = load_mount(&name).await?;
+    let webdav = command_client(&name).await?;
+    // Nextcloud listings carry extra properties the mount uses
+    let capabilities = webdav.detect_capabilities().await.ok();
+    let webdav = webdav.with_capabilities(capabilities);
 
-    println!("Connecting to: {}", config.url);
-    println!("Mount point: {}", config.mount_point.display());
+    let cache_dir = config::cache_dir(&config.name)
+        .ok_or_else(|| anyhow::anyhow!("Cannot determine cache directory (HOME not set)"))?;
+    let dir_cache = cache::DirectoryCache::open(
+        &cache_dir.join("meta.db"),
+        std::time::Duration::from_secs(5),
+        config.cache_max_entries,
+    )?
+    .with_max_memory_bytes(config.cache_max_memory_bytes)
+    .with_adaptive_ttl(config.adaptive_ttl);
+    let scheduler = prefetch::PrefetchScheduler::new(
+        tokio::runtime::Handle::current(),
+        webdav,
+        dir_cache,
+        filter::PathFilter::from_config(&config)?,
+    )
+    .with_strategy(prefetch::PrefetchStrategy::Bfs, config.prefetch_concurrency);
 
-    // Check if mount point is already mounted and try to unmount it
-    println!("Checking for existing mounts...");
-    
-    // First, check if it's mounted using mountpoint command
-    let check_mounted = std::process::Command::new("mountpoint")
-        .arg("-q")
-        .arg(&config.mount_point)
-        .status();
-    
-    let is_mounted = check_mounted.map(|s| s.success()).unwrap_or(false);
-    
-    if is_mounted {
-        println!("Found existing mount, attempting to unmount...");
-        let unmount_result = std::process::Command::new("fusermount3")
-            .arg("-u")
-            .arg(&config.mount_point)
-            .status();
-        
-        match unmount_result {
-            Ok(status) if status.success() => {
-                println!("✓ Successfully unmounted existing mount");
-                // Give it a moment to fully unmount
-                std::thread::sleep(std::time::Duration::from_millis(500));
-            }
-            Ok(_) => {
-                // Try lazy unmount as fallback
-                println!("Regular unmount failed, trying lazy unmount...");
-                let lazy_result = std::process::Command::new("fusermount3")
-                    .arg("-uz")
-                    .arg(&config.mount_point)
-                    .status();
-                
-                if lazy_result.map(|s| s.success()).unwrap_or(false) {
-                    println!("✓ Lazy unmount succeeded");
-                    std::thread::sleep(std::time::Duration::from_millis(500));
-                } else {
-                    anyhow::bail!("Failed to unmount existing mount at {}. Please run: fusermount3 -uz {}", 
-                        config.mount_point.display(), config.mount_point.display());
-                }
-            }
-            Err(e) => {
-                eprintln!("Warning: Could not unmount: {}", e);
-            }
-        }
-    } else {
-        // Even if not mounted, might be a stale directory - try unmounting anyway
-        let _ = std::process::Command::new("fusermount3")
-            .arg("-uz")
-            .arg(&config.mount_point)
-            .output();
-    }
+    let root = format!("/{}", path.as_deref().unwrap_or_default().trim_matches('/'));
+    let progress = indicatif::ProgressBar::new(1).with_style(
+        indicatif::ProgressStyle::with_template("{spinner} [{bar:40}] {pos}/{len} directories ({elapsed})")?
+            .progress_chars("=> "),
+    );
+    let summary = scheduler
+        .walk(&root, depth, |listed, found| {
+            progress.set_length(found);
+            progress.set_position(listed);
+        })
+        .await;
+    progress.finish_and_clear();
 
-    // Create mount point if it doesn't exist
-    std::fs::create_dir_all(&config.mount_point)?;
+    println!(
+        "✓ Cached {} directories with {} entries below {} of mount '{}'",
+        summary.directories, summary.entries, root, name
+    );
+    if summary.failed > 0 {
+        println!("⚠ {} directories could not be listed", summary.failed);
+    }
+    Ok(())
+}
 
-    // Create WebDAV client
-    let webdav = webdav::WebDavClient::new(
-        config.url.clone(),
-        config.username.clone(),
-        password,
-    )?;
+async fn list_calendar(name: &str, path: &str) -> Result<()> {
+    let webdav = command_client(name).await?;
+    let path = path.trim_matches('/');
+    let events = caldav::list_events(&webdav, path)
+        .await
+        .with_context(|| format!("Failed to query calendar /{}", path))?;
 
-    // Test connection
-    println!("Testing connection...");
-    match webdav.test_connection().await {
-        Ok(_) => println!("✓ Connected successfully!"),
-        Err(e) => {
-            eprintln!("✗ Connection failed: {}", e);
-            eprintln!("The filesystem will mount, but operations will fail until connection is available.");
+    println!("✓ {} events in /{} of mount '{}'", events.len(), path, name);
+    for event in &events {
+        let when = match (&event.start, &event.end) {
+            (Some(start), Some(end)) => format!("{} – {}", start, end),
+            (Some(start), None) => start.clone(),
+            (None, _) => "(no start)".to_string(),
+        };
+        let summary = if event.summary.is_empty() { &event.resource } else { &event.summary };
+        match &event.location {
+            Some(location) => println!("  {:<35} {} @ {}", when, summary, location),
+            None => println!("  {:<35} {}", when, summary),
         }
     }
+    Ok(())
+}
 
-    // Create filesystem
-    let fs = DavFS::new(webdav);
-    
-    // Start aggressive background prefetching
-    println!("Starting background prefetch...");
-    fs.prefetch_initial();
+async fn pin_path(name: &str, remote_path: &str, pin: bool) -> Result<()> {
+    let (config, _) = load_mount(name).await?;
+    let cache_dir = config::cache_dir(&config.name)
+        .ok_or_else(|| anyhow::anyhow!("Cannot determine cache directory (HOME not set)"))?;
+    let dir_cache = cache::DirectoryCache::open(
+        &cache_dir.join("meta.db"),
+        std::time::Duration::from_secs(5),
+        config.cache_max_entries,
+    )?;
 
-    println!("\nMounting filesystem at {}...", config.mount_point.display());
-    println!("Press Ctrl+C to unmount\n");
+    let path = format!("/{}", remote_path.trim_matches('/'));
+    if pin {
+        dir_cache.pin(&path)?;
+        println!("✓ Pinned {} of mount '{}'", path, name);
+        if !platform::is_mounted(&config.mount_point) {
+            println!("  It is downloaded once the mount is running");
+        }
+    } else if dir_cache.unpin(&path)? {
+        println!("✓ Unpinned {} of mount '{}'", path, name);
+    } else {
+        println!("⚠ {} of mount '{}' is not pinned", path, name);
+    }
+    Ok(())
+}
 
-    // Mount options - minimal set to avoid permission issues
-    let options = vec![
-        fuser::MountOption::FSName("davfs-sync".to_string()),
-        fuser::MountOption::RO, // Read-only for PoC
-    ];
+fn send_ioctl(mount_point: &std::path::Path, command: IoctlCommand, path: &str) -> Result<()> {
+    let file = std::fs::File::open(mount_point).with_context(|| format!("Failed to open {}", mount_point.display()))?;
+    let fd = std::os::fd::AsRawFd::as_raw_fd(&file);
 
-    // Setup signal handler for clean unmount
-    let mount_point_for_signal = config.mount_point.clone();
-    tokio::spawn(async move {
-        tokio::signal::ctrl_c().await.ok();
-        println!("\n\nReceived Ctrl+C, unmounting...");
-        // Try to unmount
-        let _ = std::process::Command::new("fusermount3")
-            .arg("-u")
-            .arg(&mount_point_for_signal)
-            .output();
-        std::process::exit(0);
-    });
+    let (request, mut arg) = match command {
+        IoctlCommand::Invalidate | IoctlCommand::Prefetch => {
+            let request = if command == IoctlCommand::Invalidate {
+                ioctl::DAVFS_IOCTL_INVALIDATE_PATH
+            } else {
+                ioctl::DAVFS_IOCTL_PREFETCH_PATH
+            };
+            let arg = ioctl::encode_path(path).with_context(|| format!("Path too long: {}", path))?;
+            (request, arg)
+        }
+        IoctlCommand::Stats => (ioctl::DAVFS_IOCTL_GET_STATS, vec![0; ioctl::DavfsStats::SIZE]),
+    };
+    if unsafe { libc::ioctl(fd, request as _, arg.as_mut_ptr()) } < 0 {
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() == Some(libc::ENOTTY) {
+            anyhow::bail!("{} is not on a davfs-sync mount", mount_point.display());
+        }
+        return Err(err).context("ioctl failed");
+    }
 
-    // Spawn blocking mount operation in a separate thread to avoid runtime conflicts
-    let mount_point = config.mount_point.clone();
-    let mount_result = tokio::task::spawn_blocking(move || {
-        fuser::mount2(fs, &mount_point, &options)
-    }).await?;
+    match command {
+        IoctlCommand::Invalidate => println!("✓ Invalidated {}", mount_point.join(path).display()),
+        IoctlCommand::Prefetch => println!("✓ Prefetching {}", mount_point.join(path).display()),
+        IoctlCommand::Stats => {
+            let stats = ioctl::DavfsStats::from_bytes(&arg).context("Short stats reply")?;
+            println!("Cached directories:  {} ({} expired)", stats.cached_directories, stats.expired_directories);
+            println!("Cache hits/misses:   {}/{}", stats.cache_hits, stats.cache_misses);
+            println!("Server requests:     {}", stats.requests);
+            println!("Pending offline ops: {}", stats.pending_journal_ops);
+            println!("Prefetch queued:     {}", stats.prefetch_queued);
+        }
+    }
+    Ok(())
+}
 
-    mount_result?;
+async fn restore_from_trash(name: String, remote_path: String) -> Result<()> {
+    let webdav = command_client(&name).await?;
+    let trash = trash::Trash::for_uid(platform::uid());
+    trash.restore(&webdav, &remote_path).await?;
+    println!("✓ Restored {}", remote_path);
+    Ok(())
+}
 
-    println!("\nFilesystem unmounted.");
+async fn empty_trash(name: String) -> Result<()> {
+    let webdav = command_client(&name).await?;
+    let trash = trash::Trash::for_uid(platform::uid());
+    if trash.empty(&webdav).await? {
+        println!("✓ Emptied the trash of mount '{}'", name);
+    } else {
+        println!("The trash of mount '{}' is already empty", name);
+    }
     Ok(())
 }
 
@@ -270,6 +1824,7 @@ async fn setup_from_nextcloud(
     name: String,
     remote_path: String,
     mount_point: String,
+    read_only: bool,
 ) -> Result<()> {
     println!("Looking for Nextcloud Desktop credentials...\n");
 
@@ -307,80 +1862,25 @@ async fn setup_from_nextcloud(
         }
     };
 
-    // Parse config to extract account info
-    let mut server_url = None;
-    let mut username = None;
-    
     println!("\nParsing config file...");
-    
-    for line in config_content.lines() {
-        let line = line.trim();
-        
-        // Try different URL formats (with or without number prefix)
-        if line.starts_with("url=") || line.contains("\\url=") {
-            let url = if let Some(stripped) = line.strip_prefix("url=") {
-                stripped
-            } else if let Some(pos) = line.find("\\url=") {
-                &line[pos + 5..]
-            } else {
-                continue;
-            };
-            server_url = Some(url.to_string());
-            println!("  Found url={}", url);
-        } else if line.starts_with("http://") || line.starts_with("https://") {
-            if server_url.is_none() {
-                server_url = Some(line.to_string());
-                println!("  Found URL in line: {}", line);
-            }
-        }
-        
-        // Try different username formats (with or without number prefix)
-        if line.starts_with("user=") || line.contains("\\user=") {
-            let user = if let Some(stripped) = line.strip_prefix("user=") {
-                stripped
-            } else if let Some(pos) = line.find("\\user=") {
-                &line[pos + 6..]
-            } else {
-                continue;
-            };
-            username = Some(user.to_string());
-            println!("  Found user={}", user);
-        } else if line.starts_with("davUser=") || line.contains("\\davUser=") {
-            let user = if let Some(stripped) = line.strip_prefix("davUser=") {
-                stripped
-            } else if let Some(pos) = line.find("\\davUser=") {
-                &line[pos + 9..]
-            } else {
-                continue;
-            };
-            username = Some(user.to_string());
-            println!("  Found davUser={}", user);
-        } else if line.starts_with("webflow_user=") || line.contains("\\webflow_user=") {
-            if username.is_none() {
-                let user = if let Some(stripped) = line.strip_prefix("webflow_user=") {
-                    stripped
-                } else if let Some(pos) = line.find("\\webflow_user=") {
-                    &line[pos + 14..]
-                } else {
-                    continue;
-                };
-                username = Some(user.to_string());
-                println!("  Found webflow_user={}", user);
-            }
-        }
+    let accounts = parse_nextcloud_accounts(&config_content);
+    for account in &accounts {
+        println!("  Found account {} at {}", account.username, account.server_url);
     }
-    
-    if server_url.is_none() || username.is_none() {
-        println!("\n⚠ Could not parse all required fields. Config file content:");
+
+    if accounts.is_empty() {
+        println!("\n⚠ Could not find a complete account. Config file content:");
         println!("---");
         for (i, line) in config_content.lines().take(100).enumerate() {
             println!("{:3}: {}", i+1, line);
         }
         println!("---\n");
+        anyhow::bail!("No account with server URL and username found in Nextcloud config");
     }
 
-    let server_url = server_url.ok_or_else(|| anyhow::anyhow!("Server URL not found in Nextcloud config"))?;
-    let username = username.ok_or_else(|| anyhow::anyhow!("Username not found in Nextcloud config"))?;
+    let account = select_nextcloud_account(accounts)?;
+    let server_url = account.server_url;
+    let username = account.username;
 
     // Construct WebDAV URL
     // Nextcloud WebDAV is at: <server>/remote.php/dav/files/<username>/<path>
@@ -425,9 +1925,12 @@ async fn setup_from_nextcloud(
     // Create config
     let config = MountConfig {
         name: name.clone(),
-        url: webdav_url,
+        urls: vec![webdav_url],
         username: username.clone(),
         mount_point: mount_point.into(),
+        read_only,
+        mount_id: Some(uuid::Uuid::new_v4()),
+        ..Default::default()
     };
 
     // Store config and password
@@ -435,12 +1938,271 @@ async fn setup_from_nextcloud(
     secret_store.store_password(&name, &password).await?;
 
     println!("\n✓ Mount '{}' configured successfully!", name);
+    let auth = webdav::AuthMethod::Basic { username, password };
+    validate_mount(&config, auth).await;
+    println!("\nTo mount:");
+    println!("  davfs-sync mount {}", name);
+
+    Ok(())
+}
+
+/// Interval and overall limit for polling a Login Flow v2 session; the
+/// server expires the token after 20 minutes.
+const LOGIN_FLOW_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+const LOGIN_FLOW_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(20 * 60);
+
+#[derive(serde::Deserialize)]
+struct LoginFlowStart {
+    poll: LoginFlowPoll,
+    login: String,
+}
+
+#[derive(serde::Deserialize)]
+struct LoginFlowPoll {
+    token: String,
+    endpoint: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LoginFlowResult {
+    server: String,
+    login_name: String,
+    app_password: String,
+}
+
+async fn setup_from_nextcloud_flow(
+    name: String,
+    server: String,
+    remote_path: String,
+    mount_point: String,
+    read_only: bool,
+) -> Result<()> {
+    let server = server.trim_end_matches('/');
+    // Nextcloud lists the session under this name in its security settings
+    let client = reqwest::Client::builder()
+        .user_agent("davfs-sync")
+        .build()?;
+
+    let start: LoginFlowStart = client
+        .post(format!("{}/index.php/login/v2", server))
+        .send()
+        .await
+        .context("Failed to start Nextcloud login")?
+        .error_for_status()
+        .context("Server does not support Nextcloud Login Flow v2")?
+        .json()
+        .await
+        .context("Invalid login flow response")?;
+
+    println!("Log in to Nextcloud in your browser to grant access:");
+    println!("  {}", start.login);
+    if let Err(e) = open::that(&start.login) {
+        println!("⚠ Could not open a browser ({}); open the link above manually", e);
+    }
+    println!("
+Waiting for login...");
+
+    // 404 until the user has granted access
+    let deadline = tokio::time::Instant::now() + LOGIN_FLOW_TIMEOUT;
+    let result: LoginFlowResult = loop {
+        if tokio::time::Instant::now() >= deadline {
+            anyhow::bail!("Login was not completed within {} minutes", LOGIN_FLOW_TIMEOUT.as_secs() / 60);
+        }
+        tokio::time::sleep(LOGIN_FLOW_POLL_INTERVAL).await;
+
+        let response = match client
+            .post(&start.poll.endpoint)
+            .form(&[("token", &start.poll.token)])
+            .send()
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => {
+                tracing::debug!("Login poll failed: {}", e);
+                continue;
+            }
+        };
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            continue;
+        }
+        break response
+            .error_for_status()
+            .context("Login failed")?
+            .json()
+            .await
+            .context("Invalid login result")?;
+    };
+    println!("✓ Logged in as {}", result.login_name);
+
+    // The files endpoint is keyed by user ID, which can differ from the
+    // login name (e.g. when logging in with an email address)
+    let server = result.server.trim_end_matches('/');
+    let user_id = match nextcloud_user_id(&client, server, &result.login_name, &result.app_password).await {
+        Ok(id) => id,
+        Err(e) => {
+            tracing::debug!("Failed to look up user ID: {}", e);
+            result.login_name.clone()
+        }
+    };
+    let webdav_url = format!(
+        "{}/remote.php/dav/files/{}/{}",
+        server,
+        user_id,
+        remote_path.trim_start_matches('/')
+    );
+    println!("  WebDAV:   {}", webdav_url);
+
+    let config = MountConfig {
+        name: name.clone(),
+        urls: vec![webdav_url],
+        username: result.login_name,
+        mount_point: mount_point.into(),
+        read_only,
+        mount_id: Some(uuid::Uuid::new_v4()),
+        ..Default::default()
+    };
+
+    let secret_store = secrets::SecretStore::new().await?;
+    secret_store.store_mount_config(&name, &config).await?;
+    secret_store.store_password(&name, &result.app_password).await?;
+
+    println!("\n✓ Mount '{}' configured successfully!", name);
+    let auth = webdav::AuthMethod::Basic {
+        username: config.username.clone(),
+        password: result.app_password,
+    };
+    validate_mount(&config, auth).await;
     println!("\nTo mount:");
     println!("  davfs-sync mount {}", name);
 
     Ok(())
 }
 
+/// User ID of the logged-in account, from the OCS user endpoint.
+async fn nextcloud_user_id(
+    client: &reqwest::Client,
+    server: &str,
+    login_name: &str,
+    password: &str,
+) -> Result<String> {
+    let response: serde_json::Value = client
+        .get(format!("{}/ocs/v1.php/cloud/user?format=json", server))
+        .basic_auth(login_name, Some(password))
+        .header("OCS-APIRequest", "true")
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    response["ocs"]["data"]["id"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| anyhow::anyhow!("OCS response has no user ID"))
+}
+
+/// One account from the Nextcloud Desktop config.
+#[derive(Debug, Clone)]
+struct NextcloudAccount {
+    server_url: String,
+    username: String,
+}
+
+/// Parse all accounts from a Nextcloud Desktop config. Accounts are keyed
+/// `N\url=` etc. in an `[Accounts]` section, in `[Accounts\N]` sections, or
+/// unprefixed for the old single-account format.
+fn parse_nextcloud_accounts(content: &str) -> Vec<NextcloudAccount> {
+    #[derive(Default)]
+    struct Fields {
+        url: Option<String>,
+        dav_user: Option<String>,
+        webflow_user: Option<String>,
+        user: Option<String>,
+    }
+
+    // Account ID ("" for the old format) to its fields
+    let mut accounts: std::collections::BTreeMap<String, Fields> = Default::default();
+    // None outside account sections; Some("") for [Accounts]
+    let mut section: Option<String> = None;
+
+    for line in content.lines() {
+        let line = line.trim();
+        if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = match header.split_once('\\') {
+                None if header == "Accounts" => Some(String::new()),
+                Some(("Accounts", id)) if id.chars().all(|c| c.is_ascii_digit()) => Some(id.to_string()),
+                _ => None,
+            };
+            continue;
+        }
+        let Some(section) = &section else {
+            continue;
+        };
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+
+        // In [Accounts], keys are prefixed with the account number; nested
+        // keys such as 0\Folders\1\localPath are not account fields
+        let (id, key) = match key.split_once('\\') {
+            None => (section.clone(), key),
+            Some((id, key))
+                if section.is_empty() && !key.contains('\\') && id.chars().all(|c| c.is_ascii_digit()) =>
+            {
+                (id.to_string(), key)
+            }
+            Some(_) => continue,
+        };
+
+        let fields = accounts.entry(id).or_default();
+        let value = Some(value.trim().to_string()).filter(|v| !v.is_empty());
+        match key {
+            "url" => fields.url = value,
+            "dav_user" | "davUser" => fields.dav_user = value,
+            "webflow_user" => fields.webflow_user = value,
+            // Older clients store "@Invalid()" here for web flow logins
+            "user" => fields.user = value.filter(|v| !v.starts_with('@')),
+            _ => {}
+        }
+    }
+
+    accounts
+        .into_values()
+        .filter_map(|fields| {
+            Some(NextcloudAccount {
+                server_url: fields.url?,
+                username: fields.dav_user.or(fields.webflow_user).or(fields.user)?,
+            })
+        })
+        .collect()
+}
+
+/// Pick one of `accounts`, asking the user if there is more than one.
+fn select_nextcloud_account(mut accounts: Vec<NextcloudAccount>) -> Result<NextcloudAccount> {
+    use std::io::Write;
+
+    if accounts.len() == 1 {
+        return Ok(accounts.remove(0));
+    }
+
+    println!("\nMultiple Nextcloud accounts found:");
+    for (i, account) in accounts.iter().enumerate() {
+        println!("  {}) {} at {}", i + 1, account.username, account.server_url);
+    }
+    loop {
+        print!("Select account [1-{}]: ", accounts.len());
+        std::io::stdout().flush()?;
+        let mut input = String::new();
+        if std::io::stdin().read_line(&mut input)? == 0 {
+            anyhow::bail!("No account selected");
+        }
+        match input.trim().parse::<usize>() {
+            Ok(n) if (1..=accounts.len()).contains(&n) => return Ok(accounts.remove(n - 1)),
+            _ => println!("Please enter a number between 1 and {}", accounts.len()),
+        }
+    }
+}
+
 async fn try_get_nextcloud_password(
     _secret_store: &secrets::SecretStore,
     username: &str,
@@ -489,6 +2251,15 @@ async fn try_get_nextcloud_password(
     anyhow::bail!("Password not found in keyring")
 }
 
+/// Server URL a running mount is using, as recorded by its sync loop.
+fn active_url(config: &MountConfig) -> Option<String> {
+    if !platform::is_mounted(&config.mount_point) {
+        return None;
+    }
+    let dir = config::cache_dir(&config.name)?;
+    sync::SyncManager::load_status(&dir.join("sync.json")).ok()??.active_url
+}
+
 async fn list_mounts() -> Result<()> {
     let secret_store = secrets::SecretStore::new().await?;
     let mounts = secret_store.list_mounts().await?;
@@ -504,9 +2275,16 @@ async fn list_mounts() -> Result<()> {
     for name in mounts {
         if let Ok(config) = secret_store.load_mount_config(&name).await {
             println!("  {} ", name);
-            println!("    URL:         {}", config.url);
+            println!("    URL:         {}", config.urls.join(", "));
+            if let Some(active) = active_url(&config) {
+                println!("    Active URL:  {}", active);
+            }
             println!("    Username:    {}", config.username);
             println!("    Mount point: {}", config.mount_point.display());
+            println!("    Read-only:   {}", if config.read_only { "yes" } else { "no" });
+            if config.allow_other || config.allow_root {
+                println!("    Shared with: {}", if config.allow_other { "all users" } else { "root" });
+            }
             println!();
         }
     }
@@ -0,0 +1,134 @@
+use anyhow::Result;
+use reqwest::{Method, StatusCode};
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Methods counted separately; anything else is reported as `OTHER`.
+const METHODS: [&str; 11] = [
+    "GET", "PUT", "POST", "DELETE", "PROPFIND", "PROPPATCH", "MKCOL", "MOVE", "COPY", "LOCK", "UNLOCK",
+];
+
+/// Upper bounds of the request duration histogram buckets, in seconds.
+const DURATION_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0];
+
+/// Counters for cache and network activity of one mount, exported in
+/// Prometheus text format by `serve`.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub cache_hits: AtomicU64,
+    pub cache_misses: AtomicU64,
+    // Indexed like METHODS, with one extra slot for OTHER
+    requests: [AtomicU64; METHODS.len() + 1],
+    request_errors: AtomicU64,
+    // Cumulative counts per bucket, plus +Inf
+    duration_buckets: [AtomicU64; DURATION_BUCKETS.len() + 1],
+    duration_sum_micros: AtomicU64,
+    /// Directories waiting to be prefetched
+    pub prefetch_queued: AtomicU64,
+}
+
+impl Metrics {
+    /// Record one HTTP request. `status` is `None` if no response arrived;
+    /// that and server errors count as request errors.
+    pub fn record_request(&self, method: &Method, duration: Duration, status: Option<StatusCode>) {
+        let index = METHODS
+            .iter()
+            .position(|m| *m == method.as_str())
+            .unwrap_or(METHODS.len());
+        self.requests[index].fetch_add(1, Ordering::Relaxed);
+
+        if status.is_none_or(|s| s.is_server_error()) {
+            self.request_errors.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let secs = duration.as_secs_f64();
+        for (bucket, bound) in self.duration_buckets.iter().zip(DURATION_BUCKETS) {
+            if secs <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.duration_buckets[DURATION_BUCKETS.len()].fetch_add(1, Ordering::Relaxed);
+        self.duration_sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Requests sent so far, over all methods.
+    pub fn requests_total(&self) -> u64 {
+        self.requests.iter().map(|counter| counter.load(Ordering::Relaxed)).sum()
+    }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let mut out = String::new();
+
+        // Writing to a String cannot fail
+        let _ = writeln!(out, "# HELP davfs_cache_hits_total Directory listings served from cache");
+        let _ = writeln!(out, "# TYPE davfs_cache_hits_total counter");
+        let _ = writeln!(out, "davfs_cache_hits_total {}", load(&self.cache_hits));
+
+        let _ = writeln!(out, "# HELP davfs_cache_misses_total Directory lookups that needed the server");
+        let _ = writeln!(out, "# TYPE davfs_cache_misses_total counter");
+        let _ = writeln!(out, "davfs_cache_misses_total {}", load(&self.cache_misses));
+
+        let _ = writeln!(out, "# HELP davfs_requests_total WebDAV requests sent, by method");
+        let _ = writeln!(out, "# TYPE davfs_requests_total counter");
+        for (method, counter) in METHODS.iter().chain(["OTHER"].iter()).zip(&self.requests) {
+            let _ = writeln!(out, "davfs_requests_total{{method=\"{}\"}} {}", method, load(counter));
+        }
+
+        let _ = writeln!(out, "# HELP davfs_request_errors_total Requests that failed or got a 5xx response");
+        let _ = writeln!(out, "# TYPE davfs_request_errors_total counter");
+        let _ = writeln!(out, "davfs_request_errors_total {}", load(&self.request_errors));
+
+        let _ = writeln!(out, "# HELP davfs_request_duration_seconds WebDAV request latency");
+        let _ = writeln!(out, "# TYPE davfs_request_duration_seconds histogram");
+        for (bound, bucket) in DURATION_BUCKETS.iter().zip(&self.duration_buckets) {
+            let _ = writeln!(out, "davfs_request_duration_seconds_bucket{{le=\"{}\"}} {}", bound, load(bucket));
+        }
+        let count = load(&self.duration_buckets[DURATION_BUCKETS.len()]);
+        let _ = writeln!(out, "davfs_request_duration_seconds_bucket{{le=\"+Inf\"}} {}", count);
+        let _ = writeln!(
+            out,
+            "davfs_request_duration_seconds_sum {}",
+            load(&self.duration_sum_micros) as f64 / 1_000_000.0
+        );
+        let _ = writeln!(out, "davfs_request_duration_seconds_count {}", count);
+
+        let _ = writeln!(out, "# HELP davfs_prefetch_queued Directories waiting to be prefetched");
+        let _ = writeln!(out, "# TYPE davfs_prefetch_queued gauge");
+        let _ = writeln!(out, "davfs_prefetch_queued {}", load(&self.prefetch_queued));
+
+        out
+    }
+}
+
+/// Serve `/metrics` on `addr` until the process exits.
+pub fn serve(metrics: Arc<Metrics>, addr: SocketAddr) -> Result<()> {
+    let server = tiny_http::Server::http(addr)
+        .map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", addr, e))?;
+    tracing::info!("Serving metrics on http://{}/metrics", addr);
+
+    tokio::task::spawn_blocking(move || {
+        for request in server.incoming_requests() {
+            let response = if request.url() == "/metrics" {
+                let content_type = tiny_http::Header::from_bytes(
+                    "Content-Type",
+                    "text/plain; version=0.0.4; charset=utf-8",
+                )
+                .expect("static header is valid");
+                tiny_http::Response::from_string(metrics.render()).with_header(content_type)
+            } else {
+                tiny_http::Response::from_string("Not Found").with_status_code(404)
+            };
+            if let Err(e) = request.respond(response) {
+                tracing::debug!("Failed to send metrics response: {}", e);
+            }
+        }
+    });
+
+    Ok(())
+}
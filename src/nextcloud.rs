@@ -0,0 +1,163 @@
+use anyhow::{Context, Result};
+use percent_encoding::percent_decode_str;
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::cache::DirectoryCache;
+use crate::filesystem::DavFS;
+use crate::webdav::WebDavClient;
+
+/// Activities fetched per request.
+const PAGE_SIZE: usize = 100;
+
+/// One event of the Nextcloud activity stream.
+#[derive(Debug, Clone)]
+pub struct Activity {
+    pub id: u64,
+    /// Kind of change, e.g. `file_created`, `file_changed` or `file_deleted`
+    pub kind: String,
+    /// Files it touched, relative to the user's files
+    pub paths: Vec<String>,
+}
+
+/// The activity stream of the mount's user, polled so listings changed
+/// elsewhere are dropped right away instead of when their TTL runs out.
+/// For servers without notify_push.
+pub struct ActivityFeed {
+    webdav: WebDavClient,
+    // Mount root relative to the user's files, like activity paths
+    root: String,
+    // Newest activity handled, persisted so changes made while unmounted
+    // are noticed on the next mount
+    since_id: Mutex<Option<u64>>,
+    state_path: PathBuf,
+}
+
+impl ActivityFeed {
+    /// Feed for `webdav`, or `None` if its URL is not a Nextcloud files URL.
+    pub fn new(webdav: WebDavClient, state_path: PathBuf) -> Option<Self> {
+        let root = user_root(webdav.active_url().path())?;
+        let since_id = std::fs::read_to_string(&state_path)
+            .ok()
+            .and_then(|content| content.trim().parse().ok());
+        Some(Self {
+            webdav,
+            root,
+            since_id: Mutex::new(since_id),
+            state_path,
+        })
+    }
+
+    /// Activities after `since_id`, oldest first.
+    pub async fn poll_activities(&self, since_id: u64) -> Result<Vec<Activity>> {
+        let mut activities = Vec::new();
+        let mut since = since_id;
+        loop {
+            let page = self
+                .fetch(&[
+                    ("since", since.to_string()),
+                    ("limit", PAGE_SIZE.to_string()),
+                    ("sort", "asc".to_string()),
+                ])
+                .await?;
+            let full = page.len() >= PAGE_SIZE;
+            since = page.last().map_or(since, |activity| activity.id);
+            activities.extend(page);
+            if !full {
+                return Ok(activities);
+            }
+        }
+    }
+
+    /// Invalidate the listings of files changed since the last call. The
+    /// first call on a new mount only notes where the stream is.
+    pub async fn invalidate_changed(&self, dir_cache: &DirectoryCache) -> Result<()> {
+        let since_id = *self.since_id.lock().unwrap();
+        let Some(since_id) = since_id else {
+            let latest = self
+                .fetch(&[("limit", "1".to_string()), ("sort", "desc".to_string())])
+                .await?;
+            self.save_since_id(latest.first().map_or(0, |activity| activity.id));
+            return Ok(());
+        };
+
+        let activities = self.poll_activities(since_id).await?;
+        let Some(last) = activities.last() else {
+            return Ok(());
+        };
+        for activity in &activities {
+            for path in activity.paths.iter().filter_map(|path| self.mount_path(path)) {
+                tracing::debug!("Remote {} of {}", activity.kind, path);
+                dir_cache.invalidate(DavFS::parent_of(&path));
+                dir_cache.invalidate_tree(&path);
+                dir_cache.remove_negative(&path);
+            }
+        }
+        tracing::info!("{} remote changes in the activity feed", activities.len());
+        self.save_since_id(last.id);
+        Ok(())
+    }
+
+    async fn fetch(&self, query: &[(&str, String)]) -> Result<Vec<Activity>> {
+        let Some(response) = self.webdav.ocs_get("apps/activity/api/v2/activity", query).await? else {
+            return Ok(Vec::new());
+        };
+        let data = response["ocs"]["data"]
+            .as_array()
+            .context("Unexpected activity API response")?;
+        Ok(data.iter().filter_map(parse_activity).collect())
+    }
+
+    /// Mount path of a path relative to the user's files, if it is inside
+    /// the mount.
+    fn mount_path(&self, path: &str) -> Option<String> {
+        let path = format!("/{}", path.trim_matches('/'));
+        if self.root == "/" {
+            return Some(path);
+        }
+        let rest = path.strip_prefix(&self.root)?;
+        match rest {
+            "" => Some("/".to_string()),
+            rest if rest.starts_with('/') => Some(rest.to_string()),
+            _ => None,
+        }
+    }
+
+    fn save_since_id(&self, id: u64) {
+        *self.since_id.lock().unwrap() = Some(id);
+        if let Err(e) = std::fs::write(&self.state_path, id.to_string()) {
+            tracing::warn!("Failed to write {}: {}", self.state_path.display(), e);
+        }
+    }
+}
+
+/// Path of the mount root below the user's files, from a
+/// `remote.php/dav/files/<user>/...` or legacy `remote.php/webdav/...` URL.
+fn user_root(url_path: &str) -> Option<String> {
+    let path = percent_decode_str(url_path).decode_utf8_lossy();
+    let root = match path.split_once("/remote.php/dav/files/") {
+        Some((_, rest)) => rest.split_once('/').map_or("", |(_, root)| root),
+        None => path.split_once("/remote.php/webdav")?.1,
+    };
+    Some(format!("/{}", root.trim_matches('/')))
+}
+
+fn parse_activity(value: &Value) -> Option<Activity> {
+    let id = value["activity_id"].as_u64()?;
+    let mut paths = Vec::new();
+    if value["object_type"] == "files" {
+        // An object per file, or `[]` when there are none
+        if let Some(objects) = value["objects"].as_object() {
+            paths.extend(objects.values().filter_map(Value::as_str).map(str::to_string));
+        }
+        if paths.is_empty() {
+            paths.extend(value["object_name"].as_str().map(str::to_string));
+        }
+    }
+    Some(Activity {
+        id,
+        kind: value["type"].as_str().unwrap_or_default().to_string(),
+        paths,
+    })
+}
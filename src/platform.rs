@@ -0,0 +1,108 @@
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// Missing extended attribute.
+#[cfg(target_os = "linux")]
+pub const ENOATTR: i32 = libc::ENODATA;
+#[cfg(target_os = "macos")]
+pub const ENOATTR: i32 = libc::ENOATTR;
+
+/// `rename` flag swapping source and target.
+#[cfg(target_os = "linux")]
+pub const RENAME_EXCHANGE: u32 = libc::RENAME_EXCHANGE;
+#[cfg(target_os = "macos")]
+pub const RENAME_EXCHANGE: u32 = libc::RENAME_SWAP;
+
+/// `rename` flag failing if the target exists.
+#[cfg(target_os = "linux")]
+pub const RENAME_NOREPLACE: u32 = libc::RENAME_NOREPLACE;
+#[cfg(target_os = "macos")]
+pub const RENAME_NOREPLACE: u32 = libc::RENAME_EXCL;
+
+/// `fallocate` mode leaving the file size alone. macOS never sends
+/// `fallocate`, so only the Linux value matters.
+#[cfg(target_os = "linux")]
+pub const FALLOC_FL_KEEP_SIZE: i32 = libc::FALLOC_FL_KEEP_SIZE;
+#[cfg(target_os = "macos")]
+pub const FALLOC_FL_KEEP_SIZE: i32 = 0x01;
+
+/// Unmount command, and the one for busy mounts: lazy on Linux, so the
+/// FUSE session ends once open files are closed, forced on macOS.
+#[cfg(target_os = "linux")]
+const UNMOUNT: &[&str] = &["fusermount3", "-u"];
+#[cfg(target_os = "linux")]
+pub const UNMOUNT_BUSY: &[&str] = &["fusermount3", "-uz"];
+#[cfg(target_os = "macos")]
+const UNMOUNT: &[&str] = &["umount"];
+#[cfg(target_os = "macos")]
+pub const UNMOUNT_BUSY: &[&str] = &["diskutil", "unmount", "force"];
+
+pub fn uid() -> u32 {
+    nix::unistd::getuid().as_raw()
+}
+
+pub fn gid() -> u32 {
+    nix::unistd::getgid().as_raw()
+}
+
+/// Whether a filesystem is mounted at `path`, i.e. it is on another
+/// device than its parent.
+pub fn is_mounted(path: &Path) -> bool {
+    let parent = path.parent().unwrap_or(path);
+    match (std::fs::metadata(path), std::fs::metadata(parent)) {
+        (Ok(meta), Ok(parent_meta)) => meta.dev() != parent_meta.dev(),
+        _ => false,
+    }
+}
+
+/// Whether this user may mount with `allow_other` or `allow_root`: root
+/// always may, others if `/etc/fuse.conf` has `user_allow_other`.
+#[cfg(target_os = "linux")]
+pub fn may_allow_other() -> bool {
+    uid() == 0
+        || std::fs::read_to_string("/etc/fuse.conf").is_ok_and(|conf| {
+            conf.lines().any(|line| line.trim() == "user_allow_other")
+        })
+}
+
+/// Whether this user may mount with `allow_other` or `allow_root`. macFUSE
+/// and FUSE-T leave that to the mounting user.
+#[cfg(target_os = "macos")]
+pub fn may_allow_other() -> bool {
+    true
+}
+
+/// Mount options for the platform's FUSE implementation.
+#[cfg(target_os = "linux")]
+pub fn mount_options(_name: &str) -> Vec<fuser::MountOption> {
+    Vec::new()
+}
+
+/// Mount options for the platform's FUSE implementation. macFUSE and FUSE-T
+/// unmount when the process exits, and show the mount name in Finder.
+#[cfg(target_os = "macos")]
+pub fn mount_options(name: &str) -> Vec<fuser::MountOption> {
+    vec![
+        fuser::MountOption::AutoUnmount,
+        fuser::MountOption::CUSTOM(format!("volname={}", name)),
+    ]
+}
+
+/// Unmount `mount_point`. Returns whether that worked.
+pub fn unmount(mount_point: &Path) -> bool {
+    run(UNMOUNT, mount_point)
+}
+
+/// Unmount `mount_point` even though files are open.
+pub fn unmount_busy(mount_point: &Path) -> bool {
+    run(UNMOUNT_BUSY, mount_point)
+}
+
+fn run(command: &[&str], mount_point: &Path) -> bool {
+    std::process::Command::new(command[0])
+        .args(&command[1..])
+        .arg(mount_point)
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+}
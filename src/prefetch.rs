@@ -0,0 +1,227 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::runtime::Handle;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::cache::DirectoryCache;
+use crate::filter::PathFilter;
+use crate::webdav::{DavEntry, DirListing, WebDavClient};
+
+/// Levels below a listed directory that are prefetched.
+const PREFETCH_DEPTH: u32 = 3;
+
+/// Order in which subdirectories are prefetched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrefetchStrategy {
+    /// Level by level: the directories the user is most likely to open
+    /// next come first
+    #[default]
+    Bfs,
+    /// One subtree after the other, e.g. ahead of a backup walking the tree
+    Dfs,
+    /// Only list directories when they are opened
+    None,
+}
+
+/// What a walk of the tree cached.
+#[derive(Debug, Default)]
+pub struct PrefetchSummary {
+    pub directories: u64,
+    pub entries: u64,
+    /// Directories that could not be listed
+    pub failed: u64,
+}
+
+/// Lists the subdirectories of opened directories in the background, with
+/// at most `concurrency` PROPFINDs in flight across all traversals.
+#[derive(Clone)]
+pub struct PrefetchScheduler {
+    runtime: Handle,
+    webdav: WebDavClient,
+    cache: DirectoryCache,
+    filter: PathFilter,
+    strategy: PrefetchStrategy,
+    permits: Arc<Semaphore>,
+}
+
+impl PrefetchScheduler {
+    pub fn new(runtime: Handle, webdav: WebDavClient, cache: DirectoryCache, filter: PathFilter) -> Self {
+        Self {
+            runtime,
+            webdav,
+            cache,
+            filter,
+            strategy: PrefetchStrategy::default(),
+            permits: Arc::new(Semaphore::new(4)),
+        }
+    }
+
+    pub fn with_strategy(mut self, strategy: PrefetchStrategy, concurrency: usize) -> Self {
+        self.strategy = strategy;
+        self.permits = Arc::new(Semaphore::new(concurrency.max(1)));
+        self
+    }
+
+    /// Prefetch the subdirectories of `dir_path`, just listed as `entries`.
+    pub fn schedule(&self, dir_path: &str, entries: &[DavEntry]) {
+        if self.strategy == PrefetchStrategy::None {
+            return;
+        }
+        let subdirs = self.subdirectories(dir_path, entries, 1);
+        if subdirs.is_empty() {
+            return;
+        }
+        self.webdav.metrics().prefetch_queued.fetch_add(subdirs.len() as u64, Ordering::Relaxed);
+        self.runtime.spawn(self.clone().run(subdirs));
+    }
+
+    /// List `path` and the directories below it in the background, even
+    /// if they are cached.
+    pub fn prefetch_path(&self, path: &str) {
+        let scheduler = self.clone();
+        let path = path.to_string();
+        self.runtime.spawn(async move {
+            let summary = scheduler.walk(&path, PREFETCH_DEPTH, |_, _| {}).await;
+            tracing::info!("Prefetched {} directories below {}", summary.directories, path);
+        });
+    }
+
+    async fn run(self, subdirs: Vec<(String, u32)>) {
+        let mut pending = VecDeque::from(subdirs);
+        let mut running = JoinSet::new();
+
+        loop {
+            while !pending.is_empty() {
+                // Wait for a permit only when nothing of ours is running
+                // that could finish and hand out more work
+                let permit = match self.permits.clone().try_acquire_owned() {
+                    Ok(permit) => permit,
+                    Err(_) if running.is_empty() => match self.permits.clone().acquire_owned().await {
+                        Ok(permit) => permit,
+                        Err(_) => return,
+                    },
+                    Err(_) => break,
+                };
+                let next = match self.strategy {
+                    PrefetchStrategy::Dfs => pending.pop_back(),
+                    _ => pending.pop_front(),
+                };
+                let Some((path, depth)) = next else { break };
+                let scheduler = self.clone();
+                running.spawn(async move {
+                    let _permit = permit;
+                    scheduler.fetch(&path, depth).await
+                });
+            }
+
+            let Some(result) = running.join_next().await else { break };
+            self.webdav.metrics().prefetch_queued.fetch_sub(1, Ordering::Relaxed);
+            let mut subdirs = result.unwrap_or_default();
+            self.webdav.metrics().prefetch_queued.fetch_add(subdirs.len() as u64, Ordering::Relaxed);
+            if self.strategy == PrefetchStrategy::Dfs {
+                // Popped from the back, so the first child goes last
+                subdirs.reverse();
+            }
+            pending.extend(subdirs);
+        }
+    }
+
+    /// List `root` and the directories up to `max_depth` levels below it
+    /// into the cache, including ones already cached, and wait until done.
+    /// After each listing `progress` is told how many directories were
+    /// listed out of the ones found so far.
+    pub async fn walk(&self, root: &str, max_depth: u32, progress: impl Fn(u64, u64)) -> PrefetchSummary {
+        let mut summary = PrefetchSummary::default();
+        let mut pending = vec![(root.to_string(), 0)];
+        let mut running = JoinSet::new();
+        let mut found = 1;
+
+        loop {
+            for (path, depth) in pending.drain(..) {
+                let scheduler = self.clone();
+                running.spawn(async move {
+                    let _permit = scheduler.permits.clone().acquire_owned().await;
+                    let result = scheduler.list(&path, depth, max_depth).await;
+                    (path, result)
+                });
+            }
+
+            let Some(joined) = running.join_next().await else { break };
+            match joined {
+                Ok((path, Ok((entries, subdirs)))) => {
+                    tracing::debug!("Cached {} ({} entries)", path, entries);
+                    summary.directories += 1;
+                    summary.entries += entries as u64;
+                    found += subdirs.len() as u64;
+                    pending = subdirs;
+                }
+                Ok((path, Err(e))) => {
+                    tracing::warn!("Failed to list {}: {}", path, e);
+                    summary.failed += 1;
+                }
+                Err(e) => {
+                    tracing::warn!("Prefetch task failed: {}", e);
+                    summary.failed += 1;
+                }
+            }
+            progress(summary.directories + summary.failed, found);
+        }
+        summary
+    }
+
+    /// List `path` into the cache. Returns its subdirectories still to be
+    /// prefetched.
+    async fn fetch(&self, path: &str, depth: u32) -> Vec<(String, u32)> {
+        if self.cache.get_stale(path).is_some() {
+            return Vec::new();
+        }
+        match self.list(path, depth, PREFETCH_DEPTH).await {
+            Ok((entries, subdirs)) => {
+                tracing::info!(
+                    "Prefetched {} (depth {}, {} entries, {} subdirs)",
+                    path, depth, entries, subdirs.len()
+                );
+                subdirs
+            }
+            Err(e) => {
+                tracing::debug!("Failed to prefetch {}: {}", path, e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// List `path`, at `depth` below where prefetching started, into the
+    /// cache. Returns its number of entries and its subdirectories if they
+    /// are within `max_depth`.
+    async fn list(&self, path: &str, depth: u32, max_depth: u32) -> anyhow::Result<(usize, Vec<(String, u32)>)> {
+        let dav_path = path.trim_start_matches('/');
+        let DirListing { entries, etag, .. } = self
+            .cache
+            .fetch_listing(path, async {
+                Ok(self.webdav.list_dir_conditional(dav_path, None).await?.unwrap_or_default())
+            })
+            .await?;
+        let subdirs = if depth < max_depth {
+            self.subdirectories(path, &entries, depth + 1)
+        } else {
+            Vec::new()
+        };
+        let count = entries.len();
+        self.cache.insert(path.to_string(), entries, etag);
+        Ok((count, subdirs))
+    }
+
+    fn subdirectories(&self, dir_path: &str, entries: &[DavEntry], depth: u32) -> Vec<(String, u32)> {
+        entries
+            .iter()
+            .filter(|e| e.is_dir)
+            .map(|e| format!("{}/{}", dir_path.trim_end_matches('/'), e.name))
+            .filter(|p| !self.filter.excludes(p, true))
+            .map(|p| (p, depth))
+            .collect()
+    }
+}
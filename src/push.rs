@@ -0,0 +1,90 @@
+use anyhow::{Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::cache::DirectoryCache;
+use crate::filesystem::DavFS;
+use crate::webdav::WebDavClient;
+
+/// Wait before reconnecting after the push connection dropped.
+const RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Listen for change notifications from Nextcloud's notify_push app at the
+/// WebSocket `endpoint` and drop the affected cached listings, reconnecting
+/// whenever the connection is lost.
+pub async fn run(webdav: WebDavClient, dir_cache: DirectoryCache, endpoint: String) {
+    loop {
+        match listen(&webdav, &dir_cache, &endpoint).await {
+            Ok(()) => tracing::info!("notify_push connection closed"),
+            Err(e) => tracing::warn!("notify_push connection failed: {:#}", e),
+        }
+        // Changes while disconnected were missed, so revalidate everything
+        dir_cache.expire_all();
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn listen(webdav: &WebDavClient, dir_cache: &DirectoryCache, endpoint: &str) -> Result<()> {
+    let Some((username, password)) = webdav.basic_credentials().await else {
+        anyhow::bail!("notify_push needs username and password authentication");
+    };
+    let (mut socket, _) = tokio_tungstenite::connect_async(endpoint)
+        .await
+        .with_context(|| format!("Failed to connect to {}", endpoint))?;
+
+    // Credentials go first, as two plain text messages
+    socket.send(Message::Text(username)).await?;
+    socket.send(Message::Text(password)).await?;
+    socket.send(Message::Text("listen notify_file_id".to_string())).await?;
+
+    while let Some(message) = socket.next().await {
+        match message? {
+            Message::Text(text) => handle_message(dir_cache, &text)?,
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn handle_message(dir_cache: &DirectoryCache, message: &str) -> Result<()> {
+    if let Some(error) = message.strip_prefix("err:") {
+        anyhow::bail!("Server rejected notify_push login: {}", error.trim());
+    }
+    let (kind, body) = message.split_once(' ').unwrap_or((message, ""));
+    match kind {
+        "authenticated" => tracing::info!("Listening for remote changes via notify_push"),
+        // IDs of changed files, when the server can tell which
+        "notify_file_id" => match serde_json::from_str::<Vec<u64>>(body) {
+            Ok(ids) => invalidate_file_ids(dir_cache, &ids),
+            Err(e) => {
+                tracing::debug!("Unexpected notify_file_id message {:?}: {}", body, e);
+                dir_cache.expire_all();
+            }
+        },
+        // Something changed for this user, without details
+        "notify_file" => dir_cache.expire_all(),
+        _ => tracing::debug!("Ignoring notify_push message {:?}", message),
+    }
+    Ok(())
+}
+
+/// Drop the listings holding the files with `ids`. Files that are not in
+/// any listing in memory may be in one on disk, so then everything is
+/// revalidated instead.
+fn invalidate_file_ids(dir_cache: &DirectoryCache, ids: &[u64]) {
+    let ids: Vec<String> = ids.iter().map(u64::to_string).collect();
+    let found = dir_cache.find_file_ids(&ids);
+    tracing::debug!("Remote change to {} files, {} cached", ids.len(), found.len());
+
+    for (dir_path, entry) in &found {
+        dir_cache.invalidate(dir_path);
+        if entry.is_dir {
+            dir_cache.invalidate(&DavFS::child_path(dir_path, &entry.name));
+        }
+    }
+    if found.len() < ids.len() {
+        dir_cache.expire_all();
+    }
+}
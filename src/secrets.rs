@@ -4,6 +4,17 @@ use secret_service::EncryptionType;
 use std::collections::HashMap;
 
 use crate::config::MountConfig;
+use crate::webdav::{AuthMethod, TlsConfig};
+
+/// `DAVFS_SYNC_PASSWORD_<NAME>`, the variable holding the password of
+/// mount `name`.
+pub fn password_env_var(name: &str) -> String {
+    let suffix: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    format!("DAVFS_SYNC_PASSWORD_{}", suffix)
+}
 
 pub struct SecretStore {
     service: SecretService<'static>,
@@ -98,6 +109,26 @@ impl SecretStore {
         Ok(())
     }
 
+    /// Password for `name` from the environment, for hosts without a Secret
+    /// Service: `DAVFS_SYNC_PASSWORD_<NAME>`, the file named by
+    /// `DAVFS_SYNC_PASSWORD_FILE_<NAME>` (e.g. a Docker secret), then
+    /// `DAVFS_SYNC_PASSWORD`. `<NAME>` is the mount name in upper case with
+    /// anything but letters and digits replaced by `_`.
+    pub fn load_password_env(name: &str) -> Result<Option<String>> {
+        let var = password_env_var(name);
+        if let Ok(password) = std::env::var(&var) {
+            return Ok(Some(password));
+        }
+        let file_var = var.replacen("DAVFS_SYNC_PASSWORD_", "DAVFS_SYNC_PASSWORD_FILE_", 1);
+        if let Some(path) = std::env::var_os(file_var) {
+            let password = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read password file {}", path.to_string_lossy()))?;
+            // Files usually end with a newline that is not part of the password
+            return Ok(Some(password.trim_end_matches(['\r', '\n']).to_string()));
+        }
+        Ok(std::env::var("DAVFS_SYNC_PASSWORD").ok())
+    }
+
     pub async fn load_password(&self, name: &str) -> Result<String> {
         let collection = self.service
             .get_default_collection()
@@ -125,6 +156,175 @@ impl SecretStore {
         Ok(String::from_utf8(secret)?)
     }
 
+    /// Store the credentials for `name`, serialized as JSON.
+    pub async fn store_auth(&self, name: &str, auth: &AuthMethod) -> Result<()> {
+        let auth_json = serde_json::to_string(auth)?;
+
+        let collection = self.service
+            .get_default_collection()
+            .await
+            .context("Failed to get default collection")?;
+
+        let mut attributes = HashMap::new();
+        attributes.insert("application", "davfs-sync");
+        attributes.insert("type", "auth");
+        attributes.insert("mount", name);
+
+        collection
+            .create_item(
+                &format!("davfs-sync credentials: {}", name),
+                attributes,
+                auth_json.as_bytes(),
+                true, // replace existing
+                "application/json",
+            )
+            .await
+            .context("Failed to store credentials")?;
+
+        Ok(())
+    }
+
+    /// Load the credentials for `name`. Returns `Ok(None)` for mounts set up
+    /// before credentials were stored as `type=auth`.
+    pub async fn load_auth(&self, name: &str) -> Result<Option<AuthMethod>> {
+        let collection = self.service
+            .get_default_collection()
+            .await
+            .context("Failed to get default collection")?;
+
+        let mut attributes = HashMap::new();
+        attributes.insert("application", "davfs-sync");
+        attributes.insert("type", "auth");
+        attributes.insert("mount", name);
+
+        let items = collection
+            .search_items(attributes)
+            .await
+            .context("Failed to search for credentials")?;
+
+        let Some(item) = items.first() else {
+            return Ok(None);
+        };
+
+        let secret = item.get_secret()
+            .await
+            .context("Failed to get secret")?;
+
+        let auth: AuthMethod = serde_json::from_str(&String::from_utf8(secret)?)?;
+        Ok(Some(auth))
+    }
+
+    /// Store the CA and client certificate settings for `name`.
+    pub async fn store_tls_config(&self, name: &str, tls: &TlsConfig) -> Result<()> {
+        let tls_json = serde_json::to_string(tls)?;
+
+        let collection = self.service
+            .get_default_collection()
+            .await
+            .context("Failed to get default collection")?;
+
+        let mut attributes = HashMap::new();
+        attributes.insert("application", "davfs-sync");
+        attributes.insert("type", "tls-config");
+        attributes.insert("mount", name);
+
+        collection
+            .create_item(
+                &format!("davfs-sync TLS config: {}", name),
+                attributes,
+                tls_json.as_bytes(),
+                true, // replace existing
+                "application/json",
+            )
+            .await
+            .context("Failed to store TLS config")?;
+
+        Ok(())
+    }
+
+    /// Load the TLS settings for `name`, or the defaults if none were stored.
+    pub async fn load_tls_config(&self, name: &str) -> Result<TlsConfig> {
+        let collection = self.service
+            .get_default_collection()
+            .await
+            .context("Failed to get default collection")?;
+
+        let mut attributes = HashMap::new();
+        attributes.insert("application", "davfs-sync");
+        attributes.insert("type", "tls-config");
+        attributes.insert("mount", name);
+
+        let items = collection
+            .search_items(attributes)
+            .await
+            .context("Failed to search for TLS config")?;
+
+        let Some(item) = items.first() else {
+            return Ok(TlsConfig::default());
+        };
+
+        let secret = item.get_secret()
+            .await
+            .context("Failed to get secret")?;
+
+        let tls: TlsConfig = serde_json::from_str(&String::from_utf8(secret)?)?;
+        Ok(tls)
+    }
+
+    /// Store the age identity (private key) of encrypted mount `name`.
+    pub async fn store_age_key(&self, name: &str, identity: &str) -> Result<()> {
+        let collection = self.service
+            .get_default_collection()
+            .await
+            .context("Failed to get default collection")?;
+
+        let mut attributes = HashMap::new();
+        attributes.insert("application", "davfs-sync");
+        attributes.insert("type", "age-key");
+        attributes.insert("mount", name);
+
+        collection
+            .create_item(
+                &format!("davfs-sync age key: {}", name),
+                attributes,
+                identity.as_bytes(),
+                true, // replace existing
+                "text/plain",
+            )
+            .await
+            .context("Failed to store age key")?;
+
+        Ok(())
+    }
+
+    /// The age identity of `name`, or `None` if none was stored.
+    pub async fn load_age_key(&self, name: &str) -> Result<Option<String>> {
+        let collection = self.service
+            .get_default_collection()
+            .await
+            .context("Failed to get default collection")?;
+
+        let mut attributes = HashMap::new();
+        attributes.insert("application", "davfs-sync");
+        attributes.insert("type", "age-key");
+        attributes.insert("mount", name);
+
+        let items = collection
+            .search_items(attributes)
+            .await
+            .context("Failed to search for age key")?;
+
+        let Some(item) = items.first() else {
+            return Ok(None);
+        };
+
+        let secret = item.get_secret()
+            .await
+            .context("Failed to get secret")?;
+
+        Ok(Some(String::from_utf8(secret)?))
+    }
+
     pub async fn list_mounts(&self) -> Result<Vec<String>> {
         let collection = self.service
             .get_default_collection()
@@ -152,4 +352,41 @@ impl SecretStore {
 
         Ok(mount_names)
     }
+
+    /// Delete the config and password items stored for `name`. The age key
+    /// of an encrypted mount is kept, as the files on the server cannot be
+    /// read without it.
+    pub async fn delete_mount(&self, name: &str) -> Result<()> {
+        let collection = self.service
+            .get_default_collection()
+            .await
+            .context("Failed to get default collection")?;
+
+        let mut attributes = HashMap::new();
+        attributes.insert("application", "davfs-sync");
+        attributes.insert("mount", name);
+
+        let items = collection
+            .search_items(attributes)
+            .await
+            .context("Failed to search for mount")?;
+
+        if items.is_empty() {
+            anyhow::bail!("Mount '{}' not found", name);
+        }
+
+        for item in items {
+            let attrs = item.get_attributes()
+                .await
+                .context("Failed to get attributes")?;
+            if attrs.get("type").is_some_and(|t| t == "age-key") {
+                continue;
+            }
+            item.delete()
+                .await
+                .context("Failed to delete keyring item")?;
+        }
+
+        Ok(())
+    }
 }
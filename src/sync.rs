@@ -0,0 +1,339 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::cache::{CacheResult, DirectoryCache};
+use crate::content_cache::ContentCache;
+use crate::filesystem::DavFS;
+use crate::journal::WriteJournal;
+use crate::nextcloud::ActivityFeed;
+use crate::webdav::{checksum_matches, is_network_error, DavEntry, WebDavClient};
+
+/// Connectivity as last seen by the sync loop.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncState {
+    #[default]
+    Unknown,
+    Online,
+    Offline,
+    /// Replaying journaled changes
+    Syncing,
+}
+
+impl SyncState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SyncState::Unknown => "unknown",
+            SyncState::Online => "online",
+            SyncState::Offline => "offline",
+            SyncState::Syncing => "syncing",
+        }
+    }
+}
+
+/// Sync state of a mount, persisted next to the cache so `status` can show
+/// it from another process.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncStatus {
+    pub state: SyncState,
+    /// Last time the journal was fully drained while online
+    pub last_sync: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_error: Option<String>,
+    /// Server URL in use, which differs from the first configured one
+    /// after a failover
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_url: Option<String>,
+}
+
+/// Background task that watches connectivity and, once the server is
+/// reachable, drains the write journal and refreshes stale listings.
+pub struct SyncManager {
+    webdav: WebDavClient,
+    dir_cache: DirectoryCache,
+    journal: Arc<WriteJournal>,
+    interval: Duration,
+    status_path: PathBuf,
+    status: Mutex<SyncStatus>,
+    // Keeps the background loop and a shutdown sync from replaying at once
+    running: tokio::sync::Mutex<()>,
+    // Remote changes to drop listings for, on Nextcloud without notify_push
+    activity_feed: Option<ActivityFeed>,
+    // Where the content of pinned files is kept
+    content_cache: Option<ContentCache>,
+}
+
+impl SyncManager {
+    pub fn new(
+        webdav: WebDavClient,
+        dir_cache: DirectoryCache,
+        journal: WriteJournal,
+        interval: Duration,
+        status_path: PathBuf,
+    ) -> Self {
+        Self {
+            webdav,
+            dir_cache,
+            journal: Arc::new(journal),
+            interval,
+            status_path,
+            status: Mutex::new(SyncStatus::default()),
+            running: tokio::sync::Mutex::new(()),
+            activity_feed: None,
+            content_cache: None,
+        }
+    }
+
+    /// Poll `activity_feed` on every sync for changes made elsewhere.
+    pub fn with_activity_feed(mut self, activity_feed: Option<ActivityFeed>) -> Self {
+        self.activity_feed = activity_feed;
+        self
+    }
+
+    /// Download the content of pinned files into `content_cache`.
+    pub fn with_content_cache(mut self, content_cache: ContentCache) -> Self {
+        self.content_cache = Some(content_cache);
+        self
+    }
+
+    pub fn journal(&self) -> &Arc<WriteJournal> {
+        &self.journal
+    }
+
+    pub fn status(&self) -> SyncStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Read the status written by a running mount, if any.
+    pub fn load_status(path: &Path) -> Result<Option<SyncStatus>> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Ok(Some(serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse {}", path.display()))?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(self.interval);
+        loop {
+            interval.tick().await;
+            self.sync_once().await;
+        }
+    }
+
+    /// Check the connection and, if the server is reachable, replay the
+    /// journal.
+    pub async fn sync_once(&self) {
+        let _running = self.running.lock().await;
+        let previous = self.status().state;
+        let was_online = previous == SyncState::Online;
+
+        self.webdav.retest_primary().await;
+        let result = self.webdav.test_connection().await;
+        let active_url = Some(self.webdav.active_url().to_string());
+        if let Err(e) = result {
+            if was_online {
+                tracing::warn!("Lost connection to server: {:#}", e);
+            }
+            self.update(|status| {
+                status.state = SyncState::Offline;
+                status.last_error = Some(format!("{:#}", e));
+                status.active_url = active_url;
+            });
+            return;
+        }
+        if !was_online {
+            tracing::info!("Server is reachable");
+        }
+
+        let mut last_error = None;
+        if !self.journal.is_empty() {
+            self.update(|status| status.state = SyncState::Syncing);
+            match self.journal.replay(&self.webdav).await {
+                Ok(replayed) => {
+                    for path in replayed.iter().flat_map(|op| op.paths()) {
+                        self.dir_cache.invalidate(DavFS::parent_of(path));
+                    }
+                    if !replayed.is_empty() {
+                        tracing::info!(
+                            "Replayed {} offline changes, {} pending",
+                            replayed.len(),
+                            self.journal.len()
+                        );
+                    }
+                }
+                Err(e) => {
+                    tracing::error!("Journal replay failed: {:#}", e);
+                    last_error = Some(format!("{:#}", e));
+                }
+            }
+        }
+
+        if let Some(feed) = &self.activity_feed {
+            if let Err(e) = feed.invalidate_changed(&self.dir_cache).await {
+                tracing::debug!("Failed to poll the activity feed: {:#}", e);
+            }
+        }
+
+        // Listings may have changed on the server while we were away. Not
+        // done on startup, where the initial prefetch already re-lists.
+        if previous == SyncState::Offline {
+            self.refresh_stale().await;
+        }
+        self.refresh_pinned().await;
+
+        let drained = self.journal.is_empty();
+        self.update(|status| {
+            status.state = SyncState::Online;
+            if drained {
+                status.last_sync = Some(chrono::Utc::now());
+            }
+            status.last_error = last_error;
+            status.active_url = active_url;
+        });
+    }
+
+    async fn refresh_stale(&self) {
+        let stale = self.dir_cache.expired_paths();
+        if stale.is_empty() {
+            return;
+        }
+        tracing::info!("Refreshing {} stale directory listings", stale.len());
+
+        for (dir_path, etag) in stale {
+            let dav_path = dir_path.trim_start_matches('/');
+            match self.webdav.list_dir_conditional(dav_path, etag.as_deref()).await {
+                Ok(None) => self.dir_cache.touch(&dir_path),
+                Ok(Some(listing)) => self.dir_cache.insert(dir_path, listing.entries, listing.etag),
+                Err(e) if is_network_error(&e) => {
+                    tracing::debug!("Connection dropped while refreshing listings: {}", e);
+                    break;
+                }
+                Err(e) => {
+                    tracing::debug!("Failed to refresh {}: {}", dir_path, e);
+                    self.dir_cache.invalidate(&dir_path);
+                }
+            }
+        }
+    }
+
+    /// Revalidate the expired listings of pinned paths and the directories
+    /// below them, and download pinned files missing from the content
+    /// cache.
+    async fn refresh_pinned(&self) {
+        for pinned in self.dir_cache.pinned_paths() {
+            if pinned != "/" {
+                let name = pinned.rsplit('/').next().unwrap_or_default();
+                let Some(siblings) = self.refreshed_listing(DavFS::parent_of(&pinned)).await else {
+                    continue;
+                };
+                match siblings.into_iter().find(|entry| entry.name == name) {
+                    Some(entry) if entry.is_dir => {}
+                    Some(entry) => {
+                        self.fetch_content(&pinned, &entry).await;
+                        continue;
+                    }
+                    None => {
+                        tracing::debug!("Pinned path {} no longer exists", pinned);
+                        continue;
+                    }
+                }
+            }
+
+            let mut dirs = vec![pinned];
+            while let Some(dir) = dirs.pop() {
+                let Some(entries) = self.refreshed_listing(&dir).await else {
+                    continue;
+                };
+                for entry in entries {
+                    let path = DavFS::child_path(&dir, &entry.name);
+                    if entry.is_dir {
+                        dirs.push(path);
+                    } else {
+                        self.fetch_content(&path, &entry).await;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The listing of `dir`, revalidated first if it expired.
+    async fn refreshed_listing(&self, dir: &str) -> Option<Vec<DavEntry>> {
+        let (cached, etag) = match self.dir_cache.peek(dir) {
+            CacheResult::Fresh(entries) => return Some(entries),
+            CacheResult::Stale { entries, etag } => (Some(entries), etag),
+            CacheResult::Miss => (None, None),
+        };
+        match self.webdav.list_dir_conditional(dir.trim_start_matches('/'), etag.as_deref()).await {
+            Ok(None) => {
+                self.dir_cache.touch(dir);
+                cached
+            }
+            Ok(Some(listing)) => {
+                self.dir_cache.insert(dir.to_string(), listing.entries.clone(), listing.etag);
+                Some(listing.entries)
+            }
+            Err(e) => {
+                tracing::debug!("Failed to refresh pinned directory {}: {}", dir, e);
+                cached
+            }
+        }
+    }
+
+    /// Download `entry` at `path` into the content cache unless this
+    /// version is there already.
+    async fn fetch_content(&self, path: &str, entry: &DavEntry) {
+        let (Some(cache), Some(etag)) = (&self.content_cache, &entry.etag) else {
+            return;
+        };
+        let Ok(url) = self.webdav.url_for(path) else {
+            return;
+        };
+        let key = ContentCache::key(url.as_str(), etag);
+        if cache.contains(&key) {
+            return;
+        }
+
+        let content = match self.webdav.read_file(path).await {
+            Ok(content) => content,
+            Err(e) => {
+                tracing::debug!("Failed to download pinned file {}: {}", path, e);
+                return;
+            }
+        };
+        // Only verified content enters the content cache
+        if self.webdav.verifies_checksums() {
+            if let Some(announced) = entry.properties.get("oc:checksums") {
+                if checksum_matches(announced, &content) == Some(false) {
+                    tracing::warn!("Checksum mismatch for pinned file {}, not caching it", path);
+                    return;
+                }
+            }
+        }
+        match cache.insert(&key, &content) {
+            Ok(()) => tracing::debug!("Downloaded pinned file {}", path),
+            Err(e) => tracing::warn!("Failed to cache content of {}: {:#}", path, e),
+        }
+    }
+
+    fn update(&self, change: impl FnOnce(&mut SyncStatus)) {
+        let status = {
+            let mut status = self.status.lock().unwrap();
+            change(&mut status);
+            status.clone()
+        };
+        if let Err(e) = self.save(&status) {
+            tracing::warn!("Failed to write sync status: {:#}", e);
+        }
+    }
+
+    fn save(&self, status: &SyncStatus) -> Result<()> {
+        let tmp = self.status_path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec(status)?)?;
+        std::fs::rename(&tmp, &self.status_path)?;
+        Ok(())
+    }
+}
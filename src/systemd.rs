@@ -0,0 +1,107 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+use crate::config::{self, MountConfig};
+
+/// Unit file name for mount `name`, escaped like `systemd-escape` does for
+/// characters not allowed in unit names.
+pub fn unit_name(name: &str) -> String {
+    let escaped: String = name
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b':' | b'_' | b'.' | b'-' => (b as char).to_string(),
+            _ => format!("\\x{:02x}", b),
+        })
+        .collect();
+    format!("davfs-sync-{}.service", escaped)
+}
+
+/// Where unit files go: the user's systemd config, or the system-wide one.
+pub fn unit_dir(user: bool) -> Result<PathBuf> {
+    if user {
+        let config_home = config::config_home()
+            .ok_or_else(|| anyhow::anyhow!("Cannot determine config directory (HOME not set)"))?;
+        Ok(config_home.join("systemd").join("user"))
+    } else {
+        Ok(PathBuf::from("/etc/systemd/system"))
+    }
+}
+
+/// Render a service unit that mounts `config` with the binary at `exe`.
+pub fn render_unit(config: &MountConfig, exe: &Path, user: bool) -> String {
+    let exe = quote(&exe.to_string_lossy());
+    let mount_point = quote(&config.mount_point.to_string_lossy());
+    let wanted_by = if user { "default.target" } else { "multi-user.target" };
+
+    format!(
+        "[Unit]\n\
+         Description=davfs-sync WebDAV mount {name} ({url})\n\
+         Wants=network-online.target\n\
+         After=network-online.target secret-service.target\n\
+         \n\
+         [Service]\n\
+         Type=simple\n\
+         ExecStart={exe} mount {quoted_name}\n\
+         ExecStopPost=-fusermount3 -uz {mount_point}\n\
+         Restart=on-failure\n\
+         RestartSec=10s\n\
+         \n\
+         [Install]\n\
+         WantedBy={wanted_by}\n",
+        name = escape_specifiers(&config.name),
+        url = escape_specifiers(config.url()),
+        quoted_name = quote(&config.name),
+    )
+}
+
+/// Write the unit for `config` and return its path.
+pub fn install(config: &MountConfig, user: bool) -> Result<PathBuf> {
+    let exe = std::env::current_exe().context("Cannot determine path of the davfs-sync binary")?;
+    let dir = unit_dir(user)?;
+    std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    let path = dir.join(unit_name(&config.name));
+    std::fs::write(&path, render_unit(config, &exe, user))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// The `systemctl` invocations that activate the unit, as argument lists.
+pub fn activation_commands(name: &str, user: bool) -> Vec<Vec<String>> {
+    let systemctl = |args: &[&str]| {
+        let mut command = vec!["systemctl".to_string()];
+        if user {
+            command.push("--user".to_string());
+        }
+        command.extend(args.iter().map(|a| a.to_string()));
+        command
+    };
+    vec![
+        systemctl(&["daemon-reload"]),
+        systemctl(&["enable", "--now", &unit_name(name)]),
+    ]
+}
+
+/// A command as the user would type it; escaped unit names contain
+/// backslashes that need quoting in the shell.
+pub fn display_command(command: &[String]) -> String {
+    command
+        .iter()
+        .map(|arg| if arg.contains('\\') { format!("'{}'", arg) } else { arg.clone() })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// `%` introduces specifiers in unit files.
+fn escape_specifiers(value: &str) -> String {
+    value.replace('%', "%%")
+}
+
+/// Quote a command line argument for ExecStart if needed.
+fn quote(arg: &str) -> String {
+    let arg = escape_specifiers(arg);
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'' || c == '\\') {
+        return arg;
+    }
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
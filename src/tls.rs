@@ -0,0 +1,90 @@
+use anyhow::{Context, Result};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::webdav::TlsConfig;
+
+/// rustls client configuration honoring `tls_min_version` and
+/// `tls_ciphers`, with the system's root certificates plus `ca_cert_path`.
+pub fn client_config(tls: &TlsConfig, http2: bool) -> Result<rustls::ClientConfig> {
+    if tls.danger_accept_invalid_certs {
+        anyhow::bail!("danger_accept_invalid_certs cannot be combined with tls_min_version = \"1.3\" or tls_ciphers");
+    }
+
+    let default = rustls::crypto::ring::default_provider();
+    let cipher_suites = if tls.tls_ciphers.is_empty() {
+        default.cipher_suites.clone()
+    } else {
+        tls.tls_ciphers
+            .iter()
+            .map(|name| {
+                default
+                    .cipher_suites
+                    .iter()
+                    .find(|suite| suite.suite().as_str().is_some_and(|known| known.eq_ignore_ascii_case(name)))
+                    .copied()
+                    .ok_or_else(|| {
+                        let known: Vec<&str> =
+                            default.cipher_suites.iter().filter_map(|suite| suite.suite().as_str()).collect();
+                        anyhow::anyhow!("Unknown TLS cipher suite {}; supported are {}", name, known.join(", "))
+                    })
+            })
+            .collect::<Result<_>>()?
+    };
+    let provider = CryptoProvider { cipher_suites, ..default };
+    let versions: &[&rustls::SupportedProtocolVersion] = match tls.tls_min_version.as_deref() {
+        Some("1.3") => &[&rustls::version::TLS13],
+        _ => &[&rustls::version::TLS12, &rustls::version::TLS13],
+    };
+    let builder = rustls::ClientConfig::builder_with_provider(Arc::new(provider))
+        .with_protocol_versions(versions)
+        .context("None of tls_ciphers is available for tls_min_version")?;
+
+    let mut roots = rustls::RootCertStore::empty();
+    let native = rustls_native_certs::load_native_certs();
+    for error in &native.errors {
+        tracing::debug!("Failed to load system root certificates: {}", error);
+    }
+    roots.add_parsable_certificates(native.certs);
+    if let Some(path) = &tls.ca_cert_path {
+        for cert in read_certs(path)? {
+            roots.add(cert).with_context(|| format!("Invalid CA certificate {}", path.display()))?;
+        }
+    }
+    let builder = builder.with_root_certificates(roots);
+
+    let mut config = match (&tls.client_cert_path, &tls.client_key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            let key = PrivateKeyDer::from_pem_file(key_path)
+                .with_context(|| format!("Invalid private key {}", key_path.display()))?;
+            builder
+                .with_client_auth_cert(read_certs(cert_path)?, key)
+                .with_context(|| format!("Invalid client certificate {}", cert_path.display()))?
+        }
+        (Some(_), None) => anyhow::bail!(
+            "PKCS#12 client certificates cannot be combined with tls_min_version = \"1.3\" or tls_ciphers; \
+             use a PEM certificate with client_key_path"
+        ),
+        _ => builder.with_no_client_auth(),
+    };
+    config.alpn_protocols = match http2 {
+        true => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+        false => vec![b"http/1.1".to_vec()],
+    };
+    Ok(config)
+}
+
+/// Certificates in the PEM or DER file at `path`.
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let certs = CertificateDer::pem_slice_iter(&data)
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid certificate {}", path.display()))?;
+    if certs.is_empty() {
+        return Ok(vec![CertificateDer::from(data)]);
+    }
+    Ok(certs)
+}
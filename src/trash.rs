@@ -0,0 +1,200 @@
+use anyhow::{Context, Result};
+use chrono::{Local, NaiveDateTime};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use reqwest::StatusCode;
+
+use crate::filesystem::DavFS;
+use crate::webdav::{DavStatusError, LockToken, PropRequest, WebDavClient};
+
+/// Escaped in `.trashinfo` paths like in URIs; `/` is left as is.
+const PATH_ESCAPE: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'?')
+    .add(b'[')
+    .add(b']');
+
+/// Names tried for an entry before giving up, `name`, `name.2`, ...
+const MAX_NAME_ATTEMPTS: u32 = 100;
+
+const DELETION_DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+/// Virtual directory at the mount root showing the caller's trash.
+pub const TRASH_DIR_NAME: &str = ".trash";
+
+/// An entry in the trash.
+#[derive(Debug, Clone)]
+pub struct TrashEntry {
+    /// Name below `files/`
+    pub name: String,
+    /// Where it was deleted from
+    pub original_path: String,
+    pub deleted: Option<NaiveDateTime>,
+}
+
+/// A user's trash on the server, laid out like a FreeDesktop
+/// `$topdir/.Trash-$uid` at the mount root: deleted entries in `files/`,
+/// where they came from in `info/<name>.trashinfo`.
+pub struct Trash {
+    root: String,
+}
+
+impl Trash {
+    pub fn for_uid(uid: u32) -> Self {
+        Self {
+            root: format!("/.Trash-{}", uid),
+        }
+    }
+
+    /// Collection holding the deleted files and directories.
+    pub fn files_dir(&self) -> String {
+        format!("{}/files", self.root)
+    }
+
+    fn info_dir(&self) -> String {
+        format!("{}/info", self.root)
+    }
+
+    fn info_path(&self, name: &str) -> String {
+        format!("{}/{}.trashinfo", self.info_dir(), name)
+    }
+
+    /// Whether `path` is the trash or inside it.
+    pub fn contains(&self, path: &str) -> bool {
+        path == self.root || path.starts_with(&format!("{}/", self.root))
+    }
+
+    /// Move `path`, locked with `lock` if that is set, into the trash.
+    /// Returns its name there.
+    pub async fn put(&self, webdav: &WebDavClient, path: &str, lock: Option<&LockToken>) -> Result<String> {
+        let base = path.rsplit('/').next().unwrap_or(path);
+        let mut created = false;
+        let mut attempt = 1;
+        let name = loop {
+            let name = if attempt == 1 {
+                base.to_string()
+            } else {
+                format!("{}.{}", base, attempt)
+            };
+            let target = DavFS::child_path(&self.files_dir(), &name);
+            let Err(e) = webdav.move_entry(path, &target, false, lock).await else {
+                break name;
+            };
+            match status_of(&e) {
+                // Taken by an earlier deletion of the same name
+                Some(StatusCode::PRECONDITION_FAILED) if attempt < MAX_NAME_ATTEMPTS => attempt += 1,
+                // No trash yet
+                Some(StatusCode::CONFLICT) if !created => {
+                    self.create(webdav).await?;
+                    created = true;
+                }
+                _ => return Err(e),
+            }
+        };
+
+        let info = format!(
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            utf8_percent_encode(path.trim_start_matches('/'), PATH_ESCAPE),
+            Local::now().format(DELETION_DATE_FORMAT),
+        );
+        // The entry is in the trash either way; without its info it can
+        // still be moved out through the mount
+        if let Err(e) = webdav.put_file(&self.info_path(&name), info.into_bytes(), None, None).await {
+            tracing::warn!("Failed to record where {} was deleted from: {}", name, e);
+        }
+        Ok(name)
+    }
+
+    async fn create(&self, webdav: &WebDavClient) -> Result<()> {
+        for dir in [self.root.clone(), self.files_dir(), self.info_dir()] {
+            match webdav.mkcol(&dir).await {
+                Ok(()) => {}
+                Err(e) if status_of(&e) == Some(StatusCode::METHOD_NOT_ALLOWED) => {}
+                Err(e) => return Err(e.context(format!("Failed to create trash collection {}", dir))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Everything in the trash that has its info recorded.
+    pub async fn entries(&self, webdav: &WebDavClient) -> Result<Vec<TrashEntry>> {
+        let infos = match webdav.list_dir(&self.info_dir(), &PropRequest::default()).await {
+            Ok(infos) => infos,
+            Err(e) if status_of(&e) == Some(StatusCode::NOT_FOUND) => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut entries = Vec::new();
+        for info in infos.iter().filter(|info| !info.is_dir) {
+            let Some(name) = info.name.strip_suffix(".trashinfo") else {
+                continue;
+            };
+            let content = webdav.read_file(&format!("{}/{}", self.info_dir(), info.name)).await?;
+            match parse_info(name, &String::from_utf8_lossy(&content)) {
+                Some(entry) => entries.push(entry),
+                None => tracing::warn!("Ignoring malformed {}", info.name),
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Move the most recently deleted entry from `original_path` back
+    /// there. Returns its name in the trash.
+    pub async fn restore(&self, webdav: &WebDavClient, original_path: &str) -> Result<String> {
+        let original_path = format!("/{}", original_path.trim_matches('/'));
+        let entry = self
+            .entries(webdav)
+            .await?
+            .into_iter()
+            .filter(|entry| entry.original_path == original_path)
+            .max_by_key(|entry| entry.deleted)
+            .with_context(|| format!("{} is not in the trash", original_path))?;
+
+        let from = DavFS::child_path(&self.files_dir(), &entry.name);
+        if let Err(e) = webdav.move_entry(&from, &original_path, false, None).await {
+            return Err(match status_of(&e) {
+                Some(StatusCode::PRECONDITION_FAILED) => anyhow::anyhow!("{} already exists", original_path),
+                Some(StatusCode::CONFLICT) => {
+                    anyhow::anyhow!("The directory {} no longer exists", DavFS::parent_of(&original_path))
+                }
+                _ => e,
+            });
+        }
+        if let Err(e) = webdav.delete(&self.info_path(&entry.name), None).await {
+            tracing::warn!("Failed to remove the trash info of {}: {}", entry.name, e);
+        }
+        Ok(entry.name)
+    }
+
+    /// Delete the trash and everything in it. False if there was none.
+    pub async fn empty(&self, webdav: &WebDavClient) -> Result<bool> {
+        match webdav.delete(&self.root, None).await {
+            Ok(()) => Ok(true),
+            Err(e) if status_of(&e) == Some(StatusCode::NOT_FOUND) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn status_of(err: &anyhow::Error) -> Option<StatusCode> {
+    err.downcast_ref::<DavStatusError>().map(|e| e.status)
+}
+
+fn parse_info(name: &str, content: &str) -> Option<TrashEntry> {
+    let mut original_path = None;
+    let mut deleted = None;
+    for line in content.lines() {
+        if let Some(path) = line.strip_prefix("Path=") {
+            original_path = Some(format!("/{}", percent_decode_str(path).decode_utf8_lossy().trim_matches('/')));
+        } else if let Some(date) = line.strip_prefix("DeletionDate=") {
+            deleted = NaiveDateTime::parse_from_str(date.trim(), DELETION_DATE_FORMAT).ok();
+        }
+    }
+    Some(TrashEntry {
+        name: name.to_string(),
+        original_path: original_path?,
+        deleted,
+    })
+}
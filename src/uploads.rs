@@ -0,0 +1,100 @@
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// An upload left unfinished on the server.
+#[derive(Debug, Clone)]
+pub struct PendingUpload {
+    /// Chunked upload collection or TUS upload URL
+    pub upload_url: String,
+    /// Content being uploaded, from `digest`
+    pub digest: String,
+    /// Bytes the server confirmed
+    pub uploaded: u64,
+}
+
+/// Large uploads in progress, kept in the mount's cache database so an
+/// upload cut off by the network continues where it stopped, even after a
+/// remount. Keyed on the path and the etag the upload replaces.
+#[derive(Clone)]
+pub struct UploadLog {
+    db: Arc<Mutex<Connection>>,
+}
+
+impl UploadLog {
+    pub fn open(db_path: &Path) -> Result<Self> {
+        let db = Connection::open(db_path)
+            .with_context(|| format!("Failed to open upload database {}", db_path.display()))?;
+        db.execute_batch(
+            "CREATE TABLE IF NOT EXISTS uploads (
+                path TEXT NOT NULL,
+                etag TEXT NOT NULL,
+                digest TEXT NOT NULL,
+                upload_url TEXT NOT NULL,
+                uploaded INTEGER NOT NULL,
+                PRIMARY KEY (path, etag)
+            );",
+        )
+        .context("Failed to initialize upload database")?;
+        Ok(Self {
+            db: Arc::new(Mutex::new(db)),
+        })
+    }
+
+    pub fn find(&self, path: &str, etag: Option<&str>) -> Option<PendingUpload> {
+        let db = self.db.lock().unwrap();
+        db.query_row(
+            "SELECT upload_url, digest, uploaded FROM uploads WHERE path = ?1 AND etag = ?2",
+            params![path, etag.unwrap_or_default()],
+            |row| {
+                Ok(PendingUpload {
+                    upload_url: row.get(0)?,
+                    digest: row.get(1)?,
+                    uploaded: row.get::<_, i64>(2)?.max(0) as u64,
+                })
+            },
+        )
+        .optional()
+        .unwrap_or_else(|e| {
+            tracing::warn!("Failed to look up pending upload of {}: {}", path, e);
+            None
+        })
+    }
+
+    pub fn start(&self, path: &str, etag: Option<&str>, digest: &str, upload_url: &str) {
+        let db = self.db.lock().unwrap();
+        if let Err(e) = db.execute(
+            "INSERT OR REPLACE INTO uploads (path, etag, digest, upload_url, uploaded) VALUES (?1, ?2, ?3, ?4, 0)",
+            params![path, etag.unwrap_or_default(), digest, upload_url],
+        ) {
+            tracing::warn!("Failed to record upload of {}: {}", path, e);
+        }
+    }
+
+    pub fn progress(&self, path: &str, etag: Option<&str>, uploaded: u64) {
+        let db = self.db.lock().unwrap();
+        if let Err(e) = db.execute(
+            "UPDATE uploads SET uploaded = ?3 WHERE path = ?1 AND etag = ?2",
+            params![path, etag.unwrap_or_default(), uploaded as i64],
+        ) {
+            tracing::warn!("Failed to record upload progress of {}: {}", path, e);
+        }
+    }
+
+    pub fn finish(&self, path: &str, etag: Option<&str>) {
+        let db = self.db.lock().unwrap();
+        if let Err(e) = db.execute(
+            "DELETE FROM uploads WHERE path = ?1 AND etag = ?2",
+            params![path, etag.unwrap_or_default()],
+        ) {
+            tracing::warn!("Failed to remove finished upload of {}: {}", path, e);
+        }
+    }
+}
+
+/// Identifies the content of an upload, so only the same data is resumed.
+pub fn digest(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
@@ -0,0 +1,131 @@
+use chrono::{DateTime, Utc};
+use reqwest::StatusCode;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::cache::DirectoryCache;
+use crate::filesystem::DavFS;
+use crate::webdav::{DavStatusError, WebDavClient};
+
+/// A file someone polled, with what was last seen of it on the server.
+struct Watched {
+    path: String,
+    modified: Option<DateTime<Utc>>,
+    // Changed since the last poll reported it
+    changed: bool,
+    // Kernel poll handles waiting for a change
+    waiting: Vec<u64>,
+}
+
+/// Remote changes of polled files. Files someone waits on in `poll(2)` are
+/// checked with a Depth: 0 PROPFIND every interval; when their
+/// modification time changes, the kernel drops their cached pages and the
+/// waiting processes are woken.
+pub struct PollWatcher {
+    webdav: WebDavClient,
+    dir_cache: DirectoryCache,
+    interval: Duration,
+    watched: Mutex<HashMap<u64, Watched>>,
+}
+
+impl PollWatcher {
+    pub fn new(webdav: WebDavClient, dir_cache: DirectoryCache, interval: Duration) -> Self {
+        Self {
+            webdav,
+            dir_cache,
+            interval,
+            watched: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Start checking in the background of the current runtime, waking
+    /// pollers through `notifier`.
+    pub fn start(self: &Arc<Self>, notifier: fuser::Notifier) {
+        let watcher = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(watcher.interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                watcher.check(&notifier).await;
+            }
+        });
+    }
+
+    /// Note a poll of `ino`, last listed as modified at `modified`. Returns
+    /// whether it changed since the last poll that was told; otherwise
+    /// `kh`, if given, is woken on the next change.
+    pub fn poll(&self, ino: u64, path: &str, modified: Option<DateTime<Utc>>, kh: Option<u64>) -> bool {
+        let mut watched = self.watched.lock().unwrap();
+        let watch = watched.entry(ino).or_insert_with(|| Watched {
+            path: path.to_string(),
+            modified,
+            changed: false,
+            waiting: Vec::new(),
+        });
+        watch.path = path.to_string();
+        if std::mem::take(&mut watch.changed) {
+            return true;
+        }
+        watch.waiting.extend(kh);
+        false
+    }
+
+    /// Stop watching `ino`, e.g. once the kernel forgot it.
+    pub fn unwatch(&self, ino: u64) {
+        self.watched.lock().unwrap().remove(&ino);
+    }
+
+    async fn check(&self, notifier: &fuser::Notifier) {
+        let waited_on: Vec<(u64, String, Option<DateTime<Utc>>)> = self
+            .watched
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, watch)| !watch.waiting.is_empty())
+            .map(|(&ino, watch)| (ino, watch.path.clone(), watch.modified))
+            .collect();
+
+        for (ino, path, modified) in waited_on {
+            let current = match self.webdav.last_modified(&path).await {
+                Ok(current) if current == modified => continue,
+                // Polled before it was listed; this is the baseline
+                Ok(current) if modified.is_none() => {
+                    if let Some(watch) = self.watched.lock().unwrap().get_mut(&ino) {
+                        watch.modified = current;
+                    }
+                    continue;
+                }
+                Ok(current) => current,
+                // Deleted, or moved away under another name
+                Err(e) if e.downcast_ref::<DavStatusError>().is_some_and(|e| e.status == StatusCode::NOT_FOUND) => {
+                    None
+                }
+                Err(e) => {
+                    tracing::debug!("Failed to check {} for changes: {}", path, e);
+                    continue;
+                }
+            };
+
+            tracing::debug!("Polled file {} changed remotely", path);
+            let waiting = match self.watched.lock().unwrap().get_mut(&ino) {
+                Some(watch) => {
+                    watch.modified = current;
+                    watch.changed = true;
+                    std::mem::take(&mut watch.waiting)
+                }
+                None => continue,
+            };
+            self.dir_cache.invalidate(DavFS::parent_of(&path));
+            if let Err(e) = notifier.inval_inode(ino, 0, 0) {
+                tracing::debug!("Failed to invalidate inode {}: {}", ino, e);
+            }
+            for kh in waiting {
+                if let Err(e) = notifier.poll(kh) {
+                    tracing::debug!("Failed to wake poller of {}: {}", path, e);
+                }
+            }
+        }
+    }
+}
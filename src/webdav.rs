@@ -1,182 +1,3217 @@
 use anyhow::{Context, Result};
-use reqwest::Client;
+use futures_util::StreamExt;
+use percent_encoding::percent_decode_str;
+use quick_xml::events::Event;
+use quick_xml::name::{Namespace, ResolveResult};
+use quick_xml::reader::NsReader;
+use rand::Rng;
+use tracing::Instrument;
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context as TaskContext, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncBufRead;
+use tokio_util::io::StreamReader;
 use url::Url;
 
+use crate::crypto::Cipher;
+use crate::metrics::Metrics;
+use crate::uploads::{self, UploadLog};
+
 #[derive(Clone)]
 pub struct WebDavClient {
     client: Client,
-    base_url: Url,
-    username: String,
-    password: String,
+    // Server URLs in failover order; requests go to the one at current_url
+    base_urls: Arc<Vec<Url>>,
+    current_url: Arc<Mutex<usize>>,
+    // Rotate through base_urls on every request
+    load_balance: bool,
+    // Behind an async mutex so a token refresh is done once for all clones
+    auth: Arc<tokio::sync::Mutex<AuthMethod>>,
+    retry_policy: RetryPolicy,
+    // Shared by all clones so every request sees the server's state
+    circuit: Arc<CircuitBreaker>,
+    // Shared by all clones so the limit applies to the whole mount
+    download_limiter: Option<Arc<TokenBucket>>,
+    upload_limiter: Option<Arc<TokenBucket>>,
+    metrics: Arc<Metrics>,
+    capabilities: Option<DavCapabilities>,
+    chunk_size: Option<u64>,
+    // List oc:checksums so downloads can be verified
+    verify_checksums: bool,
+    // Client-side encryption of contents and names
+    cipher: Option<Arc<Cipher>>,
+    // Progress of chunked and TUS uploads, to resume them
+    upload_log: Option<UploadLog>,
+    // Connections opened by the pool, counted by CountConnections
+    connections_opened: Arc<AtomicU64>,
+    // Last authentication challenge, answered up front on later requests
+    server_challenge: Arc<Mutex<Option<ServerChallenge>>>,
+    // Upload to a temporary name and MOVE it over the file; cleared for
+    // all clones once the server turns out not to support MOVE
+    atomic_upload: Arc<AtomicBool>,
+}
+
+/// Prefix of the temporary files atomic uploads write before moving them
+/// into place.
+pub const ATOMIC_UPLOAD_PREFIX: &str = ".davfs-tmp-";
+
+/// How requests are authenticated.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuthMethod {
+    Basic {
+        username: String,
+        password: String,
+    },
+    Bearer {
+        token: String,
+    },
+    /// OAuth2 access token that is refreshed at `token_url` shortly before
+    /// it expires.
+    BearerWithRefresh {
+        access_token: String,
+        refresh_token: String,
+        expires_at: chrono::DateTime<chrono::Utc>,
+        token_url: String,
+    },
+    /// HTTP Digest (MD5), falling back to Basic if the server does not
+    /// offer Digest.
+    Digest {
+        username: String,
+        password: String,
+    },
+}
+
+/// OAuth2 token endpoint response (RFC 6749 section 5.1).
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_in: Option<i64>,
+}
+
+/// What a server asked for in `WWW-Authenticate` for `AuthMethod::Digest`.
+#[derive(Debug, Clone)]
+enum ServerChallenge {
+    Digest(DigestChallenge),
+    /// No usable Digest challenge, so Basic is sent instead
+    Basic,
 }
 
+/// A Digest challenge (RFC 7616), reused until the server sends a new nonce.
 #[derive(Debug, Clone)]
+struct DigestChallenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    /// `MD5-sess` rather than `MD5`
+    session: bool,
+    /// Server accepts `qop=auth`; without it the RFC 2069 form is used
+    qop_auth: bool,
+    // Requests answered with this nonce so far
+    nonce_count: u32,
+}
+
+impl DigestChallenge {
+    /// Parse a `Digest` challenge. `None` for other schemes and for
+    /// algorithms other than MD5.
+    fn parse(header: &str) -> Option<Self> {
+        let (scheme, params) = header.trim().split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("digest") {
+            return None;
+        }
+        let params = parse_auth_params(params);
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        };
+        let session = match param("algorithm") {
+            None => false,
+            Some(algorithm) if algorithm.eq_ignore_ascii_case("MD5") => false,
+            Some(algorithm) if algorithm.eq_ignore_ascii_case("MD5-sess") => true,
+            Some(_) => return None,
+        };
+
+        Some(Self {
+            realm: param("realm")?.to_string(),
+            nonce: param("nonce")?.to_string(),
+            opaque: param("opaque").map(str::to_string),
+            session,
+            qop_auth: param("qop")
+                .is_some_and(|qop| qop.split(',').any(|q| q.trim().eq_ignore_ascii_case("auth"))),
+            nonce_count: 0,
+        })
+    }
+
+    /// `Authorization` header answering this challenge for one request.
+    fn authorization(&mut self, username: &str, password: &str, method: &Method, uri: &str) -> String {
+        let md5_hex = |data: String| format!("{:x}", md5::compute(data));
+        let cnonce = format!("{:016x}", rand::random::<u64>());
+        self.nonce_count += 1;
+        let nc = format!("{:08x}", self.nonce_count);
+
+        let mut ha1 = md5_hex(format!("{}:{}:{}", username, self.realm, password));
+        if self.session {
+            ha1 = md5_hex(format!("{}:{}:{}", ha1, self.nonce, cnonce));
+        }
+        let ha2 = md5_hex(format!("{}:{}", method, uri));
+        let response = if self.qop_auth {
+            md5_hex(format!("{}:{}:{}:{}:auth:{}", ha1, self.nonce, nc, cnonce, ha2))
+        } else {
+            md5_hex(format!("{}:{}:{}", ha1, self.nonce, ha2))
+        };
+
+        let mut header = format!(
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm={}, response=\"{}\"",
+            username,
+            self.realm,
+            self.nonce,
+            uri,
+            if self.session { "MD5-sess" } else { "MD5" },
+            response
+        );
+        if let Some(opaque) = &self.opaque {
+            header.push_str(&format!(", opaque=\"{}\"", opaque));
+        }
+        if self.qop_auth {
+            header.push_str(&format!(", qop=auth, nc={}, cnonce=\"{}\"", nc, cnonce));
+        }
+        header
+    }
+}
+
+/// Settings applied when building the underlying HTTP client.
+#[derive(Debug, Clone, Default)]
+pub struct ClientOptions {
+    /// Comma-separated hosts that bypass the proxy, as in `NO_PROXY`
+    pub no_proxy: Option<String>,
+    /// SOCKS5 proxy overriding the proxy environment variables
+    pub socks5_proxy: Option<String>,
+    pub tls: TlsConfig,
+    /// Download bandwidth limit in bytes per second
+    pub download_bps: Option<u64>,
+    /// Upload bandwidth limit in bytes per second
+    pub upload_bps: Option<u64>,
+    /// Upload files larger than this in chunks, on Nextcloud servers
+    pub chunk_size: Option<u64>,
+    /// Use HTTP/2 instead of HTTP/1.1
+    pub http2: bool,
+    /// Spread requests over all server URLs instead of failing over
+    pub load_balance: bool,
+    /// Idle connections kept per host (reqwest's default if `None`)
+    pub pool_max_idle_per_host: Option<usize>,
+    /// How long idle connections are kept (reqwest's default if `None`)
+    pub pool_idle_timeout: Option<Duration>,
+    /// Log connection reads and writes at trace level
+    pub connection_verbose: bool,
+    /// `User-Agent` header, `DEFAULT_USER_AGENT` if `None`
+    pub user_agent: Option<String>,
+    /// Headers sent with every request
+    pub extra_headers: HashMap<String, String>,
+    /// Ask for the checksums the server stores with each file
+    pub verify_checksums: bool,
+    /// Upload to a temporary file next to the target and MOVE it into place
+    pub atomic_upload: bool,
+}
+
+const DEFAULT_USER_AGENT: &str = concat!("davfs-sync/", env!("CARGO_PKG_VERSION"), " reqwest/0.12");
+
+/// How well the connection pool is being reused.
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkStats {
+    pub requests: u64,
+    pub connections_opened: u64,
+    /// Requests that went over an already open connection
+    pub connections_reused: u64,
+}
+
+/// Connector layer counting the connections the pool opens.
+#[derive(Clone)]
+struct CountConnections(Arc<AtomicU64>);
+
+impl<S> tower::Layer<S> for CountConnections {
+    type Service = CountedConnector<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CountedConnector { inner, opened: self.0.clone() }
+    }
+}
+
+#[derive(Clone)]
+struct CountedConnector<S> {
+    inner: S,
+    opened: Arc<AtomicU64>,
+}
+
+impl<S: tower::Service<R>, R> tower::Service<R> for CountedConnector<S> {
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        self.opened.fetch_add(1, Ordering::Relaxed);
+        self.inner.call(request)
+    }
+}
+
+/// Certificates for servers behind a private CA or requiring client
+/// certificates (mTLS).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// Extra root certificate (PEM or DER) to trust
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_cert_path: Option<PathBuf>,
+    /// Client certificate: a PKCS#12 bundle, or a PEM certificate when
+    /// `client_key_path` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_cert_path: Option<PathBuf>,
+    /// PKCS#8 PEM private key for `client_cert_path`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_key_path: Option<PathBuf>,
+    /// Skip certificate verification entirely (self-signed setups only)
+    #[serde(default)]
+    pub danger_accept_invalid_certs: bool,
+    /// Oldest TLS version accepted: "1.2" or "1.3"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_min_version: Option<String>,
+    /// Cipher suites offered, by IANA name (e.g.
+    /// `TLS13_AES_256_GCM_SHA384`); empty for the defaults
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tls_ciphers: Vec<String>,
+}
+
+impl TlsConfig {
+    pub fn is_empty(&self) -> bool {
+        self.ca_cert_path.is_none()
+            && self.client_cert_path.is_none()
+            && self.client_key_path.is_none()
+            && !self.danger_accept_invalid_certs
+            && self.tls_min_version.is_none()
+            && self.tls_ciphers.is_empty()
+    }
+
+    /// Configure `builder` for these settings. native-tls can neither
+    /// require TLS 1.3 nor pick cipher suites, so mounts doing either use
+    /// rustls; `http2` is then needed to offer the right ALPN protocols.
+    fn apply(&self, mut builder: reqwest::ClientBuilder, http2: bool) -> Result<reqwest::ClientBuilder> {
+        match self.tls_min_version.as_deref() {
+            None => {}
+            Some("1.2") if self.tls_ciphers.is_empty() => {
+                builder = builder.min_tls_version(reqwest::tls::Version::TLS_1_2);
+            }
+            Some("1.2" | "1.3") => {}
+            Some(other) => anyhow::bail!("tls_min_version must be \"1.2\" or \"1.3\", not \"{}\"", other),
+        }
+        if self.tls_min_version.as_deref() == Some("1.3") || !self.tls_ciphers.is_empty() {
+            return Ok(builder.use_preconfigured_tls(crate::tls::client_config(self, http2)?));
+        }
+
+        if let Some(path) = &self.ca_cert_path {
+            let data = read_file(path)?;
+            let cert = reqwest::Certificate::from_pem(&data)
+                .or_else(|_| reqwest::Certificate::from_der(&data))
+                .with_context(|| format!("Invalid CA certificate {}", path.display()))?;
+            builder = builder.add_root_certificate(cert);
+        }
+
+        if let Some(cert_path) = &self.client_cert_path {
+            let cert = read_file(cert_path)?;
+            let identity = match &self.client_key_path {
+                Some(key_path) => reqwest::Identity::from_pkcs8_pem(&cert, &read_file(key_path)?),
+                None => {
+                    let password = std::env::var("DAVFS_CLIENT_CERT_PASSWORD").unwrap_or_default();
+                    reqwest::Identity::from_pkcs12_der(&cert, &password)
+                }
+            }
+            .with_context(|| format!("Invalid client certificate {}", cert_path.display()))?;
+            builder = builder.identity(identity);
+        }
+
+        if self.danger_accept_invalid_certs {
+            tracing::warn!("TLS certificate verification is DISABLED for this mount");
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+
+        Ok(builder)
+    }
+}
+
+fn read_file(path: &std::path::Path) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))
+}
+
+/// How often and how patiently transient failures (connection errors,
+/// timeouts, 429/502/503/504) are retried.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay_ms: 200,
+            max_delay_ms: 10_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Full-jitter backoff: a random delay below `base * 2^(attempt-1)`,
+    /// capped at `max_delay_ms`.
+    fn delay(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_delay_ms
+            .saturating_mul(1u64 << (attempt - 1).min(32))
+            .min(self.max_delay_ms);
+        if exp == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_millis(rand::thread_rng().gen_range(0..exp))
+    }
+}
+
+/// Returned instead of sending a request while the circuit breaker is open.
+#[derive(Debug, thiserror::Error)]
+#[error("Server failed {failures} times in a row; not contacting it for {remaining_secs}s")]
+pub struct CircuitOpenError {
+    pub failures: u32,
+    pub remaining_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum CircuitState {
+    Closed,
+    /// Failing fast since the given time
+    Open(Instant),
+    /// Letting requests through again; the next result decides
+    HalfOpen,
+}
+
+/// Stops sending requests to a server that keeps failing, so callers get
+/// an error right away instead of waiting for each request to time out.
+/// After `failure_threshold` consecutive failures the circuit opens for
+/// `open_duration`, then requests are tried again: one success closes it,
+/// one failure opens it again.
+#[derive(Debug)]
+struct CircuitBreaker {
+    failure_threshold: u32,
+    open_duration: Duration,
+    // Circuit state and consecutive failures
+    state: Mutex<(CircuitState, u32)>,
+}
+
+impl CircuitBreaker {
+    fn new(failure_threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            open_duration,
+            state: Mutex::new((CircuitState::Closed, 0)),
+        }
+    }
+
+    fn check(&self) -> Result<(), CircuitOpenError> {
+        let mut state = self.state.lock().unwrap();
+        let CircuitState::Open(since) = state.0 else {
+            return Ok(());
+        };
+        let elapsed = since.elapsed();
+        if elapsed >= self.open_duration {
+            tracing::info!("Trying the server again after {}s", elapsed.as_secs());
+            state.0 = CircuitState::HalfOpen;
+            return Ok(());
+        }
+        Err(CircuitOpenError {
+            failures: state.1,
+            remaining_secs: (self.open_duration - elapsed).as_secs().max(1),
+        })
+    }
+
+    fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !matches!(state.0, CircuitState::Closed) {
+            tracing::info!("Server is responding again");
+        }
+        *state = (CircuitState::Closed, 0);
+    }
+
+    fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.1 += 1;
+        let reopen = match state.0 {
+            CircuitState::Closed => state.1 >= self.failure_threshold,
+            CircuitState::HalfOpen => true,
+            // Sent before the circuit opened
+            CircuitState::Open(_) => false,
+        };
+        if reopen {
+            tracing::warn!(
+                "Server failed {} times in a row; failing fast for {}s",
+                state.1,
+                self.open_duration.as_secs()
+            );
+            state.0 = CircuitState::Open(Instant::now());
+        }
+    }
+}
+
+/// TUS protocol version spoken in uploads.
+const TUS_VERSION: &str = "1.0.0";
+
+/// Upload bodies are streamed in chunks of this size when throttled.
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Thread-safe token bucket limiting throughput to `rate` bytes per second,
+/// with bursts of up to one second's worth. Callers may take more tokens
+/// than are available; the debt is paid off by sleeping, so concurrent
+/// transfers share the rate in the order they asked.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(bytes_per_second: u64) -> Self {
+        let rate = bytes_per_second.max(1) as f64;
+        Self {
+            rate,
+            state: Mutex::new(BucketState {
+                tokens: rate,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Wait until `bytes` may be transferred.
+    pub async fn acquire(&self, bytes: u64) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(state.updated).as_secs_f64() * self.rate;
+            state.tokens = (state.tokens + refill).min(self.rate) - bytes as f64;
+            state.updated = now;
+            if state.tokens >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-state.tokens / self.rate)
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DavEntry {
     pub name: String,
     pub is_dir: bool,
     pub size: u64,
     pub modified: Option<chrono::DateTime<chrono::Utc>>,
+    pub etag: Option<String>,
+    /// Other properties the server returned, keyed by prefixed name such as
+    /// `oc:fileid` (`d:`, `oc:` and `nc:` for the DAV, ownCloud and
+    /// Nextcloud namespaces, `{namespace}name` otherwise)
+    #[serde(default)]
+    pub properties: BTreeMap<String, String>,
+    /// What the user may do with the entry, from `oc:permissions`
+    #[serde(default)]
+    pub permissions: Option<DavPermissions>,
+    /// MIME type the server stores for a file, from `d:getcontenttype`
+    #[serde(default)]
+    pub content_type: Option<String>,
 }
 
-impl WebDavClient {
-    pub fn new(base_url: String, username: String, password: String) -> Result<Self> {
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(30))
-            .build()?;
+impl DavEntry {
+    /// Target if the entry is a symbolic link, i.e. a file with a
+    /// `davfs:symlink-target` property.
+    pub fn symlink_target(&self) -> Option<&str> {
+        self.properties
+            .get(SYMLINK_TARGET_PROP)
+            .map(String::as_str)
+            .filter(|target| !target.is_empty())
+    }
+}
 
-        let base_url = Url::parse(&base_url)?;
+/// Permissions from an ownCloud/Nextcloud `oc:permissions` value such as
+/// `RGDNVW`, one letter per permission.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DavPermissions(u16);
 
-        Ok(Self {
-            client,
-            base_url,
-            username,
-            password,
-        })
+impl DavPermissions {
+    pub const SHARED: u16 = 1 << 0; // S
+    pub const SHAREABLE: u16 = 1 << 1; // R
+    pub const MOUNTED: u16 = 1 << 2; // M
+    pub const READABLE: u16 = 1 << 3; // G
+    pub const DELETABLE: u16 = 1 << 4; // D
+    pub const RENAMEABLE: u16 = 1 << 5; // N
+    pub const MOVEABLE: u16 = 1 << 6; // V
+    pub const WRITABLE: u16 = 1 << 7; // W
+    pub const CREATE_FILE: u16 = 1 << 8; // C
+    pub const CREATE_DIR: u16 = 1 << 9; // K
+
+    pub fn parse(value: &str) -> Self {
+        let bits = value.chars().fold(0, |bits, letter| {
+            bits | match letter {
+                'S' => Self::SHARED,
+                'R' => Self::SHAREABLE,
+                'M' => Self::MOUNTED,
+                'G' => Self::READABLE,
+                'D' => Self::DELETABLE,
+                'N' => Self::RENAMEABLE,
+                'V' => Self::MOVEABLE,
+                'W' => Self::WRITABLE,
+                'C' => Self::CREATE_FILE,
+                'K' => Self::CREATE_DIR,
+                _ => 0,
+            }
+        });
+        Self(bits)
     }
 
-    pub async fn test_connection(&self) -> Result<()> {
-        let response = self
-            .client
-            .request(
-                reqwest::Method::from_bytes(b"PROPFIND")?,
-                self.base_url.clone(),
-            )
-            .basic_auth(&self.username, Some(&self.password))
-            .header("Depth", "0")
-            .send()
-            .await
-            .context("Failed to connect to WebDAV server")?;
+    pub fn contains(&self, bits: u16) -> bool {
+        self.0 & bits == bits
+    }
 
-        if !response.status().is_success() {
-            anyhow::bail!("Server returned status: {}", response.status());
+    /// Whether an `access(2)` check for `mask` (`R_OK`, `W_OK`, `X_OK`)
+    /// passes. Writing to a directory means creating entries in it, and
+    /// searching it needs read access.
+    pub fn allows(&self, mask: i32, is_dir: bool) -> bool {
+        let read = mask & libc::R_OK == 0 || self.contains(Self::READABLE);
+        let write = mask & libc::W_OK == 0
+            || if is_dir {
+                self.0 & (Self::CREATE_FILE | Self::CREATE_DIR) != 0
+            } else {
+                self.contains(Self::WRITABLE)
+            };
+        let execute = mask & libc::X_OK == 0 || (is_dir && self.contains(Self::READABLE));
+        read && write && execute
+    }
+}
+
+/// Properties to ask for in a PROPFIND. Names use the `d:`, `oc:` and
+/// `nc:` prefixes, e.g. `oc:fileid` or `nc:has-preview`.
+#[derive(Debug, Clone)]
+pub enum PropRequest {
+    Named(Vec<&'static str>),
+    /// Every property the server is willing to return
+    Allprop,
+}
+
+impl Default for PropRequest {
+    /// Just what the filesystem needs, to keep listings small.
+    fn default() -> Self {
+        PropRequest::Named(vec![
+            "d:displayname",
+            "d:getcontentlength",
+            "d:getlastmodified",
+            "d:getetag",
+            "d:resourcetype",
+            "d:getcontenttype",
+        ])
+    }
+}
+
+impl PropRequest {
+    /// Add `extra` properties to a named request.
+    fn with_props(&self, extra: &[&'static str]) -> PropRequest {
+        match self {
+            PropRequest::Named(names) => {
+                let mut names = names.clone();
+                for &prop in extra {
+                    if !names.contains(&prop) {
+                        names.push(prop);
+                    }
+                }
+                PropRequest::Named(names)
+            }
+            PropRequest::Allprop => PropRequest::Allprop,
         }
+    }
 
-        Ok(())
+    fn body(&self) -> String {
+        let props = match self {
+            PropRequest::Named(names) => {
+                let names: String = names.iter().map(|name| format!("<{}/>", name)).collect();
+                format!("<d:prop>{}</d:prop>", names)
+            }
+            PropRequest::Allprop => "<d:allprop/>".to_string(),
+        };
+        format!(
+            r#"<?xml version="1.0"?>
+<d:propfind xmlns:d="DAV:" xmlns:oc="{}" xmlns:nc="{}" xmlns:davfs="{}">{}</d:propfind>"#,
+            OWNCLOUD_NS, NEXTCLOUD_NS, DAVFS_NS, props
+        )
     }
+}
 
-    pub async fn list_dir(&self, path: &str) -> Result<Vec<DavEntry>> {
-        // For root or empty path, use base_url directly
-        let url = if path.is_empty() || path == "/" {
-            self.base_url.clone()
-        } else {
-            self.base_url.join(path.trim_start_matches('/'))?
+/// A DASL (RFC 5323) query for `WebDavClient::search`.
+#[derive(Debug, Clone)]
+pub enum DavSearchQuery {
+    /// Basic search for names matching a SQL `LIKE` pattern, where `%`
+    /// matches any run of characters
+    NameLike(String),
+    /// A complete `d:searchrequest` document, sent as is
+    Raw(String),
+}
+
+impl DavSearchQuery {
+    fn body(&self, scope_href: &str) -> String {
+        let pattern = match self {
+            DavSearchQuery::Raw(xml) => return xml.clone(),
+            DavSearchQuery::NameLike(pattern) => pattern,
         };
+        format!(
+            r#"<?xml version="1.0"?>
+<d:searchrequest xmlns:d="DAV:" xmlns:oc="{}" xmlns:nc="{}">
+  <d:basicsearch>
+    <d:select><d:prop><d:displayname/><d:resourcetype/><d:getcontentlength/><d:getlastmodified/><d:getetag/></d:prop></d:select>
+    <d:from><d:scope><d:href>{}</d:href><d:depth>infinity</d:depth></d:scope></d:from>
+    <d:where><d:like><d:prop><d:displayname/></d:prop><d:literal>{}</d:literal></d:like></d:where>
+    <d:orderby/>
+  </d:basicsearch>
+</d:searchrequest>"#,
+            OWNCLOUD_NS,
+            NEXTCLOUD_NS,
+            quick_xml::escape::escape(scope_href),
+            quick_xml::escape::escape(pattern.as_str())
+        )
+    }
+}
 
-        let response = self
-            .client
-            .request(reqwest::Method::from_bytes(b"PROPFIND")?, url.clone())
-            .basic_auth(&self.username, Some(&self.password))
-            .header("Depth", "1")
-            .header("Content-Type", "application/xml")
-            .body(
-                r#"<?xml version="1.0"?>
-                <d:propfind xmlns:d="DAV:">
-                  <d:prop>
-                    <d:displayname/>
-                    <d:getcontentlength/>
-                    <d:getlastmodified/>
-                    <d:resourcetype/>
-                  </d:prop>
-                </d:propfind>"#,
-            )
-            .send()
-            .await
-            .context("Failed to list directory")?;
+const OWNCLOUD_NS: &str = "http://owncloud.org/ns";
+/// Properties listed on Nextcloud servers in addition to the requested ones
+const NEXTCLOUD_PROPS: [&str; 4] = ["oc:fileid", "oc:permissions", "oc:tags", "oc:favorite"];
+const NEXTCLOUD_NS: &str = "http://nextcloud.org/ns";
+/// Dead properties kept by this client, with the `davfs:` prefix
+const DAVFS_NS: &str = "urn:davfs-sync:1";
+/// Real name (encrypted) and plaintext size of entries of encrypted mounts
+const ENCRYPTION_PROPS: [&str; 2] = ["davfs:name", "davfs:size"];
+/// Target of a symbolic link, stored on an empty file
+const SYMLINK_TARGET_PROP: &str = "davfs:symlink-target";
 
-        if !response.status().is_success() {
-            anyhow::bail!("Failed to list directory: {} (url: {})", response.status(), url);
-        }
+/// MIME type for a file named `path`, from its extension.
+pub fn guess_content_type(path: &str) -> String {
+    mime_guess::from_path(path).first_or_octet_stream().essence_str().to_string()
+}
 
-        let body = response.text().await?;
-        self.parse_propfind_response(&body)
+/// Prefixed name of a property element outside the DAV: namespace.
+fn qualified_name(ns: &ResolveResult, local: &[u8]) -> String {
+    let local = String::from_utf8_lossy(local);
+    match ns {
+        ResolveResult::Bound(Namespace(ns)) if *ns == OWNCLOUD_NS.as_bytes() => format!("oc:{}", local),
+        ResolveResult::Bound(Namespace(ns)) if *ns == NEXTCLOUD_NS.as_bytes() => format!("nc:{}", local),
+        ResolveResult::Bound(Namespace(ns)) if *ns == DAVFS_NS.as_bytes() => format!("davfs:{}", local),
+        ResolveResult::Bound(Namespace(ns)) => format!("{{{}}}{}", String::from_utf8_lossy(ns), local),
+        _ => local.into_owned(),
     }
+}
 
-    fn parse_propfind_response(&self, xml: &str) -> Result<Vec<DavEntry>> {
-        // Simple XML parsing - in production use a proper XML parser like quick-xml
-        let mut entries = Vec::new();
+/// Whether `data` matches the checksums in an `oc:checksums` value such as
+/// `SHA1:… MD5:…`. `None` if none of them uses an algorithm we compute.
+pub fn checksum_matches(announced: &str, data: &[u8]) -> Option<bool> {
+    use sha2::Digest;
 
-        // Split into response blocks
-        let responses: Vec<&str> = xml.split("<d:response>").collect();
-        
-        for response in responses.iter().skip(1) { // Skip first empty part
-            let mut name = String::new();
-            let mut is_dir = false;
-            let mut size: u64 = 0;
-            
-            // Extract displayname or href
-            for line in response.lines() {
-                if line.contains("<d:displayname>") {
-                    if let Some(n) = extract_tag_content(line, "d:displayname") {
-                        name = n;
-                    }
-                } else if name.is_empty() && line.contains("<d:href>") {
-                    // Fallback to href if no displayname
-                    if let Some(href) = extract_tag_content(line, "d:href") {
-                        // Extract last path component
-                        let path = href.trim_end_matches('/');
-                        if let Some(last) = path.split('/').last() {
-                            name = last.to_string();
-                        }
-                    }
-                }
-                
-                // Check if it's a collection (directory)
-                if line.contains("<d:collection") || line.contains("<d:collection/>") {
-                    is_dir = true;
-                }
-                
-                // Extract file size
-                if line.contains("<d:getcontentlength>") {
-                    if let Some(size_str) = extract_tag_content(line, "d:getcontentlength") {
-                        size = size_str.parse().unwrap_or(0);
-                    }
+    announced.split_whitespace().find_map(|checksum| {
+        let (algorithm, expected) = checksum.split_once(':')?;
+        let actual = match algorithm.to_ascii_uppercase().as_str() {
+            "SHA256" => format!("{:x}", sha2::Sha256::digest(data)),
+            "MD5" => format!("{:x}", md5::compute(data)),
+            _ => return None,
+        };
+        Some(actual.eq_ignore_ascii_case(expected))
+    })
+}
+
+/// The body of a multistatus `response`, read as it arrives rather than
+/// buffered whole, so large listings are parsed chunk by chunk.
+fn body_reader(response: Response) -> impl AsyncBufRead + Unpin {
+    StreamReader::new(Box::pin(response.bytes_stream().map(|chunk| chunk.map_err(std::io::Error::other))))
+}
+
+/// Opening and closing tags for a property name as accepted by `proppatch`.
+pub(crate) fn property_tags(name: &str) -> Result<(String, String)> {
+    let valid_local = |local: &str| {
+        local.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && local.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    };
+    if let Some((ns, local)) = name.strip_prefix('{').and_then(|rest| rest.split_once('}')) {
+        if !valid_local(local) {
+            anyhow::bail!("Invalid property name '{}'", name);
+        }
+        let ns = quick_xml::escape::escape(ns);
+        return Ok((format!("<x:{} xmlns:x=\"{}\">", local, ns), format!("</x:{}>", local)));
+    }
+    match name.split_once(':') {
+        Some(("d" | "oc" | "nc" | "davfs", local)) if valid_local(local) => Ok((format!("<{}>", name), format!("</{}>", name))),
+        _ => anyhow::bail!("Property '{}' needs a d:, oc: or nc: prefix or a {{namespace}}", name),
+    }
+}
+
+/// Result of a Depth: 1 PROPFIND: the children plus the etag of the
+/// collection itself, used to revalidate the cached listing.
+#[derive(Debug, Clone, Default)]
+pub struct DirListing {
+    pub entries: Vec<DavEntry>,
+    pub etag: Option<String>,
+    /// Whether the requested resource itself is a collection
+    pub is_collection: bool,
+    /// Quota of the requested resource, if the server reports one
+    pub quota: Quota,
+    /// Reading stopped at the entry limit, so `entries` is incomplete
+    pub truncated: bool,
+    /// Last modification of the requested resource
+    pub modified: Option<chrono::DateTime<chrono::Utc>>,
+    /// Other properties of the requested resource, like
+    /// `DavEntry::properties`
+    pub properties: BTreeMap<String, String>,
+}
+
+/// RFC 4331 quota properties. `None` where the server does not report a
+/// value or reports it as unknown/unlimited (Nextcloud uses negative
+/// numbers for that).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Quota {
+    pub available: Option<u64>,
+    pub used: Option<u64>,
+}
+
+/// Compliance classes and extensions advertised in the `DAV:` header of an
+/// OPTIONS response.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DavCapabilities {
+    pub class1: bool,
+    /// Locking (LOCK/UNLOCK)
+    pub class2: bool,
+    pub class3: bool,
+    /// MKCOL with a request body (RFC 5689)
+    pub extended_mkcol: bool,
+    pub nextcloud: bool,
+    /// Resumable uploads (`Tus-Resumable` header)
+    pub tus: bool,
+}
+
+impl DavCapabilities {
+    fn from_headers(headers: &reqwest::header::HeaderMap) -> Self {
+        let mut capabilities = Self::default();
+        let tokens = headers
+            .get_all("DAV")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim);
+        for token in tokens {
+            match token {
+                "1" => capabilities.class1 = true,
+                "2" => capabilities.class2 = true,
+                "3" => capabilities.class3 = true,
+                "extended-mkcol" => capabilities.extended_mkcol = true,
+                // e.g. nextcloud-checksum-update, nc-calendar-search
+                t if t.starts_with("nextcloud-") || t.starts_with("nc-") => {
+                    capabilities.nextcloud = true;
                 }
-            }
-            
-            // Add entry if we have a name and it's not the parent directory
-            if !name.is_empty() && name != "." && !name.contains("..") {
-                entries.push(DavEntry {
-                    name,
-                    is_dir,
-                    size,
-                    modified: None,
-                });
+                _ => {}
             }
         }
+        capabilities.tus = headers.contains_key("Tus-Resumable");
+        capabilities
+    }
 
-        Ok(entries)
+    /// Short human-readable list, e.g. "class 1, class 2, Nextcloud".
+    pub fn summary(&self) -> String {
+        let features = [
+            (self.class1, "class 1"),
+            (self.class2, "class 2"),
+            (self.class3, "class 3"),
+            (self.extended_mkcol, "extended-mkcol"),
+            (self.nextcloud, "Nextcloud"),
+            (self.tus, "TUS"),
+        ];
+        let names: Vec<&str> = features
+            .iter()
+            .filter(|(supported, _)| *supported)
+            .map(|(_, name)| *name)
+            .collect();
+        if names.is_empty() {
+            "none advertised".to_string()
+        } else {
+            names.join(", ")
+        }
     }
+}
 
-    pub async fn read_file(&self, path: &str) -> Result<Vec<u8>> {
-        let url = self.base_url.join(path)?;
+/// Scope of a WebDAV write lock: exclusive, or shared with other shared
+/// lock holders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockScope {
+    Exclusive,
+    Shared,
+}
 
-        let response = self
-            .client
-            .get(url)
-            .basic_auth(&self.username, Some(&self.password))
-            .send()
-            .await
-            .context("Failed to download file")?;
+/// Token identifying a WebDAV lock held by this client, without the
+/// surrounding angle brackets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockToken(pub String);
 
-        if !response.status().is_success() {
-            anyhow::bail!("Failed to read file: {}", response.status());
+/// A WebDAV request that reached the server but was answered with an
+/// unexpected status. Callers downcast to this to map statuses to errnos.
+#[derive(Debug, thiserror::Error)]
+#[error("{method} {url} failed: {status}")]
+pub struct DavStatusError {
+    pub method: Method,
+    pub url: Url,
+    pub status: StatusCode,
+}
+
+/// Properties collected from one `<d:response>` of a multistatus body.
+#[derive(Default)]
+struct PropfindResponse {
+    href: String,
+    displayname: Option<String>,
+    is_dir: bool,
+    size: Option<u64>,
+    modified: Option<chrono::DateTime<chrono::Utc>>,
+    etag: Option<String>,
+    quota: Quota,
+    properties: BTreeMap<String, String>,
+}
+
+/// Properties from one `<d:propstat>`, kept apart until its status is known.
+#[derive(Default)]
+struct PropStat {
+    status: Option<String>,
+    displayname: Option<String>,
+    is_dir: bool,
+    size: Option<u64>,
+    modified: Option<chrono::DateTime<chrono::Utc>>,
+    etag: Option<String>,
+    quota: Quota,
+    properties: BTreeMap<String, String>,
+}
+
+impl PropStat {
+    fn is_success(&self) -> bool {
+        // "HTTP/1.1 200 OK"; a missing status is treated as success
+        match &self.status {
+            Some(status) => status
+                .split_whitespace()
+                .nth(1)
+                .is_some_and(|code| code.starts_with('2')),
+            None => true,
+        }
+    }
+}
+
+impl PropfindResponse {
+    fn merge(&mut self, propstat: PropStat) {
+        self.displayname = self.displayname.take().or(propstat.displayname);
+        self.is_dir |= propstat.is_dir;
+        self.size = self.size.or(propstat.size);
+        self.modified = self.modified.or(propstat.modified);
+        self.etag = self.etag.take().or(propstat.etag);
+        self.quota.available = self.quota.available.or(propstat.quota.available);
+        self.quota.used = self.quota.used.or(propstat.quota.used);
+        for (name, value) in propstat.properties {
+            self.properties.entry(name).or_insert(value);
+        }
+    }
+
+    /// Entry named after its displayname or last href segment, or with
+    /// `names_below` after its href path relative to that collection.
+    fn into_entry(self, names_below: Option<&str>) -> Option<DavEntry> {
+        // Prefer displayname, fall back to the last href segment
+        let name = match (names_below, self.displayname.filter(|n| !n.is_empty())) {
+            (Some(base), _) => relative_path(&self.href, base)?,
+            (None, Some(name)) => name,
+            (None, None) => {
+                let segment = self.href.trim_end_matches('/').rsplit('/').next()?;
+                percent_decode_str(segment).decode_utf8_lossy().into_owned()
+            }
+        };
+        if name.is_empty() || name == "." || name == ".." {
+            return None;
         }
 
-        Ok(response.bytes().await?.to_vec())
+        let permissions = self.properties.get("oc:permissions").map(|value| DavPermissions::parse(value));
+        let content_type = self
+            .properties
+            .get("d:getcontenttype")
+            .filter(|value| !value.is_empty() && !self.is_dir)
+            .cloned();
+        Some(DavEntry {
+            name,
+            is_dir: self.is_dir,
+            size: self.size.unwrap_or(0),
+            modified: self.modified,
+            etag: self.etag,
+            properties: self.properties,
+            permissions,
+            content_type,
+        })
     }
 }
 
-fn extract_tag_content(line: &str, tag: &str) -> Option<String> {
-    let start_tag = format!("<{}>", tag);
-    let end_tag = format!("</{}>", tag);
+impl WebDavClient {
+    /// Client for the server at `urls[0]`, failing over to the other URLs
+    /// (mirrors of the same storage) when it cannot be reached.
+    pub fn new(urls: &[String], auth: AuthMethod, options: &ClientOptions) -> Result<Self> {
+        let base_urls = urls
+            .iter()
+            .map(|url| Url::parse(url).with_context(|| format!("Invalid server URL {}", url)))
+            .collect::<Result<Vec<_>>>()?;
+        let Some(base_url) = base_urls.first() else {
+            anyhow::bail!("No server URL configured");
+        };
+
+        let mut builder = options
+            .tls
+            .apply(Client::builder().timeout(Duration::from_secs(30)), options.http2 && base_url.scheme() == "https")?;
+        let proxy = match &options.socks5_proxy {
+            Some(addr) => Some(socks5_proxy(addr, options.no_proxy.as_deref())?),
+            None => proxy_from_env(base_url, options.no_proxy.as_deref())?,
+        };
+        if let Some(proxy) = proxy {
+            builder = builder.proxy(proxy);
+        }
+        builder = match (options.http2, base_url.scheme()) {
+            (false, _) => builder.http1_only(),
+            // No ALPN without TLS, so the server has to speak HTTP/2 directly
+            (true, "http") => builder.http2_prior_knowledge(),
+            (true, _) => builder,
+        };
+        if let Some(max_idle) = options.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+        if let Some(timeout) = options.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(timeout);
+        }
+        let mut headers = reqwest::header::HeaderMap::new();
+        for (name, value) in &options.extra_headers {
+            let name = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("Invalid header name {:?}", name))?;
+            let value = reqwest::header::HeaderValue::from_str(value)
+                .with_context(|| format!("Invalid value for header {}", name))?;
+            headers.insert(name, value);
+        }
+        let connections_opened = Arc::new(AtomicU64::new(0));
+        let client = builder
+            .connection_verbose(options.connection_verbose)
+            .user_agent(options.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT))
+            .default_headers(headers)
+            .connector_layer(CountConnections(connections_opened.clone()))
+            .build()?;
+
+        Ok(Self {
+            client,
+            base_urls: Arc::new(base_urls),
+            current_url: Arc::new(Mutex::new(0)),
+            load_balance: options.load_balance,
+            auth: Arc::new(tokio::sync::Mutex::new(auth)),
+            retry_policy: RetryPolicy::default(),
+            circuit: Arc::new(CircuitBreaker::new(3, Duration::from_secs(30))),
+            download_limiter: options.download_bps.map(|bps| Arc::new(TokenBucket::new(bps))),
+            upload_limiter: options.upload_bps.map(|bps| Arc::new(TokenBucket::new(bps))),
+            metrics: Arc::new(Metrics::default()),
+            capabilities: None,
+            chunk_size: options.chunk_size,
+            verify_checksums: options.verify_checksums,
+            cipher: None,
+            upload_log: None,
+            connections_opened,
+            server_challenge: Arc::new(Mutex::new(None)),
+            atomic_upload: Arc::new(AtomicBool::new(options.atomic_upload)),
+        })
+    }
+
+    /// Server URL requests currently go to.
+    pub fn active_url(&self) -> Url {
+        self.base_urls[*self.current_url.lock().unwrap()].clone()
+    }
+
+    /// Switch to the next server URL after `failed` could not be reached.
+    fn fail_over(&self, failed: &Url) {
+        let mut current = self.current_url.lock().unwrap();
+        // Another request may have switched already
+        if self.base_urls[*current] == *failed {
+            *current = (*current + 1) % self.base_urls.len();
+            tracing::warn!("{} is unreachable, switching to {}", failed, self.base_urls[*current]);
+        }
+    }
+
+    /// Point `request`, built against any of the server URLs, at the
+    /// active one, including URLs in `Destination` and `If` headers.
+    fn rebase(&self, request: &mut reqwest::Request) {
+        if self.base_urls.len() < 2 {
+            return;
+        }
+        let active = self.active_url();
+        let active = active.as_str().trim_end_matches('/');
+        let Some(from) = self
+            .base_urls
+            .iter()
+            .map(|base| base.as_str().trim_end_matches('/'))
+            .find(|base| request.url().as_str().starts_with(base))
+        else {
+            return;
+        };
+        if from == active {
+            return;
+        }
 
-    if let Some(start) = line.find(&start_tag) {
-        if let Some(end) = line.find(&end_tag) {
-            let content_start = start + start_tag.len();
-            if content_start < end {
-                return Some(line[content_start..end].trim().to_string());
+        let rebased = format!("{}{}", active, &request.url().as_str()[from.len()..]);
+        match Url::parse(&rebased) {
+            Ok(url) => *request.url_mut() = url,
+            Err(e) => tracing::warn!("Cannot move request to {}: {}", active, e),
+        }
+        for name in ["Destination", "If"] {
+            let Some(value) = request.headers().get(name).and_then(|v| v.to_str().ok()) else {
+                continue;
+            };
+            if let Ok(value) = reqwest::header::HeaderValue::from_str(&value.replace(from, active)) {
+                request.headers_mut().insert(name, value);
             }
         }
     }
-    None
+
+    /// Switch back to the primary server URL if it is reachable again.
+    pub async fn retest_primary(&self) {
+        if self.load_balance || *self.current_url.lock().unwrap() == 0 {
+            return;
+        }
+        let primary = self.base_urls[0].clone();
+        let request = match Method::from_bytes(b"PROPFIND") {
+            Ok(propfind) => self.request(propfind, primary.clone()).header("Depth", "0"),
+            Err(_) => return,
+        };
+        // Sent without rebase, which would redirect it to the active URL
+        let Ok(request) = self.authorize(request).await else {
+            return;
+        };
+        let Ok(mut request) = request.build() else {
+            return;
+        };
+        self.answer_digest(&mut request).await;
+        match self.client.execute(request).await {
+            Ok(response) if response.status().is_success() => {
+                tracing::info!("{} is reachable again, switching back", primary);
+                *self.current_url.lock().unwrap() = 0;
+            }
+            Ok(response) => tracing::debug!("Primary server answered {}", response.status()),
+            Err(e) => tracing::debug!("Primary server still unreachable: {}", e),
+        }
+    }
+
+    /// Username and password, for services that only take those.
+    pub async fn basic_credentials(&self) -> Option<(String, String)> {
+        match &*self.auth.lock().await {
+            AuthMethod::Basic { username, password } | AuthMethod::Digest { username, password } => {
+                Some((username.clone(), password.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Replace the credentials used by this client and all its clones.
+    /// Returns the ones replaced.
+    pub async fn set_auth(&self, auth: AuthMethod) -> AuthMethod {
+        std::mem::replace(&mut *self.auth.lock().await, auth)
+    }
+
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    pub fn network_stats(&self) -> NetworkStats {
+        let requests = self.metrics.requests_total();
+        let connections_opened = self.connections_opened.load(Ordering::Relaxed);
+        NetworkStats {
+            requests,
+            connections_opened,
+            connections_reused: requests.saturating_sub(connections_opened),
+        }
+    }
+
+    /// Whether downloads are checked against `oc:checksums`. Checksums of
+    /// encrypted files are of the ciphertext, so those are not checked.
+    pub fn verifies_checksums(&self) -> bool {
+        self.verify_checksums && self.cipher.is_none()
+    }
+
+    /// Encrypt contents and hide names on the server with `cipher`.
+    pub fn with_encryption(mut self, cipher: Option<Cipher>) -> Self {
+        self.cipher = cipher.map(Arc::new);
+        self
+    }
+
+    pub fn encrypts(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    /// Record the progress of large uploads in `upload_log`, so they resume
+    /// after a network error instead of starting over.
+    pub fn with_upload_log(mut self, upload_log: Option<UploadLog>) -> Self {
+        self.upload_log = upload_log;
+        self
+    }
+
+    /// Enable protocol extensions the server advertised, such as Nextcloud
+    /// chunked uploads.
+    pub fn with_capabilities(mut self, capabilities: Option<DavCapabilities>) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Fail fast for `open_duration` once `failure_threshold` requests in a
+    /// row could not reach the server.
+    pub fn with_circuit_breaker(mut self, failure_threshold: u32, open_duration: Duration) -> Self {
+        self.circuit = Arc::new(CircuitBreaker::new(failure_threshold, open_duration));
+        self
+    }
+
+    fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        self.client.request(method, url)
+    }
+
+    /// Add credentials to `request`, refreshing an OAuth2 access token first
+    /// if it expires within the next minute.
+    async fn authorize(&self, request: RequestBuilder) -> Result<RequestBuilder> {
+        let mut auth = self.auth.lock().await;
+
+        if let AuthMethod::BearerWithRefresh { expires_at, .. } = &*auth {
+            if *expires_at - chrono::Utc::now() < chrono::Duration::seconds(60) {
+                self.refresh_token(&mut auth).await?;
+            }
+        }
+
+        Ok(match &*auth {
+            AuthMethod::Basic { username, password } => request.basic_auth(username, Some(password)),
+            AuthMethod::Bearer { token } => request.bearer_auth(token),
+            AuthMethod::BearerWithRefresh { access_token, .. } => request.bearer_auth(access_token),
+            // Digest needs the final method and URL, see answer_digest
+            AuthMethod::Digest { username, password } => match *self.server_challenge.lock().unwrap() {
+                Some(ServerChallenge::Basic) => request.basic_auth(username, Some(password)),
+                _ => request,
+            },
+        })
+    }
+
+    /// Add a Digest `Authorization` header to `request` if a challenge
+    /// from the server is known.
+    async fn answer_digest(&self, request: &mut reqwest::Request) {
+        let auth = self.auth.lock().await;
+        let AuthMethod::Digest { username, password } = &*auth else {
+            return;
+        };
+        let mut server_challenge = self.server_challenge.lock().unwrap();
+        let Some(ServerChallenge::Digest(challenge)) = server_challenge.as_mut() else {
+            return;
+        };
+
+        let uri = match request.url().query() {
+            Some(query) => format!("{}?{}", request.url().path(), query),
+            None => request.url().path().to_string(),
+        };
+        let header = challenge.authorization(username, password, request.method(), &uri);
+        match reqwest::header::HeaderValue::from_str(&header) {
+            Ok(value) => {
+                request.headers_mut().insert("Authorization", value);
+            }
+            Err(e) => tracing::warn!("Cannot send Digest credentials: {}", e),
+        }
+    }
+
+    /// Remember the challenge of a 401 response for Digest auth. Returns
+    /// whether it differs from the one just answered, i.e. whether a retry
+    /// can succeed.
+    async fn accept_challenge(&self, response: &Response) -> bool {
+        if !matches!(*self.auth.lock().await, AuthMethod::Digest { .. }) {
+            return false;
+        }
+        let challenges: Vec<&str> = response
+            .headers()
+            .get_all("WWW-Authenticate")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect();
+
+        let offers_basic = challenges
+            .iter()
+            .any(|header| header.trim().to_ascii_lowercase().starts_with("basic"));
+        let next = match challenges.iter().find_map(|header| DigestChallenge::parse(header)) {
+            Some(digest) => ServerChallenge::Digest(digest),
+            None if offers_basic => ServerChallenge::Basic,
+            None => return false,
+        };
+
+        let mut current = self.server_challenge.lock().unwrap();
+        // Same nonce again means the credentials were rejected
+        let changed = match (current.as_ref(), &next) {
+            (Some(ServerChallenge::Digest(old)), ServerChallenge::Digest(new)) => old.nonce != new.nonce,
+            (Some(ServerChallenge::Basic), ServerChallenge::Basic) => false,
+            _ => true,
+        };
+        if changed {
+            if matches!(next, ServerChallenge::Basic) {
+                tracing::info!("Server does not offer Digest authentication, falling back to Basic");
+            }
+            *current = Some(next);
+        }
+        changed
+    }
+
+    async fn refresh_token(&self, auth: &mut AuthMethod) -> Result<()> {
+        let AuthMethod::BearerWithRefresh { refresh_token, token_url, .. } = &*auth else {
+            return Ok(());
+        };
+        tracing::info!("Refreshing access token at {}", token_url);
+
+        let response = self
+            .client
+            .post(token_url.as_str())
+            .form(&[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token.as_str()),
+            ])
+            .send()
+            .await
+            .context("Failed to refresh access token")?;
+        if !response.status().is_success() {
+            anyhow::bail!("Token refresh failed: {}", response.status());
+        }
+        let token: TokenResponse = response
+            .json()
+            .await
+            .context("Invalid token refresh response")?;
+
+        let refresh_token = token.refresh_token.unwrap_or_else(|| refresh_token.clone());
+        let token_url = token_url.clone();
+        *auth = AuthMethod::BearerWithRefresh {
+            access_token: token.access_token,
+            refresh_token,
+            expires_at: chrono::Utc::now() + chrono::Duration::seconds(token.expires_in.unwrap_or(3600)),
+            token_url,
+        };
+        Ok(())
+    }
+
+    /// Authorize and send `request`, retrying transient failures according
+    /// to the retry policy. The last response or error is returned once
+    /// attempts run out, or right away while the circuit breaker is open.
+    async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        self.circuit.check()?;
+        let result = self.send_with_retries(request).await;
+        let failed = match &result {
+            Ok(response) => is_transient_status(response.status()) && response.status().is_server_error(),
+            Err(e) => is_network_error(e),
+        };
+        if failed {
+            self.circuit.record_failure();
+        } else {
+            self.circuit.record_success();
+        }
+        result
+    }
+
+    async fn send_with_retries(&self, request: RequestBuilder) -> Result<Response> {
+        let max_attempts = self.retry_policy.max_attempts.max(1);
+        let mut attempt = 1;
+
+        loop {
+            // Bodies are in memory, so the builder can be cloned, except for
+            // throttled uploads which only get a single attempt
+            let Some(this_attempt) = request.try_clone().filter(|_| attempt < max_attempts) else {
+                return Ok(self.execute(request).await??);
+            };
+
+            let retry_after = match self.execute(this_attempt).await? {
+                Ok(response) if is_transient_status(response.status()) => {
+                    tracing::debug!(
+                        "{} from {}, retrying (attempt {}/{})",
+                        response.status(), response.url(), attempt, max_attempts
+                    );
+                    retry_after(&response)
+                }
+                Ok(response) => return Ok(response),
+                Err(e) if e.is_connect() || e.is_timeout() => {
+                    tracing::debug!("{}, retrying (attempt {}/{})", e, attempt, max_attempts);
+                    None
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            let delay = retry_after
+                .map(|d| d.min(Duration::from_millis(self.retry_policy.max_delay_ms)))
+                .unwrap_or_else(|| self.retry_policy.delay(attempt));
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Authorize and send a single attempt, answering a new Digest
+    /// challenge once. The outer error is for failures before anything
+    /// was sent.
+    async fn execute(&self, mut request: RequestBuilder) -> Result<reqwest::Result<Response>> {
+        if self.load_balance {
+            let mut current = self.current_url.lock().unwrap();
+            *current = (*current + 1) % self.base_urls.len();
+        }
+
+        let mut challenged = false;
+        // Each server URL is tried once on connection failures
+        let mut untried = self.base_urls.len() - 1;
+        loop {
+            let target = self.active_url();
+            let retry = request.try_clone();
+            let result = self.execute_once(request).await?;
+            let Some(retry) = retry else {
+                return Ok(result);
+            };
+            match &result {
+                Ok(response)
+                    if response.status() == StatusCode::UNAUTHORIZED
+                        && !challenged
+                        && self.accept_challenge(response).await =>
+                {
+                    challenged = true;
+                }
+                Err(e) if e.is_connect() && untried > 0 => {
+                    self.fail_over(&target);
+                    untried -= 1;
+                }
+                _ => return Ok(result),
+            }
+            request = retry;
+        }
+    }
+
+    /// Authorize and send `request`, recording it in the metrics.
+    async fn execute_once(&self, request: RequestBuilder) -> Result<reqwest::Result<Response>> {
+        let mut request = self.authorize(request).await?.build()?;
+        self.rebase(&mut request);
+        self.answer_digest(&mut request).await;
+        let method = request.method().clone();
+
+        // Exported over OpenTelemetry when that is enabled
+        let span = tracing::info_span!(
+            "webdav.request",
+            otel.name = %method,
+            otel.kind = "client",
+            http.method = %method,
+            http.url = %request.url(),
+            http.status_code = tracing::field::Empty,
+            net.peer.name = request.url().host_str().unwrap_or_default(),
+            davfs.path = relative_path(request.url().path(), self.active_url().path()).unwrap_or_default(),
+        );
+
+        let started = Instant::now();
+        let result = self.client.execute(request).instrument(span.clone()).await;
+        let status = result.as_ref().ok().map(|r| r.status());
+        if let Some(status) = status {
+            span.record("http.status_code", status.as_u16());
+        }
+        self.metrics.record_request(&method, started.elapsed(), status);
+
+        Ok(result)
+    }
+
+    /// Resolve a mount-relative path (with or without leading slash)
+    /// against the base URL, percent-encoding each segment. Segments are
+    /// hashed on encrypted mounts.
+    pub fn url_for(&self, path: &str) -> Result<Url> {
+        let mut url = self.active_url();
+        let segments: Vec<String> = path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(|s| match &self.cipher {
+                Some(cipher) => cipher.hash_name(s),
+                None => s.to_string(),
+            })
+            .collect();
+        if segments.is_empty() {
+            return Ok(url);
+        }
+
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Base URL cannot have a path: {}", self.active_url()))?
+            .pop_if_empty()
+            .extend(segments);
+        Ok(url)
+    }
+
+    fn check_status(method: Method, url: Url, response: Response) -> Result<Response> {
+        if response.status().is_success() {
+            Ok(response)
+        } else {
+            Err(DavStatusError {
+                method,
+                url,
+                status: response.status(),
+            }
+            .into())
+        }
+    }
+
+    pub async fn test_connection(&self) -> Result<()> {
+        let method = Method::from_bytes(b"PROPFIND")?;
+        let url = self.active_url();
+        let request = self
+            .request(method.clone(), url.clone())
+            .header("Depth", "0");
+        let response = self
+            .send(request)
+            .await
+            .context("Failed to connect to WebDAV server")?;
+        Self::check_status(method, url, response)?;
+
+        Ok(())
+    }
+
+    /// Ask the server which WebDAV classes and extensions it supports.
+    pub async fn detect_capabilities(&self) -> Result<DavCapabilities> {
+        let url = self.active_url();
+        let request = self.request(Method::OPTIONS, url.clone());
+        let response = self
+            .send(request)
+            .await
+            .context("Failed to query server capabilities")?;
+        let response = Self::check_status(Method::OPTIONS, url, response)?;
+
+        Ok(DavCapabilities::from_headers(response.headers()))
+    }
+
+    /// WebSocket endpoint of Nextcloud's notify_push app, if the server has
+    /// it installed.
+    pub async fn notify_push_endpoint(&self) -> Result<Option<String>> {
+        if !self.active_url().path().contains("/remote.php/") {
+            return Ok(None);
+        }
+        let capabilities = self
+            .ocs_get("cloud/capabilities", &[])
+            .await
+            .context("Failed to query Nextcloud capabilities")?
+            .unwrap_or_default();
+
+        Ok(capabilities["ocs"]["data"]["capabilities"]["notify_push"]["endpoints"]["websocket"]
+            .as_str()
+            .map(str::to_string))
+    }
+
+    /// Nextcloud collection of the earlier versions of the file with
+    /// `file_id` (its `oc:fileid`).
+    fn versions_url(&self, file_id: &str) -> Result<Url> {
+        let mut url = self.active_url();
+        let Some((root, rest)) = url.path().split_once("/remote.php/dav/files/") else {
+            anyhow::bail!("{} is not a Nextcloud files URL; versions need one", url);
+        };
+        let user = rest.split('/').next().unwrap_or_default();
+        url.set_path(&format!("{}/remote.php/dav/versions/{}/versions/{}/", root, user, file_id));
+        Ok(url)
+    }
+
+    /// Earlier versions of the file with `file_id`, named by their
+    /// timestamp.
+    pub async fn list_versions(&self, file_id: &str) -> Result<Vec<DavEntry>> {
+        let url = self.versions_url(file_id)?;
+        let method = Method::from_bytes(b"PROPFIND")?;
+
+        let request = self
+            .request(method.clone(), url.clone())
+            .header("Depth", "1")
+            .header("Content-Type", "application/xml")
+            .body(PropRequest::default().body());
+        let response = self.send(request).await.context("Failed to list versions")?;
+        let response = Self::check_status(method, url.clone(), response)?;
+
+        let listing = self
+            .parse_propfind_response(body_reader(response), url.path(), None, None)
+            .await?;
+        Ok(listing.entries)
+    }
+
+    /// Read part of an earlier version of the file with `file_id`.
+    pub async fn read_version_range(&self, file_id: &str, version: &str, offset: u64, size: u64) -> Result<Vec<u8>> {
+        let mut url = self.versions_url(file_id)?;
+        url.set_path(&format!("{}{}", url.path(), version));
+        self.read_url_range(url, offset, size).await
+    }
+
+    /// GET the Nextcloud OCS API `endpoint` (below `ocs/v2.php/`) as JSON.
+    /// `None` if the server answered 304 Not Modified.
+    pub async fn ocs_get(&self, endpoint: &str, query: &[(&str, String)]) -> Result<Option<serde_json::Value>> {
+        let mut url = self.active_url();
+        let Some((root, _)) = url.path().split_once("/remote.php/") else {
+            anyhow::bail!("{} is not a Nextcloud WebDAV URL", url);
+        };
+        url.set_path(&format!("{}/ocs/v2.php/{}", root, endpoint));
+        url.query_pairs_mut()
+            .clear()
+            .append_pair("format", "json")
+            .extend_pairs(query.iter().map(|(name, value)| (name, value)));
+
+        let request = self.request(Method::GET, url.clone()).header("OCS-APIRequest", "true");
+        let response = self.send(request).await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        let response = Self::check_status(Method::GET, url, response)?;
+        Ok(Some(response.json().await?))
+    }
+
+    pub async fn list_dir(&self, path: &str, props: &PropRequest) -> Result<Vec<DavEntry>> {
+        let listing = self.propfind_listing(path, None, props).await?;
+        Ok(listing.map(|l| l.entries).unwrap_or_default())
+    }
+
+    /// List a directory with every property the server returns.
+    pub async fn list_dir_full(&self, path: &str) -> Result<Vec<DavEntry>> {
+        self.list_dir(path, &PropRequest::Allprop).await
+    }
+
+    /// List a directory, sending `If-None-Match` when `etag` is given.
+    /// Returns `None` if the server answered 304 Not Modified.
+    pub async fn list_dir_conditional(
+        &self,
+        path: &str,
+        etag: Option<&str>,
+    ) -> Result<Option<DirListing>> {
+        self.propfind_listing(path, etag, &PropRequest::default()).await
+    }
+
+    async fn propfind_listing(
+        &self,
+        path: &str,
+        etag: Option<&str>,
+        props: &PropRequest,
+    ) -> Result<Option<DirListing>> {
+        let url = self.url_for(path)?;
+        let method = Method::from_bytes(b"PROPFIND")?;
+
+        let mut request = self
+            .request(method.clone(), url.clone())
+            .header("Depth", "1")
+            .header("Content-Type", "application/xml")
+            .body(self.listing_props(props).body());
+        if let Some(etag) = etag {
+            request = request.header("If-None-Match", etag);
+        }
+
+        let response = self.send(request).await.context("Failed to list directory")?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        let response = Self::check_status(method, url.clone(), response)?;
+
+        self.parse_propfind_response(body_reader(response), url.path(), None, None)
+            .await
+            .map(Some)
+    }
+
+    /// Search below `scope` with a DASL `SEARCH`. Entries are named by
+    /// their path relative to the mount root.
+    pub async fn search(&self, scope: &str, query: &DavSearchQuery) -> Result<Vec<DavEntry>> {
+        if self.cipher.is_some() {
+            anyhow::bail!("Search is not available on encrypted mounts: the server only knows hashed names");
+        }
+        let base = self.active_url();
+        let scope_url = self.url_for(scope)?;
+        // Nextcloud answers SEARCH on the DAV root, for scopes relative to it
+        let (url, scope_href) = match base.path().split_once("/remote.php/dav/") {
+            Some((root, _)) => {
+                let dav_root = format!("{}/remote.php/dav", root);
+                let mut url = base.clone();
+                url.set_path(&format!("{}/", dav_root));
+                (url, scope_url.path()[dav_root.len()..].to_string())
+            }
+            None => (scope_url.clone(), scope_url.path().to_string()),
+        };
+        let scope_href = percent_decode_str(&scope_href).decode_utf8_lossy().into_owned();
+        let method = Method::from_bytes(b"SEARCH")?;
+
+        let request = self
+            .request(method.clone(), url.clone())
+            .header("Content-Type", "application/xml")
+            .body(query.body(&scope_href));
+        let response = self.send(request).await.context("Failed to search")?;
+        let response = Self::check_status(method, url.clone(), response)?;
+
+        let listing = self
+            .parse_propfind_response(body_reader(response), url.path(), Some(base.path()), None)
+            .await?;
+        Ok(listing.entries)
+    }
+
+    /// Send the REPORT `report_xml`, e.g. a CalDAV `calendar-query`, to the
+    /// collection `path`. The properties the report asked for end up in the
+    /// entries' `properties`.
+    pub async fn report(&self, path: &str, report_xml: &str) -> Result<Vec<DavEntry>> {
+        let url = self.url_for(path)?;
+        let method = Method::from_bytes(b"REPORT")?;
+
+        let request = self
+            .request(method.clone(), url.clone())
+            .header("Depth", "1")
+            .header("Content-Type", "application/xml; charset=utf-8")
+            .body(report_xml.to_string());
+        let response = self.send(request).await.context("Failed to send REPORT")?;
+        let response = Self::check_status(method, url.clone(), response)?;
+
+        let listing = self.parse_propfind_response(body_reader(response), url.path(), None, None).await?;
+        Ok(listing.entries)
+    }
+
+    /// Everything below `path` in one `Depth: infinity` PROPFIND, with
+    /// entries named by their path relative to `path`. Stops reading after
+    /// `max_entries` entries and marks the listing as truncated.
+    pub async fn list_tree(&self, path: &str, max_entries: usize) -> Result<DirListing> {
+        // Entries would be named by paths of hashed segments
+        if self.cipher.is_some() {
+            anyhow::bail!("Tree listings are not available on encrypted mounts");
+        }
+        let url = self.url_for(path)?;
+        let method = Method::from_bytes(b"PROPFIND")?;
+
+        let request = self
+            .request(method.clone(), url.clone())
+            .header("Depth", "infinity")
+            .header("Content-Type", "application/xml")
+            .body(self.listing_props(&PropRequest::default()).body());
+        let response = self.send(request).await.context("Failed to list tree")?;
+        let response = Self::check_status(method, url.clone(), response)?;
+
+        self.parse_propfind_response(body_reader(response), url.path(), Some(url.path()), Some(max_entries))
+            .await
+    }
+
+    /// `props` plus what this server and mount add to every listing.
+    fn listing_props(&self, props: &PropRequest) -> PropRequest {
+        let mut props = props.clone();
+        if self.capabilities.is_some_and(|c| c.nextcloud) {
+            props = props.with_props(&NEXTCLOUD_PROPS);
+        }
+        if self.verifies_checksums() {
+            props = props.with_props(&["oc:checksums"]);
+        }
+        if self.cipher.is_some() {
+            props = props.with_props(&ENCRYPTION_PROPS);
+        } else {
+            props = props.with_props(&[SYMLINK_TARGET_PROP]);
+        }
+        props
+    }
+
+    /// Parse a multistatus listing of `request_path` as it arrives. With
+    /// `names_below`, entries are named by their path relative to that
+    /// collection. Reading stops once there are `max_entries` entries.
+    async fn parse_propfind_response(
+        &self,
+        xml: impl AsyncBufRead + Unpin,
+        request_path: &str,
+        names_below: Option<&str>,
+        max_entries: Option<usize>,
+    ) -> Result<DirListing> {
+        let mut reader = NsReader::from_reader(xml);
+        let mut buf = Vec::new();
+
+        let mut entries = Vec::new();
+        let mut collection_etag = None;
+        let mut is_collection = false;
+        let mut quota = Quota::default();
+        let mut modified = None;
+        let mut properties = BTreeMap::new();
+        // Local names of open elements; elements outside DAV: are pushed
+        // with a prefix (see qualified_name), so they never match DAV names
+        let mut stack: Vec<String> = Vec::new();
+        let mut text = String::new();
+        let mut saw_multistatus = false;
+        let mut response = PropfindResponse::default();
+        let mut propstat = PropStat::default();
+        let mut truncated = false;
+
+        loop {
+            buf.clear();
+            let position = reader.buffer_position();
+            let (ns, event) = reader
+                .read_resolved_event_into_async(&mut buf)
+                .await
+                .with_context(|| format!("Malformed PROPFIND response near byte {}", position))?;
+            let is_dav = matches!(ns, ResolveResult::Bound(Namespace(b"DAV:")));
+
+            match event {
+                Event::Start(e) => {
+                    let name = if is_dav {
+                        String::from_utf8_lossy(e.local_name().as_ref()).into_owned()
+                    } else {
+                        qualified_name(&ns, e.local_name().as_ref())
+                    };
+                    match name.as_str() {
+                        "multistatus" => saw_multistatus = true,
+                        "response" => response = PropfindResponse::default(),
+                        "propstat" => propstat = PropStat::default(),
+                        _ => {}
+                    }
+                    stack.push(name);
+                    text.clear();
+                }
+                Event::Empty(e) if is_dav && e.local_name().as_ref() == b"collection" => {
+                    propstat.is_dir = true;
+                }
+                Event::Empty(e)
+                    if stack.last().is_some_and(|parent| parent == "prop")
+                        && !(is_dav && e.local_name().as_ref() == b"resourcetype") =>
+                {
+                    let name = if is_dav {
+                        format!("d:{}", String::from_utf8_lossy(e.local_name().as_ref()))
+                    } else {
+                        qualified_name(&ns, e.local_name().as_ref())
+                    };
+                    propstat.properties.entry(name).or_default();
+                }
+                Event::Text(t) => {
+                    text.push_str(&t.unescape().context("Invalid text in PROPFIND response")?);
+                }
+                Event::CData(c) => {
+                    text.push_str(std::str::from_utf8(&c).context("Invalid CDATA in PROPFIND response")?);
+                }
+                Event::End(_) => {
+                    let name = stack.pop().unwrap_or_default();
+                    let parent = stack.last().map(String::as_str).unwrap_or("");
+                    let value = std::mem::take(&mut text);
+                    match (parent, name.as_str()) {
+                        ("response", "href") => response.href = value.trim().to_string(),
+                        ("propstat", "status") => propstat.status = Some(value.trim().to_string()),
+                        ("prop", "displayname") => propstat.displayname = Some(value),
+                        ("prop", "getcontentlength") => propstat.size = value.trim().parse().ok(),
+                        ("prop", "getlastmodified") => propstat.modified = parse_http_date(value.trim()),
+                        ("prop", "getetag") => propstat.etag = Some(value.trim().to_string()),
+                        ("resourcetype", "collection") => propstat.is_dir = true,
+                        ("prop", "quota-available-bytes") => propstat.quota.available = parse_quota(&value),
+                        ("prop", "quota-used-bytes") => propstat.quota.used = parse_quota(&value),
+                        ("prop", "resourcetype") => {}
+                        // Tags come as one <oc:tag> element each
+                        ("oc:tags", "oc:tag") => {
+                            let tags = propstat.properties.entry("oc:tags".to_string()).or_default();
+                            if !tags.is_empty() {
+                                tags.push(',');
+                            }
+                            tags.push_str(value.trim());
+                        }
+                        // Nextcloud puts all checksums of a file into one
+                        // <oc:checksum>, but allow one element per checksum too
+                        ("oc:checksums", "oc:checksum") => {
+                            let checksums = propstat.properties.entry("oc:checksums".to_string()).or_default();
+                            if !checksums.is_empty() {
+                                checksums.push(' ');
+                            }
+                            checksums.push_str(value.trim());
+                        }
+                        ("prop", other) => {
+                            let name = if is_dav { format!("d:{}", other) } else { other.to_string() };
+                            let value = value.trim();
+                            let slot = propstat.properties.entry(name).or_default();
+                            if !value.is_empty() {
+                                *slot = value.to_string();
+                            }
+                        }
+                        // Properties the server could not return come back in a
+                        // non-2xx propstat; only merge the successful ones.
+                        (_, "propstat") if propstat.is_success() => {
+                            response.merge(std::mem::take(&mut propstat));
+                        }
+                        (_, "response") => {
+                            let response = std::mem::take(&mut response);
+                            // Depth: 1 also reports the collection itself
+                            if same_path(&response.href, request_path) {
+                                collection_etag = response.etag;
+                                is_collection = response.is_dir;
+                                quota = response.quota;
+                                modified = response.modified;
+                                properties = response.properties;
+                                continue;
+                            }
+                            // Left behind by an atomic upload that was cut off;
+                            // checked before decrypting, which renames it on
+                            // encrypted mounts if it carries a stored name
+                            if let Some(entry) = response
+                                .into_entry(names_below)
+                                .filter(|entry| !entry.name.rsplit('/').next().unwrap_or_default().starts_with(ATOMIC_UPLOAD_PREFIX))
+                            {
+                                entries.push(self.decrypt_entry(entry));
+                            }
+                            if max_entries.is_some_and(|max| entries.len() >= max) {
+                                truncated = true;
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        if !saw_multistatus {
+            anyhow::bail!("PROPFIND response is not a DAV:multistatus document");
+        }
+
+        Ok(DirListing {
+            entries,
+            etag: collection_etag,
+            is_collection,
+            quota,
+            truncated,
+            modified,
+            properties,
+        })
+    }
+
+    /// Restore the real name and size of an entry of an encrypted mount.
+    /// Entries the server has no name for keep their hashed name.
+    fn decrypt_entry(&self, mut entry: DavEntry) -> DavEntry {
+        let Some(cipher) = &self.cipher else {
+            return entry;
+        };
+        match entry.properties.remove("davfs:name").and_then(|value| cipher.decrypt_name(&value)) {
+            Some(name) => entry.name = name,
+            None => tracing::debug!("No name stored for encrypted entry {}", entry.name),
+        }
+        if let Some(size) = entry.properties.remove("davfs:size").and_then(|value| value.parse().ok()) {
+            entry.size = size;
+        }
+        // The server only sees ciphertext
+        if !entry.is_dir {
+            entry.content_type = Some(guess_content_type(&entry.name));
+        }
+        entry
+    }
+
+    /// Store the encrypted name of `path`, and its plaintext size for files,
+    /// after it was created or moved there.
+    async fn store_plain_metadata(&self, path: &str, size: Option<u64>, lock: Option<&LockToken>) -> Result<()> {
+        let Some(cipher) = &self.cipher else {
+            return Ok(());
+        };
+        let name = path.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+        let mut set = vec![("davfs:name".to_string(), cipher.encrypt_name(name)?)];
+        if let Some(size) = size {
+            set.push(("davfs:size".to_string(), size.to_string()));
+        }
+        self.proppatch_locked(path, &set, &[], lock)
+            .await
+            .context("Failed to store the encrypted name")
+    }
+
+    /// Check with a Depth: 0 PROPFIND whether `path` is a collection.
+    pub async fn is_collection(&self, path: &str) -> Result<bool> {
+        let url = self.url_for(path)?;
+        let method = Method::from_bytes(b"PROPFIND")?;
+
+        let request = self
+            .request(method.clone(), url.clone())
+            .header("Depth", "0")
+            .header("Content-Type", "application/xml")
+            .body(
+                r#"<?xml version="1.0"?>
+                <d:propfind xmlns:d="DAV:">
+                  <d:prop>
+                    <d:resourcetype/>
+                  </d:prop>
+                </d:propfind>"#,
+            );
+
+        let response = self.send(request).await.context("Failed to stat")?;
+        let response = Self::check_status(method, url.clone(), response)?;
+
+        let listing = self.parse_propfind_response(body_reader(response), url.path(), None, None).await?;
+        Ok(listing.is_collection)
+    }
+
+    /// Last modification time of `path` from a Depth: 0 PROPFIND.
+    pub async fn last_modified(&self, path: &str) -> Result<Option<chrono::DateTime<chrono::Utc>>> {
+        let url = self.url_for(path)?;
+        let method = Method::from_bytes(b"PROPFIND")?;
+
+        let request = self
+            .request(method.clone(), url.clone())
+            .header("Depth", "0")
+            .header("Content-Type", "application/xml")
+            .body(PropRequest::Named(vec!["d:getlastmodified"]).body());
+
+        let response = self.send(request).await.context("Failed to stat")?;
+        let response = Self::check_status(method, url.clone(), response)?;
+
+        let listing = self.parse_propfind_response(body_reader(response), url.path(), None, None).await?;
+        Ok(listing.modified)
+    }
+
+    /// Target of the symbolic link at `path` from a Depth: 0 PROPFIND, or
+    /// `None` if it is a plain file.
+    pub async fn symlink_target(&self, path: &str) -> Result<Option<String>> {
+        let url = self.url_for(path)?;
+        let method = Method::from_bytes(b"PROPFIND")?;
+
+        let request = self
+            .request(method.clone(), url.clone())
+            .header("Depth", "0")
+            .header("Content-Type", "application/xml")
+            .body(PropRequest::Named(vec![SYMLINK_TARGET_PROP]).body());
+
+        let response = self.send(request).await.context("Failed to read link")?;
+        let response = Self::check_status(method, url.clone(), response)?;
+
+        let mut listing = self.parse_propfind_response(body_reader(response), url.path(), None, None).await?;
+        Ok(listing.properties.remove(SYMLINK_TARGET_PROP).filter(|target| !target.is_empty()))
+    }
+
+    /// Create a symbolic link to `target` at `path`: an empty file carrying
+    /// the target as a property. Fails with 412 if `path` already exists.
+    pub async fn create_symlink(&self, path: &str, target: &str) -> Result<Option<String>> {
+        let etag = self.create_file(path).await?;
+        let set = [(SYMLINK_TARGET_PROP.to_string(), target.to_string())];
+        if let Err(e) = self.proppatch(path, &set, &[]).await {
+            // Don't leave an empty file behind in place of the link
+            if let Err(e) = self.delete(path, None).await {
+                tracing::warn!("Failed to remove {} after failing to make it a link: {}", path, e);
+            }
+            return Err(e.context("Failed to store the link target"));
+        }
+        Ok(etag)
+    }
+
+    /// Fetch the RFC 4331 quota of `path` with a Depth: 0 PROPFIND.
+    pub async fn quota(&self, path: &str) -> Result<Quota> {
+        let url = self.url_for(path)?;
+        let method = Method::from_bytes(b"PROPFIND")?;
+
+        let request = self
+            .request(method.clone(), url.clone())
+            .header("Depth", "0")
+            .header("Content-Type", "application/xml")
+            .body(
+                r#"<?xml version="1.0"?>
+                <d:propfind xmlns:d="DAV:">
+                  <d:prop>
+                    <d:quota-available-bytes/>
+                    <d:quota-used-bytes/>
+                  </d:prop>
+                </d:propfind>"#,
+            );
+
+        let response = self.send(request).await.context("Failed to query quota")?;
+        let response = Self::check_status(method, url.clone(), response)?;
+
+        let listing = self.parse_propfind_response(body_reader(response), url.path(), None, None).await?;
+        Ok(listing.quota)
+    }
+
+    pub async fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        let url = self.url_for(path)?;
+
+        let request = self.request(Method::GET, url);
+        let response = self
+            .send(request)
+            .await
+            .context("Failed to download file")?;
+
+        if !response.status().is_success() {
+            anyhow::bail!("Failed to read file: {}", response.status());
+        }
+
+        let data = self.read_body(response).await?;
+        match &self.cipher {
+            Some(cipher) => cipher.decrypt(&data),
+            None => Ok(data),
+        }
+    }
+
+    /// Collect a response body, throttled by the download limit.
+    async fn read_body(&self, mut response: Response) -> Result<Vec<u8>> {
+        let Some(limiter) = &self.download_limiter else {
+            return Ok(response.bytes().await?.to_vec());
+        };
+
+        let mut data = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            limiter.acquire(chunk.len() as u64).await;
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data)
+    }
+
+    /// Fetch `size` bytes starting at `offset` with a Range request. Servers
+    /// that ignore Range answer 200 with the whole body; that is streamed and
+    /// only the requested slice kept.
+    pub async fn read_range(&self, path: &str, offset: u64, size: u64) -> Result<Vec<u8>> {
+        if size == 0 {
+            return Ok(Vec::new());
+        }
+        // Offsets in the plaintext do not map to the ciphertext
+        if self.cipher.is_some() {
+            let data = self.read_file(path).await?;
+            let start = (offset as usize).min(data.len());
+            let end = offset.saturating_add(size).min(data.len() as u64) as usize;
+            return Ok(data[start..end].to_vec());
+        }
+        self.read_url_range(self.url_for(path)?, offset, size).await
+    }
+
+    async fn read_url_range(&self, url: Url, offset: u64, size: u64) -> Result<Vec<u8>> {
+        if size == 0 {
+            return Ok(Vec::new());
+        }
+        let request = self
+            .request(Method::GET, url.clone())
+            .header("Range", format!("bytes={}-{}", offset, offset + size - 1));
+        let response = self
+            .send(request)
+            .await
+            .context("Failed to download file range")?;
+
+        match response.status() {
+            StatusCode::PARTIAL_CONTENT => self.read_body(response).await,
+            // Offset at or past the end of the file
+            StatusCode::RANGE_NOT_SATISFIABLE => Ok(Vec::new()),
+            StatusCode::OK => {
+                let mut response = response;
+                let end = offset + size;
+                let mut position = 0u64;
+                let mut data = Vec::with_capacity(size as usize);
+
+                while let Some(chunk) = response.chunk().await? {
+                    if let Some(limiter) = &self.download_limiter {
+                        limiter.acquire(chunk.len() as u64).await;
+                    }
+                    let chunk_start = position;
+                    position += chunk.len() as u64;
+                    if position <= offset {
+                        continue;
+                    }
+                    let from = offset.saturating_sub(chunk_start) as usize;
+                    let to = (end.min(position) - chunk_start) as usize;
+                    data.extend_from_slice(&chunk[from..to]);
+                    if position >= end {
+                        break;
+                    }
+                }
+                Ok(data)
+            }
+            status => Err(DavStatusError {
+                method: Method::GET,
+                url,
+                status,
+            }
+            .into()),
+        }
+    }
+
+    /// Upload `data` to `path`. With `if_match` set the upload only succeeds
+    /// if the server still has that etag. Returns the new etag if the server
+    /// reported one.
+    pub async fn put_file(
+        &self,
+        path: &str,
+        data: Vec<u8>,
+        if_match: Option<&str>,
+        lock: Option<&LockToken>,
+    ) -> Result<Option<String>> {
+        let Some(cipher) = &self.cipher else {
+            return self.upload(path, data, if_match, lock).await;
+        };
+        let size = data.len() as u64;
+        let etag = self.upload(path, cipher.encrypt(&data)?, if_match, lock).await?;
+        self.store_plain_metadata(path, Some(size), lock).await?;
+        Ok(etag)
+    }
+
+    async fn upload(
+        &self,
+        path: &str,
+        data: Vec<u8>,
+        if_match: Option<&str>,
+        lock: Option<&LockToken>,
+    ) -> Result<Option<String>> {
+        if let Some(uploads) = self.chunked_upload_base(data.len() as u64) {
+            return self.put_file_chunked(path, data, uploads, if_match, lock).await;
+        }
+        // TUS has no conditional uploads, so only plain writes use it
+        if if_match.is_none() && lock.is_none() && self.uses_tus(data.len() as u64) {
+            return self.put_file_tus(path, data).await;
+        }
+
+        // Nextcloud already writes PUTs atomically, and a MOVE over the file
+        // would give it a new file id
+        let nextcloud = self.capabilities.is_some_and(|c| c.nextcloud);
+        if !nextcloud && self.atomic_upload.load(Ordering::Relaxed) {
+            match self.put_file_atomic(path, &data, if_match, lock).await {
+                Err(e) if e.downcast_ref::<DavStatusError>().is_some_and(|e| {
+                    e.method.as_str() == "MOVE"
+                        && matches!(e.status, StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED)
+                }) =>
+                {
+                    tracing::warn!("Server does not support MOVE; uploading files in place");
+                    self.atomic_upload.store(false, Ordering::Relaxed);
+                }
+                result => return result,
+            }
+        }
+        self.put_in_place(path, data, if_match, lock).await
+    }
+
+    /// Plain PUT of `data` to `path`.
+    async fn put_in_place(
+        &self,
+        path: &str,
+        data: Vec<u8>,
+        if_match: Option<&str>,
+        lock: Option<&LockToken>,
+    ) -> Result<Option<String>> {
+        let url = self.url_for(path)?;
+
+        let mut request = self
+            .request(Method::PUT, url.clone())
+            .header("Content-Type", self.upload_content_type(path))
+            .body(self.upload_body(data));
+        if let Some(etag) = if_match {
+            request = request.header("If-Match", etag);
+        }
+        // A locked resource only accepts writes that submit the lock token
+        if let Some(token) = lock {
+            request = request.header("If", format!("(<{}>)", token.0));
+        }
+
+        let response = self.send(request).await.context("Failed to upload file")?;
+        let response = Self::check_status(Method::PUT, url, response)?;
+
+        Ok(response_etag(&response))
+    }
+
+    /// PUT `data` to a temporary file in the directory of `path`, then MOVE
+    /// it over `path`, so readers never see a partly written file. The MOVE
+    /// replaces the resource, so the dead properties of `path` are copied to
+    /// the temporary file first; where that fails the file is uploaded in
+    /// place instead. The temporary file is removed if the upload fails.
+    async fn put_file_atomic(
+        &self,
+        path: &str,
+        data: &[u8],
+        if_match: Option<&str>,
+        lock: Option<&LockToken>,
+    ) -> Result<Option<String>> {
+        let properties = self.dead_properties(path).await?;
+
+        // Not hashed on encrypted mounts, so leftovers are recognizable
+        let (parent, _) = path.rsplit_once('/').unwrap_or(("", path));
+        let mut tmp_url = self.url_for(parent)?;
+        tmp_url
+            .path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Base URL cannot have a path: {}", self.active_url()))?
+            .pop_if_empty()
+            .push(&format!("{}{}", ATOMIC_UPLOAD_PREFIX, uuid::Uuid::new_v4()));
+        let destination = self.url_for(path)?;
+
+        let request = self
+            .request(Method::PUT, tmp_url.clone())
+            .header("Content-Type", self.upload_content_type(path))
+            .body(self.upload_body(data.to_vec()));
+        let result = match self.send(request).await.context("Failed to upload file") {
+            Ok(response) => Self::check_status(Method::PUT, tmp_url.clone(), response).map(drop),
+            Err(e) => Err(e),
+        };
+
+        let mut in_place = false;
+        if result.is_ok() && !properties.is_empty() {
+            if let Err(e) = self.proppatch_url(&tmp_url, &properties, &[], None).await {
+                tracing::debug!("Failed to copy the properties of {}, uploading it in place: {:#}", path, e);
+                in_place = true;
+            }
+        }
+
+        let result = match result {
+            Ok(()) if !in_place => self.move_upload(&tmp_url, &destination, if_match, lock).await,
+            Ok(()) => Ok(None),
+            Err(e) => Err(e),
+        };
+        if result.is_err() || in_place {
+            let request = self.request(Method::DELETE, tmp_url.clone());
+            if let Err(e) = self.send(request).await {
+                tracing::debug!("Failed to remove upload {}: {}", tmp_url, e);
+            }
+        }
+        if in_place {
+            return self.put_in_place(path, data.to_vec(), if_match, lock).await;
+        }
+        result
+    }
+
+    /// Properties of `path` outside the `DAV:`, ownCloud and Nextcloud
+    /// namespaces, which hold the server's live properties, and other than
+    /// the encryption metadata; empty if `path` does not exist yet.
+    async fn dead_properties(&self, path: &str) -> Result<Vec<(String, String)>> {
+        let url = self.url_for(path)?;
+        let method = Method::from_bytes(b"PROPFIND")?;
+
+        let request = self
+            .request(method.clone(), url.clone())
+            .header("Depth", "0")
+            .header("Content-Type", "application/xml")
+            .body(PropRequest::Allprop.body());
+        let response = self.send(request).await.context("Failed to read properties")?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+        let response = Self::check_status(method, url.clone(), response)?;
+
+        let listing = self.parse_propfind_response(body_reader(response), url.path(), None, None).await?;
+        Ok(listing
+            .properties
+            .into_iter()
+            .filter(|(name, _)| !["d:", "oc:", "nc:"].iter().any(|prefix| name.starts_with(prefix)))
+            // Written again for the new content once it is in place
+            .filter(|(name, _)| !ENCRYPTION_PROPS.contains(&name.as_str()))
+            .collect())
+    }
+
+    /// MOVE the uploaded `source` over `destination`, with the conditions
+    /// of the upload.
+    async fn move_upload(
+        &self,
+        source: &Url,
+        destination: &Url,
+        if_match: Option<&str>,
+        lock: Option<&LockToken>,
+    ) -> Result<Option<String>> {
+        let method = Method::from_bytes(b"MOVE")?;
+        let mut request = self
+            .request(method.clone(), source.clone())
+            .header("Destination", destination.as_str())
+            .header("Overwrite", "T");
+        // As for chunked uploads, a tagged If header checks the destination
+        // rather than the temporary file
+        let conditions: String = [
+            lock.map(|token| format!("<{}>", token.0)),
+            if_match.map(|etag| format!("[{}]", etag)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" ");
+        if !conditions.is_empty() {
+            request = request.header("If", format!("<{}> ({})", destination, conditions));
+        }
+
+        let response = self.send(request).await.context("Failed to move upload into place")?;
+        let response = Self::check_status(method, source.clone(), response)?;
+        Ok(response_etag(&response))
+    }
+
+    /// Collection under which Nextcloud assembles chunked uploads, if `size`
+    /// bytes should be uploaded in chunks. Only Nextcloud (and only with the
+    /// `remote.php/dav/files/<user>` endpoint) supports this.
+    fn chunked_upload_base(&self, size: u64) -> Option<Url> {
+        let chunk_size = self.chunk_size?;
+        if size <= chunk_size || !self.capabilities.is_some_and(|c| c.nextcloud) {
+            return None;
+        }
+        let mut url = self.active_url();
+        let (root, rest) = url.path().split_once("/remote.php/dav/files/")?;
+        let user = rest.split('/').next().filter(|user| !user.is_empty())?;
+
+        url.set_path(&format!("{}/remote.php/dav/uploads/{}/", root, user));
+        Some(url)
+    }
+
+    /// Upload `data` in chunks into a temporary collection below `uploads`,
+    /// then MOVE the assembled file to `path`. An upload of the same data
+    /// cut off earlier is continued.
+    async fn put_file_chunked(
+        &self,
+        path: &str,
+        data: Vec<u8>,
+        uploads: Url,
+        if_match: Option<&str>,
+        lock: Option<&LockToken>,
+    ) -> Result<Option<String>> {
+        let digest = uploads::digest(&data);
+        if let Some(pending) = self.pending_upload(path, if_match, &digest).await {
+            tracing::info!("Resuming upload of {} at byte {}", path, pending.uploaded);
+            let upload_url = Url::parse(&pending.upload_url)?;
+            let result = self
+                .upload_chunks(path, &upload_url, &data, pending.uploaded, if_match, lock)
+                .await;
+            // Servers expire unfinished uploads
+            match result.as_ref().err().and_then(|e| e.downcast_ref::<DavStatusError>()) {
+                Some(e)
+                    if e.url.as_str().starts_with(upload_url.as_str())
+                        && matches!(e.status, StatusCode::NOT_FOUND | StatusCode::CONFLICT) =>
+                {
+                    tracing::info!("Upload of {} expired on the server; starting over", path);
+                }
+                _ => return self.settle_upload(path, if_match, &upload_url, result).await,
+            }
+        }
+
+        let upload_url = uploads.join(&format!("{}/", uuid::Uuid::new_v4()))?;
+        let method = Method::from_bytes(b"MKCOL")?;
+
+        let request = self.request(method.clone(), upload_url.clone());
+        let response = self
+            .send(request)
+            .await
+            .context("Failed to start chunked upload")?;
+        Self::check_status(method, upload_url.clone(), response)?;
+        if let Some(log) = &self.upload_log {
+            log.start(path, if_match, &digest, upload_url.as_str());
+        }
+
+        let result = self.upload_chunks(path, &upload_url, &data, 0, if_match, lock).await;
+        self.settle_upload(path, if_match, &upload_url, result).await
+    }
+
+    /// The unfinished upload of `path` if it was of the content with
+    /// `digest`. One of other content is removed from the server.
+    async fn pending_upload(&self, path: &str, if_match: Option<&str>, digest: &str) -> Option<uploads::PendingUpload> {
+        let log = self.upload_log.as_ref()?;
+        let pending = log.find(path, if_match)?;
+        if pending.digest == digest {
+            return Some(pending);
+        }
+        log.finish(path, if_match);
+        if let Ok(url) = Url::parse(&pending.upload_url) {
+            let request = self.request(Method::DELETE, url.clone());
+            if let Err(e) = self.send(request).await {
+                tracing::debug!("Failed to remove abandoned upload {}: {}", url, e);
+            }
+        }
+        None
+    }
+
+    /// Forget a finished upload. A failed one is removed from the server,
+    /// unless the connection broke and it can be resumed later.
+    async fn settle_upload(
+        &self,
+        path: &str,
+        if_match: Option<&str>,
+        upload_url: &Url,
+        result: Result<Option<String>>,
+    ) -> Result<Option<String>> {
+        let resumable = match &result {
+            Ok(_) => false,
+            Err(e) => self.upload_log.is_some() && (is_network_error(e) || e.is::<reqwest::Error>()),
+        };
+        if resumable {
+            tracing::info!("Upload of {} interrupted; it resumes on the next attempt", path);
+            return result;
+        }
+        if let Some(log) = &self.upload_log {
+            log.finish(path, if_match);
+        }
+        if result.is_err() {
+            // Don't leave the partial upload behind on the server
+            let request = self.request(Method::DELETE, upload_url.clone());
+            if let Err(e) = self.send(request).await {
+                tracing::debug!("Failed to remove upload {}: {}", upload_url, e);
+            }
+        }
+        result
+    }
+
+    /// Upload the chunks of `data` from `start` on, then assemble them.
+    async fn upload_chunks(
+        &self,
+        path: &str,
+        upload_url: &Url,
+        data: &[u8],
+        start: u64,
+        if_match: Option<&str>,
+        lock: Option<&LockToken>,
+    ) -> Result<Option<String>> {
+        let destination = self.url_for(path)?;
+        let chunk_size = self.chunk_size.unwrap_or(u64::MAX).max(1) as usize;
+        let total = data.len();
+
+        // Chunks are named by their zero-padded offset so they sort in order
+        for offset in (start as usize..total).step_by(chunk_size) {
+            let end = (offset + chunk_size).min(total);
+            let chunk_url = upload_url.join(&format!("{:015}", offset))?;
+            let request = self
+                .request(Method::PUT, chunk_url.clone())
+                .body(self.upload_body(data[offset..end].to_vec()));
+            let response = self.send(request).await.context("Failed to upload chunk")?;
+            Self::check_status(Method::PUT, chunk_url, response)?;
+            if let Some(log) = &self.upload_log {
+                log.progress(path, if_match, end as u64);
+            }
+            tracing::info!("Uploaded {} of {} bytes of {}", end, total, path);
+        }
+
+        let source = upload_url.join(".file")?;
+        let method = Method::from_bytes(b"MOVE")?;
+        let mut request = self
+            .request(method.clone(), source.clone())
+            .header("Destination", destination.as_str())
+            .header("Overwrite", "T")
+            .header("OC-Total-Length", total.to_string());
+        // Plain If-Match would apply to the upload collection; a tagged If
+        // header checks the destination's etag and lock instead
+        let conditions: String = [
+            lock.map(|token| format!("<{}>", token.0)),
+            if_match.map(|etag| format!("[{}]", etag)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" ");
+        if !conditions.is_empty() {
+            request = request.header("If", format!("<{}> ({})", destination, conditions));
+        }
+
+        let response = self
+            .send(request)
+            .await
+            .context("Failed to assemble chunked upload")?;
+        let response = Self::check_status(method, source, response)?;
+        tracing::info!("Finished chunked upload of {} ({} bytes)", path, total);
+
+        Ok(response_etag(&response))
+    }
+
+    /// Whether `size` bytes should be uploaded with TUS.
+    fn uses_tus(&self, size: u64) -> bool {
+        self.capabilities.is_some_and(|c| c.tus) && self.chunk_size.is_some_and(|chunk_size| size > chunk_size)
+    }
+
+    /// Upload `data` to `path` with the TUS protocol: create an upload in
+    /// the parent collection, then PATCH it chunk by chunk. An upload of
+    /// the same data cut off earlier continues at the offset the server
+    /// reports.
+    async fn put_file_tus(&self, path: &str, data: Vec<u8>) -> Result<Option<String>> {
+        let digest = uploads::digest(&data);
+        let pending = match self.pending_upload(path, None, &digest).await {
+            Some(pending) => {
+                let upload_url = Url::parse(&pending.upload_url)?;
+                match self.tus_offset(&upload_url).await {
+                    Ok(offset) => Some((upload_url, offset)),
+                    Err(e) => {
+                        tracing::info!("Upload of {} can't be resumed ({}); starting over", path, e);
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        let (upload_url, start) = match pending {
+            Some((upload_url, offset)) => {
+                tracing::info!("Resuming upload of {} at byte {}", path, offset);
+                (upload_url, offset)
+            }
+            None => {
+                let upload_url = self.tus_create(path, data.len() as u64).await?;
+                if let Some(log) = &self.upload_log {
+                    log.start(path, None, &digest, upload_url.as_str());
+                }
+                (upload_url, 0)
+            }
+        };
+
+        let result = self.tus_patch(path, &upload_url, &data, start).await;
+        self.settle_upload(path, None, &upload_url, result).await
+    }
+
+    async fn tus_create(&self, path: &str, total: u64) -> Result<Url> {
+        use base64::Engine;
+
+        let destination = self.url_for(path)?;
+        let name = destination.path_segments().and_then(|mut s| s.next_back()).unwrap_or_default();
+        let name = percent_decode_str(name).decode_utf8_lossy();
+        let parent = destination.join("./")?;
+
+        let request = self
+            .request(Method::POST, parent.clone())
+            .header("Tus-Resumable", TUS_VERSION)
+            .header("Upload-Length", total.to_string())
+            .header(
+                "Upload-Metadata",
+                format!("filename {}", base64::engine::general_purpose::STANDARD.encode(name.as_bytes())),
+            );
+        let response = self.send(request).await.context("Failed to start upload")?;
+        let response = Self::check_status(Method::POST, parent.clone(), response)?;
+        let location = response
+            .headers()
+            .get("Location")
+            .and_then(|v| v.to_str().ok())
+            .context("Server did not say where to upload")?;
+        Ok(parent.join(location)?)
+    }
+
+    /// Bytes of the upload at `upload_url` the server has.
+    async fn tus_offset(&self, upload_url: &Url) -> Result<u64> {
+        let request = self
+            .request(Method::HEAD, upload_url.clone())
+            .header("Tus-Resumable", TUS_VERSION);
+        let response = self.send(request).await?;
+        let response = Self::check_status(Method::HEAD, upload_url.clone(), response)?;
+        response
+            .headers()
+            .get("Upload-Offset")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .context("Server did not report the upload offset")
+    }
+
+    async fn tus_patch(&self, path: &str, upload_url: &Url, data: &[u8], start: u64) -> Result<Option<String>> {
+        let chunk_size = self.chunk_size.unwrap_or(u64::MAX).max(1) as usize;
+        let total = data.len();
+        let method = Method::PATCH;
+
+        let mut offset = start as usize;
+        let mut etag = None;
+        while offset < total {
+            let end = (offset + chunk_size).min(total);
+            let request = self
+                .request(method.clone(), upload_url.clone())
+                .header("Tus-Resumable", TUS_VERSION)
+                .header("Upload-Offset", offset.to_string())
+                .header("Content-Type", "application/offset+octet-stream")
+                .body(self.upload_body(data[offset..end].to_vec()));
+            let response = self.send(request).await.context("Failed to upload chunk")?;
+            let response = Self::check_status(method.clone(), upload_url.clone(), response)?;
+            etag = response_etag(&response);
+            offset = response
+                .headers()
+                .get("Upload-Offset")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(end);
+            if let Some(log) = &self.upload_log {
+                log.progress(path, None, offset as u64);
+            }
+            tracing::info!("Uploaded {} of {} bytes of {}", offset, total, path);
+        }
+        Ok(etag)
+    }
+
+    /// Request body for an upload: the data as is, or streamed in chunks
+    /// paced by the upload limit.
+    fn upload_body(&self, data: Vec<u8>) -> reqwest::Body {
+        let Some(limiter) = self.upload_limiter.clone() else {
+            return data.into();
+        };
+
+        let chunks = futures_util::stream::unfold((data, 0), move |(data, offset)| {
+            let limiter = limiter.clone();
+            async move {
+                if offset >= data.len() {
+                    return None;
+                }
+                let end = (offset + UPLOAD_CHUNK_SIZE).min(data.len());
+                limiter.acquire((end - offset) as u64).await;
+                let chunk = data[offset..end].to_vec();
+                Some((Ok::<_, std::io::Error>(chunk), (data, end)))
+            }
+        });
+        reqwest::Body::wrap_stream(chunks)
+    }
+
+    /// `Content-Type` to upload `path` with. Encrypted content is opaque,
+    /// and its real type would give away part of the hidden name.
+    fn upload_content_type(&self, path: &str) -> String {
+        match self.cipher {
+            Some(_) => "application/octet-stream".to_string(),
+            None => guess_content_type(path),
+        }
+    }
+
+    /// Create an empty file at `path`, failing with 412 if it already exists.
+    pub async fn create_file(&self, path: &str) -> Result<Option<String>> {
+        let url = self.url_for(path)?;
+
+        let body = match &self.cipher {
+            Some(cipher) => cipher.encrypt(&[])?,
+            None => Vec::new(),
+        };
+        let request = self
+            .request(Method::PUT, url.clone())
+            .header("If-None-Match", "*")
+            .header("Content-Type", self.upload_content_type(path))
+            .body(body);
+        let response = self
+            .send(request)
+            .await
+            .context("Failed to create file")?;
+        let response = Self::check_status(Method::PUT, url, response)?;
+
+        self.store_plain_metadata(path, Some(0), None).await?;
+        Ok(response_etag(&response))
+    }
+
+    /// Delete `path`. `lock` is the token of a lock held on it, if any.
+    pub async fn delete(&self, path: &str, lock: Option<&LockToken>) -> Result<()> {
+        let url = self.url_for(path)?;
+
+        let mut request = self.request(Method::DELETE, url.clone());
+        if let Some(token) = lock {
+            request = request.header("If", format!("(<{}>)", token.0));
+        }
+        let response = self
+            .send(request)
+            .await
+            .context("Failed to delete")?;
+        Self::check_status(Method::DELETE, url, response)?;
+
+        Ok(())
+    }
+
+    pub async fn mkcol(&self, path: &str) -> Result<()> {
+        let url = self.url_for(path)?;
+        let method = Method::from_bytes(b"MKCOL")?;
+
+        let request = self.request(method.clone(), url.clone());
+        let response = self
+            .send(request)
+            .await
+            .context("Failed to create directory")?;
+        Self::check_status(method, url, response)?;
+
+        self.store_plain_metadata(path, None, None).await
+    }
+
+    /// Move `from` to `to` on the server. `lock` is the token of a lock held
+    /// on `from`, if any; the server drops it with the move.
+    pub async fn move_entry(&self, from: &str, to: &str, overwrite: bool, lock: Option<&LockToken>) -> Result<()> {
+        let url = self.url_for(from)?;
+        let destination = self.url_for(to)?;
+        let method = Method::from_bytes(b"MOVE")?;
+
+        let mut request = self
+            .request(method.clone(), url.clone())
+            .header("Destination", destination.as_str())
+            .header("Overwrite", if overwrite { "T" } else { "F" });
+        if let Some(token) = lock {
+            request = request.header("If", format!("<{}> (<{}>)", url, token.0));
+        }
+        let response = self
+            .send(request)
+            .await
+            .context("Failed to move")?;
+        Self::check_status(method, url, response)?;
+
+        self.store_plain_metadata(to, None, None).await
+    }
+
+    /// Copy `src` to `dst` on the server. `lock` is the token of a lock held
+    /// on the destination, if any.
+    pub async fn copy_entry(
+        &self,
+        src: &str,
+        dst: &str,
+        overwrite: bool,
+        lock: Option<&LockToken>,
+    ) -> Result<()> {
+        let url = self.url_for(src)?;
+        let destination = self.url_for(dst)?;
+        let method = Method::from_bytes(b"COPY")?;
+
+        let mut request = self
+            .request(method.clone(), url.clone())
+            .header("Destination", destination.as_str())
+            .header("Overwrite", if overwrite { "T" } else { "F" });
+        // The lock is on the destination, not the request URL, so tag it
+        if let Some(token) = lock {
+            request = request.header("If", format!("<{}> (<{}>)", destination, token.0));
+        }
+        let response = self
+            .send(request)
+            .await
+            .context("Failed to copy")?;
+        Self::check_status(method, url, response)?;
+
+        self.store_plain_metadata(dst, None, lock).await
+    }
+
+    /// Set the last-modified time of `path` with PROPPATCH.
+    pub async fn set_modified(&self, path: &str, mtime: chrono::DateTime<chrono::Utc>) -> Result<()> {
+        let date = mtime.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        self.proppatch(path, &[("d:getlastmodified".to_string(), date)], &[])
+            .await
+            .context("Failed to set modification time")
+    }
+
+    /// Set and remove properties of `path` in one PROPPATCH. Names use the
+    /// same prefixes as `PropRequest` (`d:`, `oc:`, `nc:`) or the
+    /// `{namespace}name` form. `oc:tags` takes a comma-separated list.
+    pub async fn proppatch(&self, path: &str, set: &[(String, String)], remove: &[String]) -> Result<()> {
+        self.proppatch_locked(path, set, remove, None).await
+    }
+
+    /// `proppatch` of a resource that may be locked with `lock`.
+    async fn proppatch_locked(
+        &self,
+        path: &str,
+        set: &[(String, String)],
+        remove: &[String],
+        lock: Option<&LockToken>,
+    ) -> Result<()> {
+        self.proppatch_url(&self.url_for(path)?, set, remove, lock).await
+    }
+
+    async fn proppatch_url(
+        &self,
+        url: &Url,
+        set: &[(String, String)],
+        remove: &[String],
+        lock: Option<&LockToken>,
+    ) -> Result<()> {
+        let method = Method::from_bytes(b"PROPPATCH")?;
+
+        let mut updates = String::new();
+        if !set.is_empty() {
+            updates.push_str("<d:set><d:prop>");
+            for (name, value) in set {
+                let (open, close) = property_tags(name)?;
+                let value = if name == "oc:tags" {
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|tag| !tag.is_empty())
+                        .map(|tag| format!("<oc:tag>{}</oc:tag>", quick_xml::escape::escape(tag)))
+                        .collect()
+                } else {
+                    quick_xml::escape::escape(value.as_str()).into_owned()
+                };
+                updates.push_str(&format!("{}{}{}", open, value, close));
+            }
+            updates.push_str("</d:prop></d:set>");
+        }
+        if !remove.is_empty() {
+            updates.push_str("<d:remove><d:prop>");
+            for name in remove {
+                let (open, close) = property_tags(name)?;
+                updates.push_str(&format!("{}{}", open, close));
+            }
+            updates.push_str("</d:prop></d:remove>");
+        }
+        let body = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propertyupdate xmlns:d="DAV:" xmlns:oc="{}" xmlns:nc="{}" xmlns:davfs="{}">{}</d:propertyupdate>"#,
+            OWNCLOUD_NS, NEXTCLOUD_NS, DAVFS_NS, updates
+        );
+
+        let mut request = self
+            .request(method.clone(), url.clone())
+            .header("Content-Type", "application/xml")
+            .body(body);
+        if let Some(token) = lock {
+            request = request.header("If", format!("(<{}>)", token.0));
+        }
+        let response = self
+            .send(request)
+            .await
+            .context("Failed to update properties")?;
+        let response = Self::check_status(method.clone(), url.clone(), response)?;
+
+        // 207 Multi-Status can still report a per-property failure
+        let body = response.text().await?;
+        if let Some(status) = propstat_failure(&body) {
+            return Err(DavStatusError { method, url: url.clone(), status }.into());
+        }
+
+        Ok(())
+    }
+
+    /// Take a write lock on `path` for `timeout_secs` seconds.
+    pub async fn lock(&self, path: &str, depth: &str, scope: LockScope, timeout_secs: u32) -> Result<LockToken> {
+        let url = self.url_for(path)?;
+        let method = Method::from_bytes(b"LOCK")?;
+
+        let body = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<d:lockinfo xmlns:d="DAV:">
+  <d:lockscope><d:{}/></d:lockscope>
+  <d:locktype><d:write/></d:locktype>
+  <d:owner>davfs-sync</d:owner>
+</d:lockinfo>"#,
+            match scope {
+                LockScope::Exclusive => "exclusive",
+                LockScope::Shared => "shared",
+            }
+        );
+
+        let request = self
+            .request(method.clone(), url.clone())
+            .header("Depth", depth)
+            .header("Timeout", format!("Second-{}", timeout_secs))
+            .header("Content-Type", "application/xml")
+            .body(body);
+        let response = self
+            .send(request)
+            .await
+            .context("Failed to lock")?;
+        let response = Self::check_status(method, url, response)?;
+
+        let token = response
+            .headers()
+            .get("Lock-Token")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+            .filter(|v| !v.is_empty())
+            .with_context(|| format!("LOCK response for {} has no Lock-Token", path))?;
+
+        Ok(LockToken(token))
+    }
+
+    pub async fn unlock(&self, path: &str, token: &LockToken) -> Result<()> {
+        let url = self.url_for(path)?;
+        let method = Method::from_bytes(b"UNLOCK")?;
+
+        let request = self
+            .request(method.clone(), url.clone())
+            .header("Lock-Token", format!("<{}>", token.0));
+        let response = self
+            .send(request)
+            .await
+            .context("Failed to unlock")?;
+        Self::check_status(method, url, response)?;
+
+        Ok(())
+    }
+}
+
+/// Parse a quota byte count; negative values mean unknown or unlimited.
+fn parse_quota(value: &str) -> Option<u64> {
+    value.trim().parse::<i64>().ok().and_then(|n| u64::try_from(n).ok())
+}
+
+/// First non-2xx propstat status in a PROPPATCH multistatus body.
+fn propstat_failure(xml: &str) -> Option<StatusCode> {
+    let mut reader = NsReader::from_str(xml);
+    let mut in_status = false;
+
+    loop {
+        match reader.read_resolved_event().ok()? {
+            (ResolveResult::Bound(Namespace(b"DAV:")), Event::Start(e))
+                if e.local_name().as_ref() == b"status" =>
+            {
+                in_status = true;
+            }
+            (_, Event::Text(t)) if in_status => {
+                // "HTTP/1.1 403 Forbidden"
+                let text = t.unescape().ok()?;
+                let status = text.split_whitespace().nth(1)?.parse::<u16>().ok()?;
+                let status = StatusCode::from_u16(status).ok()?;
+                if !status.is_success() {
+                    return Some(status);
+                }
+            }
+            (_, Event::End(_)) => in_status = false,
+            (_, Event::Eof) => return None,
+            _ => {}
+        }
+    }
+}
+
+fn parse_http_date(value: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|dt| dt.with_timezone(&chrono::Utc))
+}
+
+/// Compare an href (absolute URL or path) with a request path, ignoring
+/// trailing slashes and differences in percent-encoding.
+fn same_path(href: &str, path: &str) -> bool {
+    let href_path = match Url::parse(href) {
+        Ok(url) => url.path().to_string(),
+        Err(_) => href.to_string(),
+    };
+    let decode = |p: &str| percent_decode_str(p.trim_end_matches('/')).decode_utf8_lossy().into_owned();
+    decode(&href_path) == decode(path)
+}
+
+/// Decoded path of `href` below the collection at `base`, without leading
+/// or trailing slashes. `None` if `href` is outside `base`.
+fn relative_path(href: &str, base: &str) -> Option<String> {
+    let href_path = match Url::parse(href) {
+        Ok(url) => url.path().to_string(),
+        Err(_) => href.to_string(),
+    };
+    let decode = |p: &str| percent_decode_str(p).decode_utf8_lossy().into_owned();
+    let href_path = decode(&href_path);
+    let rest = href_path.strip_prefix(decode(base).trim_end_matches('/'))?;
+    if !rest.is_empty() && !rest.starts_with('/') {
+        return None;
+    }
+    Some(rest.trim_matches('/').to_string())
+}
+
+/// Whether `err` means the server could not be reached at all, as opposed
+/// to an error response.
+pub fn is_network_error(err: &anyhow::Error) -> bool {
+    err.is::<CircuitOpenError>()
+        || err
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(|e| e.is_connect() || e.is_timeout())
+}
+
+/// Build a proxy from the environment using curl's precedence: the
+/// scheme-specific `HTTPS_PROXY` / `HTTP_PROXY`, then `ALL_PROXY`. Hosts in
+/// `no_proxy`, or else `NO_PROXY`, are contacted directly.
+fn proxy_from_env(base_url: &Url, no_proxy: Option<&str>) -> Result<Option<reqwest::Proxy>> {
+    let scheme_var = if base_url.scheme() == "https" { "HTTPS_PROXY" } else { "HTTP_PROXY" };
+    let Some((var, proxy_url)) = [scheme_var, "ALL_PROXY"]
+        .into_iter()
+        .find_map(|var| env_var_any_case(var).map(|value| (var, value)))
+    else {
+        return Ok(None);
+    };
+
+    tracing::info!("Using proxy {} from {}", proxy_url, var);
+    let proxy = reqwest::Proxy::all(proxy_url.as_str())
+        .with_context(|| format!("Invalid proxy URL in {}", var))?
+        .no_proxy(no_proxy_list(no_proxy));
+    Ok(Some(proxy))
+}
+
+/// Proxy for the configured SOCKS5 address. A bare `host:port` gets the
+/// `socks5h` scheme, so host names are resolved by the proxy.
+fn socks5_proxy(addr: &str, no_proxy: Option<&str>) -> Result<reqwest::Proxy> {
+    let proxy_url = if addr.contains("://") {
+        addr.to_string()
+    } else {
+        format!("socks5h://{}", addr)
+    };
+    tracing::info!("Using SOCKS5 proxy {}", proxy_url);
+    Ok(reqwest::Proxy::all(proxy_url.as_str())
+        .with_context(|| format!("Invalid SOCKS5 proxy {}", addr))?
+        .no_proxy(no_proxy_list(no_proxy)))
+}
+
+/// Hosts that bypass the proxy: `no_proxy` if given, else `NO_PROXY`.
+fn no_proxy_list(no_proxy: Option<&str>) -> Option<reqwest::NoProxy> {
+    match no_proxy {
+        Some(list) => reqwest::NoProxy::from_string(list),
+        None => reqwest::NoProxy::from_env(),
+    }
+}
+
+/// Split `WWW-Authenticate` parameters such as `realm="a, b", qop=auth`
+/// into key/value pairs, unquoting values.
+fn parse_auth_params(params: &str) -> Vec<(String, String)> {
+    let mut result = Vec::new();
+    let mut rest = params.trim();
+    while let Some((key, after)) = rest.split_once('=') {
+        let after = after.trim_start();
+        let (value, remainder) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut end = quoted.len();
+                let mut chars = quoted.char_indices();
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '\\' => value.extend(chars.next().map(|(_, escaped)| escaped)),
+                        '"' => {
+                            end = i + 1;
+                            break;
+                        }
+                        _ => value.push(c),
+                    }
+                }
+                (value, &quoted[end..])
+            }
+            None => {
+                let (value, remainder) = after.split_once(',').unwrap_or((after, ""));
+                (value.trim().to_string(), remainder)
+            }
+        };
+        result.push((key.trim().to_string(), value));
+        rest = remainder.trim_start().trim_start_matches(',').trim_start();
+    }
+    result
+}
+
+/// Read a non-empty environment variable, accepting the lowercase spelling
+/// as well.
+fn env_var_any_case(name: &str) -> Option<String> {
+    [name.to_string(), name.to_lowercase()]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|v| !v.is_empty()))
+}
+
+/// Statuses worth retrying: the server is overloaded or a gateway failed.
+fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Parse a `Retry-After` header given in seconds.
+fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get("Retry-After")?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+fn response_etag(response: &Response) -> Option<String> {
+    response
+        .headers()
+        .get("ETag")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "https://dav.example.com/remote.php/dav/files/alice/";
+
+    fn client() -> WebDavClient {
+        let auth = AuthMethod::Basic {
+            username: "alice".to_string(),
+            password: "secret".to_string(),
+        };
+        WebDavClient::new(&[BASE.to_string()], auth, &ClientOptions::default()).unwrap()
+    }
+
+    fn multistatus(hrefs: &[&str]) -> String {
+        let responses: String = hrefs
+            .iter()
+            .map(|href| {
+                format!(
+                    "<d:response><d:href>{}</d:href><d:propstat><d:prop><d:getcontentlength>1</d:getcontentlength>\
+                     </d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>",
+                    href
+                )
+            })
+            .collect();
+        format!(r#"<?xml version="1.0"?><d:multistatus xmlns:d="DAV:">{}</d:multistatus>"#, responses)
+    }
+
+    #[test]
+    fn url_for_encodes_each_segment() {
+        let client = client();
+        let cases = [
+            ("/Urlaub 2024/Strand Foto.jpg", "Urlaub%202024/Strand%20Foto.jpg"),
+            ("/Документы/отчёт.txt", "%D0%94%D0%BE%D0%BA%D1%83%D0%BC%D0%B5%D0%BD%D1%82%D1%8B/%D0%BE%D1%82%D1%87%D1%91%D1%82.txt"),
+            ("/🎉/party 🎂.png", "%F0%9F%8E%89/party%20%F0%9F%8E%82.png"),
+            ("/100%20done", "100%2520done"),
+            ("/a#b?c", "a%23b%3Fc"),
+        ];
+        for (path, encoded) in cases {
+            assert_eq!(client.url_for(path).unwrap().as_str(), format!("{}{}", BASE, encoded), "{}", path);
+        }
+    }
+
+    #[test]
+    fn url_for_segments_decode_to_the_path() {
+        let client = client();
+        for path in ["/Urlaub 2024/Strand Foto.jpg", "/Документы/отчёт.txt", "/🎉/party 🎂.png", "/100%20done"] {
+            let url = client.url_for(path).unwrap();
+            let decoded: Vec<String> = url
+                .path_segments()
+                .unwrap()
+                .skip(4)
+                .map(|s| percent_decode_str(s).decode_utf8_lossy().into_owned())
+                .collect();
+            assert_eq!(format!("/{}", decoded.join("/")), path);
+        }
+    }
+
+    #[tokio::test]
+    async fn propfind_hrefs_are_percent_decoded() {
+        let base = "/remote.php/dav/files/alice/";
+        let xml = multistatus(&[
+            base,
+            "/remote.php/dav/files/alice/Strand%20Foto.jpg",
+            "/remote.php/dav/files/alice/%D0%BE%D1%82%D1%87%D1%91%D1%82.txt",
+            "https://dav.example.com/remote.php/dav/files/alice/party%20%F0%9F%8E%82.png",
+            "/remote.php/dav/files/alice/100%2520done",
+            "/remote.php/dav/files/alice/Urlaub%202024/",
+        ]);
+        let listing = client()
+            .parse_propfind_response(xml.as_bytes(), base, None, None)
+            .await
+            .unwrap();
+
+        let names: Vec<&str> = listing.entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["Strand Foto.jpg", "отчёт.txt", "party 🎂.png", "100%20done", "Urlaub 2024"]);
+    }
+
+    #[tokio::test]
+    async fn propfind_collection_matches_encoded_request_path() {
+        let xml = multistatus(&["/remote.php/dav/files/alice/Urlaub%202024/", "/remote.php/dav/files/alice/Urlaub%202024/a.txt"]);
+        let listing = client()
+            .parse_propfind_response(xml.as_bytes(), "/remote.php/dav/files/alice/Urlaub 2024", None, None)
+            .await
+            .unwrap();
+
+        assert_eq!(listing.entries.len(), 1);
+        assert_eq!(listing.entries[0].name, "a.txt");
+    }
+
+    #[tokio::test]
+    async fn propfind_skips_atomic_upload_leftovers_with_stored_name() {
+        let (identity, recipient) = Cipher::generate_identity();
+        let cipher = Cipher::new(&crate::crypto::EncryptionConfig { recipient }, &identity).unwrap();
+        let hashed = cipher.hash_name("notes.txt");
+        let stored_name = cipher.encrypt_name("notes.txt").unwrap();
+        let client = client().with_encryption(Some(cipher));
+
+        let base = "/remote.php/dav/files/alice/";
+        let responses: String = [hashed.as_str(), ".davfs-tmp-0b5e2f14-6c1e-4d7a-9f3e-2a8c41d0e6b7"]
+            .iter()
+            .map(|name| {
+                format!(
+                    "<d:response><d:href>{}{}</d:href><d:propstat><d:prop><davfs:name>{}</davfs:name>\
+                     <davfs:size>5</davfs:size></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>",
+                    base, name, stored_name
+                )
+            })
+            .collect();
+        let xml = format!(
+            r#"<?xml version="1.0"?><d:multistatus xmlns:d="DAV:" xmlns:davfs="{}"><d:response><d:href>{}</d:href></d:response>{}</d:multistatus>"#,
+            DAVFS_NS, base, responses
+        );
+        let listing = client.parse_propfind_response(xml.as_bytes(), base, None, None).await.unwrap();
+
+        let names: Vec<&str> = listing.entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["notes.txt"]);
+    }
 }